@@ -74,6 +74,9 @@
 #![doc(html_root_url = "https://docs.rs/automaat-core/0.1.0")]
 
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use std::{error, fmt, io, path};
 use tempfile::{tempdir, TempDir};
 
@@ -113,11 +116,22 @@ pub trait Processor<'de>: fmt::Debug + Serialize + Deserialize<'de> {
 /// The `Context` is an object that can be shared across multiple processor runs
 /// for any required shared state.
 ///
-/// At the moment, it is used to provide a shared location on the local
-/// file system to store and retrieve data from.
+/// It provides a shared location on the local file system to store and
+/// retrieve data from, a way to signal processors that their run should be
+/// cancelled, and an optional deadline by which processors should finish
+/// their run.
+///
+/// A context can be forked, to run multiple processors concurrently in the
+/// same workspace, each with their own deadline.
+///
+/// Processors can also stream their output while they run, through the
+/// [`LogSink`] of the context, if one is set.
 #[derive(Debug)]
 pub struct Context {
-    workspace: TempDir,
+    workspace: Arc<Workspace>,
+    cancellation: Cancellation,
+    deadline: Mutex<Option<Instant>>,
+    log_sink: Mutex<Option<Arc<dyn LogSink>>>,
 }
 
 impl Context {
@@ -130,14 +144,199 @@ impl Context {
     /// returned. Specifically the `ContextError::Io` variant.
     pub fn new() -> Result<Self, ContextError> {
         Ok(Self {
-            workspace: tempdir()?,
+            workspace: Arc::new(Workspace::Temporary(tempdir()?)),
+            cancellation: Cancellation::default(),
+            deadline: Mutex::default(),
+            log_sink: Mutex::default(),
         })
     }
 
+    /// Create a new `Context` object, using an existing directory as its
+    /// workspace.
+    ///
+    /// Unlike the workspace of a new context, the directory is not removed
+    /// once the context is dropped. This allows processors running in another
+    /// process to share the workspace of a context.
+    ///
+    /// # Errors
+    ///
+    /// If the provided path is not an existing directory, a [`ContextError`]
+    /// enum is returned. Specifically the `ContextError::Io` variant.
+    pub fn in_workspace<P: Into<path::PathBuf>>(path: P) -> Result<Self, ContextError> {
+        let path = path.into();
+        if !path.is_dir() {
+            let message = format!("workspace {} is not a directory", path.display());
+            return Err(io::Error::new(io::ErrorKind::NotFound, message).into());
+        }
+
+        Ok(Self {
+            workspace: Arc::new(Workspace::Existing(path)),
+            cancellation: Cancellation::default(),
+            deadline: Mutex::default(),
+            log_sink: Mutex::default(),
+        })
+    }
+
+    /// Create a new `Context` object, sharing the workspace and cancellation
+    /// of this context.
+    ///
+    /// The forked context starts with the same deadline and log sink as this
+    /// context, but changing either of them on one context does not affect the
+    /// other.
+    ///
+    /// The workspace is removed once this context and all of its forks are
+    /// dropped.
+    pub fn fork(&self) -> Self {
+        Self {
+            workspace: Arc::clone(&self.workspace),
+            cancellation: self.cancellation(),
+            deadline: Mutex::new(self.deadline()),
+            log_sink: Mutex::new(self.log_sink()),
+        }
+    }
+
     /// Returns a [`std::path::Path`] reference to the shared workspace.
     pub fn workspace_path(&self) -> &path::Path {
         self.workspace.path()
     }
+
+    /// Returns a [`Cancellation`] handle that can be used to cancel any
+    /// processor running within this context, for example from another thread.
+    pub fn cancellation(&self) -> Cancellation {
+        self.cancellation.clone()
+    }
+
+    /// Returns `true` if the context was cancelled.
+    ///
+    /// Long-running processors are expected to check this value periodically,
+    /// and stop their work as soon as possible once it returns `true`.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+
+    /// Set the moment by which processors running within this context should
+    /// finish their run, or remove the deadline by passing `None`.
+    pub fn set_deadline(&self, deadline: Option<Instant>) {
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner) = deadline;
+    }
+
+    /// Returns the moment by which processors running within this context
+    /// should finish their run, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        *self.deadline.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the time left until the deadline of the context passes, or
+    /// `None` if the context has no deadline.
+    ///
+    /// Processors that block on external resources, such as network requests,
+    /// are expected to use this value as their timeout.
+    pub fn remaining(&self) -> Option<Duration> {
+        let now = Instant::now();
+
+        self.deadline().map(|deadline| {
+            if deadline > now {
+                deadline - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// Set the sink receiving the log output of processors running within this
+    /// context, or remove the sink by passing `None`.
+    pub fn set_log_sink(&self, sink: Option<Arc<dyn LogSink>>) {
+        *self.log_sink.lock().unwrap_or_else(PoisonError::into_inner) = sink;
+    }
+
+    /// Returns the sink receiving the log output of processors running within
+    /// this context, if any.
+    ///
+    /// Processors that produce output on other threads, such as the output
+    /// streams of a child process, can pass the sink to those threads.
+    pub fn log_sink(&self) -> Option<Arc<dyn LogSink>> {
+        self.log_sink
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Append a chunk of log output to the log sink of the context.
+    ///
+    /// The output is dropped if the context has no log sink.
+    pub fn log(&self, stream: LogStream, chunk: &str) {
+        if let Some(sink) = self.log_sink() {
+            sink.append(stream, chunk)
+        }
+    }
+
+    /// Returns `true` if the deadline of the context has passed.
+    ///
+    /// Long-running processors are expected to check this value periodically,
+    /// and stop their work as soon as possible once it returns `true`.
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline()
+            .map_or(false, |deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Receives the log output of processors, while they run.
+///
+/// The output is expected to be stored incrementally, so that it can be
+/// followed while a long-running processor is still running.
+pub trait LogSink: fmt::Debug + Send + Sync {
+    /// Append a chunk of output, written by a processor to the provided
+    /// stream.
+    fn append(&self, stream: LogStream, chunk: &str);
+}
+
+/// The stream to which a processor wrote a chunk of log output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum LogStream {
+    /// The standard output stream.
+    Stdout,
+
+    /// The standard error stream.
+    Stderr,
+}
+
+/// The workspace shared by a [`Context`] and its forks.
+#[derive(Debug)]
+enum Workspace {
+    /// A temporary directory, removed once dropped.
+    Temporary(TempDir),
+
+    /// An existing directory, owned by someone else.
+    Existing(path::PathBuf),
+}
+
+impl Workspace {
+    fn path(&self) -> &path::Path {
+        match self {
+            Workspace::Temporary(dir) => dir.path(),
+            Workspace::Existing(path) => path,
+        }
+    }
+}
+
+/// A handle to signal cancellation to all processors running within a
+/// [`Context`].
+///
+/// The handle can be cloned and sent across threads. Once cancelled, a context
+/// stays cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    /// Cancel the context this handle belongs to.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst)
+    }
+
+    /// Returns `true` if the context this handle belongs to was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
 }
 
 /// Represents all the ways that a [`Context`] can fail.
@@ -188,6 +387,100 @@ mod tests {
         assert!(context.workspace_path().exists())
     }
 
+    #[test]
+    fn test_context_cancellation() {
+        let context = Context::new().unwrap();
+        let cancellation = context.cancellation();
+
+        assert!(!context.is_cancelled());
+        cancellation.cancel();
+        assert!(context.is_cancelled());
+    }
+
+    #[test]
+    fn test_context_deadline() {
+        let context = Context::new().unwrap();
+
+        assert!(!context.is_timed_out());
+        assert_eq!(context.remaining(), None);
+
+        context.set_deadline(Some(Instant::now() + Duration::from_secs(60)));
+        assert!(!context.is_timed_out());
+        assert!(context.remaining().unwrap() > Duration::from_secs(0));
+
+        context.set_deadline(Some(Instant::now()));
+        assert!(context.is_timed_out());
+        assert_eq!(context.remaining(), Some(Duration::from_secs(0)));
+
+        context.set_deadline(None);
+        assert!(!context.is_timed_out());
+    }
+
+    #[test]
+    fn test_context_fork() {
+        let context = Context::new().unwrap();
+        let fork = context.fork();
+
+        assert_eq!(context.workspace_path(), fork.workspace_path());
+
+        fork.set_deadline(Some(Instant::now()));
+        assert!(fork.is_timed_out());
+        assert!(!context.is_timed_out());
+
+        context.cancellation().cancel();
+        assert!(fork.is_cancelled());
+
+        let path = context.workspace_path().to_owned();
+        drop(context);
+        assert!(path.exists());
+
+        drop(fork);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_context_in_workspace() {
+        let context = Context::new().unwrap();
+        let path = context.workspace_path().to_owned();
+
+        let borrowed = Context::in_workspace(&path).unwrap();
+        assert_eq!(borrowed.workspace_path(), path);
+
+        drop(borrowed);
+        assert!(path.exists());
+
+        drop(context);
+        assert!(Context::in_workspace(&path).is_err());
+    }
+
+    #[derive(Debug, Default)]
+    struct Sink(Mutex<Vec<(LogStream, String)>>);
+
+    impl LogSink for Sink {
+        fn append(&self, stream: LogStream, chunk: &str) {
+            self.0.lock().unwrap().push((stream, chunk.to_owned()))
+        }
+    }
+
+    #[test]
+    fn test_context_log_sink() {
+        let context = Context::new().unwrap();
+        context.log(LogStream::Stdout, "dropped");
+
+        let sink = Arc::new(Sink::default());
+        context.set_log_sink(Some(sink.clone()));
+        context.log(LogStream::Stdout, "hello");
+        context.fork().log(LogStream::Stderr, "world");
+
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            vec![
+                (LogStream::Stdout, "hello".to_owned()),
+                (LogStream::Stderr, "world".to_owned())
+            ]
+        );
+    }
+
     #[test]
     fn test_readme_deps() {
         version_sync::assert_markdown_deps_updated!("README.md");
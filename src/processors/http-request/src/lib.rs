@@ -7,9 +7,13 @@
 //! you can always use the [Shell Command] processor combined with a utility
 //! like [`cURL`] if you need more advanced functionality.
 //!
+//! If the [`Context`] has a deadline, the request times out once the deadline
+//! passes.
+//!
 //! [Automaat]: automaat_core
 //! [Shell Command]: https://docs.rs/automaat-processor-shell-command
 //! [`cURL`]: https://curl.haxx.se/
+//! [`Context`]: automaat_core::Context
 //!
 //! # Examples
 //!
@@ -302,16 +306,24 @@ impl<'a> Processor<'a> for HttpRequest {
     /// If the provided HTTP headers are invalid, the [`Error::Header`] error
     /// variant is returned.
     ///
-    /// If the request fails, or the response body cannot be read, the
-    /// [`Error::Response`] error variant is returned.
+    /// If the request fails, times out, or the response body cannot be read,
+    /// the [`Error::Response`] error variant is returned.
     ///
     /// If the response status does not match one of the provided status
     /// assertions, the [`Error::Status`] error variant is returned.
-    fn run(&self, _context: &Context) -> Result<Option<Self::Output>, Self::Error> {
+    fn run(&self, context: &Context) -> Result<Option<Self::Output>, Self::Error> {
         self.validate()?;
 
+        // client, using the default timeout if the context has no deadline
+        let mut client = Client::builder();
+        if let Some(remaining) = context.remaining() {
+            client = client.timeout(remaining);
+        }
+
         // request builder
-        let mut request = Client::new().request(self.method.into(), self.url.as_str());
+        let mut request = client
+            .build()?
+            .request(self.method.into(), self.url.as_str());
 
         // headers
         let mut map = header::HeaderMap::new();
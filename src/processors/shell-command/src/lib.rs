@@ -11,6 +11,13 @@
 //!
 //! All commands are executed within the [`Context`] workspace.
 //!
+//! If the [`Context`] is cancelled while the command is running, or its
+//! deadline passes, the command is killed, together with any processes it
+//! started, and the processor returns an error.
+//!
+//! While the command runs, its _stdout_ and _stderr_ output is streamed to the
+//! log sink of the [`Context`], if any.
+//!
 //! [Automaat]: automaat_core
 //! [`Context`]: automaat_core::Context
 //!
@@ -71,11 +78,17 @@
 #![allow(clippy::multiple_crate_versions, missing_doc_code_examples)]
 #![doc(html_root_url = "https://docs.rs/automaat-processor-shell-command/0.1.0")]
 
-use automaat_core::{Context, Processor};
+use automaat_core::{Context, LogSink, LogStream, Processor};
 use serde::{Deserialize, Serialize};
-use std::io::Write;
-use std::process::{Command, Stdio};
-use std::{env, error, fmt, io, path};
+use std::io::{Read, Write};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, error, fmt, io, path, str, thread};
+
+/// The interval at which a running command is checked for completion, or
+/// cancellation of the [`Context`].
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
 
 /// The processor configuration.
 #[cfg_attr(feature = "juniper", derive(juniper::GraphQLObject))]
@@ -210,6 +223,12 @@ impl<'a> Processor<'a> for ShellCommand {
     /// If the run fails, an [`Error`] result value is returned. The variant can
     /// differ, depending on if the command itself failed, some IO error
     /// happened, or the configuration is invalid.
+    ///
+    /// If the context is cancelled before the command exits, the command is
+    /// killed, and [`Error::Cancelled`] is returned.
+    ///
+    /// If the deadline of the context passes before the command exits, the
+    /// command is killed, and [`Error::TimedOut`] is returned.
     fn run(&self, context: &Context) -> Result<Option<Self::Output>, Self::Error> {
         self.validate()?;
 
@@ -234,24 +253,68 @@ impl<'a> Processor<'a> for ShellCommand {
             None => new_paths,
         };
 
+        let stdin = match self.stdin {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        };
+
         let mut command = Command::new(&self.command);
-        let command = command
+        let _ = command
             .current_dir(cwd)
             .env("PATH", env::join_paths(path)?)
-            .args(arguments);
+            .args(arguments)
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        // The command runs in a process group of its own, so that any
+        // processes it starts are killed together with the command.
+        #[cfg(unix)]
+        let _ = std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        let mut child = command.spawn()?;
+
+        if let Some(input) = &self.stdin {
+            // Taking ownership of the stdin handle closes it once the input is
+            // written, signaling the end of the input to the command.
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(input.as_bytes())?;
+            }
+        }
+
+        // The output streams are read on separate threads, to prevent the
+        // command from blocking on a full pipe while we wait for it to exit.
+        let stdout = read_to_end(child.stdout.take(), context.log_sink(), LogStream::Stdout);
+        let stderr = read_to_end(child.stderr.take(), context.log_sink(), LogStream::Stderr);
+
+        let status = loop {
+            let error = if context.is_cancelled() {
+                Some(Error::Cancelled)
+            } else if context.is_timed_out() {
+                Some(Error::TimedOut)
+            } else {
+                None
+            };
 
-        let output = if let Some(input) = &self.stdin {
-            let mut spawn = command
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()?;
+            if let Some(error) = error {
+                kill(&mut child);
+                let _ = child.wait()?;
 
-            spawn.stdin.as_mut().unwrap().write_all(input.as_bytes())?;
-            spawn.wait_with_output()
-        } else {
-            command.output()
-        }?;
+                return Err(error);
+            }
+
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+
+            thread::sleep(WAIT_INTERVAL);
+        };
+
+        let output = Output {
+            status,
+            stdout: join_reader(stdout)?,
+            stderr: join_reader(stderr)?,
+        };
 
         if !output.status.success() {
             if output.stderr.is_empty() {
@@ -279,6 +342,90 @@ impl<'a> Processor<'a> for ShellCommand {
     }
 }
 
+/// Kill the command, and any processes it started.
+///
+/// On Unix, the standard library can only signal the command itself, so the
+/// process group of the command is killed using the `kill` utility.
+///
+/// The command might have exited in the meantime, in which case killing it
+/// fails, which is fine.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    let _ = Command::new("kill")
+        .args(&["-s", "KILL", "--", &format!("-{}", child.id())])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    let _ = child.kill();
+}
+
+/// Read the stream to the end, appending each chunk to the log sink, if any,
+/// as soon as it is read.
+///
+/// A multi-byte character split across two reads is logged once the rest of
+/// the character is read.
+fn read_to_end<R>(
+    stream: Option<R>,
+    sink: Option<Arc<dyn LogSink>>,
+    log_stream: LogStream,
+) -> thread::JoinHandle<io::Result<Vec<u8>>>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut buffer = vec![];
+        let mut stream = match stream {
+            Some(stream) => stream,
+            None => return Ok(buffer),
+        };
+
+        let mut chunk = [0; 8192];
+        let mut logged = 0;
+
+        loop {
+            let read = match stream.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            buffer.extend_from_slice(&chunk[..read]);
+
+            if let Some(sink) = &sink {
+                let pending = &buffer[logged..];
+                let complete = match str::from_utf8(pending) {
+                    Err(err) if err.error_len().is_none() => err.valid_up_to(),
+                    _ => pending.len(),
+                };
+
+                if complete > 0 {
+                    sink.append(log_stream, &String::from_utf8_lossy(&pending[..complete]));
+                    logged += complete;
+                }
+            }
+        }
+
+        if let Some(sink) = &sink {
+            if logged < buffer.len() {
+                sink.append(log_stream, &String::from_utf8_lossy(&buffer[logged..]));
+            }
+        }
+
+        Ok(buffer)
+    })
+}
+
+/// Wait for a reader thread to finish, and return the read bytes.
+fn join_reader(handle: thread::JoinHandle<io::Result<Vec<u8>>>) -> Result<Vec<u8>, Error> {
+    handle
+        .join()
+        .map_err(|_| Error::Command("unable to read command output".into()))?
+        .map_err(Into::into)
+}
+
 /// Represents all the ways that [`ShellCommand`] can fail.
 ///
 /// This type is not intended to be exhaustively matched, and new variants may
@@ -292,6 +439,16 @@ pub enum Error {
     /// The string value represents the _stderr_ output of the command.
     Command(String),
 
+    /// The command was killed, because the [`Context`] was cancelled.
+    ///
+    /// [`Context`]: automaat_core::Context
+    Cancelled,
+
+    /// The command was killed, because the deadline of the [`Context`] passed.
+    ///
+    /// [`Context`]: automaat_core::Context
+    TimedOut,
+
     /// An I/O operation failed.
     ///
     /// This is a wrapper around [`std::io::Error`].
@@ -309,6 +466,8 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
             Error::Command(ref err) => write!(f, "Command error: {}", err),
+            Error::Cancelled => write!(f, "Command cancelled"),
+            Error::TimedOut => write!(f, "Command timed out"),
             Error::Io(ref err) => write!(f, "IO error: {}", err),
             Error::Path(ref err) => write!(f, "Path error: {}", err),
             Error::__Unknown => unreachable!(),
@@ -319,7 +478,7 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            Error::Command(_) | Error::Path(_) => None,
+            Error::Command(_) | Error::Cancelled | Error::TimedOut | Error::Path(_) => None,
             Error::Io(ref err) => Some(err),
             Error::__Unknown => unreachable!(),
         }
@@ -437,6 +596,98 @@ mod tests {
             )
         }
 
+        #[test]
+        fn test_command_log_sink() {
+            #[derive(Debug, Default)]
+            struct Sink(std::sync::Mutex<Vec<(LogStream, String)>>);
+
+            impl LogSink for Sink {
+                fn append(&self, stream: LogStream, chunk: &str) {
+                    self.0.lock().unwrap().push((stream, chunk.to_owned()))
+                }
+            }
+
+            let mut processor = processor_stub();
+            processor.command = "sh".to_owned();
+            processor.arguments = Some(vec![
+                "-c".to_owned(),
+                "echo hello; echo world >&2".to_owned(),
+            ]);
+
+            let sink = Arc::new(Sink::default());
+            let context = Context::new().unwrap();
+            context.set_log_sink(Some(sink.clone()));
+
+            let output = processor.run(&context).unwrap();
+            let mut logs = sink.0.lock().unwrap().clone();
+            logs.sort_by_key(|(stream, _)| *stream == LogStream::Stderr);
+
+            assert_eq!(output, Some("hello".to_owned()));
+            assert_eq!(
+                logs,
+                vec![
+                    (LogStream::Stdout, "hello\n".to_owned()),
+                    (LogStream::Stderr, "world\n".to_owned())
+                ]
+            );
+        }
+
+        #[test]
+        fn test_cancelled_context() {
+            let mut processor = processor_stub();
+            processor.command = "sleep".to_owned();
+            processor.arguments = Some(vec!["10".to_owned()]);
+
+            let context = Context::new().unwrap();
+            let cancellation = context.cancellation();
+            let _ = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                cancellation.cancel()
+            });
+
+            let error = processor.run(&context).unwrap_err();
+
+            assert_eq!(error.to_string(), "Command cancelled".to_owned())
+        }
+
+        #[test]
+        fn test_cancelled_context_kills_started_processes() {
+            let mut processor = processor_stub();
+            processor.command = "sh".to_owned();
+            processor.arguments = Some(vec![
+                "-c".to_owned(),
+                "(sleep 1 && touch started) & wait".to_owned(),
+            ]);
+
+            let context = Context::new().unwrap();
+            let cancellation = context.cancellation();
+            let _ = thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                cancellation.cancel()
+            });
+
+            let error = processor.run(&context).unwrap_err();
+            assert_eq!(error.to_string(), "Command cancelled".to_owned());
+
+            thread::sleep(Duration::from_millis(1500));
+            assert!(!context.workspace_path().join("started").exists());
+        }
+
+        #[test]
+        fn test_timed_out_context() {
+            let mut processor = processor_stub();
+            processor.command = "sleep".to_owned();
+            processor.arguments = Some(vec!["10".to_owned()]);
+
+            let context = Context::new().unwrap();
+            let deadline = std::time::Instant::now() + Duration::from_millis(50);
+            context.set_deadline(Some(deadline));
+
+            let error = processor.run(&context).unwrap_err();
+
+            assert_eq!(error.to_string(), "Command timed out".to_owned())
+        }
+
         #[test]
         fn test_appending_paths() {
             let mut processor = processor_stub();
@@ -13,7 +13,11 @@
 //! Combining this processor with the "[JSON Editor]" processor allows you to
 //! transform the returned data before presenting it to the user.
 //!
+//! If the [`Context`] has a deadline, the statement is cancelled by the
+//! database once the deadline passes.
+//!
 //! [Automaat]: automaat_core
+//! [`Context`]: automaat_core::Context
 //! [JSON Editor]: https://docs.rs/automaat-processor-json-editor
 //!
 //! # Example
@@ -103,6 +107,7 @@ use sqlparser::ast::Statement;
 use sqlparser::dialect::{Dialect, GenericDialect};
 use sqlparser::parser::{Parser, ParserError};
 use std::collections::HashMap;
+use std::time::Duration;
 use std::{error, fmt, str::FromStr};
 use url::Url;
 
@@ -232,11 +237,23 @@ impl SqlQuery {
         }
     }
 
-    fn run_postgres_statement(&self, parameters: &[&dyn ToSql]) -> Result<Option<String>, Error> {
+    fn run_postgres_statement(
+        &self,
+        parameters: &[&dyn ToSql],
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>, Error> {
         use postgres::{types::Type as T, Client, NoTls};
         use serde_json::{to_string, to_value};
 
         let mut conn = Client::connect(self.url.as_str(), NoTls).map_err(Error::from)?;
+
+        // A statement timeout of zero disables the timeout, so the timeout is
+        // at least one millisecond.
+        if let Some(timeout) = timeout {
+            let millis = timeout.as_millis().max(1);
+            conn.batch_execute(&format!("SET statement_timeout = {}", millis))
+                .map_err(Error::from)?;
+        }
         let rows = conn
             .query(self.statement.as_str(), parameters)
             .map_err(Error::from)?;
@@ -318,7 +335,7 @@ impl<'a> Processor<'a> for SqlQuery {
     ///
     /// If anything happens during serialization, the [`Error::Serde`] error is
     /// returned.
-    fn run(&self, _context: &Context) -> Result<Option<Self::Output>, Self::Error> {
+    fn run(&self, context: &Context) -> Result<Option<Self::Output>, Self::Error> {
         self.validate()?;
 
         let mut parameters: Vec<&dyn ToSql> = vec![];
@@ -342,7 +359,7 @@ impl<'a> Processor<'a> for SqlQuery {
         }
 
         match self.url()?.scheme() {
-            "postgres" => self.run_postgres_statement(&parameters),
+            "postgres" => self.run_postgres_statement(&parameters, context.remaining()),
             "sqlite" => self.run_sqlite_statement(),
             "mysql" => self.run_mysql_statement(),
             _ => unimplemented!(),
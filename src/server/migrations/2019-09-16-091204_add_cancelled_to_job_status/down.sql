@@ -0,0 +1,7 @@
+UPDATE jobs SET status = 'failed' WHERE status = 'cancelled';
+
+ALTER TYPE JobStatus RENAME TO JobStatusNew;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'failed', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+DROP TYPE JobStatusNew;
@@ -0,0 +1,8 @@
+-- The `cancelled` value was never added to the job status type, even though
+-- jobs can be cancelled. Enum values cannot be added within a transaction, so
+-- the type is recreated instead.
+ALTER TYPE JobStatus RENAME TO JobStatusOld;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+DROP TYPE JobStatusOld;
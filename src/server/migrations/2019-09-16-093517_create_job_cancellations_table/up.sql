@@ -0,0 +1,7 @@
+CREATE TABLE job_cancellations (
+    id           Serial    PRIMARY KEY,
+    requested_at Timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    job_id       Integer   NOT NULL REFERENCES jobs ON DELETE CASCADE,
+
+    UNIQUE(job_id)
+);
@@ -0,0 +1,2 @@
+DROP TABLE schedule_variables;
+DROP TABLE schedules;
@@ -0,0 +1,18 @@
+CREATE TABLE schedules (
+    id         Serial  PRIMARY KEY,
+    expression Text    NOT NULL,
+    timezone   Text    NOT NULL DEFAULT 'UTC',
+    paused     Boolean NOT NULL DEFAULT false,
+    task_id    Integer NOT NULL REFERENCES tasks ON DELETE CASCADE
+);
+
+CREATE INDEX ON schedules (task_id);
+
+CREATE TABLE schedule_variables (
+    id          Serial  PRIMARY KEY,
+    key         Text    NOT NULL,
+    value       Bytea   NOT NULL,
+    schedule_id Integer NOT NULL REFERENCES schedules ON DELETE CASCADE,
+
+    UNIQUE(key, schedule_id)
+);
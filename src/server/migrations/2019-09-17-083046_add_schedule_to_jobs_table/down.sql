@@ -0,0 +1,2 @@
+ALTER TABLE jobs DROP COLUMN schedule_id;
+ALTER TABLE jobs DROP COLUMN scheduled_for;
@@ -0,0 +1,6 @@
+ALTER TABLE jobs ADD COLUMN scheduled_for Timestamp;
+ALTER TABLE jobs ADD COLUMN schedule_id Integer REFERENCES schedules ON DELETE SET NULL;
+
+-- A schedule can only ever create a single job for each moment in time, which
+-- guarantees a schedule never fires twice, even with multiple schedulers.
+ALTER TABLE jobs ADD UNIQUE (schedule_id, scheduled_for);
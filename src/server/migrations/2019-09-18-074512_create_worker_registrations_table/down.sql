@@ -0,0 +1,2 @@
+ALTER TABLE jobs DROP COLUMN worker_id;
+DROP TABLE worker_registrations;
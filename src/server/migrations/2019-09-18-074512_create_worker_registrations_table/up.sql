@@ -0,0 +1,8 @@
+CREATE TABLE worker_registrations (
+    id           Serial    PRIMARY KEY,
+    started_at   Timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    heartbeat_at Timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc')
+);
+
+ALTER TABLE jobs ADD COLUMN worker_id Integer REFERENCES worker_registrations ON DELETE SET NULL;
+CREATE INDEX ON jobs (worker_id);
@@ -0,0 +1 @@
+ALTER TABLE tasks DROP COLUMN requeue_on_worker_loss;
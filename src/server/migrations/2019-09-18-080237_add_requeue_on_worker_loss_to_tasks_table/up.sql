@@ -0,0 +1 @@
+ALTER TABLE tasks ADD COLUMN requeue_on_worker_loss Boolean NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+ALTER TABLE job_steps DROP COLUMN retry_policy;
+ALTER TABLE steps DROP COLUMN retry_policy;
@@ -0,0 +1,2 @@
+ALTER TABLE steps ADD COLUMN retry_policy Jsonb;
+ALTER TABLE job_steps ADD COLUMN retry_policy Jsonb;
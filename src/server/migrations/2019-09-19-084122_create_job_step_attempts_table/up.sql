@@ -0,0 +1,10 @@
+CREATE TABLE job_step_attempts (
+    id          Serial    PRIMARY KEY,
+    attempt     Integer   NOT NULL,
+    started_at  Timestamp NOT NULL,
+    finished_at Timestamp,
+    error       Text,
+    job_step_id Integer   NOT NULL REFERENCES job_steps ON DELETE CASCADE
+);
+
+CREATE INDEX ON job_step_attempts (job_step_id);
@@ -0,0 +1,2 @@
+ALTER TABLE job_steps DROP COLUMN "when";
+ALTER TABLE steps DROP COLUMN "when";
@@ -0,0 +1,2 @@
+ALTER TABLE steps ADD COLUMN "when" Text;
+ALTER TABLE job_steps ADD COLUMN "when" Text;
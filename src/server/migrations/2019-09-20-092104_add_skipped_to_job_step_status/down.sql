@@ -0,0 +1,9 @@
+UPDATE job_steps SET status = 'ok' WHERE status = 'skipped';
+
+ALTER TYPE JobStepStatus RENAME TO JobStepStatusNew;
+CREATE TYPE JobStepStatus AS ENUM ('initialized', 'pending', 'running', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE job_steps ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE job_steps ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+ALTER TABLE job_steps ALTER COLUMN status SET DEFAULT 'pending';
+DROP TYPE JobStepStatusNew;
@@ -0,0 +1,9 @@
+-- Enum values cannot be added within a transaction, so the type is recreated
+-- instead.
+ALTER TYPE JobStepStatus RENAME TO JobStepStatusOld;
+CREATE TYPE JobStepStatus AS ENUM ('initialized', 'pending', 'running', 'failed', 'cancelled', 'skipped', 'ok');
+
+ALTER TABLE job_steps ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE job_steps ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+ALTER TABLE job_steps ALTER COLUMN status SET DEFAULT 'pending';
+DROP TYPE JobStepStatusOld;
@@ -0,0 +1,4 @@
+ALTER TABLE job_steps DROP COLUMN run_policy;
+ALTER TABLE steps DROP COLUMN run_policy;
+
+DROP TYPE StepRunPolicy;
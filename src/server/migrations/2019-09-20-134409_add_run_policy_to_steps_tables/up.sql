@@ -0,0 +1,4 @@
+CREATE TYPE StepRunPolicy AS ENUM ('on_success', 'on_failure', 'always');
+
+ALTER TABLE steps ADD COLUMN run_policy StepRunPolicy NOT NULL DEFAULT 'on_success';
+ALTER TABLE job_steps ADD COLUMN run_policy StepRunPolicy NOT NULL DEFAULT 'on_success';
@@ -0,0 +1,3 @@
+ALTER TABLE job_step_attempts DROP COLUMN item;
+ALTER TABLE job_steps DROP COLUMN for_each;
+ALTER TABLE steps DROP COLUMN for_each;
@@ -0,0 +1,3 @@
+ALTER TABLE steps ADD COLUMN for_each Jsonb;
+ALTER TABLE job_steps ADD COLUMN for_each Jsonb;
+ALTER TABLE job_step_attempts ADD COLUMN item Integer;
@@ -0,0 +1,2 @@
+ALTER TABLE jobs ADD COLUMN parent_id Integer REFERENCES jobs ON DELETE SET NULL;
+CREATE INDEX ON jobs (parent_id);
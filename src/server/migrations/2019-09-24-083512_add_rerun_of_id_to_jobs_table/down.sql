@@ -0,0 +1 @@
+ALTER TABLE jobs DROP COLUMN rerun_of_id;
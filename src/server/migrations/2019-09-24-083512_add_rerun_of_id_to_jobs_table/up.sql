@@ -0,0 +1,2 @@
+ALTER TABLE jobs ADD COLUMN rerun_of_id Integer REFERENCES jobs ON DELETE SET NULL;
+CREATE INDEX ON jobs (rerun_of_id);
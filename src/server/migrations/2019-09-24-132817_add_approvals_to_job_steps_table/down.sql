@@ -0,0 +1,25 @@
+ALTER TABLE job_steps DROP COLUMN approval_comment;
+ALTER TABLE job_steps DROP COLUMN approval_decided_at;
+ALTER TABLE job_steps DROP COLUMN approval_decided_by;
+ALTER TABLE job_steps DROP COLUMN approval_decision;
+ALTER TABLE job_steps DROP COLUMN approval_expires_at;
+
+DROP TYPE ApprovalDecision;
+
+UPDATE job_steps SET status = 'cancelled' WHERE status = 'awaiting_approval';
+
+ALTER TYPE JobStepStatus RENAME TO JobStepStatusNew;
+CREATE TYPE JobStepStatus AS ENUM ('initialized', 'pending', 'running', 'failed', 'cancelled', 'skipped', 'ok');
+
+ALTER TABLE job_steps ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE job_steps ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+ALTER TABLE job_steps ALTER COLUMN status SET DEFAULT 'pending';
+DROP TYPE JobStepStatusNew;
+
+UPDATE jobs SET status = 'cancelled' WHERE status = 'awaiting_approval';
+
+ALTER TYPE JobStatus RENAME TO JobStatusNew;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+DROP TYPE JobStatusNew;
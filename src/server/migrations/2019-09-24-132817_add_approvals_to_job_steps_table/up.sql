@@ -0,0 +1,23 @@
+-- Enum values cannot be added within a transaction, so the types are
+-- recreated instead.
+ALTER TYPE JobStatus RENAME TO JobStatusOld;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'awaiting_approval', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+DROP TYPE JobStatusOld;
+
+ALTER TYPE JobStepStatus RENAME TO JobStepStatusOld;
+CREATE TYPE JobStepStatus AS ENUM ('initialized', 'pending', 'running', 'awaiting_approval', 'failed', 'cancelled', 'skipped', 'ok');
+
+ALTER TABLE job_steps ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE job_steps ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+ALTER TABLE job_steps ALTER COLUMN status SET DEFAULT 'pending';
+DROP TYPE JobStepStatusOld;
+
+CREATE TYPE ApprovalDecision AS ENUM ('approved', 'rejected');
+
+ALTER TABLE job_steps ADD COLUMN approval_expires_at Timestamp;
+ALTER TABLE job_steps ADD COLUMN approval_decision ApprovalDecision;
+ALTER TABLE job_steps ADD COLUMN approval_decided_by Integer REFERENCES sessions ON DELETE SET NULL;
+ALTER TABLE job_steps ADD COLUMN approval_decided_at Timestamp;
+ALTER TABLE job_steps ADD COLUMN approval_comment Text;
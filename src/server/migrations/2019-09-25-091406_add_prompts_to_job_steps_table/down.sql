@@ -0,0 +1,23 @@
+ALTER TABLE job_steps DROP COLUMN prompt_answered_at;
+ALTER TABLE job_steps DROP COLUMN prompt_answered_by;
+ALTER TABLE job_steps DROP COLUMN prompt_options;
+ALTER TABLE job_steps DROP COLUMN prompt_question;
+ALTER TABLE job_steps DROP COLUMN prompt_variable;
+
+UPDATE job_steps SET status = 'cancelled' WHERE status = 'awaiting_input';
+
+ALTER TYPE JobStepStatus RENAME TO JobStepStatusNew;
+CREATE TYPE JobStepStatus AS ENUM ('initialized', 'pending', 'running', 'awaiting_approval', 'failed', 'cancelled', 'skipped', 'ok');
+
+ALTER TABLE job_steps ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE job_steps ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+ALTER TABLE job_steps ALTER COLUMN status SET DEFAULT 'pending';
+DROP TYPE JobStepStatusNew;
+
+UPDATE jobs SET status = 'cancelled' WHERE status = 'awaiting_input';
+
+ALTER TYPE JobStatus RENAME TO JobStatusNew;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'awaiting_approval', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+DROP TYPE JobStatusNew;
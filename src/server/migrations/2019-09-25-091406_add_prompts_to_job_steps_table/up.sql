@@ -0,0 +1,21 @@
+-- Enum values cannot be added within a transaction, so the types are
+-- recreated instead.
+ALTER TYPE JobStatus RENAME TO JobStatusOld;
+CREATE TYPE JobStatus AS ENUM ('scheduled', 'pending', 'running', 'awaiting_approval', 'awaiting_input', 'failed', 'cancelled', 'ok');
+
+ALTER TABLE jobs ALTER COLUMN status TYPE JobStatus USING status::Text::JobStatus;
+DROP TYPE JobStatusOld;
+
+ALTER TYPE JobStepStatus RENAME TO JobStepStatusOld;
+CREATE TYPE JobStepStatus AS ENUM ('initialized', 'pending', 'running', 'awaiting_approval', 'awaiting_input', 'failed', 'cancelled', 'skipped', 'ok');
+
+ALTER TABLE job_steps ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE job_steps ALTER COLUMN status TYPE JobStepStatus USING status::Text::JobStepStatus;
+ALTER TABLE job_steps ALTER COLUMN status SET DEFAULT 'pending';
+DROP TYPE JobStepStatusOld;
+
+ALTER TABLE job_steps ADD COLUMN prompt_variable Text;
+ALTER TABLE job_steps ADD COLUMN prompt_question Text;
+ALTER TABLE job_steps ADD COLUMN prompt_options Text[];
+ALTER TABLE job_steps ADD COLUMN prompt_answered_by Integer REFERENCES sessions ON DELETE SET NULL;
+ALTER TABLE job_steps ADD COLUMN prompt_answered_at Timestamp;
@@ -0,0 +1,4 @@
+ALTER TABLE jobs DROP COLUMN blocked_reason;
+
+ALTER TABLE tasks DROP COLUMN lock_group;
+ALTER TABLE tasks DROP COLUMN max_concurrent_jobs;
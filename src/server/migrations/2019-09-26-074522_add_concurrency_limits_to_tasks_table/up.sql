@@ -0,0 +1,5 @@
+ALTER TABLE tasks ADD COLUMN max_concurrent_jobs Integer;
+ALTER TABLE tasks ADD COLUMN lock_group Text;
+CREATE INDEX ON tasks (lock_group);
+
+ALTER TABLE jobs ADD COLUMN blocked_reason Text;
@@ -0,0 +1,3 @@
+ALTER TABLE jobs DROP COLUMN priority;
+
+ALTER TABLE tasks DROP COLUMN priority;
@@ -0,0 +1,4 @@
+ALTER TABLE tasks ADD COLUMN priority Integer NOT NULL DEFAULT 0;
+
+ALTER TABLE jobs ADD COLUMN priority Integer NOT NULL DEFAULT 0;
+CREATE INDEX ON jobs (status, priority DESC, id);
@@ -0,0 +1,5 @@
+ALTER TABLE jobs DROP COLUMN queue;
+
+ALTER TABLE tasks DROP COLUMN queue;
+
+ALTER TABLE worker_registrations DROP COLUMN queues;
@@ -0,0 +1,6 @@
+ALTER TABLE worker_registrations ADD COLUMN queues Text[] NOT NULL DEFAULT '{}';
+
+ALTER TABLE tasks ADD COLUMN queue Text;
+
+ALTER TABLE jobs ADD COLUMN queue Text;
+CREATE INDEX ON jobs (queue);
@@ -0,0 +1,6 @@
+DROP TABLE agent_dispatches;
+
+ALTER TABLE jobs DROP COLUMN agent_id;
+
+ALTER TABLE worker_registrations DROP COLUMN session_id;
+ALTER TABLE worker_registrations DROP COLUMN concurrency;
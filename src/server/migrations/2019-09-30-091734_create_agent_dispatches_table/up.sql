@@ -0,0 +1,21 @@
+ALTER TABLE worker_registrations ADD COLUMN concurrency Integer NOT NULL DEFAULT 1;
+ALTER TABLE worker_registrations ADD COLUMN session_id Integer REFERENCES sessions ON DELETE CASCADE;
+
+ALTER TABLE jobs ADD COLUMN agent_id Integer REFERENCES worker_registrations ON DELETE SET NULL;
+CREATE INDEX ON jobs (agent_id);
+
+CREATE TABLE agent_dispatches (
+    id          Serial    PRIMARY KEY,
+    processor   Bytea     NOT NULL,
+    deadline_at Timestamp,
+    claimed_at  Timestamp,
+    finished_at Timestamp,
+    output      Text,
+    error       Text,
+    agent_id    Integer   NOT NULL REFERENCES worker_registrations ON DELETE CASCADE,
+    job_id      Integer   NOT NULL REFERENCES jobs ON DELETE CASCADE,
+    job_step_id Integer   NOT NULL REFERENCES job_steps ON DELETE CASCADE
+);
+
+CREATE INDEX ON agent_dispatches (agent_id);
+CREATE INDEX ON agent_dispatches (job_id);
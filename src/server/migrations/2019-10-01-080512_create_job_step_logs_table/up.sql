@@ -0,0 +1,11 @@
+CREATE TYPE JobStepLogStream AS ENUM ('stdout', 'stderr');
+
+CREATE TABLE job_step_logs (
+    id          Serial           PRIMARY KEY,
+    stream      JobStepLogStream NOT NULL,
+    chunk       Text             NOT NULL,
+    created_at  Timestamp        NOT NULL DEFAULT (now() AT TIME ZONE 'utc'),
+    job_step_id Integer          NOT NULL REFERENCES job_steps ON DELETE CASCADE
+);
+
+CREATE INDEX ON job_step_logs (job_step_id, id);
@@ -0,0 +1,6 @@
+DROP TRIGGER notify_job_step_log ON job_step_logs;
+DROP TRIGGER notify_job_updated ON job_steps;
+DROP TRIGGER notify_job_updated ON jobs;
+
+DROP FUNCTION automaat_notify_job_step_log();
+DROP FUNCTION automaat_notify_job_updated();
@@ -0,0 +1,28 @@
+CREATE FUNCTION automaat_notify_job_updated() RETURNS trigger AS $$
+BEGIN
+    IF TG_TABLE_NAME = 'jobs' THEN
+        PERFORM pg_notify('automaat_job_updates', NEW.id::text);
+    ELSE
+        PERFORM pg_notify('automaat_job_updates', NEW.job_id::text);
+    END IF;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION automaat_notify_job_step_log() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('automaat_job_step_logs', NEW.job_step_id::text);
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notify_job_updated AFTER INSERT OR UPDATE ON jobs
+    FOR EACH ROW EXECUTE PROCEDURE automaat_notify_job_updated();
+
+CREATE TRIGGER notify_job_updated AFTER INSERT OR UPDATE ON job_steps
+    FOR EACH ROW EXECUTE PROCEDURE automaat_notify_job_updated();
+
+CREATE TRIGGER notify_job_step_log AFTER INSERT ON job_step_logs
+    FOR EACH ROW EXECUTE PROCEDURE automaat_notify_job_step_log();
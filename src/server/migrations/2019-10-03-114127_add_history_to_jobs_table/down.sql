@@ -0,0 +1,6 @@
+DROP TRIGGER set_job_finished_at ON jobs;
+DROP FUNCTION automaat_set_job_finished_at();
+
+ALTER TABLE jobs DROP COLUMN session_id;
+ALTER TABLE jobs DROP COLUMN finished_at;
+ALTER TABLE jobs DROP COLUMN created_at;
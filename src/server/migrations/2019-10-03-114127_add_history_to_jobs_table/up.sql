@@ -0,0 +1,45 @@
+ALTER TABLE jobs ADD COLUMN created_at Timestamp NOT NULL DEFAULT (now() AT TIME ZONE 'utc');
+ALTER TABLE jobs ADD COLUMN finished_at Timestamp;
+ALTER TABLE jobs ADD COLUMN session_id Integer REFERENCES sessions ON DELETE SET NULL;
+
+-- Existing jobs did not record these moments, so they are derived from the
+-- moments their steps started and finished running, where possible. The steps
+-- are aggregated in a single pass, instead of once for every job.
+UPDATE jobs SET
+    created_at = COALESCE(steps.started_at, jobs.scheduled_for, jobs.created_at),
+    finished_at = CASE WHEN jobs.status IN ('failed', 'cancelled', 'ok') THEN COALESCE(
+        steps.finished_at,
+        jobs.created_at
+    ) END
+FROM jobs AS job
+LEFT JOIN (
+    SELECT job_id, MIN(started_at) AS started_at, MAX(finished_at) AS finished_at
+    FROM job_steps
+    GROUP BY job_id
+) AS steps ON steps.job_id = job.id
+WHERE job.id = jobs.id;
+
+CREATE FUNCTION automaat_set_job_finished_at() RETURNS trigger AS $$
+BEGIN
+    IF NEW.status NOT IN ('failed', 'cancelled', 'ok') THEN
+        NEW.finished_at := NULL;
+    ELSIF TG_OP = 'INSERT' THEN
+        NEW.finished_at := (now() AT TIME ZONE 'utc');
+    ELSIF OLD.status IN ('failed', 'cancelled', 'ok') THEN
+        NEW.finished_at := OLD.finished_at;
+    ELSE
+        NEW.finished_at := (now() AT TIME ZONE 'utc');
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER set_job_finished_at BEFORE INSERT OR UPDATE ON jobs
+    FOR EACH ROW EXECUTE PROCEDURE automaat_set_job_finished_at();
+
+CREATE INDEX ON jobs (task_reference, id);
+CREATE INDEX ON jobs (status, id);
+CREATE INDEX ON jobs (session_id, id);
+CREATE INDEX ON jobs (created_at);
+CREATE INDEX ON jobs (finished_at);
@@ -0,0 +1 @@
+ALTER TABLE tasks DROP COLUMN last_claimed_at;
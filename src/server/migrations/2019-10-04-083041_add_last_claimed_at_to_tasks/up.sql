@@ -0,0 +1 @@
+ALTER TABLE tasks ADD COLUMN last_claimed_at Timestamp;
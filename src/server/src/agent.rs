@@ -0,0 +1,415 @@
+//! An [`Agent`] runs the processors of jobs on behalf of a server, without
+//! access to the database, or to the encryption secret.
+//!
+//! The agent registers itself with the server, and then keeps claiming
+//! processors to run, which the server dispatches with their configuration
+//! fully rendered. Once a processor finishes, the agent reports its result
+//! back to the server, which stores it, and continues running the job.
+//!
+//! All communication happens over the HTTP API of the server, using the
+//! requests described in the [`protocol`] module. This allows agents to run
+//! in isolated networks, as long as they can reach the server.
+
+use crate::isolation;
+use crate::worker::{parse_queues, parse_queues_option};
+use automaat_core::{Context, LogSink, LogStream};
+use protocol::{
+    Claim, Dispatch, DispatchResult, Heartbeat, HeartbeatResponse, LogChunk, Registered,
+    Registration,
+};
+use reqwest::{header::AUTHORIZATION, Client, Response, StatusCode};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::{env, error::Error, thread, time};
+
+pub(crate) mod protocol;
+
+/// The interval at which the agent records a heartbeat with the server.
+const HEARTBEAT_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+/// The time to wait before retrying a request, after the server could not be
+/// reached.
+const RETRY_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+/// The maximum time a request to the server can take. This is longer than the
+/// time the server waits before responding to a claim without a dispatch.
+const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+#[derive(Clone, Debug)]
+pub(crate) struct Agent {
+    client: Client,
+    server_url: String,
+    token: String,
+
+    /// The queues from which the server claims jobs for the agent, in addition
+    /// to jobs that do not require a queue.
+    queues: Vec<String>,
+
+    /// The number of jobs the server runs concurrently for the agent.
+    concurrency: i32,
+
+    /// The contexts of the jobs running on the agent, by job ID.
+    ///
+    /// The processors of the same job run in forks of the same context,
+    /// sharing a single workspace, until the server reports the job stopped.
+    jobs: Arc<Mutex<HashMap<i32, Context>>>,
+
+    /// The number of dispatched processors currently running.
+    active: Arc<AtomicUsize>,
+
+    /// Set once the server no longer knows about the agent.
+    lost: Arc<AtomicBool>,
+}
+
+impl Agent {
+    /// Create a new agent instance.
+    ///
+    /// The server is configured using `AGENT_SERVER_URL`, and the agent
+    /// authenticates using the session token in `AGENT_TOKEN`. The session
+    /// needs the `mutation_run_agent` privilege.
+    ///
+    /// Similar to a worker, the provided arguments can contain a `--queues`
+    /// option. Without that option, the queues are read from `AGENT_QUEUES`.
+    pub(crate) fn from_environment(args: &[String]) -> Result<Self, Box<dyn Error>> {
+        let server_url = env::var("AGENT_SERVER_URL")?;
+        let token = env::var("AGENT_TOKEN")?;
+        let queues = parse_agent_queues(args, env::var("AGENT_QUEUES").ok().as_ref())?;
+        let concurrency = parse_concurrency(env::var("AGENT_CONCURRENCY").ok().as_ref())?;
+
+        let client = Client::builder().timeout(REQUEST_TIMEOUT).build()?;
+
+        Ok(Self {
+            client,
+            server_url: server_url.trim_end_matches('/').to_owned(),
+            token,
+            queues,
+            concurrency,
+            jobs: Arc::default(),
+            active: Arc::default(),
+            lost: Arc::default(),
+        })
+    }
+
+    /// Register with the server, and run dispatched processors until
+    /// stopped.
+    ///
+    /// Each dispatched processor runs on a separate thread. Meanwhile, another
+    /// thread records heartbeats, to signal the agent is still alive, and to
+    /// cancel the processors of jobs that are no longer running.
+    ///
+    /// This method blocks until a Unix `SIGINT` or `SIGTERM` signal is
+    /// received. When any of these signals are received, the agent stops
+    /// accepting new jobs, and keeps running the processors of its running
+    /// jobs, until those jobs finish. The agent then removes its registration,
+    /// and the method returns.
+    ///
+    /// If the agent is considered lost by the server, any running processors
+    /// are cancelled, and an error is returned.
+    pub(crate) fn run_to_completion(self) -> Result<(), Box<dyn Error>> {
+        let running = Arc::new(AtomicBool::new(true));
+        let closer = running.clone();
+        ctrlc::set_handler(move || closer.store(false, Ordering::SeqCst))?;
+
+        let id = self.register()?;
+        let (stop, stopped) = mpsc::channel();
+        let heartbeat = {
+            let agent = self.clone();
+            thread::spawn(move || agent.run_heartbeat(id, &stopped))
+        };
+
+        let result = self.run_claims(id, &running);
+
+        let _ = stop.send(());
+        let _ = heartbeat.join();
+
+        if self.lost.load(Ordering::SeqCst) {
+            self.cancel_jobs(None);
+            return Err("agent was considered lost after missing heartbeats".into());
+        }
+
+        result.and_then(|_| self.deregister(id))
+    }
+
+    /// Claim dispatched processors, and run each of them on a separate
+    /// thread, until the `running` flag is unset, and no more jobs run for
+    /// the agent.
+    fn run_claims(&self, id: i32, running: &AtomicBool) -> Result<(), Box<dyn Error>> {
+        while !self.lost.load(Ordering::SeqCst) {
+            let accept_jobs = running.load(Ordering::SeqCst);
+            if !accept_jobs && self.active.load(Ordering::SeqCst) == 0 {
+                match self.heartbeat(id) {
+                    Ok(Some(response)) if response.running_jobs == 0 => return Ok(()),
+                    Ok(_) => {}
+                    Err(_) => thread::sleep(RETRY_INTERVAL),
+                }
+            }
+
+            let claim = Claim { accept_jobs };
+            let dispatch: Dispatch = match self.post(&format!("/{}/dispatches", id), &claim) {
+                Ok(None) => {
+                    self.lost.store(true, Ordering::SeqCst);
+                    break;
+                }
+                Ok(Some(mut response)) => match response.status() {
+                    StatusCode::NO_CONTENT => continue,
+                    _ => response.json()?,
+                },
+                Err(_) => {
+                    thread::sleep(RETRY_INTERVAL);
+                    continue;
+                }
+            };
+
+            let agent = self.clone();
+            let _ = self.active.fetch_add(1, Ordering::SeqCst);
+            let _ = thread::spawn(move || {
+                agent.run_dispatch(id, dispatch);
+                let _ = agent.active.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Run a dispatched processor, and report its result to the server.
+    ///
+    /// The processor runs in a child process, using a fork of the context of
+    /// its job, and the timeout provided by the server. Output logged by the
+    /// processor is sent to the server while it runs.
+    fn run_dispatch(&self, id: i32, dispatch: Dispatch) {
+        let context = {
+            let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+            match jobs.get(&dispatch.job_id) {
+                Some(context) => Ok(context.fork()),
+                None => Context::new().map(|context| {
+                    let fork = context.fork();
+                    let _ = jobs.insert(dispatch.job_id, context);
+                    fork
+                }),
+            }
+        };
+
+        let result = match context {
+            Err(err) => Err(err.to_string()),
+            Ok(context) => {
+                let deadline = dispatch
+                    .timeout
+                    .map(|timeout| time::Instant::now() + timeout);
+                context.set_deadline(deadline);
+                context.set_log_sink(Some(Arc::new(DispatchLogSink {
+                    agent: self.clone(),
+                    path: format!("/{}/dispatches/{}/logs", id, dispatch.id),
+                })));
+
+                isolation::run(&dispatch.processor, &context).map_err(|e| e.to_string())
+            }
+        };
+
+        // The server returns a `404` status if it no longer waits for the
+        // result, in which case the result is dropped.
+        let path = format!("/{}/dispatches/{}", id, dispatch.id);
+        let result = DispatchResult::from(result);
+        while self.post(&path, &result).is_err() && !self.lost.load(Ordering::SeqCst) {
+            thread::sleep(RETRY_INTERVAL);
+        }
+    }
+
+    /// Record heartbeats until stopped, or until the agent is considered lost.
+    ///
+    /// Failing heartbeats are retried at the next interval.
+    fn run_heartbeat(&self, id: i32, stopped: &mpsc::Receiver<()>) {
+        loop {
+            if let Ok(None) = self.heartbeat(id) {
+                return;
+            }
+
+            match stopped.recv_timeout(HEARTBEAT_INTERVAL) {
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                _ => return,
+            }
+        }
+    }
+
+    /// Record a single heartbeat, and cancel the processors of any jobs that
+    /// stopped running for the agent.
+    ///
+    /// Returns `None` if the agent was considered lost.
+    fn heartbeat(&self, id: i32) -> Result<Option<HeartbeatResponse>, Box<dyn Error>> {
+        let jobs = self
+            .jobs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect();
+
+        let response: HeartbeatResponse =
+            match self.post(&format!("/{}/heartbeat", id), &Heartbeat { jobs })? {
+                None => {
+                    self.lost.store(true, Ordering::SeqCst);
+                    return Ok(None);
+                }
+                Some(mut response) => response.json()?,
+            };
+
+        self.cancel_jobs(Some(&response.stopped_jobs));
+        Ok(Some(response))
+    }
+
+    /// Cancel the running processors of the provided jobs, or of all jobs if
+    /// `None` is provided.
+    ///
+    /// The workspace of a job is removed once all of its processors stopped.
+    fn cancel_jobs(&self, ids: Option<&[i32]>) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(PoisonError::into_inner);
+        let ids: Vec<i32> = match ids {
+            Some(ids) => ids.to_vec(),
+            None => jobs.keys().cloned().collect(),
+        };
+
+        for id in ids {
+            if let Some(context) = jobs.remove(&id) {
+                context.cancellation().cancel();
+            }
+        }
+    }
+
+    fn register(&self) -> Result<i32, Box<dyn Error>> {
+        let registration = Registration {
+            queues: self.queues.clone(),
+            concurrency: self.concurrency,
+        };
+
+        match self.post("", &registration)? {
+            None => Err("agent API not found on server".into()),
+            Some(mut response) => Ok(response.json::<Registered>()?.id),
+        }
+    }
+
+    fn deregister(&self, id: i32) -> Result<(), Box<dyn Error>> {
+        let url = format!("{}{}/{}", self.server_url, protocol::PATH, id);
+
+        let _ = self
+            .client
+            .delete(&url)
+            .header(AUTHORIZATION, self.token.as_str())
+            .send()?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Send a request to the agent API of the server.
+    ///
+    /// Returns `None` if the server responded with a `404` status.
+    fn post<T: Serialize>(&self, path: &str, body: &T) -> Result<Option<Response>, Box<dyn Error>> {
+        let url = format!("{}{}{}", self.server_url, protocol::PATH, path);
+        let response = self
+            .client
+            .post(&url)
+            .header(AUTHORIZATION, self.token.as_str())
+            .json(body)
+            .send()?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        Ok(Some(response.error_for_status()?))
+    }
+}
+
+/// Returns the queues provided with the `--queues` option, or else the queues
+/// provided by the `AGENT_QUEUES` environment variable, if any.
+fn parse_agent_queues(
+    args: &[String],
+    variable: Option<&String>,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    match parse_queues_option(args)? {
+        Some(queues) => Ok(queues),
+        None => variable.map_or_else(|| Ok(vec![]), |queues| parse_queues(queues)),
+    }
+}
+
+/// Parse the number of jobs the agent runs concurrently, provided by the
+/// `AGENT_CONCURRENCY` environment variable. Defaults to a single job.
+fn parse_concurrency(variable: Option<&String>) -> Result<i32, Box<dyn Error>> {
+    let concurrency = match variable {
+        None => 1,
+        Some(concurrency) => concurrency.parse()?,
+    };
+
+    if concurrency < 1 {
+        return Err("AGENT_CONCURRENCY must be at least 1".into());
+    }
+
+    Ok(concurrency)
+}
+
+/// A [`LogSink`] sending the output logged by a dispatched processor to the
+/// server.
+///
+/// Output that can't be sent is dropped, without failing the processor.
+#[derive(Debug)]
+struct DispatchLogSink {
+    agent: Agent,
+    path: String,
+}
+
+impl LogSink for DispatchLogSink {
+    fn append(&self, stream: LogStream, chunk: &str) {
+        let chunk = LogChunk {
+            stream,
+            chunk: chunk.to_owned(),
+        };
+
+        let _ = self.agent.post(&self.path, &chunk);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_agent_queues() {
+        let variable = "deploy, db".to_owned();
+
+        assert!(parse_agent_queues(&args(&[]), None).unwrap().is_empty());
+        assert_eq!(
+            parse_agent_queues(&args(&[]), Some(&variable)).unwrap(),
+            vec!["deploy".to_owned(), "db".to_owned()]
+        );
+        assert_eq!(
+            parse_agent_queues(&args(&["--queues=build"]), Some(&variable)).unwrap(),
+            vec!["build".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_invalid_agent_queues() {
+        let variable = "deploy,,db".to_owned();
+
+        assert!(parse_agent_queues(&args(&[]), Some(&variable)).is_err());
+        assert!(parse_agent_queues(&args(&["--queues"]), None).is_err());
+    }
+
+    #[test]
+    fn test_concurrency() {
+        assert_eq!(parse_concurrency(None).unwrap(), 1);
+        assert_eq!(parse_concurrency(Some(&"4".to_owned())).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_invalid_concurrency() {
+        assert!(parse_concurrency(Some(&"0".to_owned())).is_err());
+        assert!(parse_concurrency(Some(&"-1".to_owned())).is_err());
+        assert!(parse_concurrency(Some(&"many".to_owned())).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! The messages exchanged between the server and remote agents, serialized as
+//! JSON.
+//!
+//! Each request of an agent is authenticated using the token of a session with
+//! the `mutation_run_agent` privilege, provided in the `Authorization` header.
+//!
+//! | Request                                       | Body             | Response               |
+//! |-----------------------------------------------|------------------|------------------------|
+//! | `POST /agent`                                 | [`Registration`] | [`Registered`]         |
+//! | `POST /agent/{id}/heartbeat`                  | [`Heartbeat`]    | [`HeartbeatResponse`]  |
+//! | `POST /agent/{id}/dispatches`                 | [`Claim`]        | [`Dispatch`], or `204` |
+//! | `POST /agent/{id}/dispatches/{dispatch}`      | [`Result`]       | `204`                  |
+//! | `POST /agent/{id}/dispatches/{dispatch}/logs` | [`LogChunk`]     | `204`                  |
+//! | `DELETE /agent/{id}`                          |                  | `204`                  |
+//!
+//! Requests for an agent that is no longer registered, for example because it
+//! was considered lost, return a `404` status. The same status is returned when
+//! reporting the result of a dispatch that was withdrawn, for example because
+//! its job was cancelled, or when logging output for a dispatch that already
+//! finished.
+//!
+//! [`Result`]: DispatchResult
+
+use crate::Processor;
+use automaat_core::LogStream;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The route prefix of all agent requests.
+pub(crate) const PATH: &str = "/agent";
+
+/// The privilege a session needs to register and run an agent.
+pub(crate) const PRIVILEGE: &str = "mutation_run_agent";
+
+/// Registers a new agent with the server.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Registration {
+    /// The queues from which the agent runs jobs.
+    pub(crate) queues: Vec<String>,
+
+    /// The number of jobs the agent runs concurrently.
+    pub(crate) concurrency: i32,
+}
+
+/// The registration of an agent, as stored by the server.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub(crate) struct Registered {
+    /// The ID of the agent, used in all other requests.
+    pub(crate) id: i32,
+}
+
+/// Signals the agent is still alive.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Heartbeat {
+    /// The IDs of the jobs for which the agent keeps a workspace.
+    pub(crate) jobs: Vec<i32>,
+}
+
+/// The response to a [`Heartbeat`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(crate) struct HeartbeatResponse {
+    /// The IDs of the jobs that no longer run on the agent, or that were
+    /// cancelled. The agent cancels any processors still running for these
+    /// jobs, and removes their workspaces.
+    pub(crate) stopped_jobs: Vec<i32>,
+
+    /// The number of jobs running for the agent, including jobs for which no
+    /// processor was dispatched yet.
+    pub(crate) running_jobs: i64,
+}
+
+/// Waits for the next processor to run on the agent.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub(crate) struct Claim {
+    /// Whether the server can claim new jobs for the agent, if the agent has
+    /// capacity left.
+    ///
+    /// An agent that is shutting down stops accepting new jobs, but keeps
+    /// claiming the processors of the jobs already running for it.
+    pub(crate) accept_jobs: bool,
+}
+
+/// A processor of a job step, to be run by the agent.
+///
+/// Processors of the same job share a workspace on the agent, for as long as
+/// the job runs.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Dispatch {
+    pub(crate) id: i32,
+    pub(crate) job_id: i32,
+
+    /// The rendered processor configuration.
+    pub(crate) processor: Processor,
+
+    /// The time the processor is allowed to run, if limited.
+    pub(crate) timeout: Option<Duration>,
+}
+
+/// The result of running the processor of a [`Dispatch`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct DispatchResult {
+    /// The output of the processor, if it succeeded.
+    pub(crate) output: Option<String>,
+
+    /// The error returned by the processor, if it failed.
+    pub(crate) error: Option<String>,
+}
+
+/// A chunk of output logged by the processor of a [`Dispatch`], while it runs.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct LogChunk {
+    pub(crate) stream: LogStream,
+    pub(crate) chunk: String,
+}
+
+impl From<Result<Option<String>, String>> for DispatchResult {
+    fn from(result: Result<Option<String>, String>) -> Self {
+        match result {
+            Ok(output) => Self {
+                output,
+                error: None,
+            },
+            Err(error) => Self {
+                output: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+impl Into<Result<Option<String>, String>> for DispatchResult {
+    fn into(self) -> Result<Option<String>, String> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, to_value};
+
+    #[test]
+    fn test_dispatch_result_from_result() {
+        let result = DispatchResult::from(Ok(Some("done".to_owned())));
+        assert_eq!(result.output, Some("done".to_owned()));
+        assert_eq!(result.error, None);
+
+        let result = DispatchResult::from(Ok(None));
+        assert_eq!(result.output, None);
+        assert_eq!(result.error, None);
+
+        let result = DispatchResult::from(Err("failed".to_owned()));
+        assert_eq!(result.output, None);
+        assert_eq!(result.error, Some("failed".to_owned()));
+    }
+
+    #[test]
+    fn test_dispatch_result_into_result() {
+        let result = |output: Option<&str>, error: Option<&str>| -> Result<_, _> {
+            DispatchResult {
+                output: output.map(str::to_owned),
+                error: error.map(str::to_owned),
+            }
+            .into()
+        };
+
+        assert_eq!(result(Some("done"), None), Ok(Some("done".to_owned())));
+        assert_eq!(result(None, None), Ok(None));
+        assert_eq!(result(None, Some("failed")), Err("failed".to_owned()));
+        assert_eq!(
+            result(Some("done"), Some("failed")),
+            Err("failed".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_messages_json() {
+        let registration = Registration {
+            queues: vec!["deploy".to_owned()],
+            concurrency: 2,
+        };
+
+        assert_eq!(
+            to_value(registration).unwrap(),
+            json!({ "queues": ["deploy"], "concurrency": 2 })
+        );
+        assert_eq!(to_value(Registered { id: 1 }).unwrap(), json!({ "id": 1 }));
+        assert_eq!(
+            to_value(Heartbeat { jobs: vec![1, 2] }).unwrap(),
+            json!({ "jobs": [1, 2] })
+        );
+        assert_eq!(
+            to_value(HeartbeatResponse {
+                stopped_jobs: vec![2],
+                running_jobs: 1,
+            })
+            .unwrap(),
+            json!({ "stopped_jobs": [2], "running_jobs": 1 })
+        );
+        assert_eq!(
+            to_value(Claim { accept_jobs: true }).unwrap(),
+            json!({ "accept_jobs": true })
+        );
+        assert_eq!(
+            to_value(DispatchResult::from(Err("failed".to_owned()))).unwrap(),
+            json!({ "output": null, "error": "failed" })
+        );
+    }
+
+    #[test]
+    fn test_dispatch_json() {
+        let value = json!({
+            "id": 1,
+            "job_id": 2,
+            "processor": { "PrintOutput": { "output": "hello" } },
+            "timeout": { "secs": 5, "nanos": 0 },
+        });
+
+        let dispatch: Dispatch = serde_json::from_value(value.clone()).unwrap();
+        assert_eq!(dispatch.id, 1);
+        assert_eq!(dispatch.job_id, 2);
+        assert_eq!(dispatch.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(to_value(dispatch).unwrap(), value);
+    }
+
+    #[test]
+    fn test_dispatch_result_missing_fields() {
+        let result: DispatchResult = serde_json::from_value(json!({})).unwrap();
+
+        assert_eq!(result.output, None);
+        assert_eq!(result.error, None);
+    }
+}
@@ -1,19 +1,27 @@
 use crate::models::{NewGlobalVariable, NewSession, Session};
 use crate::resources::{
-    CreateJobFromTaskInput, CreateSessionInput, CreateTaskInput, GlobalVariableInput, Job, NewJob,
-    NewJobVariable, NewTask, OnConflict, SearchTaskInput, Task, UpdatePrivilegesInput,
+    ApprovalDecision, CreateJobFromTaskInput, CreateScheduleInput, CreateSessionInput,
+    CreateTaskInput, GlobalVariableInput, Job, JobConnection, JobFilter, JobFilterInput, JobRange,
+    JobStep, JobStepLog, JobVariableInput, NewJob, NewJobVariable, NewSchedule, NewTask,
+    OnConflict, ResumeJobPayload, ResumedWorkspace, Schedule, SearchTaskInput, Task,
+    UpdatePrivilegesInput,
 };
 use crate::schema::*;
-use crate::server::RequestState;
+use crate::server::{RequestState, Topic};
+use crate::Processor;
 use diesel::prelude::*;
-use juniper::{object, Context, FieldResult, RootNode, ID};
+use juniper::{object, Context, EmptyMutation, FieldResult, RootNode, ID};
+use std::collections::HashSet;
 use std::convert::TryFrom;
 
 impl Context for RequestState {}
 
 pub(crate) type Schema = RootNode<'static, QueryRoot, MutationRoot>;
+pub(crate) type SubscriptionSchema =
+    RootNode<'static, SubscriptionRoot, EmptyMutation<RequestState>>;
 pub(crate) struct QueryRoot;
 pub(crate) struct MutationRoot;
+pub(crate) struct SubscriptionRoot;
 
 #[object(Context = RequestState)]
 impl QueryRoot {
@@ -33,10 +41,41 @@ impl QueryRoot {
         Task::search(name, description, &context.conn).map_err(Into::into)
     }
 
-    /// Return a list of jobs.
-    fn jobs(context: &RequestState) -> FieldResult<Vec<Job>> {
-        jobs::table
-            .order(jobs::id)
+    /// Return a page of jobs, ordered from newest to oldest.
+    ///
+    /// Pages follow the Relay cursor connection specification. Use `first`
+    /// and `after` to page forward, or `last` and `before` to page backward.
+    /// If neither `first` nor `last` is provided, the first 50 jobs are
+    /// returned. A single page contains at most 250 jobs.
+    ///
+    /// You can optionally filter the returned set of jobs by providing the
+    /// `JobFilterInput` value.
+    fn jobs(
+        context: &RequestState,
+        first: Option<i32>,
+        after: Option<String>,
+        last: Option<i32>,
+        before: Option<String>,
+        filter: Option<JobFilterInput>,
+    ) -> FieldResult<JobConnection> {
+        let after = after.as_ref().map(String::as_str);
+        let before = before.as_ref().map(String::as_str);
+        let range = JobRange::new(first, after, last, before)?;
+        let filter = filter.as_ref().map(JobFilter::try_from).transpose()?;
+
+        JobConnection::find(filter.unwrap_or_default(), range, &context.conn).map_err(Into::into)
+    }
+
+    /// Return a list of pending jobs that cannot run, as they require a
+    /// queue that is not served by any live worker.
+    fn unserved_jobs(context: &RequestState) -> FieldResult<Vec<Job>> {
+        Job::find_unserved(&context.conn).map_err(Into::into)
+    }
+
+    /// Return a list of schedules.
+    fn schedules(context: &RequestState) -> FieldResult<Vec<Schedule>> {
+        schedules::table
+            .order(schedules::id)
             .load(&context.conn)
             .map_err(Into::into)
     }
@@ -71,6 +110,74 @@ impl QueryRoot {
     }
 }
 
+/// Subscriptions are served over a WebSocket at `/graphql/subscriptions`,
+/// using the `graphql-ws` protocol.
+///
+/// The result of a subscription is sent when it starts, and again every time
+/// it changes.
+#[object(Context = RequestState)]
+impl SubscriptionRoot {
+    /// Receive a single job, based on the job ID, every time the job or any
+    /// of its steps changes.
+    ///
+    /// This subscription returns `null` if no job is found matching the
+    /// provided ID.
+    fn job_updated(context: &RequestState, id: ID) -> FieldResult<Option<Job>> {
+        let id = id.parse::<i32>()?;
+        let mut subscription = context.subscription()?.borrow_mut();
+        let _ = subscription.topics.insert(Topic::Job(id));
+
+        jobs::table
+            .filter(jobs::id.eq(id))
+            .first(&context.conn)
+            .optional()
+            .map_err(Into::into)
+    }
+
+    /// Receive the output of a single job step, based on the job step ID,
+    /// while it is written.
+    ///
+    /// The first result contains all output written so far, skipping the
+    /// first `offset` chunks, if provided. Every next result only contains
+    /// the chunks written since the previous one.
+    ///
+    /// This subscription returns `null` if no job step is found matching the
+    /// provided ID.
+    fn job_step_logs(
+        context: &RequestState,
+        id: ID,
+        offset: Option<i32>,
+    ) -> FieldResult<Option<Vec<JobStepLog>>> {
+        let id = id.parse::<i32>()?;
+        let mut subscription = context.subscription()?.borrow_mut();
+        let _ = subscription.topics.insert(Topic::JobStepLogs(id));
+
+        let step: JobStep = match job_steps::table
+            .filter(job_steps::id.eq(id))
+            .first(&context.conn)
+            .optional()?
+        {
+            Some(step) => step,
+            None => return Ok(None),
+        };
+
+        let offset = subscription
+            .log_offsets
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| i64::from(offset.unwrap_or(0).max(0)));
+
+        let logs = step.logs(offset, None, &context.conn)?;
+
+        #[allow(clippy::cast_possible_wrap)]
+        let _ = subscription
+            .log_offsets
+            .insert(id, offset + logs.len() as i64);
+
+        Ok(Some(logs))
+    }
+}
+
 #[object(Context = RequestState)]
 impl MutationRoot {
     /// Create a new task.
@@ -79,15 +186,23 @@ impl MutationRoot {
     ///
     /// This mutation requires the `mutation_create_task` privilege to be set
     /// for the provided session.
+    ///
+    /// If any of the task steps use the `RunTask` processor, the session also
+    /// needs the privileges required to create a job from each of the tasks
+    /// run by those steps.
     fn createTask(context: &RequestState, task: CreateTaskInput) -> FieldResult<Task> {
         authorization_guard(&["mutation_create_task"], &context.session)?;
 
         let new_task = NewTask::try_from(&task)?;
-        match &task.on_conflict.as_ref().unwrap_or(&OnConflict::Abort) {
-            OnConflict::Abort => new_task.create(&context.conn),
-            OnConflict::Update => new_task.create_or_update(&context.conn),
-        }
-        .map_err(Into::into)
+        context.conn.transaction(|| {
+            let task = match &task.on_conflict.as_ref().unwrap_or(&OnConflict::Abort) {
+                OnConflict::Abort => new_task.create(&context.conn),
+                OnConflict::Update => new_task.create_or_update(&context.conn),
+            }?;
+
+            authorize_sub_tasks(context, &task)?;
+            Ok(task)
+        })
     }
 
     /// Create a job from an existing task ID.
@@ -103,23 +218,306 @@ impl MutationRoot {
     /// task. If a task has one or more labels, then an authenticated session
     /// must exist, and at least one privilege must match one of the task
     /// labels.
+    ///
+    /// The same applies to every task run by the task steps using the
+    /// `RunTask` processor.
+    ///
+    /// Overriding the priority of the task requires the
+    /// `mutation_set_job_priority` privilege.
     fn createJobFromTask(context: &RequestState, job: CreateJobFromTaskInput) -> FieldResult<Job> {
         let task: Task = tasks::table
             .filter(tasks::id.eq(job.task_id.parse::<i32>()?))
             .first(&context.conn)?;
 
+        authorize_task(context, &task)?;
+
+        if job.priority.is_some() {
+            authorization_guard(&["mutation_set_job_priority"], &context.session)?;
+        }
+
+        let variables = job
+            .variables
+            .iter()
+            .map(Into::into)
+            .collect::<Vec<NewJobVariable<'_>>>();
+
+        NewJob::create_from_task(
+            &context.conn,
+            &task,
+            variables,
+            job.priority,
+            context.session.as_ref(),
+        )
+        .map_err(Into::into)
+    }
+
+    /// Cancel an existing job.
+    ///
+    /// A job that is not yet picked up by a worker is cancelled immediately,
+    /// and none of its steps will run.
+    ///
+    /// If the job is already running, the worker running the job is asked to
+    /// stop it. The worker interrupts the running step (if the step supports
+    /// it, such as the `ShellCommand` processor), skips all remaining steps and
+    /// marks the job as `CANCELLED`. The interrupted step is also marked as
+    /// `CANCELLED`, with its output explaining the step was interrupted.
+    ///
+    /// Cancelling a job that already finished running returns an error, as
+    /// does cancelling a child job, which is cancelled by cancelling its
+    /// parent job instead.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges required to create a job from a task, are also
+    /// required to cancel a job created from that task.
+    fn cancelJob(context: &RequestState, id: ID) -> FieldResult<Job> {
+        let job: Job = jobs::table
+            .filter(jobs::id.eq(id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        if job.parent_id.is_some() {
+            return Err("child jobs are cancelled by cancelling their parent job".into());
+        }
+
+        let labels = job.task(&context.conn)?.map_or(vec![], |t| t.labels);
+
         authorization_guard(
-            &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+            &labels.iter().map(String::as_str).collect::<Vec<_>>(),
             &context.session,
         )?;
 
-        let variables = job
-            .variables
+        job.cancel(&context.conn).map_err(Into::into)
+    }
+
+    /// Run an existing job again, by creating a new job from the same task.
+    ///
+    /// The new job uses the variable values of the existing job, with any of
+    /// the provided `overrides` replacing the value of the variable with the
+    /// same key, or adding a value for a variable that had none. The values
+    /// are validated against the current variables of the task, as they are
+    /// when creating any other job.
+    ///
+    /// The new job is linked to the existing one, see `Job.rerunOf`.
+    ///
+    /// Rerunning a job of which the task has been removed returns an error.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges required to create a job from a task, are also
+    /// required to rerun a job created from that task.
+    fn rerunJob(
+        context: &RequestState,
+        id: ID,
+        overrides: Option<Vec<JobVariableInput>>,
+    ) -> FieldResult<Job> {
+        let job: Job = jobs::table
+            .filter(jobs::id.eq(id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        if let Some(task) = job.task(&context.conn)? {
+            authorize_task(context, &task)?;
+        }
+
+        let overrides = overrides.unwrap_or_default();
+        let overrides = overrides
             .iter()
             .map(Into::into)
             .collect::<Vec<NewJobVariable<'_>>>();
 
-        NewJob::create_from_task(&context.conn, &task, variables).map_err(Into::into)
+        job.rerun(&context.conn, &overrides, context.session.as_ref())
+            .map_err(Into::into)
+    }
+
+    /// Resume a failed job, running its failed steps again.
+    ///
+    /// The failed and cancelled steps are reset and run again, once a worker
+    /// picks up the job, as are all steps that depend on them, directly or
+    /// indirectly. The steps that succeeded (or were skipped) and do not depend
+    /// on a failed step do not run again, their stored output remains
+    /// available to the steps that run after them.
+    ///
+    /// The workspace of the failed run is not restored, as reported by the
+    /// `workspace` field of the payload: the resumed job starts with a new,
+    /// empty workspace.
+    ///
+    /// Resuming a job that did not fail returns an error, as does resuming a
+    /// child job. Resume the parent job instead, which runs a new child job.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges required to create a job from a task, are also
+    /// required to resume a job created from that task.
+    fn resumeJob(context: &RequestState, id: ID) -> FieldResult<ResumeJobPayload> {
+        let job: Job = jobs::table
+            .filter(jobs::id.eq(id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        if let Some(task) = job.task(&context.conn)? {
+            authorize_task(context, &task)?;
+        }
+
+        Ok(ResumeJobPayload {
+            job: job.resume(&context.conn)?,
+            workspace: ResumedWorkspace::Recreated,
+        })
+    }
+
+    /// Approve a job step that is awaiting approval, with an optional comment.
+    ///
+    /// The step succeeds, and the job continues running its remaining steps,
+    /// once the worker that ran the job picks it up again. The job continues
+    /// in the same workspace, with the files written before it paused.
+    ///
+    /// The session, the moment of approval and the comment are stored on the
+    /// step, and shown in its output.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the privilege configured in the
+    /// `WaitForApproval` processor of the step to be set for the provided
+    /// session.
+    fn approveJobStep(
+        context: &RequestState,
+        id: ID,
+        comment: Option<String>,
+    ) -> FieldResult<JobStep> {
+        decide_approval(context, &id, ApprovalDecision::Approved, comment)
+    }
+
+    /// Reject a job step that is awaiting approval, with an optional comment.
+    ///
+    /// The step fails, after which the job continues to run the steps that
+    /// run after a failure, if any, before the job fails.
+    ///
+    /// The session, the moment of rejection and the comment are stored on the
+    /// step, and shown in its output.
+    ///
+    /// # Privileges
+    ///
+    /// This mutation requires the privilege configured in the
+    /// `WaitForApproval` processor of the step to be set for the provided
+    /// session.
+    fn rejectJobStep(
+        context: &RequestState,
+        id: ID,
+        comment: Option<String>,
+    ) -> FieldResult<JobStep> {
+        decide_approval(context, &id, ApprovalDecision::Rejected, comment)
+    }
+
+    /// Answer the prompt of a job step that is awaiting input.
+    ///
+    /// The answer is stored as an encrypted variable of the job, with the key
+    /// configured in the `Prompt` processor of the step, so that the steps
+    /// running after the prompt can use it in their templates. If the prompt
+    /// has a list of options, the answer has to be one of them.
+    ///
+    /// The step succeeds, and the job continues running its remaining steps,
+    /// once the worker that ran the job picks it up again. The job continues
+    /// in the same workspace, with the files written before it paused.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges required to create a job from a task, are also
+    /// required to answer the prompts of a job created from that task.
+    ///
+    /// Only the session that created the job can answer its prompts, unless
+    /// the `mutation_answer_any_job_step` privilege is set for the provided
+    /// session.
+    fn answerJobStep(context: &RequestState, id: ID, value: String) -> FieldResult<JobStep> {
+        let mut step: JobStep = job_steps::table
+            .filter(job_steps::id.eq(id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        let job = step.job(&context.conn)?;
+        if let Some(task) = job.task(&context.conn)? {
+            authorize_task(context, &task)?;
+        }
+
+        let creator =
+            job.session_id.is_some() && job.session_id == context.session.as_ref().map(|s| s.id);
+
+        if !creator {
+            authorization_guard(&["mutation_answer_any_job_step"], &context.session)?;
+        }
+
+        step.answer_prompt(&context.conn, &value, context.session.as_ref())?;
+        Ok(step)
+    }
+
+    /// Create a schedule, to run a task at fixed moments in time.
+    ///
+    /// The upcoming job of the schedule is created right away, with the
+    /// `SCHEDULED` status. Once the job is due, it is picked up by a worker,
+    /// after which the next job of the schedule is created.
+    ///
+    /// Cancelling the upcoming job of a schedule skips that single run of the
+    /// schedule.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges required to create a job from a task, are also
+    /// required to create a schedule for that task.
+    fn createSchedule(
+        context: &RequestState,
+        schedule: CreateScheduleInput,
+    ) -> FieldResult<Schedule> {
+        let new_schedule = NewSchedule::try_from(&schedule)?;
+        let task: Task = tasks::table
+            .filter(tasks::id.eq(schedule.task_id.parse::<i32>()?))
+            .first(&context.conn)?;
+
+        authorize_task(context, &task)?;
+
+        new_schedule.create(&context.conn).map_err(Into::into)
+    }
+
+    /// Pause an active schedule.
+    ///
+    /// The upcoming job of the schedule is removed, and no new jobs are
+    /// created until the schedule is resumed.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges required to create a schedule for a task, are also
+    /// required to pause it.
+    fn pauseSchedule(context: &RequestState, id: ID) -> FieldResult<Schedule> {
+        let mut schedule = find_authorized_schedule(context, &id)?;
+
+        schedule.pause(&context.conn).map_err(Into::into)
+    }
+
+    /// Resume a paused schedule.
+    ///
+    /// The schedule continues from the current moment in time, any runs missed
+    /// while the schedule was paused are skipped.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges required to create a schedule for a task, are also
+    /// required to resume it.
+    fn resumeSchedule(context: &RequestState, id: ID) -> FieldResult<Schedule> {
+        let mut schedule = find_authorized_schedule(context, &id)?;
+
+        schedule.resume(&context.conn).map_err(Into::into)
+    }
+
+    /// Delete a schedule, including its upcoming job.
+    ///
+    /// Jobs created by the schedule that are already running, or finished
+    /// running, are kept.
+    ///
+    /// # Privileges
+    ///
+    /// The same privileges required to create a schedule for a task, are also
+    /// required to delete it.
+    fn deleteSchedule(context: &RequestState, id: ID) -> FieldResult<bool> {
+        let schedule = find_authorized_schedule(context, &id)?;
+
+        schedule
+            .delete(&context.conn)
+            .map(|_| true)
+            .map_err(Into::into)
     }
 
     /// Create a new global variable.
@@ -199,6 +597,84 @@ impl MutationRoot {
     }
 }
 
+/// Find the schedule matching the provided ID, and guard it against access by
+/// sessions that are not authorized to run the task of the schedule.
+fn find_authorized_schedule(context: &RequestState, id: &ID) -> FieldResult<Schedule> {
+    let schedule: Schedule = schedules::table
+        .filter(schedules::id.eq(id.parse::<i32>()?))
+        .first(&context.conn)?;
+
+    let task = schedule.task(&context.conn)?;
+
+    authorization_guard(
+        &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+        &context.session,
+    )?;
+
+    Ok(schedule)
+}
+
+/// Approve or reject the job step matching the provided ID, on behalf of the
+/// session of the request.
+///
+/// The session needs the privilege configured in the `WaitForApproval`
+/// processor of the step.
+fn decide_approval(
+    context: &RequestState,
+    id: &ID,
+    decision: ApprovalDecision,
+    comment: Option<String>,
+) -> FieldResult<JobStep> {
+    let mut step: JobStep = job_steps::table
+        .filter(job_steps::id.eq(id.parse::<i32>()?))
+        .first(&context.conn)?;
+
+    let privilege = match step.processor() {
+        Some(Processor::WaitForApproval(processor)) => processor.privilege,
+        _ => return Err("job step does not wait for approval".into()),
+    };
+
+    authorization_guard(&[privilege.as_str()], &context.session)?;
+
+    step.decide_approval(&context.conn, decision, context.session.as_ref(), comment)?;
+    Ok(step)
+}
+
+/// Guard the provided task against access by sessions that are not authorized
+/// to create jobs from it, or from any of the tasks it runs.
+fn authorize_task(context: &RequestState, task: &Task) -> FieldResult<()> {
+    authorization_guard(
+        &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+        &context.session,
+    )?;
+
+    authorize_sub_tasks(context, task)
+}
+
+/// Guard the tasks run by the `RunTask` steps of the provided task, and any
+/// tasks run by those tasks in turn, against access by sessions that are not
+/// authorized to create jobs from them.
+fn authorize_sub_tasks(context: &RequestState, task: &Task) -> FieldResult<()> {
+    let mut guarded = HashSet::new();
+    let _ = guarded.insert(task.id);
+
+    let mut pending = task.sub_tasks(&context.conn)?;
+    while let Some(task) = pending.pop() {
+        if !guarded.insert(task.id) {
+            continue;
+        }
+
+        authorization_guard(
+            &task.labels.iter().map(String::as_str).collect::<Vec<_>>(),
+            &context.session,
+        )?;
+
+        pending.append(&mut task.sub_tasks(&context.conn)?);
+    }
+
+    Ok(())
+}
+
 /// A guard function that returns an error if none of the defined labels are
 /// present in the provided session privileges.
 ///
@@ -1,9 +1,18 @@
-use crate::graphql::Schema;
-use crate::models::Session;
-use crate::server::{RequestState, ServerError, State};
-use actix_web::web::{block, Data, Json};
+use crate::agent::protocol::{
+    Claim, DispatchResult, Heartbeat, HeartbeatResponse, LogChunk, Registered, Registration,
+    PRIVILEGE,
+};
+use crate::graphql::{Schema, SubscriptionSchema};
+use crate::models::{AgentDispatch, Session, WorkerRegistration};
+use crate::resources::{Job, NewJobStepLog};
+use crate::server::{
+    RequestState, ServerError, State, SubscriptionConnection, SUBSCRIPTION_PROTOCOL,
+};
+use actix_web::web::{block, Data, Json, Path, Payload};
 use actix_web::{HttpRequest, HttpResponse};
+use actix_web_actors::ws;
 use diesel::pg::PgConnection;
+use diesel::Connection;
 use futures::future::Future;
 use juniper::http::{graphiql, playground, GraphQLRequest};
 use serde::{Deserialize, Serialize};
@@ -68,6 +77,26 @@ pub(super) fn graphql(
     })
 }
 
+pub(super) fn subscriptions(
+    state: Data<Arc<State>>,
+    schema: Data<Arc<SubscriptionSchema>>,
+    (request, stream): (HttpRequest, Payload),
+) -> Result<HttpResponse, actix_web::Error> {
+    let token = match auth_token(&request) {
+        None => None,
+        Some(token) => Some(token?),
+    };
+
+    let connection = SubscriptionConnection::new(
+        state.pool.clone(),
+        schema.get_ref().clone(),
+        state.subscriptions.clone(),
+        token,
+    );
+
+    ws::start_with_protocols(connection, &[SUBSCRIPTION_PROTOCOL], &request, stream)
+}
+
 pub(super) fn health() -> HttpResponse {
     let health = Health {
         status: Status::Pass,
@@ -80,7 +109,201 @@ pub(super) fn health() -> HttpResponse {
         .json(health)
 }
 
-fn authenticate(token: &str, conn: &PgConnection) -> Result<Session, ServerError> {
+pub(super) fn register_agent(
+    state: Data<Arc<State>>,
+    (registration, request): (Json<Registration>, HttpRequest),
+) -> impl Future<Item = HttpResponse, Error = ServerError> {
+    let token = auth_token(&request);
+
+    block(move || {
+        let conn = state.pool.get()?;
+        let session = authenticate_agent(token, &conn)?;
+
+        if registration.concurrency < 1 {
+            return Err(ServerError::BadRequest(
+                "agent concurrency must be at least 1".to_owned(),
+            ));
+        }
+
+        let agent = WorkerRegistration::create_agent(
+            &conn,
+            &session,
+            &registration.queues,
+            registration.concurrency,
+        )?;
+
+        Ok(Registered { id: agent.id })
+    })
+    .map_err(Into::into)
+    .map(|registered| HttpResponse::Created().json(registered))
+}
+
+pub(super) fn deregister_agent(
+    state: Data<Arc<State>>,
+    id: Path<i32>,
+    request: HttpRequest,
+) -> impl Future<Item = HttpResponse, Error = ServerError> {
+    let token = auth_token(&request);
+
+    block(move || {
+        let conn = state.pool.get()?;
+        let agent = find_agent(token, *id, &conn)?;
+
+        // The jobs claimed for the agent can no longer continue once the
+        // agent is gone, so they are recovered as if the agent was lost.
+        conn.transaction(|| {
+            for job in Job::find_orphaned(&agent, &conn)? {
+                let _ = job.recover(&conn)?;
+            }
+
+            agent.delete(&conn)
+        })
+        .map_err(Into::<ServerError>::into)
+    })
+    .map_err(Into::into)
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+pub(super) fn agent_heartbeat(
+    state: Data<Arc<State>>,
+    id: Path<i32>,
+    (heartbeat, request): (Json<Heartbeat>, HttpRequest),
+) -> impl Future<Item = HttpResponse, Error = ServerError> {
+    let token = auth_token(&request);
+
+    block(move || {
+        let conn = state.pool.get()?;
+        let agent = find_agent(token, *id, &conn)?;
+
+        // The agent can be considered lost in between finding and updating
+        // its registration.
+        if !agent.heartbeat(&conn)? {
+            return Err(ServerError::NotFound);
+        }
+
+        Ok(HeartbeatResponse {
+            stopped_jobs: Job::find_stopped_for_agent(&agent, &heartbeat.jobs, &conn)?,
+            running_jobs: Job::count_running_for_agent(&agent, &conn)?,
+        })
+    })
+    .map_err(Into::into)
+    .map(|response| HttpResponse::Ok().json(response))
+}
+
+/// Wait for the next processor to run on the agent.
+///
+/// Waiting for a dispatch can take a while, so it happens on the claim threads
+/// of the agent hub, instead of on the thread pool used for other blocking
+/// operations, see [`AgentHub::claim`].
+///
+/// [`AgentHub::claim`]: crate::server::AgentHub::claim
+pub(super) fn claim_dispatch(
+    state: Data<Arc<State>>,
+    id: Path<i32>,
+    (claim, request): (Json<Claim>, HttpRequest),
+) -> impl Future<Item = HttpResponse, Error = ServerError> {
+    let token = auth_token(&request);
+    let hub = state.agents.clone();
+
+    block(move || find_agent(token, *id, &state.pool.get()?))
+        .map_err(Into::into)
+        .and_then(move |agent| {
+            hub.claim(agent, claim.accept_jobs)
+                .map_err(ServerError::Internal)
+        })
+        .map(|dispatch| match dispatch {
+            Some(dispatch) => HttpResponse::Ok().json(dispatch),
+            None => HttpResponse::NoContent().finish(),
+        })
+}
+
+pub(super) fn finish_dispatch(
+    state: Data<Arc<State>>,
+    path: Path<(i32, i32)>,
+    (result, request): (Json<DispatchResult>, HttpRequest),
+) -> impl Future<Item = HttpResponse, Error = ServerError> {
+    let token = auth_token(&request);
+
+    block(move || {
+        let (id, dispatch_id) = path.into_inner();
+        let conn = state.pool.get()?;
+        let agent = find_agent(token, id, &conn)?;
+
+        match AgentDispatch::find(dispatch_id, &conn)? {
+            Some(ref dispatch) if dispatch.agent_id == agent.id => {
+                let _ = dispatch.finish(&conn, result.into_inner().into())?;
+                Ok(())
+            }
+            _ => Err(ServerError::NotFound),
+        }
+    })
+    .map_err(Into::into)
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+pub(super) fn append_dispatch_log(
+    state: Data<Arc<State>>,
+    path: Path<(i32, i32)>,
+    (log, request): (Json<LogChunk>, HttpRequest),
+) -> impl Future<Item = HttpResponse, Error = ServerError> {
+    let token = auth_token(&request);
+
+    block(move || {
+        let (id, dispatch_id) = path.into_inner();
+        let conn = state.pool.get()?;
+        let agent = find_agent(token, id, &conn)?;
+
+        match AgentDispatch::find(dispatch_id, &conn)? {
+            Some(ref dispatch)
+                if dispatch.agent_id == agent.id && dispatch.finished_at.is_none() =>
+            {
+                NewJobStepLog::new(dispatch.job_step_id, log.stream.into(), &log.chunk)
+                    .create(&conn)
+                    .map_err(Into::into)
+            }
+            _ => Err(ServerError::NotFound),
+        }
+    })
+    .map_err(Into::into)
+    .map(|_| HttpResponse::NoContent().finish())
+}
+
+/// Authenticate the session of an agent, and find the registration of the
+/// agent with the provided ID.
+fn find_agent(
+    token: Option<Result<String, ServerError>>,
+    id: i32,
+    conn: &PgConnection,
+) -> Result<WorkerRegistration, ServerError> {
+    let session = authenticate_agent(token, conn)?;
+
+    WorkerRegistration::find_agent(id, &session, conn)?.ok_or(ServerError::NotFound)
+}
+
+/// Authenticate the session of an agent, which requires the agent privilege.
+fn authenticate_agent(
+    token: Option<Result<String, ServerError>>,
+    conn: &PgConnection,
+) -> Result<Session, ServerError> {
+    let token = match token {
+        Some(token) => token?,
+        None => return Err(ServerError::Authentication),
+    };
+
+    let session = authenticate(&token, conn)?;
+
+    if session
+        .privileges
+        .iter()
+        .any(|privilege| privilege == PRIVILEGE)
+    {
+        Ok(session)
+    } else {
+        Err(ServerError::Authentication)
+    }
+}
+
+pub(crate) fn authenticate(token: &str, conn: &PgConnection) -> Result<Session, ServerError> {
     Uuid::from_str(token)
         .ok()
         .and_then(|token| Session::find_by_token(token, conn).ok())
@@ -0,0 +1,505 @@
+//! Runs processors in a child process, supervised by the process running the
+//! job.
+//!
+//! A processor that panics, or crashes the process it runs in, for example
+//! because of a bug in a native library it uses, only takes down its own child
+//! process. The supervising process reports the crash as the error of the
+//! processor, and keeps running.
+//!
+//! The child process runs the `automaat run-processor` command, which reads a
+//! [`Request`] as a JSON line from its standard input, runs the processor in
+//! the workspace of the job, and writes [`Message`]s as JSON lines to its
+//! standard output: the output logged by the processor while it runs, which is
+//! passed on to the log sink of the supervising process, and finally the result
+//! of the processor.
+//!
+//! The standard input of the child process stays open while the processor
+//! runs. Once it is closed, the processor is cancelled, giving it the chance to
+//! stop any processes it started itself, such as the command of a
+//! `ShellCommand` processor.
+
+use crate::Processor;
+use automaat_core::{Context, LogSink, LogStream};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{env, error::Error, thread};
+
+/// The command used to run a processor in a child process.
+pub(crate) const MODE: &str = "run-processor";
+
+/// The interval at which the child process is checked for its exit status,
+/// and the context of the processor for cancellation.
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The time a processor is given to stop on its own after it was cancelled, or
+/// after its deadline passed, before its process is killed.
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// The number of lines of the standard error of a crashed process included in
+/// the error of the processor.
+const STDERR_LINES: usize = 20;
+
+/// The processor to run in a child process.
+#[derive(Debug, Deserialize, Serialize)]
+struct Request {
+    processor: Processor,
+
+    /// The workspace of the job, shared with the other processors of the job.
+    workspace: PathBuf,
+
+    /// The time the processor is allowed to run, if limited.
+    timeout: Option<Duration>,
+}
+
+/// A line written by the child process to its standard output.
+#[derive(Debug, Deserialize, Serialize)]
+enum Message {
+    /// A chunk of output logged by the processor.
+    Log(LogStream, String),
+
+    /// The result of the processor, written once it finished.
+    Result(Result<Option<String>, String>),
+}
+
+/// The [`LogSink`] of a processor running in a child process, writing each
+/// chunk as a [`Message`] to the standard output.
+#[derive(Debug)]
+struct StdoutSink;
+
+impl LogSink for StdoutSink {
+    fn append(&self, stream: LogStream, chunk: &str) {
+        let _ = write_message(&Message::Log(stream, chunk.to_owned()));
+    }
+}
+
+/// The messages read from the standard output of the child process.
+#[derive(Debug, Default)]
+struct Output {
+    /// The result of the processor, if reported.
+    result: Option<Result<Option<String>, String>>,
+
+    /// The last non-empty line that is not a message, if any.
+    last_line: Option<String>,
+}
+
+/// Run the provided processor in a child process, and wait for it to finish.
+///
+/// If the context is cancelled, the processor is cancelled as well, and if
+/// the deadline of the context passes, the processor times out on its own.
+/// Either way, the processor is given a short grace period to stop, before
+/// the process is killed.
+pub(crate) fn run(
+    processor: &Processor,
+    context: &Context,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut command = Command::new(env::current_exe()?);
+    let _ = command.arg(MODE);
+
+    run_command(command, processor, context)
+}
+
+/// Run the provided processor in a child process started using the provided
+/// command, which is expected to call [`run_child`].
+fn run_command(
+    mut command: Command,
+    processor: &Processor,
+    context: &Context,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let request = Request {
+        processor: processor.clone(),
+        workspace: context.workspace_path().to_owned(),
+        timeout: context.remaining(),
+    };
+
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("processor process could not be started: {}", e))?;
+
+    // If the request can't be written, the child process exits without a
+    // result, which is reported below.
+    let mut stdin = child.stdin.take();
+    if let Some(stdin) = &mut stdin {
+        let _ = serde_json::to_writer(&mut *stdin, &request)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(stdin));
+    }
+
+    // The output is read on separate threads, to prevent the child process
+    // from blocking on a full pipe. Logged output is passed on while the
+    // processor runs.
+    let stdout = read_messages(child.stdout.take(), context.log_sink());
+    let stderr = read_to_end(child.stderr.take());
+
+    let (status, stopped) = supervise(&mut child, stdin, context)?;
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    if let Some(reason) = stopped {
+        return Err(reason.into());
+    }
+
+    let last_line = stdout.last_line.as_ref().map(String::as_str);
+    match (status.success(), stdout.result) {
+        (true, Some(result)) => result.map_err(Into::into),
+        _ => Err(crash_message(status, last_line, &stderr).into()),
+    }
+}
+
+/// Run the processor of the request read from the standard input, and write
+/// its result to the standard output.
+///
+/// The processor is cancelled once the standard input is closed.
+///
+/// A processor that panics returns the panic message as its error.
+pub(crate) fn run_child() -> Result<(), Box<dyn Error>> {
+    let mut line = String::new();
+    let _ = io::stdin().read_line(&mut line)?;
+
+    let request: Request = serde_json::from_str(&line)?;
+    let context = Context::in_workspace(request.workspace)?;
+    context.set_deadline(request.timeout.map(|timeout| Instant::now() + timeout));
+    context.set_log_sink(Some(Arc::new(StdoutSink)));
+
+    let cancellation = context.cancellation();
+    let _ = thread::spawn(move || {
+        let _ = io::copy(&mut io::stdin(), &mut io::sink());
+        cancellation.cancel();
+    });
+
+    let processor = request.processor;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        processor.run(&context).map_err(|e| e.to_string())
+    }))
+    .unwrap_or_else(|panic| Err(format!("processor panicked: {}", panic_message(&*panic))));
+
+    write_message(&Message::Result(result)).map_err(Into::into)
+}
+
+/// Write a message to the standard output, on a line of its own.
+///
+/// The message starts on a new line, in case the processor wrote anything to
+/// the standard output itself.
+fn write_message(message: &Message) -> io::Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    writeln!(stdout)?;
+    serde_json::to_writer(&mut stdout, message)?;
+    writeln!(stdout)?;
+    stdout.flush()
+}
+
+/// Wait for the child process to exit.
+///
+/// Once the context is cancelled, the standard input of the child process is
+/// closed, to cancel the processor. The process is killed if the processor
+/// did not stop within the grace period after it was cancelled, or after its
+/// deadline passed.
+///
+/// Returns the exit status of the process, and the reason it was stopped, if
+/// it was cancelled or killed.
+fn supervise(
+    child: &mut Child,
+    mut stdin: Option<ChildStdin>,
+    context: &Context,
+) -> io::Result<(ExitStatus, Option<&'static str>)> {
+    let cancelled = "processor stopped: the job was cancelled";
+    let mut cancelled_at = None;
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, cancelled_at.map(|_| cancelled)));
+        }
+
+        if cancelled_at.is_none() && context.is_cancelled() {
+            drop(stdin.take());
+            cancelled_at = Some(Instant::now());
+        }
+
+        let now = Instant::now();
+        let abandoned = cancelled_at.map_or(false, |at| now >= at + GRACE_PERIOD);
+        let overdue = context
+            .deadline()
+            .map_or(false, |deadline| now >= deadline + GRACE_PERIOD);
+
+        let reason = if abandoned {
+            cancelled
+        } else if overdue {
+            "processor stopped: the processor did not stop after its deadline passed"
+        } else {
+            thread::sleep(SUPERVISE_INTERVAL);
+            continue;
+        };
+
+        // The process can exit right before it is killed, in which case its
+        // exit status is still returned.
+        let _ = child.kill();
+        return child.wait().map(|status| (status, Some(reason)));
+    }
+}
+
+/// Read the messages written by the child process, passing on logged output
+/// to the provided sink.
+fn read_messages<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    sink: Option<Arc<dyn LogSink>>,
+) -> thread::JoinHandle<Output> {
+    thread::spawn(move || {
+        let mut output = Output::default();
+        let pipe = match pipe {
+            Some(pipe) => BufReader::new(pipe),
+            None => return output,
+        };
+
+        // Anything the processor wrote to the standard output itself is not
+        // guaranteed to be valid UTF-8.
+        for line in pipe.split(b'\n') {
+            let line = match line {
+                Ok(line) => String::from_utf8_lossy(&line).into_owned(),
+                Err(_) => break,
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match serde_json::from_str(&line) {
+                Ok(Message::Log(stream, chunk)) => {
+                    if let Some(sink) = &sink {
+                        sink.append(stream, &chunk);
+                    }
+                }
+                Ok(Message::Result(result)) => output.result = Some(result),
+                Err(_) => output.last_line = Some(line),
+            }
+        }
+
+        output
+    })
+}
+
+fn read_to_end<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut output);
+        }
+
+        output
+    })
+}
+
+/// Describe why the child process did not report a result.
+///
+/// The last lines of the standard error of the process are included, as they
+/// usually explain the crash.
+fn crash_message(status: ExitStatus, stdout: Option<&str>, stderr: &str) -> String {
+    let mut message = match (status.code(), signal(status)) {
+        (_, Some(signal)) => format!(
+            "processor crashed: the process was terminated by signal {}",
+            signal
+        ),
+        (Some(code), _) if code != 0 => {
+            format!("processor crashed: the process exited with status {}", code)
+        }
+        _ => "processor crashed: the process did not report a result".to_owned(),
+    };
+
+    let lines: Vec<_> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+    let tail = lines[lines.len().saturating_sub(STDERR_LINES)..].join("\n");
+    let detail = if tail.is_empty() {
+        stdout
+    } else {
+        Some(tail.as_str())
+    };
+
+    if let Some(detail) = detail {
+        message.push_str("\n\n");
+        message.push_str(detail);
+    }
+
+    message
+}
+
+/// The signal that terminated the process, if any, including its name if it
+/// is a common one.
+#[cfg(unix)]
+fn signal(status: ExitStatus) -> Option<String> {
+    use std::os::unix::process::ExitStatusExt;
+
+    status.signal().map(|signal| {
+        let name = match signal {
+            4 => "SIGILL",
+            6 => "SIGABRT",
+            7 => "SIGBUS",
+            8 => "SIGFPE",
+            9 => "SIGKILL",
+            11 => "SIGSEGV",
+            15 => "SIGTERM",
+            _ => return signal.to_string(),
+        };
+
+        format!("{} ({})", signal, name)
+    })
+}
+
+#[cfg(not(unix))]
+fn signal(_status: ExitStatus) -> Option<String> {
+    None
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        return (*message).to_owned();
+    }
+
+    match panic.downcast_ref::<String>() {
+        Some(message) => message.to_owned(),
+        None => "unknown panic".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_panic_message() {
+        let panic = panic::catch_unwind(|| panic!("boom")).unwrap_err();
+        assert_eq!(panic_message(&*panic), "boom");
+
+        let panic = panic::catch_unwind(|| panic!("{} {}", "boom", 1)).unwrap_err();
+        assert_eq!(panic_message(&*panic), "boom 1");
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingSink(std::sync::Mutex<Vec<(LogStream, String)>>);
+
+    impl LogSink for RecordingSink {
+        fn append(&self, stream: LogStream, chunk: &str) {
+            self.0.lock().unwrap().push((stream, chunk.to_owned()));
+        }
+    }
+
+    #[test]
+    fn test_read_messages() {
+        let stdout = [
+            "\n{\"Log\":[\"Stdout\",\"hello\\n\"]}\n",
+            "stray output\n",
+            "\n{\"Log\":[\"Stderr\",\"oops\"]}\n",
+            "\n{\"Result\":{\"Ok\":\"done\"}}\n",
+        ]
+        .concat();
+
+        let sink = Arc::new(RecordingSink::default());
+        let output = read_messages(Some(io::Cursor::new(stdout)), Some(sink.clone()))
+            .join()
+            .unwrap();
+
+        assert_eq!(output.result, Some(Ok(Some("done".to_owned()))));
+        assert_eq!(output.last_line, Some("stray output".to_owned()));
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            vec![
+                (LogStream::Stdout, "hello\n".to_owned()),
+                (LogStream::Stderr, "oops".to_owned()),
+            ]
+        );
+    }
+
+    /// Set for the child process of [`test_run_cancelled`], in which case
+    /// [`test_child_process`] runs the processor.
+    const CHILD_PROCESS: &str = "AUTOMAAT_TEST_CHILD_PROCESS";
+
+    #[test]
+    fn test_child_process() {
+        if env::var_os(CHILD_PROCESS).is_some() {
+            run_child().unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_cancelled() {
+        use processor_shell_command_v1::ShellCommand;
+
+        // The test binary itself runs the processor, using the test above.
+        let mut command = Command::new(env::current_exe().unwrap());
+        let _ = command
+            .args(&["isolation::tests::test_child_process", "--exact"])
+            .args(&["--nocapture", "--quiet"])
+            .env(CHILD_PROCESS, "1");
+
+        let processor = Processor::ShellCommand(ShellCommand {
+            command: "sh".to_owned(),
+            arguments: Some(vec![
+                "-c".to_owned(),
+                "echo $$ > pid; exec sleep 30".to_owned(),
+            ]),
+            stdin: None,
+            cwd: None,
+            paths: None,
+        });
+
+        let context = Context::new().unwrap();
+        let pid = context.workspace_path().join("pid");
+        let cancellation = context.cancellation();
+        let _ = thread::spawn(move || {
+            thread::sleep(Duration::from_secs(1));
+            cancellation.cancel();
+        });
+
+        let started = Instant::now();
+        let err = run_command(command, &processor, &context).unwrap_err();
+
+        assert_eq!(err.to_string(), "processor stopped: the job was cancelled");
+        assert!(started.elapsed() < GRACE_PERIOD);
+
+        // The `sleep` command was stopped along with the processor.
+        let pid = std::fs::read_to_string(pid).unwrap();
+        let running = Command::new("kill")
+            .args(&["-0", pid.trim()])
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+
+        assert!(!running.success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_crash_message_signal() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let status = ExitStatus::from_raw(11);
+        let message = crash_message(status, None, "first\n\nsegfault\n");
+
+        assert_eq!(
+            message,
+            "processor crashed: the process was terminated by signal 11 (SIGSEGV)\n\nfirst\nsegfault"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_crash_message_exit_code() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let status = ExitStatus::from_raw(101 << 8);
+        let message = crash_message(status, Some("invalid request"), "");
+
+        assert_eq!(
+            message,
+            "processor crashed: the process exited with status 101\n\ninvalid request"
+        );
+    }
+}
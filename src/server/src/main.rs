@@ -51,16 +51,20 @@ extern crate diesel_migrations;
 #[macro_use]
 extern crate diesel_derive_enum;
 
+mod agent;
 mod graphql;
 mod handlers;
+mod isolation;
 mod middleware;
 mod models;
 mod processor;
 mod resources;
+mod scheduler;
 mod schema;
 mod server;
 mod worker;
 
+use crate::agent::Agent;
 use crate::processor::{Input as ProcessorInput, Processor};
 use crate::server::Server;
 use crate::worker::Worker;
@@ -73,14 +77,21 @@ lazy_static::lazy_static! {
 }
 
 fn main() {
-    // Make sure encryption secret is set by loading it once.
-    let _ = &ENCRYPTION_SECRET.to_string();
-
     let args: Vec<String> = env::args().collect();
-    let run = || match args.get(1).map(String::as_str) {
+    let mode = args.get(1).map(String::as_str);
+
+    // Make sure encryption secret is set by loading it once. Agents and
+    // processors never touch the database, so they do not need the secret.
+    if let Some("server") | Some("worker") = mode {
+        let _ = &ENCRYPTION_SECRET.to_string();
+    }
+
+    let run = || match mode {
         Some("server") => Server::from_environment()?.run_to_completion(),
-        Some("worker") => Worker::from_environment()?.run_to_completion(),
-        _ => Err("usage: automaat [server|worker]".into()),
+        Some("worker") => Worker::from_environment(&args[2..])?.run_to_completion(),
+        Some("agent") => Agent::from_environment(&args[2..])?.run_to_completion(),
+        Some(isolation::MODE) => isolation::run_child(),
+        _ => Err("usage: automaat [server|worker|agent [--queues <queue>,...]]".into()),
     };
 
     if let Err(err) = run() {
@@ -1,7 +1,15 @@
+mod agent_dispatch;
 mod global_variable;
+mod job_cancellation;
+mod schedule_variable;
 mod session;
 mod variable_advertisement;
+mod worker_registration;
 
+pub(crate) use agent_dispatch::{AgentDispatch, NewAgentDispatch};
 pub(crate) use global_variable::{GlobalVariable, NewGlobalVariable};
+pub(crate) use job_cancellation::{JobCancellation, NewJobCancellation};
+pub(crate) use schedule_variable::{NewScheduleVariable, ScheduleVariable};
 pub(crate) use session::{NewSession, Session};
 pub(crate) use variable_advertisement::{NewVariableAdvertisement, VariableAdvertisement};
+pub(crate) use worker_registration::WorkerRegistration;
@@ -0,0 +1,201 @@
+use crate::models::WorkerRegistration;
+use crate::resources::{Job, JobStep};
+use crate::schema::agent_dispatches;
+use crate::ENCRYPTION_SECRET;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::{Bytea, Text};
+
+/// The columns of a dispatch, except for its (encrypted) processor
+/// configuration, which is only loaded when an agent claims the dispatch.
+type Columns = (
+    agent_dispatches::id,
+    agent_dispatches::deadline_at,
+    agent_dispatches::claimed_at,
+    agent_dispatches::finished_at,
+    agent_dispatches::output,
+    agent_dispatches::error,
+    agent_dispatches::agent_id,
+    agent_dispatches::job_id,
+    agent_dispatches::job_step_id,
+);
+
+const COLUMNS: Columns = (
+    agent_dispatches::id,
+    agent_dispatches::deadline_at,
+    agent_dispatches::claimed_at,
+    agent_dispatches::finished_at,
+    agent_dispatches::output,
+    agent_dispatches::error,
+    agent_dispatches::agent_id,
+    agent_dispatches::job_id,
+    agent_dispatches::job_step_id,
+);
+
+/// The model representing a processor of a job step that is dispatched to a
+/// remote agent, stored in the database.
+///
+/// The worker running the job stores the rendered processor configuration,
+/// and waits for the agent to claim the dispatch, run the processor, and
+/// report back its result.
+#[derive(Clone, Debug, Associations, Identifiable, Queryable)]
+#[belongs_to(WorkerRegistration, foreign_key = "agent_id")]
+#[belongs_to(Job)]
+#[table_name = "agent_dispatches"]
+pub(crate) struct AgentDispatch {
+    pub(crate) id: i32,
+
+    /// The moment by which the processor has to finish running, if any.
+    pub(crate) deadline_at: Option<NaiveDateTime>,
+    pub(crate) claimed_at: Option<NaiveDateTime>,
+    pub(crate) finished_at: Option<NaiveDateTime>,
+    pub(crate) output: Option<String>,
+    pub(crate) error: Option<String>,
+    pub(crate) agent_id: i32,
+    pub(crate) job_id: i32,
+    pub(crate) job_step_id: i32,
+}
+
+impl AgentDispatch {
+    /// The Postgres channel on which a notification is sent whenever a new
+    /// dispatch is ready to be claimed by an agent.
+    pub(crate) const CHANNEL: &'static str = "automaat_agent_dispatches";
+
+    /// Find the dispatch with the provided ID, if it still exists.
+    ///
+    /// A dispatch no longer exists if the agent it was dispatched to was lost,
+    /// or if the step stopped waiting for its result.
+    pub(crate) fn find(id: i32, conn: &PgConnection) -> QueryResult<Option<Self>> {
+        agent_dispatches::table
+            .find(id)
+            .select(COLUMNS)
+            .first(conn)
+            .optional()
+    }
+
+    /// Claim the oldest dispatch of the provided agent that was not claimed
+    /// yet, if any.
+    pub(crate) fn claim_next(
+        agent: &WorkerRegistration,
+        conn: &PgConnection,
+    ) -> QueryResult<Option<Self>> {
+        conn.transaction(|| {
+            let dispatch: Option<Self> = Self::belonging_to(agent)
+                .filter(agent_dispatches::claimed_at.is_null())
+                .select(COLUMNS)
+                .order(agent_dispatches::id)
+                .for_update()
+                .skip_locked()
+                .first(conn)
+                .optional()?;
+
+            match dispatch {
+                None => Ok(None),
+                Some(dispatch) => diesel::update(&dispatch)
+                    .set(agent_dispatches::claimed_at.eq(Utc::now().naive_utc()))
+                    .returning(COLUMNS)
+                    .get_result(conn)
+                    .map(Some),
+            }
+        })
+    }
+
+    /// The decrypted, rendered processor configuration, serialized as JSON.
+    pub(crate) fn processor(&self, conn: &PgConnection) -> QueryResult<String> {
+        agent_dispatches::table
+            .find(self.id)
+            .select(pgp_sym_decrypt(
+                agent_dispatches::processor,
+                ENCRYPTION_SECRET.as_str(),
+            ))
+            .first(conn)
+    }
+
+    /// Store the result of the processor, as reported by the agent.
+    ///
+    /// Returns `false` if the dispatch already finished.
+    pub(crate) fn finish(
+        &self,
+        conn: &PgConnection,
+        result: Result<Option<String>, String>,
+    ) -> QueryResult<bool> {
+        let (output, error) = match result {
+            Ok(output) => (output, None),
+            Err(error) => (None, Some(error)),
+        };
+
+        diesel::update(self)
+            .filter(agent_dispatches::finished_at.is_null())
+            .set((
+                agent_dispatches::finished_at.eq(Utc::now().naive_utc()),
+                agent_dispatches::output.eq(output),
+                agent_dispatches::error.eq(error),
+            ))
+            .execute(conn)
+            .map(|count| count > 0)
+    }
+
+    /// Remove all dispatches of the provided job.
+    pub(crate) fn delete_for_job(job: &Job, conn: &PgConnection) -> QueryResult<()> {
+        diesel::delete(Self::belonging_to(job))
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Remove the dispatch, after which any result reported by the agent is
+    /// ignored.
+    pub(crate) fn delete(&self, conn: &PgConnection) -> QueryResult<()> {
+        diesel::delete(self).execute(conn).map(|_| ())
+    }
+}
+
+/// Use this struct to dispatch the processor of a job step to an agent.
+#[derive(Debug, Insertable)]
+#[table_name = "agent_dispatches"]
+pub(crate) struct NewAgentDispatch<'a> {
+    processor: pgp_sym_encrypt::HelperType<&'a str, &'static str>,
+    deadline_at: Option<NaiveDateTime>,
+    agent_id: i32,
+    job_id: i32,
+    job_step_id: i32,
+}
+
+impl<'a> NewAgentDispatch<'a> {
+    /// Initialize a new dispatch of the provided processor configuration,
+    /// serialized as JSON.
+    ///
+    /// This function makes sure the configuration stored in the database is
+    /// encrypted, as it can contain the values of secret variables.
+    pub(crate) fn new(
+        agent_id: i32,
+        step: &JobStep,
+        processor: &'a str,
+        deadline_at: Option<NaiveDateTime>,
+    ) -> Self {
+        Self {
+            processor: pgp_sym_encrypt(processor, ENCRYPTION_SECRET.as_str()),
+            deadline_at,
+            agent_id,
+            job_id: step.job_id,
+            job_step_id: step.id,
+        }
+    }
+
+    /// Save the dispatch in the database, and notify the agents waiting for
+    /// a dispatch.
+    pub(crate) fn create(self, conn: &PgConnection) -> QueryResult<AgentDispatch> {
+        conn.transaction(|| {
+            let dispatch = diesel::insert_into(agent_dispatches::table)
+                .values(&self)
+                .returning(COLUMNS)
+                .get_result(conn)?;
+
+            let _ =
+                diesel::sql_query(format!("NOTIFY {}", AgentDispatch::CHANNEL)).execute(conn)?;
+            Ok(dispatch)
+        })
+    }
+}
+
+sql_function!(fn pgp_sym_encrypt(data: Text, secret: Text) -> Bytea);
+sql_function!(fn pgp_sym_decrypt(data: Bytea, secret: Text) -> Text);
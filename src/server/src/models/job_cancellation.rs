@@ -0,0 +1,51 @@
+use crate::resources::Job;
+use crate::schema::job_cancellations;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+/// The model representing a request to cancel a job, stored in the database.
+///
+/// A cancellation request is stored when a job is cancelled while a worker is
+/// already running it. The worker watches for these requests, and stops the
+/// job once one is found.
+#[derive(Debug, Associations, Identifiable, Queryable)]
+#[belongs_to(Job)]
+pub(crate) struct JobCancellation {
+    pub(crate) id: i32,
+    pub(crate) requested_at: NaiveDateTime,
+    pub(crate) job_id: i32,
+}
+
+impl JobCancellation {
+    /// Find the cancellation request of the provided job, if any.
+    pub(crate) fn find_by_job(job: &Job, conn: &PgConnection) -> QueryResult<Option<Self>> {
+        Self::belonging_to(job).first(conn).optional()
+    }
+}
+
+/// Use this struct to create a new job cancellation request.
+#[derive(Debug, Insertable)]
+#[table_name = "job_cancellations"]
+pub(crate) struct NewJobCancellation {
+    job_id: i32,
+}
+
+impl NewJobCancellation {
+    /// Initialize a new job cancellation request.
+    pub(crate) const fn new(job_id: i32) -> Self {
+        Self { job_id }
+    }
+
+    /// Save the cancellation request in the database.
+    ///
+    /// If the job already has a cancellation request, the existing request is
+    /// kept as-is.
+    pub(crate) fn create(self, conn: &PgConnection) -> QueryResult<()> {
+        diesel::insert_into(job_cancellations::table)
+            .values(&self)
+            .on_conflict(job_cancellations::job_id)
+            .do_nothing()
+            .execute(conn)
+            .map(|_| ())
+    }
+}
@@ -0,0 +1,70 @@
+use crate::resources::Schedule;
+use crate::schema::schedule_variables;
+use crate::ENCRYPTION_SECRET;
+use diesel::prelude::*;
+use diesel::sql_types::{Bytea, Text};
+
+/// The model representing a variable value of a schedule, stored in the
+/// database.
+///
+/// The values are used as the job variables of each job created by the
+/// schedule.
+#[derive(Debug, Associations, Identifiable, Queryable)]
+#[belongs_to(Schedule)]
+pub(crate) struct ScheduleVariable {
+    pub(crate) id: i32,
+    pub(crate) key: String,
+    pub(crate) value: String,
+    pub(crate) schedule_id: i32,
+}
+
+impl ScheduleVariable {
+    /// Find all variables of the provided schedule, with their decrypted
+    /// values.
+    pub(crate) fn find_by_schedule(
+        schedule: &Schedule,
+        conn: &PgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        use crate::schema::schedule_variables::dsl::*;
+
+        let secret = ENCRYPTION_SECRET.as_str();
+        Self::belonging_to(schedule)
+            .select((id, key, pgp_sym_decrypt(value, secret), schedule_id))
+            .order(id.asc())
+            .load(conn)
+    }
+}
+
+/// Use this struct to create a new schedule variable.
+#[derive(Debug, Insertable)]
+#[table_name = "schedule_variables"]
+pub(crate) struct NewScheduleVariable<'a> {
+    key: &'a str,
+    value: pgp_sym_encrypt::HelperType<&'a str, &'static str>,
+    schedule_id: i32,
+}
+
+impl<'a> NewScheduleVariable<'a> {
+    /// Initialize a new schedule variable.
+    ///
+    /// This function makes sure the eventual value stored in the database is
+    /// encrypted.
+    pub(crate) fn new(key: &'a str, value: &'a str, schedule_id: i32) -> Self {
+        Self {
+            key,
+            value: pgp_sym_encrypt(value, ENCRYPTION_SECRET.as_str()),
+            schedule_id,
+        }
+    }
+
+    /// Save the new schedule variable in the database.
+    pub(crate) fn create(self, conn: &PgConnection) -> QueryResult<()> {
+        diesel::insert_into(schedule_variables::table)
+            .values(&self)
+            .execute(conn)
+            .map(|_| ())
+    }
+}
+
+sql_function!(fn pgp_sym_encrypt(data: Text, secret: Text) -> Bytea);
+sql_function!(fn pgp_sym_decrypt(data: Bytea, secret: Text) -> Text);
@@ -0,0 +1,138 @@
+use crate::models::Session;
+use crate::schema::worker_registrations;
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::prelude::*;
+
+/// The model representing a running worker, stored in the database.
+///
+/// Each worker registers itself when it starts, and periodically updates its
+/// heartbeat while running. A worker that stopped sending heartbeats is
+/// considered lost, and any jobs it was running are recovered by the reaper.
+///
+/// Remote agents register in the same way, through the agent API of the
+/// server, using the session of the agent.
+///
+/// Heartbeats are stored as UTC, in the same way as the column defaults, so
+/// they are compared against the current UTC time, and not against `now()`,
+/// which depends on the time zone of the database session.
+#[derive(Clone, Debug, Identifiable, Queryable)]
+pub(crate) struct WorkerRegistration {
+    pub(crate) id: i32,
+    pub(crate) started_at: NaiveDateTime,
+    pub(crate) heartbeat_at: NaiveDateTime,
+
+    /// The queues from which the worker claims jobs, in addition to jobs that
+    /// do not require a queue.
+    pub(crate) queues: Vec<String>,
+
+    /// The number of jobs the worker runs concurrently.
+    pub(crate) concurrency: i32,
+
+    /// The session that registered the worker, if the worker is a remote
+    /// agent.
+    pub(crate) session_id: Option<i32>,
+}
+
+impl WorkerRegistration {
+    /// Register a new worker, serving the provided queues.
+    pub(crate) fn create(
+        conn: &PgConnection,
+        queues: &[String],
+        concurrency: i32,
+    ) -> QueryResult<Self> {
+        diesel::insert_into(worker_registrations::table)
+            .values((
+                worker_registrations::queues.eq(queues),
+                worker_registrations::concurrency.eq(concurrency),
+            ))
+            .get_result(conn)
+    }
+
+    /// Register a new remote agent for the provided session, serving the
+    /// provided queues.
+    pub(crate) fn create_agent(
+        conn: &PgConnection,
+        session: &Session,
+        queues: &[String],
+        concurrency: i32,
+    ) -> QueryResult<Self> {
+        diesel::insert_into(worker_registrations::table)
+            .values((
+                worker_registrations::queues.eq(queues),
+                worker_registrations::concurrency.eq(concurrency),
+                worker_registrations::session_id.eq(session.id),
+            ))
+            .get_result(conn)
+    }
+
+    /// Find the remote agent with the provided ID, registered by the provided
+    /// session.
+    pub(crate) fn find_agent(
+        id: i32,
+        session: &Session,
+        conn: &PgConnection,
+    ) -> QueryResult<Option<Self>> {
+        worker_registrations::table
+            .find(id)
+            .filter(worker_registrations::session_id.eq(session.id))
+            .first(conn)
+            .optional()
+    }
+
+    /// Find all queues served by workers that sent a heartbeat within the
+    /// provided number of seconds.
+    pub(crate) fn live_queues(seconds: i32, conn: &PgConnection) -> QueryResult<Vec<String>> {
+        use crate::schema::worker_registrations::dsl::*;
+
+        let mut live = worker_registrations
+            .filter(heartbeat_at.ge(heartbeat_cutoff(seconds)))
+            .select(queues)
+            .load::<Vec<String>>(conn)?
+            .concat();
+
+        live.sort();
+        live.dedup();
+        Ok(live)
+    }
+
+    /// Find all workers that did not send a heartbeat for the provided number
+    /// of seconds.
+    ///
+    /// The returned registrations are locked until the end of the current
+    /// transaction. Any registration already locked by another transaction is
+    /// skipped.
+    pub(crate) fn find_lost(seconds: i32, conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        use crate::schema::worker_registrations::dsl::*;
+
+        worker_registrations
+            .filter(heartbeat_at.lt(heartbeat_cutoff(seconds)))
+            .order(id.asc())
+            .for_update()
+            .skip_locked()
+            .load(conn)
+    }
+
+    /// Record a new heartbeat of the worker.
+    ///
+    /// Returns `false` if the registration no longer exists, meaning the
+    /// worker was considered lost, and its jobs have been recovered.
+    pub(crate) fn heartbeat(&self, conn: &PgConnection) -> QueryResult<bool> {
+        use crate::schema::worker_registrations::dsl::*;
+
+        diesel::update(self)
+            .set(heartbeat_at.eq(Utc::now().naive_utc()))
+            .execute(conn)
+            .map(|count| count > 0)
+    }
+
+    /// Remove the registration of the worker.
+    pub(crate) fn delete(&self, conn: &PgConnection) -> QueryResult<()> {
+        diesel::delete(self).execute(conn).map(|_| ())
+    }
+}
+
+/// The moment, in UTC, before which a worker that last sent a heartbeat is
+/// considered lost, if workers are lost after the provided number of seconds.
+fn heartbeat_cutoff(seconds: i32) -> NaiveDateTime {
+    Utc::now().naive_utc() - Duration::seconds(i64::from(seconds))
+}
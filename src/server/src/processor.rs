@@ -1,9 +1,35 @@
+//! The processors available to the steps of a task.
+//!
+//! Most processors are provided by the processor crates. The [`Prompt`],
+//! [`RunTask`] and [`WaitForApproval`] processors are part of the server
+//! itself instead, as they need access to the database, to pause the job
+//! running them, or to create and run a child job. They mirror the layout of
+//! the processor crates, so that they can be exposed alongside them.
+//!
+//! These processors are run by the job step using them, see [`JobStep::run`].
+//! Running them on their own returns an error.
+//!
+//! [`Prompt`]: prompt::Prompt
+//! [`RunTask`]: run_task::RunTask
+//! [`WaitForApproval`]: wait_for_approval::WaitForApproval
+//! [`JobStep::run`]: crate::resources::JobStep::run
+
 use automaat_core::{Context, Processor as CoreProcessor};
 use juniper::GraphQLInputObject;
 use serde::{Deserialize, Serialize};
 use std::convert::TryFrom;
 use std::error;
 
+pub(crate) mod prompt;
+pub(crate) mod run_task;
+pub(crate) mod wait_for_approval;
+
+// The `Prompt`, `RunTask` and `WaitForApproval` processors are provided by the
+// server itself, aliased to match the naming of the processor crates.
+use prompt as processor_prompt_v1;
+use run_task as processor_run_task_v1;
+use wait_for_approval as processor_wait_for_approval_v1;
+
 // Macro to create all required processor implementations without having to
 // change tens of lines for every new processor added.
 //
@@ -17,7 +43,7 @@ macro_rules! impl_processors {
 
         impl Processor {
             pub(crate) fn run(
-                self,
+                &self,
                 context: &Context,
             ) -> Result<Option<String>, Box<dyn error::Error>> {
                 match self {
@@ -105,12 +131,15 @@ macro_rules! impl_processors {
 // `GitClonev2` option alongside the regular `GitClone` one, and deprecate the
 // regular one.
 impl_processors! {
-    git_clone:     GitClone,
-    http_request:  HttpRequest,
-    json_edit:     JsonEdit,
-    print_output:  PrintOutput,
-    redis_command: RedisCommand,
-    shell_command: ShellCommand,
-    sql_query:     SqlQuery,
-    string_regex:  StringRegex
+    git_clone:         GitClone,
+    http_request:      HttpRequest,
+    json_edit:         JsonEdit,
+    print_output:      PrintOutput,
+    prompt:            Prompt,
+    redis_command:     RedisCommand,
+    run_task:          RunTask,
+    shell_command:     ShellCommand,
+    sql_query:         SqlQuery,
+    string_regex:      StringRegex,
+    wait_for_approval: WaitForApproval
 }
@@ -0,0 +1,164 @@
+//! The [`Prompt`] processor pauses a job, until a session answers the question
+//! asked by the job step using the processor.
+//!
+//! The answer is stored as an (encrypted) variable of the job, so that the
+//! steps running after the prompt can use it in their templates, for example
+//! `{{ var["Branch"] }}`.
+//!
+//! The processor is part of the server itself, see [`processor`].
+//!
+//! [`processor`]: crate::processor
+
+use automaat_core::{Context, Processor};
+use juniper::{GraphQLInputObject, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use std::{error, fmt};
+
+/// The processor configuration.
+#[derive(Clone, Debug, Serialize, Deserialize, GraphQLObject)]
+pub(crate) struct Prompt {
+    /// The key of the job variable in which the answer is stored.
+    pub(crate) variable: String,
+
+    /// The question asked to the session answering the prompt.
+    pub(crate) question: String,
+
+    /// A template that renders to a JSON array of values, one of which has to
+    /// be picked as the answer, if the answer is limited to a selection.
+    pub(crate) options: Option<String>,
+}
+
+/// The processor configuration.
+#[graphql(name = "PromptInput")]
+#[derive(Clone, Debug, Serialize, Deserialize, GraphQLInputObject)]
+pub(crate) struct Input {
+    /// The key of the job variable in which the answer is stored.
+    ///
+    /// The steps running after the prompt can use the answer in their
+    /// templates, for example `{{ var["Branch"] }}`.
+    variable: String,
+
+    /// The question asked to the session answering the prompt.
+    question: String,
+
+    /// A template that renders to a JSON array of values, one of which has to
+    /// be picked as the answer.
+    ///
+    /// The template uses the same variables that are available in any other
+    /// processor configuration, for example `{{ output["List Branches"] }}`.
+    ///
+    /// Without this value, any answer is accepted.
+    options: Option<String>,
+}
+
+impl From<Input> for Prompt {
+    fn from(input: Input) -> Self {
+        Self {
+            variable: input.variable,
+            question: input.question,
+            options: input.options,
+        }
+    }
+}
+
+impl Prompt {
+    /// The values one of which has to be picked as the answer, or `None` if
+    /// any answer is accepted.
+    ///
+    /// The options have to be rendered to a JSON array before calling this
+    /// method. Values that are not a string are converted to their JSON
+    /// representation.
+    ///
+    /// Returns an error if the options are not a JSON array, or if the array
+    /// is empty.
+    pub(crate) fn options(&self) -> Result<Option<Vec<String>>, Box<dyn error::Error>> {
+        let options = match &self.options {
+            Some(options) => options,
+            None => return Ok(None),
+        };
+
+        let values: Vec<serde_json::Value> = serde_json::from_str(options)
+            .map_err(|e| format!("prompt options cannot be evaluated: {}", e))?;
+
+        if values.is_empty() {
+            return Err("prompt options cannot be evaluated: no options to pick from".into());
+        }
+
+        let options = values
+            .into_iter()
+            .map(|value| match value {
+                serde_json::Value::String(string) => string,
+                value => value.to_string(),
+            })
+            .collect();
+
+        Ok(Some(options))
+    }
+}
+
+impl<'a> Processor<'a> for Prompt {
+    const NAME: &'static str = "Prompt";
+
+    type Error = Error;
+    type Output = String;
+
+    fn run(&self, _context: &Context) -> Result<Option<Self::Output>, Self::Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Represents all the ways that [`Prompt`] can fail on its own.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Error {
+    /// The processor needs a job step to pause, which is not available when
+    /// running the processor on its own, or for each item of a step.
+    Unsupported,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Unsupported => write!(
+                f,
+                "the Prompt processor can only run once per job step, not for each item"
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(options: Option<&str>) -> Prompt {
+        Prompt {
+            variable: "Branch".to_owned(),
+            question: "Which branch?".to_owned(),
+            options: options.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_no_options() {
+        assert_eq!(prompt(None).options().unwrap(), None);
+    }
+
+    #[test]
+    fn test_options() {
+        let options = prompt(Some(r#"["master", 1, true]"#)).options().unwrap();
+
+        assert_eq!(
+            options,
+            Some(vec!["master".to_owned(), "1".to_owned(), "true".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        assert!(prompt(Some("master")).options().is_err());
+        assert!(prompt(Some(r#"{"branch": "master"}"#)).options().is_err());
+        assert!(prompt(Some("[]")).options().is_err());
+    }
+}
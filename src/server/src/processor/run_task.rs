@@ -0,0 +1,182 @@
+//! The [`RunTask`] processor runs another [`Task`] as a child job of the job
+//! running the processor, and waits for that child job to finish.
+//!
+//! The processor is part of the server itself, see [`processor`].
+//!
+//! [`Task`]: crate::resources::Task
+//! [`processor`]: crate::processor
+
+use crate::resources::{NewJobVariable, Task};
+use crate::schema::tasks;
+use automaat_core::{Context, Processor};
+use diesel::prelude::*;
+use juniper::{GraphQLInputObject, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use std::{error, fmt};
+
+/// The processor configuration.
+#[derive(Clone, Debug, Serialize, Deserialize, GraphQLObject)]
+pub(crate) struct RunTask {
+    /// The name of the task to run, if the task is referenced by name.
+    pub(crate) task_name: Option<String>,
+
+    /// The ID of the task to run, if the task is referenced by ID.
+    pub(crate) task_id: Option<String>,
+
+    /// The variable values provided to the task.
+    pub(crate) variables: Vec<Variable>,
+}
+
+/// A variable value provided to the task.
+#[derive(Clone, Debug, Serialize, Deserialize, GraphQLObject)]
+#[graphql(name = "RunTaskVariable")]
+pub(crate) struct Variable {
+    /// The key of the task variable.
+    pub(crate) key: String,
+
+    /// The value of the task variable.
+    pub(crate) value: String,
+}
+
+/// The processor configuration.
+#[graphql(name = "RunTaskInput")]
+#[derive(Clone, Debug, Serialize, Deserialize, GraphQLInputObject)]
+pub(crate) struct Input {
+    /// The name of the task to run.
+    ///
+    /// Either the name, or the ID of the task has to be provided.
+    task_name: Option<String>,
+
+    /// The ID of the task to run.
+    task_id: Option<String>,
+
+    /// The variable values provided to the task.
+    ///
+    /// The values can use the same templates as any other processor
+    /// configuration, for example `{{ output["Build"] }}`.
+    variables: Option<Vec<VariableInput>>,
+}
+
+/// A variable value provided to the task.
+#[graphql(name = "RunTaskVariableInput")]
+#[derive(Clone, Debug, Serialize, Deserialize, GraphQLInputObject)]
+pub(crate) struct VariableInput {
+    /// The key of the task variable.
+    key: String,
+
+    /// The value of the task variable.
+    value: String,
+}
+
+impl From<Input> for RunTask {
+    fn from(input: Input) -> Self {
+        Self {
+            task_name: input.task_name,
+            task_id: input.task_id,
+            variables: input
+                .variables
+                .unwrap_or_default()
+                .into_iter()
+                .map(|v| Variable {
+                    key: v.key,
+                    value: v.value,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl RunTask {
+    /// Find the task referenced by the processor.
+    ///
+    /// Returns an error if the referenced task does not exist, or if the
+    /// processor does not reference exactly one task.
+    ///
+    /// The reference cannot use templates, as the privileges required to run
+    /// the referenced task are checked when creating the task running it,
+    /// before any templates are rendered.
+    pub(crate) fn task(&self, conn: &PgConnection) -> Result<Task, Box<dyn error::Error>> {
+        if self
+            .task_name
+            .iter()
+            .chain(&self.task_id)
+            .any(|r| is_template(r))
+        {
+            return Err("task name or task ID cannot use templates".into());
+        }
+
+        let task = match (&self.task_name, &self.task_id) {
+            (Some(name), None) => tasks::table
+                .filter(tasks::name.eq(name))
+                .first(conn)
+                .optional()?
+                .ok_or_else(|| format!("unknown task: {}", name))?,
+            (None, Some(id)) => tasks::table
+                .filter(tasks::id.eq(id.parse::<i32>()?))
+                .first(conn)
+                .optional()?
+                .ok_or_else(|| format!("unknown task ID: {}", id))?,
+            _ => return Err("must provide either a task name or a task ID".into()),
+        };
+
+        Ok(task)
+    }
+
+    /// The variable values to create the child job with.
+    pub(crate) fn job_variables(&self) -> Vec<NewJobVariable<'_>> {
+        self.variables
+            .iter()
+            .map(|v| NewJobVariable::new(&v.key, &v.value))
+            .collect()
+    }
+}
+
+/// Returns `true` if the provided value contains any template syntax.
+fn is_template(value: &str) -> bool {
+    ["{{", "{%", "{#"].iter().any(|tag| value.contains(tag))
+}
+
+impl<'a> Processor<'a> for RunTask {
+    const NAME: &'static str = "Run Task";
+
+    type Error = Error;
+    type Output = String;
+
+    fn run(&self, _context: &Context) -> Result<Option<Self::Output>, Self::Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Represents all the ways that [`RunTask`] can fail on its own.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Error {
+    /// The processor needs a job step to run, which is not available when
+    /// running the processor on its own, or for each item of a step.
+    Unsupported,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Unsupported => write!(
+                f,
+                "the Run Task processor can only run once per job step, not for each item"
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_template() {
+        assert!(is_template(r#"{{ var["Task"] }}"#));
+        assert!(is_template("{% if true %}Deploy{% endif %}"));
+        assert!(is_template("Deploy {# comment #}"));
+        assert!(!is_template("Deploy"));
+    }
+}
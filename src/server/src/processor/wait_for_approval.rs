@@ -0,0 +1,77 @@
+//! The [`WaitForApproval`] processor pauses a job, until a session with the
+//! required privilege approves or rejects the job step using the processor.
+//!
+//! The processor is part of the server itself, see [`processor`].
+//!
+//! [`processor`]: crate::processor
+
+use automaat_core::{Context, Processor};
+use juniper::{GraphQLInputObject, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use std::{error, fmt};
+
+/// The processor configuration.
+#[derive(Clone, Debug, Serialize, Deserialize, GraphQLObject)]
+pub(crate) struct WaitForApproval {
+    /// The privilege a session needs to approve or reject the step.
+    pub(crate) privilege: String,
+
+    /// The number of seconds after which the step is rejected, if no decision
+    /// was made before then.
+    pub(crate) expires_after_seconds: Option<i32>,
+}
+
+/// The processor configuration.
+#[graphql(name = "WaitForApprovalInput")]
+#[derive(Clone, Debug, Serialize, Deserialize, GraphQLInputObject)]
+pub(crate) struct Input {
+    /// The privilege a session needs to approve or reject the step.
+    privilege: String,
+
+    /// The number of seconds after which the step is rejected automatically,
+    /// if no decision was made before then.
+    ///
+    /// Without this value, the step waits for a decision indefinitely.
+    expires_after_seconds: Option<i32>,
+}
+
+impl From<Input> for WaitForApproval {
+    fn from(input: Input) -> Self {
+        Self {
+            privilege: input.privilege,
+            expires_after_seconds: input.expires_after_seconds,
+        }
+    }
+}
+
+impl<'a> Processor<'a> for WaitForApproval {
+    const NAME: &'static str = "Wait For Approval";
+
+    type Error = Error;
+    type Output = String;
+
+    fn run(&self, _context: &Context) -> Result<Option<Self::Output>, Self::Error> {
+        Err(Error::Unsupported)
+    }
+}
+
+/// Represents all the ways that [`WaitForApproval`] can fail on its own.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Error {
+    /// The processor needs a job step to pause, which is not available when
+    /// running the processor on its own, or for each item of a step.
+    Unsupported,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Unsupported => write!(
+                f,
+                "the Wait For Approval processor can only run once per job step, not for each item"
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
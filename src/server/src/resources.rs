@@ -1,20 +1,41 @@
+mod for_each;
 mod global_variable;
 mod job;
+mod retry_policy;
+mod schedule;
 mod session;
 mod step;
 mod task;
 pub(crate) mod variable;
 
+pub(crate) use for_each::{graphql::ForEachInput, FailureMode as ForEachFailureMode, ForEach};
 pub(crate) use global_variable::graphql::GlobalVariableInput;
+pub(crate) use job::connection::{
+    graphql::JobFilterInput, Filter as JobFilter, JobConnection, Range as JobRange,
+};
+pub(crate) use job::executor::Executor;
 pub(crate) use job::step::{
+    attempt::{JobStepAttempt, NewJobStepAttempt},
+    log::{
+        JobStepLog, NewJobStepLog, Sink as JobStepLogSink, Stream as JobStepLogStream,
+        StreamMapping as JobStepLogStreamMapping,
+    },
+    validate_condition as validate_step_condition, ApprovalDecision, ApprovalDecisionMapping,
     JobStep, NewJobStep, Status as JobStepStatus, StatusMapping as JobStepStatusMapping,
 };
 pub(crate) use job::variable::{graphql::JobVariableInput, JobVariable, NewJobVariable};
 pub(crate) use job::{
-    graphql::CreateJobFromTaskInput, Job, NewJob, StatusMapping as JobStatusMapping,
+    connections_per_job,
+    graphql::{CreateJobFromTaskInput, ResumeJobPayload, ResumedWorkspace},
+    Job, NewJob, Status as JobStatus, StatusMapping as JobStatusMapping, MAX_CHILD_JOB_DEPTH,
 };
+pub(crate) use retry_policy::{graphql::RetryPolicyInput, RetryPolicy};
+pub(crate) use schedule::{graphql::CreateScheduleInput, NewSchedule, Schedule};
 pub(crate) use session::graphql::{CreateSessionInput, UpdatePrivilegesInput};
-pub(crate) use step::{graphql::CreateStepInput, NewStep, Step};
+pub(crate) use step::{
+    dependencies::Dependencies as StepDependencies, graphql::CreateStepInput, NewStep,
+    RunPolicy as StepRunPolicy, RunPolicyMapping as StepRunPolicyMapping, Step,
+};
 pub(crate) use task::{
     graphql::{CreateTaskInput, SearchTaskInput},
     NewTask, Task,
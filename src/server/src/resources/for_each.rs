@@ -0,0 +1,145 @@
+//! A [`ForEach`] configuration runs the processor of a step once for every
+//! item in a JSON array.
+//!
+//! The configuration is stored alongside a [`Step`], and copied to every
+//! [`JobStep`] created from that step. The array is provided as a template,
+//! which is rendered right before the step runs, so it can use the output of
+//! any steps that ran before it.
+//!
+//! [`Step`]: crate::resources::Step
+//! [`JobStep`]: crate::resources::JobStep
+
+use juniper::{GraphQLEnum, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// The maximum number of items of a single step that run concurrently.
+const MAX_CONCURRENCY: i32 = 16;
+
+/// Defines what happens when the processor fails to run for one of the items.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize, GraphQLEnum)]
+#[graphql(name = "ForEachFailureMode")]
+pub(crate) enum FailureMode {
+    /// Stop running items once the processor fails for one of them.
+    ///
+    /// Items that are already running are allowed to finish.
+    FailFast,
+
+    /// Run the processor for all items, and report the errors of all failed
+    /// items once done.
+    Collect,
+}
+
+/// The configuration used to run the processor of a step once for every item
+/// in a JSON array.
+#[derive(Clone, Debug, Deserialize, Serialize, GraphQLObject)]
+pub(crate) struct ForEach {
+    /// A template that renders to a JSON array of items.
+    pub(crate) items: String,
+
+    /// The maximum number of items for which the processor runs concurrently.
+    pub(crate) concurrency: i32,
+
+    /// Defines what happens when the processor fails to run for one of the
+    /// items.
+    pub(crate) failure_mode: FailureMode,
+}
+
+impl ForEach {
+    /// Returns the maximum number of items for which the processor runs
+    /// concurrently.
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn concurrency(&self) -> usize {
+        self.concurrency.max(1) as usize
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use juniper::GraphQLInputObject;
+
+    /// Contains all the data needed to run the processor of a step once for
+    /// every item in a JSON array.
+    #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
+    pub(crate) struct ForEachInput {
+        /// A template that renders to a JSON array of items.
+        ///
+        /// The template uses the same variables that are available in the
+        /// processor configuration, for example `{{ output["Fetch IDs"] }}`.
+        ///
+        /// While the processor runs for an item, the processor configuration
+        /// can use the `item` and `index` template variables.
+        pub(crate) items: String,
+
+        /// The maximum number of items for which the processor runs
+        /// concurrently.
+        ///
+        /// This value has to be between `1` and `16`. Defaults to `1`.
+        pub(crate) concurrency: Option<i32>,
+
+        /// Defines what happens when the processor fails to run for one of the
+        /// items.
+        ///
+        /// Defaults to `FAIL_FAST`.
+        pub(crate) failure_mode: Option<FailureMode>,
+    }
+}
+
+impl TryFrom<&graphql::ForEachInput> for ForEach {
+    type Error = String;
+
+    fn try_from(input: &graphql::ForEachInput) -> Result<Self, Self::Error> {
+        let concurrency = input.concurrency.unwrap_or(1);
+
+        if concurrency < 1 || concurrency > MAX_CONCURRENCY {
+            return Err(format!(
+                "For each concurrency must be between 1 and {}.",
+                MAX_CONCURRENCY
+            ));
+        }
+
+        Ok(Self {
+            items: input.items.clone(),
+            concurrency,
+            failure_mode: input.failure_mode.unwrap_or(FailureMode::FailFast),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(concurrency: Option<i32>) -> graphql::ForEachInput {
+        graphql::ForEachInput {
+            items: "[1, 2, 3]".to_owned(),
+            concurrency,
+            failure_mode: None,
+        }
+    }
+
+    #[test]
+    fn test_defaults() {
+        let for_each = ForEach::try_from(&input(None)).unwrap();
+
+        assert_eq!(for_each.concurrency(), 1);
+        assert_eq!(for_each.failure_mode, FailureMode::FailFast);
+    }
+
+    #[test]
+    fn test_invalid_concurrency() {
+        assert!(ForEach::try_from(&input(Some(0))).is_err());
+        assert!(ForEach::try_from(&input(Some(17))).is_err());
+        assert!(ForEach::try_from(&input(Some(16))).is_ok());
+    }
+}
@@ -5,22 +5,84 @@
 //! a set of steps that are _ready to run_ and have their variables swapped for
 //! real values.
 
-use crate::resources::{JobStep, JobStepStatus, JobVariable, NewJobStep, NewJobVariable, Task};
-use crate::schema::jobs;
-use crate::{server::RequestState, ENCRYPTION_SECRET};
+use crate::models::{
+    AgentDispatch, JobCancellation, NewJobCancellation, Session, WorkerRegistration,
+};
+use crate::resources::{
+    Executor, JobStep, JobStepStatus, JobVariable, NewJobStep, NewJobVariable, Schedule,
+    StepDependencies, StepRunPolicy, Task,
+};
+use crate::schema::{job_cancellations, job_steps, jobs, tasks};
+use crate::server::{DatabasePool, RequestState};
+use crate::worker::LOST_AFTER_SECONDS;
+use crate::ENCRYPTION_SECRET;
 use automaat_core::Context;
+use chrono::{NaiveDateTime, Utc};
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
 use diesel::prelude::*;
+use diesel::sql_types::{Array, Integer, Text, Timestamp};
 use juniper::GraphQLEnum;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::{Into, TryInto};
 use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
 
+pub(crate) mod connection;
+pub(crate) mod executor;
 pub(crate) mod step;
 pub(crate) mod variable;
 
+use step::{Failure, Timeout};
+
+/// The maximum number of steps of a single job that run concurrently.
+pub(crate) const MAX_CONCURRENT_STEPS: usize = 4;
+
+/// The maximum number of levels of child jobs below a job that was claimed by
+/// a worker. With a single level, child jobs cannot run child jobs of their
+/// own.
+///
+/// Every level multiplies the number of database connections a job can use,
+/// see [`connections_per_job`].
+pub(crate) const MAX_CHILD_JOB_DEPTH: usize = 1;
+
+/// Returns the maximum number of database connections used to run a single
+/// job, including its child jobs.
+///
+/// Running the job uses one connection, and watching for cancellation
+/// requests another. Every running step uses one connection. A step running a
+/// child job runs the steps of that job on connections of their own, so with
+/// `S` steps running concurrently, and `D` levels of child jobs, a job uses up
+/// to `2 + S + S^2 + ... + S^(D + 1)` connections.
+///
+/// With four concurrent steps and a single level of child jobs, that is
+/// `2 + 4 + 16 = 22` connections.
+pub(crate) fn connections_per_job() -> usize {
+    let mut steps = 1;
+    let mut connections = 2;
+
+    for _ in 0..=MAX_CHILD_JOB_DEPTH {
+        steps *= MAX_CONCURRENT_STEPS;
+        connections += steps;
+    }
+
+    connections
+}
+
+/// The key of the Postgres advisory lock that is held while claiming a job of
+/// a task with a concurrency limit, or a lock group.
+const CONCURRENCY_LOCK_KEY: i64 = 7_163_124;
+
+/// The result of a job step that ran on its own thread, containing the index
+/// of the step in the job, the step itself, and either the output of the step,
+/// if it produced any, or the error that made the step fail.
+type StepResult = (usize, JobStep, Result<Option<String>, String>);
+
 /// The status of the [`Job`].
-#[derive(Clone, Copy, Debug, Serialize, Deserialize, GraphQLEnum, DbEnum)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize, GraphQLEnum, DbEnum)]
 #[PgType = "JobStatus"]
 #[graphql(name = "JobStatus")]
 pub(crate) enum Status {
@@ -31,9 +93,17 @@ pub(crate) enum Status {
     /// it up.
     Pending,
 
-    /// The job is currently running its steps one by one.
+    /// The job is currently running its steps.
     Running,
 
+    /// The job paused, as one of its steps is waiting for a session to approve
+    /// or reject it. The job continues once a decision is made.
+    AwaitingApproval,
+
+    /// The job paused, as one of its steps is waiting for a session to answer
+    /// its prompt. The job continues once the prompt is answered.
+    AwaitingInput,
+
     /// One of the job steps failed, resulting in the job itself to fail.
     Failed,
 
@@ -52,8 +122,11 @@ impl From<JobStepStatus> for Status {
             JobStepStatus::Initialized => Scheduled,
             JobStepStatus::Pending => Pending,
             JobStepStatus::Running => Running,
+            JobStepStatus::AwaitingApproval => AwaitingApproval,
+            JobStepStatus::AwaitingInput => AwaitingInput,
             JobStepStatus::Failed => Failed,
             JobStepStatus::Cancelled => Cancelled,
+            JobStepStatus::Skipped => Ok,
             JobStepStatus::Ok => Ok,
         }
     }
@@ -63,6 +136,9 @@ impl From<JobStepStatus> for Status {
     Clone, Debug, Deserialize, Serialize, AsChangeset, Associations, Identifiable, Queryable,
 )]
 #[belongs_to(Task, foreign_key = "task_reference")]
+#[belongs_to(Schedule)]
+#[belongs_to(WorkerRegistration, foreign_key = "worker_id")]
+#[belongs_to(Session)]
 #[table_name = "jobs"]
 /// The model representing a job stored in the database.
 pub(crate) struct Job {
@@ -78,27 +154,476 @@ pub(crate) struct Job {
     // Similarly, a job can be created separately from a task, in which case
     // this field is also `None`.
     pub(crate) task_reference: Option<i32>,
+
+    /// The moment at which a job created by a schedule is due to run.
+    pub(crate) scheduled_for: Option<NaiveDateTime>,
+    pub(crate) schedule_id: Option<i32>,
+
+    /// The worker that claimed the job to run it, if any.
+    pub(crate) worker_id: Option<i32>,
+
+    /// The number of seconds the job is allowed to run, if limited.
+    pub(crate) timeout_seconds: Option<i32>,
+
+    /// The job that ran this job as part of one of its steps, if any.
+    pub(crate) parent_id: Option<i32>,
+
+    /// The job of which this job is a rerun, if any.
+    pub(crate) rerun_of_id: Option<i32>,
+
+    /// The reason the job could not run when a worker last tried to claim it,
+    /// if any.
+    pub(crate) blocked_reason: Option<String>,
+
+    /// The priority of the job. Pending jobs with a higher priority run first.
+    pub(crate) priority: i32,
+
+    /// The queue a worker has to serve to claim the job, if any.
+    pub(crate) queue: Option<String>,
+
+    /// The remote agent running the processors of the job, if the job was
+    /// claimed for an agent.
+    pub(crate) agent_id: Option<i32>,
+
+    /// The moment at which the job was created.
+    pub(crate) created_at: NaiveDateTime,
+
+    /// The moment at which the job failed, was cancelled or succeeded, if it
+    /// finished running.
+    ///
+    /// This value is kept up to date by a database trigger, whenever the
+    /// status of the job changes.
+    pub(crate) finished_at: Option<NaiveDateTime>,
+
+    /// The session that created the job, if any.
+    pub(crate) session_id: Option<i32>,
 }
 
 impl Job {
-    pub(crate) fn find_next_unlocked_pending(conn: &PgConnection) -> QueryResult<Option<Self>> {
+    /// The Postgres channel on which a notification is sent whenever a job is
+    /// ready to be picked up by a worker.
+    pub(crate) const PENDING_CHANNEL: &'static str = "automaat_pending_jobs";
+
+    /// The Postgres channel on which a notification is sent whenever a job,
+    /// or one of its steps, is created or updated. The payload of the
+    /// notification is the ID of the job.
+    ///
+    /// These notifications are sent by database triggers.
+    pub(crate) const UPDATED_CHANNEL: &'static str = "automaat_job_updates";
+
+    /// Find the first pending job that is not locked by another worker, that
+    /// can be served by a worker serving the provided queues, and that is
+    /// allowed to run by the concurrency limits of its task.
+    ///
+    /// A worker serves jobs that do not require a queue, and jobs that require
+    /// one of the queues of the worker.
+    ///
+    /// Jobs with a higher priority are found first. Among jobs with the same
+    /// priority, jobs of the task that had a job claimed least recently are
+    /// found first, so that the tasks take turns, and a large number of
+    /// pending jobs of one task does not hold up the jobs of other tasks. Any
+    /// remaining ties are resolved in the order in which the jobs were
+    /// created.
+    ///
+    /// Child jobs are never found, as they run as part of a step of their
+    /// parent job, on the worker running that job. Jobs that paused are only
+    /// found by the worker that ran them, for the same agent, if any, as that
+    /// worker keeps their workspace, see [`Job::pause`].
+    ///
+    /// Pending jobs that are not yet allowed to run are skipped, and the
+    /// reason is stored on the job, until a worker is able to claim it. Once a
+    /// job is found to be blocked by its task, or by its lock group, the other
+    /// pending jobs of the same task, or lock group, are blocked for the same
+    /// reason, and are skipped without checking them one by one.
+    ///
+    /// The returned job is locked until the end of the current transaction.
+    pub(crate) fn find_next_unlocked_pending(
+        conn: &PgConnection,
+        worker: &WorkerRegistration,
+        agent: Option<&WorkerRegistration>,
+    ) -> QueryResult<Option<Self>> {
+        let queues = &agent.unwrap_or(worker).queues;
+        let agent_id = agent.map(|agent| agent.id);
+        let mut blocked_tasks = vec![];
+
+        loop {
+            let job: Self = match jobs::table
+                .filter(jobs::status.eq(Status::Pending))
+                .filter(jobs::parent_id.is_null())
+                .filter(
+                    jobs::worker_id.is_null().or(jobs::worker_id
+                        .eq(worker.id)
+                        .and(jobs::agent_id.is_not_distinct_from(agent_id))),
+                )
+                .filter(jobs::queue.is_null().or(jobs::queue.eq_any(queues)))
+                .filter(
+                    jobs::task_reference
+                        .is_null()
+                        .or(jobs::task_reference.ne_all(&blocked_tasks)),
+                )
+                .order((jobs::priority.desc(), task_last_claimed_at(), jobs::id))
+                .for_update()
+                .skip_locked()
+                .first(conn)
+                .optional()?
+            {
+                Some(job) => job,
+                None => return Ok(None),
+            };
+
+            let conflict = match job.concurrency_conflict(conn)? {
+                Some(conflict) => conflict,
+                None => return Ok(Some(job)),
+            };
+
+            // The reason is only written if it changed. Jobs locked by other
+            // workers are left alone, their reason is stored by a later pass.
+            let _ = diesel::sql_query(
+                "UPDATE jobs SET blocked_reason = $1 WHERE id IN ( \
+                 SELECT id FROM jobs \
+                 WHERE status = 'pending' AND task_reference = ANY($2) \
+                 AND blocked_reason IS DISTINCT FROM $1 \
+                 FOR UPDATE SKIP LOCKED)",
+            )
+            .bind::<Text, _>(&conflict.reason)
+            .bind::<Array<Integer>, _>(&conflict.task_ids)
+            .execute(conn)?;
+
+            blocked_tasks.extend(conflict.task_ids);
+        }
+    }
+
+    /// Returns the reason the job is not allowed to run yet, because of the
+    /// concurrency limit or the lock group of its task, if any.
+    fn concurrency_conflict(&self, conn: &PgConnection) -> QueryResult<Option<Conflict>> {
+        match self.task(conn)? {
+            Some(task) => Self::task_conflict(conn, &task, &[self.id]),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the reason a new job of the provided task is not allowed to
+    /// run yet, because of the concurrency limit or the lock group of the
+    /// task, if any.
+    ///
+    /// Jobs that are running, or paused, count towards these limits, except
+    /// for the jobs with the provided IDs.
+    ///
+    /// Claiming jobs with limits is serialized using a lock that is held until
+    /// the end of the current transaction. The job has to be claimed, or
+    /// created, within the same transaction.
+    fn task_conflict(
+        conn: &PgConnection,
+        task: &Task,
+        excluded: &[i32],
+    ) -> QueryResult<Option<Conflict>> {
+        if task.max_concurrent_jobs.is_none() && task.lock_group.is_none() {
+            return Ok(None);
+        }
+
+        // Claiming jobs with limits is serialized, so that two workers can't
+        // both claim a job, while only one of them is allowed to run.
+        let _ = diesel::sql_query(format!(
+            "SELECT pg_advisory_xact_lock({})",
+            CONCURRENCY_LOCK_KEY
+        ))
+        .execute(conn)?;
+
+        let active = || {
+            jobs::table
+                .filter(jobs::id.ne_all(excluded))
+                .filter(jobs::status.eq_any(vec![
+                    Status::Running,
+                    Status::AwaitingApproval,
+                    Status::AwaitingInput,
+                ]))
+        };
+
+        if let Some(max) = task.max_concurrent_jobs {
+            let running = active()
+                .filter(jobs::task_reference.eq(task.id))
+                .count()
+                .get_result::<i64>(conn)?;
+
+            if running >= i64::from(max) {
+                return Ok(Some(Conflict {
+                    reason: format!(
+                        "Waiting for one of the {} running jobs of task `{}` to finish.",
+                        running, task.name
+                    ),
+                    task_ids: vec![task.id],
+                }));
+            }
+        }
+
+        if let Some(group) = &task.lock_group {
+            let holder: Option<String> = active()
+                .inner_join(tasks::table.on(jobs::task_reference.eq(tasks::id.nullable())))
+                .filter(tasks::lock_group.eq(group))
+                .select(jobs::name)
+                .first(conn)
+                .optional()?;
+
+            if let Some(holder) = holder {
+                return Ok(Some(Conflict {
+                    reason: format!(
+                        "Waiting for job `{}` to release the `{}` lock group.",
+                        holder, group
+                    ),
+                    task_ids: tasks::table
+                        .filter(tasks::lock_group.eq(group))
+                        .select(tasks::id)
+                        .load(conn)?,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Claim the next pending job for the provided worker, by marking it as
+    /// running.
+    ///
+    /// If an agent is provided, the job is claimed for that agent instead,
+    /// using the queues of the agent. The worker then runs the job, and
+    /// dispatches its processors to the agent.
+    ///
+    /// A job that paused, and continues running, is claimed again by the same
+    /// worker, for the same agent, if any.
+    ///
+    /// The moment the job is claimed is stored on its task, see
+    /// [`Job::find_next_unlocked_pending`].
+    ///
+    /// The job is claimed in a short transaction of its own. Once claimed, the
+    /// `Running` status prevents other workers from claiming the same job, and
+    /// the job is run outside of the transaction, which makes the progress of
+    /// each step visible as soon as it happens.
+    pub(crate) fn claim_next_pending(
+        conn: &PgConnection,
+        worker: &WorkerRegistration,
+        agent: Option<&WorkerRegistration>,
+    ) -> QueryResult<Option<Self>> {
+        conn.transaction(|| {
+            let job = match Self::find_next_unlocked_pending(conn, worker, agent)? {
+                Some(job) => job,
+                None => return Ok(None),
+            };
+
+            if let Some(task_id) = job.task_reference {
+                let _ = diesel::update(tasks::table.find(task_id))
+                    .set(tasks::last_claimed_at.eq(Utc::now().naive_utc()))
+                    .execute(conn)?;
+            }
+
+            diesel::update(&job)
+                .set((
+                    jobs::status.eq(Status::Running),
+                    jobs::worker_id.eq(worker.id),
+                    jobs::agent_id.eq(agent.map(|agent| agent.id)),
+                    jobs::blocked_reason.eq(None::<String>),
+                ))
+                .get_result(conn)
+                .map(Some)
+        })
+    }
+
+    /// Find all pending jobs that require a queue that is not served by any
+    /// live worker.
+    ///
+    /// These jobs stay pending until a worker serving their queue is started.
+    pub(crate) fn find_unserved(conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        let served = WorkerRegistration::live_queues(LOST_AFTER_SECONDS, conn)?;
+
         jobs::table
             .filter(jobs::status.eq(Status::Pending))
+            .filter(jobs::queue.is_not_null())
+            .filter(jobs::queue.ne_all(served))
             .order(jobs::id)
-            .for_update()
-            .skip_locked()
-            .first(conn)
-            .optional()
+            .load(conn)
     }
 
-    pub(crate) fn as_running(&mut self, conn: &PgConnection) -> QueryResult<Self> {
-        self.status = Status::Running;
-        self.save_changes(conn)
+    /// Returns `true` if the job requires a queue that is not in the provided
+    /// list of queues served by live workers.
+    pub(crate) fn is_unserved(&self, served: &[String]) -> bool {
+        self.queue
+            .as_ref()
+            .map_or(false, |queue| !served.contains(queue))
+    }
+
+    /// Returns the number of jobs running for the provided agent.
+    pub(crate) fn count_running_for_agent(
+        agent: &WorkerRegistration,
+        conn: &PgConnection,
+    ) -> QueryResult<i64> {
+        jobs::table
+            .filter(jobs::agent_id.eq(agent.id))
+            .filter(jobs::status.eq(Status::Running))
+            .count()
+            .get_result(conn)
+    }
+
+    /// Returns the IDs of the provided jobs that no longer run for the
+    /// provided agent, or that are requested to be cancelled.
+    ///
+    /// Jobs that paused, and continue on the same agent, still run for the
+    /// agent, see [`Job::pause`].
+    ///
+    /// The agent stops these jobs, and removes their workspaces.
+    pub(crate) fn find_stopped_for_agent(
+        agent: &WorkerRegistration,
+        ids: &[i32],
+        conn: &PgConnection,
+    ) -> QueryResult<Vec<i32>> {
+        let running: Vec<i32> = jobs::table
+            .left_join(job_cancellations::table)
+            .filter(jobs::id.eq_any(ids))
+            .filter(jobs::agent_id.eq(agent.id))
+            .filter(jobs::status.eq_any(claimed_statuses()))
+            .filter(job_cancellations::id.is_null())
+            .select(jobs::id)
+            .load(conn)?;
+
+        Ok(ids
+            .iter()
+            .filter(|id| !running.contains(id))
+            .cloned()
+            .collect())
     }
 
-    pub(crate) fn as_failed(&mut self, conn: &PgConnection) -> QueryResult<Self> {
+    /// Find all jobs the provided worker was running, including the jobs that
+    /// paused, and continue on the worker, see [`Job::pause`].
+    ///
+    /// If the worker is a remote agent, the jobs claimed for the agent are
+    /// returned instead. These jobs are run by the agent hub of a server, and
+    /// only refer to the agent they were claimed for.
+    ///
+    /// Child jobs are not returned, they are recovered along with their parent
+    /// job, see [`Job::recover`].
+    ///
+    /// The returned jobs are locked until the end of the current transaction.
+    pub(crate) fn find_orphaned(
+        worker: &WorkerRegistration,
+        conn: &PgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        let orphaned = jobs::table
+            .filter(jobs::status.eq_any(claimed_statuses()))
+            .filter(jobs::parent_id.is_null());
+
+        if worker.session_id.is_some() {
+            orphaned
+                .filter(jobs::agent_id.eq(worker.id))
+                .order(jobs::id)
+                .for_update()
+                .load(conn)
+        } else {
+            orphaned
+                .filter(jobs::worker_id.eq(worker.id))
+                .order(jobs::id)
+                .for_update()
+                .load(conn)
+        }
+    }
+
+    /// Move all scheduled jobs that are due to run to the `Pending` state.
+    ///
+    /// Returns the number of jobs that are now pending.
+    pub(crate) fn enqueue_due(conn: &PgConnection) -> QueryResult<usize> {
+        let due = jobs::table
+            .filter(jobs::status.eq(Status::Scheduled))
+            .filter(jobs::scheduled_for.le(Utc::now().naive_utc()));
+
+        let count = diesel::update(due)
+            .set(jobs::status.eq(Status::Pending))
+            .execute(conn)?;
+
+        if count > 0 {
+            Self::notify_pending(conn)?;
+        }
+
+        Ok(count)
+    }
+
+    /// Notify all listening workers that a job is ready to run.
+    ///
+    /// When called within a transaction, the notification is delivered once
+    /// the transaction is committed.
+    pub(crate) fn notify_pending(conn: &PgConnection) -> QueryResult<()> {
+        diesel::sql_query(format!("NOTIFY {}", Self::PENDING_CHANNEL))
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Notify all listening workers if any pending job is not allowed to run
+    /// yet, as the job that stopped running might be the one it waits for.
+    fn notify_blocked(conn: &PgConnection) -> QueryResult<()> {
+        use diesel::dsl::exists;
+
+        let blocked = jobs::table
+            .filter(jobs::status.eq(Status::Pending))
+            .filter(jobs::blocked_reason.is_not_null());
+
+        if diesel::select(exists(blocked)).get_result(conn)? {
+            Self::notify_pending(conn)?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark the job as failed, unless the worker that claimed the job no
+    /// longer runs it, see [`Job::is_claimed`].
+    pub(crate) fn as_failed(&mut self, conn: &PgConnection) -> QueryResult<()> {
         self.status = Status::Failed;
-        self.save_changes(conn)
+
+        let claimed = jobs::table
+            .find(self.id)
+            .filter(jobs::worker_id.eq(self.worker_id));
+
+        diesel::update(claimed)
+            .set(jobs::status.eq(self.status))
+            .execute(conn)
+            .map(|_| ())
+    }
+
+    /// Returns `true` if the job is still claimed by the worker that claimed
+    /// it when this job was loaded.
+    ///
+    /// A job is no longer claimed once it was recovered, because its worker
+    /// was considered lost. The worker can still be running the job at that
+    /// point, but it no longer owns it, and should stop without storing any
+    /// results.
+    pub(crate) fn is_claimed(&self, conn: &PgConnection) -> QueryResult<bool> {
+        jobs::table
+            .find(self.id)
+            .filter(jobs::worker_id.eq(self.worker_id))
+            .select(jobs::id)
+            .first::<i32>(conn)
+            .optional()
+            .map(|id| id.is_some())
+    }
+
+    /// Returns `true` if the job paused, and continues on the worker that
+    /// claimed it when this job was loaded, see [`Job::pause`].
+    pub(crate) fn is_paused(&self, conn: &PgConnection) -> QueryResult<bool> {
+        jobs::table
+            .find(self.id)
+            .filter(jobs::worker_id.eq(self.worker_id))
+            .filter(jobs::status.eq_any(paused_statuses()))
+            .select(jobs::id)
+            .first::<i32>(conn)
+            .optional()
+            .map(|id| id.is_some())
+    }
+
+    /// Returns the IDs of the jobs that paused while running on the provided
+    /// worker, and continue on that worker.
+    pub(crate) fn find_paused(
+        worker: &WorkerRegistration,
+        conn: &PgConnection,
+    ) -> QueryResult<Vec<i32>> {
+        Self::belonging_to(worker)
+            .filter(jobs::status.eq_any(paused_statuses()))
+            .select(jobs::id)
+            .load(conn)
     }
 
     pub(crate) fn task(&self, conn: &PgConnection) -> QueryResult<Option<Task>> {
@@ -110,12 +635,134 @@ impl Job {
         }
     }
 
+    pub(crate) fn schedule(&self, conn: &PgConnection) -> QueryResult<Option<Schedule>> {
+        use crate::schema::schedules::dsl::*;
+
+        match self.schedule_id {
+            None => Ok(None),
+            Some(schedule_id) => schedules.filter(id.eq(schedule_id)).first(conn).optional(),
+        }
+    }
+
     pub(crate) fn steps(&self, conn: &PgConnection) -> QueryResult<Vec<JobStep>> {
         use crate::schema::job_steps::dsl::*;
 
         JobStep::belonging_to(self).order(position.asc()).load(conn)
     }
 
+    /// Returns the job that ran this job as part of one of its steps, if any.
+    pub(crate) fn parent(&self, conn: &PgConnection) -> QueryResult<Option<Self>> {
+        match self.parent_id {
+            None => Ok(None),
+            Some(parent_id) => jobs::table.find(parent_id).first(conn).optional(),
+        }
+    }
+
+    /// Returns the jobs run by the steps of this job.
+    pub(crate) fn children(&self, conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        jobs::table
+            .filter(jobs::parent_id.eq(self.id))
+            .order(jobs::id)
+            .load(conn)
+    }
+
+    /// Returns the job of which this job is a rerun, if any.
+    pub(crate) fn rerun_of(&self, conn: &PgConnection) -> QueryResult<Option<Self>> {
+        match self.rerun_of_id {
+            None => Ok(None),
+            Some(rerun_of_id) => jobs::table.find(rerun_of_id).first(conn).optional(),
+        }
+    }
+
+    /// Returns the jobs created as a rerun of this job.
+    pub(crate) fn reruns(&self, conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        jobs::table
+            .filter(jobs::rerun_of_id.eq(self.id))
+            .order(jobs::id)
+            .load(conn)
+    }
+
+    /// Returns the number of ancestors of this job, which is zero for a job
+    /// that is not a child job.
+    pub(crate) fn depth(&self, conn: &PgConnection) -> QueryResult<usize> {
+        self.lineage(conn).map(|ids| ids.len() - 1)
+    }
+
+    /// Returns the IDs of this job and all of its ancestors.
+    fn lineage(&self, conn: &PgConnection) -> QueryResult<Vec<i32>> {
+        let mut ids = vec![self.id];
+        let mut parent_id = self.parent_id;
+
+        while let Some(id) = parent_id {
+            ids.push(id);
+            parent_id = jobs::table.find(id).select(jobs::parent_id).first(conn)?;
+        }
+
+        Ok(ids)
+    }
+
+    /// Returns whether this job, or any of its ancestors, was created from the
+    /// provided task.
+    pub(crate) fn runs_task(&self, conn: &PgConnection, task: &Task) -> QueryResult<bool> {
+        let mut job = Some(self.clone());
+
+        while let Some(ancestor) = job {
+            if ancestor.task_reference == Some(task.id) {
+                return Ok(true);
+            }
+
+            job = ancestor.parent(conn)?;
+        }
+
+        Ok(false)
+    }
+
+    /// Returns whether the worker running this job serves the provided queue.
+    ///
+    /// If the job runs for a remote agent, the queues of the agent are used.
+    pub(crate) fn worker_serves(&self, conn: &PgConnection, queue: &str) -> QueryResult<bool> {
+        use crate::schema::worker_registrations;
+
+        let worker_id = self.agent_id.or(self.worker_id);
+        let queues: Option<Vec<String>> = worker_registrations::table
+            .filter(worker_registrations::id.nullable().eq(worker_id))
+            .select(worker_registrations::queues)
+            .first(conn)
+            .optional()?;
+
+        Ok(queues.map_or(false, |queues| queues.iter().any(|q| q == queue)))
+    }
+
+    /// Returns the final output of the job, which is the output of the last
+    /// step that ran successfully.
+    pub(crate) fn output(&self, conn: &PgConnection) -> QueryResult<Option<String>> {
+        Ok(self
+            .steps(conn)?
+            .into_iter()
+            .filter(|step| match step.status {
+                JobStepStatus::Ok => true,
+                _ => false,
+            })
+            .last()
+            .and_then(|step| step.output))
+    }
+
+    /// Returns the name and output of the first step of the job that failed,
+    /// if any.
+    pub(crate) fn failure(&self, conn: &PgConnection) -> QueryResult<Option<Failure>> {
+        Ok(self
+            .steps(conn)?
+            .into_iter()
+            .find(|step| match step.status {
+                JobStepStatus::Failed => true,
+                _ => false,
+            })
+            .map(|step| Failure {
+                step: step.name,
+                error: step.output.unwrap_or_default(),
+            }))
+    }
+
     pub(crate) fn variables(&self, conn: &PgConnection) -> QueryResult<Vec<JobVariable>> {
         use crate::schema::job_variables::dsl::*;
 
@@ -125,29 +772,652 @@ impl Job {
             .load(conn)
     }
 
-    // TODO: implement some kind of `JobRunner`, that has a reference to
-    // &Database, and then impl `Drop` so that if the runner stops, we can check
-    // the result, and update the database based on the final status.
-    pub(crate) fn run(&self, conn: &PgConnection) -> Result<(), Box<dyn Error>> {
-        use crate::schema::jobs::dsl::*;
+    /// Cancel the job.
+    ///
+    /// A job that is not yet picked up by a worker is cancelled immediately,
+    /// as are all of its steps.
+    ///
+    /// If a worker is already running (or claiming) the job, a cancellation
+    /// request is stored instead. The worker acts on this request by
+    /// interrupting the running step, and skipping any remaining steps.
+    ///
+    /// An error is returned if the job already finished running.
+    pub(crate) fn cancel(&self, conn: &PgConnection) -> Result<Self, Box<dyn Error>> {
+        conn.transaction(|| {
+            // Any job that is not running, and not locked by a worker that is
+            // about to run it, can be cancelled right away.
+            let waiting = jobs::table
+                .find(self.id)
+                .filter(jobs::status.eq_any(vec![
+                    Status::Scheduled,
+                    Status::Pending,
+                    Status::AwaitingApproval,
+                    Status::AwaitingInput,
+                ]))
+                .for_update()
+                .skip_locked()
+                .first::<Self>(conn)
+                .optional()?;
+
+            if let Some(mut job) = waiting {
+                let unfinished =
+                    JobStep::belonging_to(&job).filter(job_steps::status.eq_any(vec![
+                        JobStepStatus::Initialized,
+                        JobStepStatus::Pending,
+                        JobStepStatus::AwaitingApproval,
+                        JobStepStatus::AwaitingInput,
+                    ]));
+
+                let _ = diesel::update(unfinished)
+                    .set(job_steps::status.eq(JobStepStatus::Cancelled))
+                    .execute(conn)?;
+
+                job.status = Status::Cancelled;
+                let job = job.save_changes(conn)?;
+
+                Self::notify_blocked(conn)?;
+                return Ok(job);
+            }
+
+            match self.status {
+                Status::Scheduled
+                | Status::Pending
+                | Status::Running
+                | Status::AwaitingApproval
+                | Status::AwaitingInput => {
+                    NewJobCancellation::new(self.id).create(conn)?;
+                    Ok(self.clone())
+                }
+                _ => Err("job already finished running".into()),
+            }
+        })
+    }
+
+    /// Returns the cancellation request of the job, if any.
+    pub(crate) fn cancellation(&self, conn: &PgConnection) -> QueryResult<Option<JobCancellation>> {
+        JobCancellation::find_by_job(self, conn)
+    }
+
+    /// Recover a job that was running on a worker that was lost.
+    ///
+    /// The step that was running is marked as failed, with an output explaining
+    /// the worker was lost, any remaining steps are cancelled, and the job is
+    /// marked as failed. If the job paused, the steps awaiting approval or
+    /// input are marked as failed instead, as the workspace of the job was
+    /// lost along with the worker.
+    ///
+    /// If the task of the job opts in to requeue jobs of lost workers, all
+    /// steps are reset instead, and the job is moved back to `Pending`, to run
+    /// again from the first step. A job that had its cancellation requested is
+    /// never requeued.
+    ///
+    /// Any processors of the job dispatched to a remote agent are withdrawn,
+    /// so the agent no longer picks them up.
+    ///
+    /// The running child jobs of the job are recovered as well, but are never
+    /// requeued, as they only run as part of a step of their parent job. If
+    /// the parent job is requeued, the step runs a new child job once it runs
+    /// again.
+    pub(crate) fn recover(&self, conn: &PgConnection) -> QueryResult<Self> {
+        conn.transaction(|| {
+            let requeue = self.cancellation(conn)?.is_none()
+                && self
+                    .task(conn)?
+                    .map_or(false, |task| task.requeue_on_worker_loss);
+
+            self.recover_with(conn, requeue)
+        })
+    }
+
+    fn recover_with(&self, conn: &PgConnection, requeue: bool) -> QueryResult<Self> {
+        AgentDispatch::delete_for_job(self, conn)?;
+
+        let children = jobs::table
+            .filter(jobs::parent_id.eq(self.id))
+            .filter(jobs::status.eq(Status::Running))
+            .order(jobs::id)
+            .for_update()
+            .load::<Self>(conn)?;
+
+        for child in children {
+            let _ = child.recover_with(conn, false)?;
+        }
+
+        for mut step in self.steps(conn)? {
+            match step.status {
+                _ if requeue => step.requeue(conn)?,
+                JobStepStatus::Running => step.lost(conn)?,
+                JobStepStatus::AwaitingApproval | JobStepStatus::AwaitingInput => {
+                    step.workspace_lost(conn)?
+                }
+                JobStepStatus::Initialized | JobStepStatus::Pending => step.cancel(conn)?,
+                _ => {}
+            }
+        }
+
+        let status = if requeue {
+            Self::notify_pending(conn)?;
+            Status::Pending
+        } else {
+            Self::notify_blocked(conn)?;
+            Status::Failed
+        };
+
+        diesel::update(self)
+            .set((
+                jobs::status.eq(status),
+                jobs::worker_id.eq(None::<i32>),
+                jobs::agent_id.eq(None::<i32>),
+            ))
+            .get_result(conn)
+    }
+
+    /// Create a new job from the task of this job, using the variable values
+    /// of this job, with the provided overrides applied.
+    ///
+    /// The variable values are validated against the current variables of
+    /// the task, in the same way as the values of any other new job.
+    ///
+    /// The new job is created on behalf of the provided session, if any.
+    ///
+    /// An error is returned if the task of this job no longer exists.
+    pub(crate) fn rerun(
+        &self,
+        conn: &PgConnection,
+        overrides: &[NewJobVariable<'_>],
+        session: Option<&Session>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let task = self
+            .task(conn)?
+            .ok_or("job cannot be rerun, as its task no longer exists")?;
+
+        let variables = self.variables(conn)?;
+        let mut values: Vec<_> = variables
+            .iter()
+            .filter(|v| overrides.iter().all(|o| o.key() != v.key))
+            .map(|v| NewJobVariable::new(&v.key, &v.value))
+            .collect();
+
+        values.extend(overrides.iter().cloned());
+
+        NewJob::create_rerun(conn, &task, self, values, session)
+    }
+
+    /// Resume a failed job, running its failed steps again.
+    ///
+    /// The steps that did not finish successfully, or were not skipped, such
+    /// as the failed and cancelled steps, are reset, as are all steps that
+    /// depend on them, directly or indirectly. All other steps are kept, along
+    /// with their output. The job is moved back to `Pending`, to be picked up
+    /// by a worker again. Any cancellation request of the failed run is
+    /// removed.
+    ///
+    /// The workspace of the failed run is not kept, so the resumed job runs in
+    /// a new, empty workspace.
+    ///
+    /// An error is returned if the job did not fail, or if the job is a child
+    /// job, which only runs as part of a step of its parent job. The parent
+    /// job is resumed instead, which runs a new child job.
+    pub(crate) fn resume(&self, conn: &PgConnection) -> Result<Self, Box<dyn Error>> {
+        conn.transaction(|| {
+            let job = jobs::table.find(self.id).for_update().first::<Self>(conn)?;
+
+            if job.parent_id.is_some() {
+                return Err("child jobs cannot be resumed, resume the parent job instead".into());
+            }
+
+            match job.status {
+                Status::Failed => {}
+                _ => return Err("only failed jobs can be resumed".into()),
+            }
+
+            let steps = job.steps(conn)?;
+            if steps
+                .iter()
+                .all(|step| step.status != JobStepStatus::Failed)
+            {
+                return Err("job has no failed step to resume from".into());
+            }
+
+            let unfinished: Vec<_> = steps
+                .iter()
+                .map(|step| match step.status {
+                    JobStepStatus::Ok | JobStepStatus::Skipped => false,
+                    _ => true,
+                })
+                .collect();
+
+            let requeue = step_dependencies(&steps)?.with_dependents(&unfinished);
+            for (mut step, requeue) in steps.into_iter().zip(requeue) {
+                if requeue {
+                    step.requeue(conn)?;
+                }
+            }
+
+            let cancellations =
+                job_cancellations::table.filter(job_cancellations::job_id.eq(job.id));
+            let _ = diesel::delete(cancellations).execute(conn)?;
+
+            Self::notify_pending(conn)?;
+
+            diesel::update(&job)
+                .set((
+                    jobs::status.eq(Status::Pending),
+                    jobs::worker_id.eq(None::<i32>),
+                    jobs::agent_id.eq(None::<i32>),
+                ))
+                .get_result(conn)
+                .map_err(Into::into)
+        })
+    }
+
+    /// Run all steps of the job, each step running once all the steps it
+    /// depends on finished.
+    ///
+    /// Steps that do not depend on each other run concurrently, each on its
+    /// own thread, using its own database connection from the provided pool.
+    /// All steps share the workspace of the provided context. The output of
+    /// every step is available to the steps that start after it finished.
+    ///
+    /// Once a step fails, the remaining steps are cancelled, except for the
+    /// steps with an `OnFailure` or `Always` run policy, which run with the
+    /// details of the failed step available to their templates. If no step
+    /// failed by the time its dependencies finished, a step with an
+    /// `OnFailure` run policy is skipped.
+    ///
+    /// The job stops running once the provided context is cancelled, or once
+    /// the timeout of the job passes. If the job is the child of another job,
+    /// it also stops once the deadline of the provided context passes. Any
+    /// steps with an `OnSuccess` run policy that did not get to run are marked
+    /// as cancelled.
+    ///
+    /// Steps running after a failure, cancellation or timeout of the job run
+    /// in a fresh context, sharing the workspace of the job, so that they are
+    /// not cancelled themselves, and are only limited by their own timeout.
+    ///
+    /// Once a step is awaiting approval or input, no more steps start running.
+    /// Once the running steps finished, the job pauses, releasing the worker
+    /// slot, until a decision is made, or the prompt is answered. The worker
+    /// keeps the workspace, see [`Job::pause`]. If a step fails,
+    /// or the job is cancelled, the awaiting steps are cancelled instead.
+    ///
+    /// Steps that already finished before the job was resumed, or before it
+    /// paused, do not run again. The output of these steps is available to the
+    /// steps that run after them, and a failed step among them is treated as
+    /// the failure of the job.
+    ///
+    /// The processors of the steps run using the provided executor, either on
+    /// the current worker, or on the remote agent the job was claimed for.
+    ///
+    /// If the dependencies between the steps cannot be resolved, no step
+    /// runs. The first unfinished step fails with the reason, the other
+    /// unfinished steps are cancelled, and an error is returned.
+    ///
+    /// Once done, the job status is updated to reflect the final result of
+    /// the run. If a step failed, the job status reflects that failure, even
+    /// if any of the steps running after it failed as well.
+    pub(crate) fn run(
+        &self,
+        conn: &PgConnection,
+        pool: &DatabasePool,
+        context: &Context,
+        executor: &Executor,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut steps: Vec<_> = self.steps(conn)?.into_iter().map(Some).collect();
+        let dependencies = match step_dependencies(steps.iter().flatten()) {
+            Ok(dependencies) => dependencies,
+            Err(err) => {
+                stop_unresolved(conn, steps.into_iter().flatten(), &err)?;
+                return Err(err.into());
+            }
+        };
+
+        let mut finished = vec![false; steps.len()];
+        let mut output: HashMap<String, String> = HashMap::default();
+        let mut result = Status::Ok;
+        let mut failure: Option<Failure> = None;
+        let mut awaiting: Vec<(usize, JobStep)> = vec![];
+
+        // Steps that finished before the job was resumed, or paused, do not
+        // run again, and steps still awaiting approval or input keep waiting.
+        for (index, slot) in steps.iter_mut().enumerate() {
+            let step = match slot {
+                Some(step) => step,
+                None => continue,
+            };
+
+            match step.status {
+                JobStepStatus::Ok => {
+                    let out = step.output.clone().unwrap_or_default();
+                    let _ = output.insert(step.name.clone(), out);
+                }
+                JobStepStatus::Failed if failure.is_none() => {
+                    result = Status::Failed;
+                    failure = Some(Failure {
+                        step: step.name.clone(),
+                        error: step.output.clone().unwrap_or_default(),
+                    });
+                }
+                JobStepStatus::Skipped | JobStepStatus::Failed => {}
+                JobStepStatus::AwaitingApproval | JobStepStatus::AwaitingInput => {
+                    if let Some(step) = slot.take() {
+                        awaiting.push((index, step));
+                    }
+
+                    continue;
+                }
+                _ => continue,
+            }
+
+            *slot = None;
+            finished[index] = true;
+        }
+
+        let mut running = 0;
+        let timeout = Timeout::for_job(self.timeout_seconds, context.deadline());
+        let (sender, receiver) = mpsc::channel::<StepResult>();
+
+        loop {
+            // A job is also cancelled once it is no longer claimed by this
+            // worker, in which case the job stops without storing anything,
+            // as it was already recovered.
+            if context.is_cancelled() && !self.is_claimed(conn)? {
+                return Err("job is no longer claimed by this worker".into());
+            }
+
+            // Once a step failed, or the job is cancelled, the job no longer
+            // waits for any approvals or input.
+            if !awaiting.is_empty() && (failure.is_some() || context.is_cancelled()) {
+                if failure.is_none() {
+                    result = Status::Cancelled;
+                }
+
+                for (index, mut step) in awaiting.drain(..) {
+                    step.cancel(conn)?;
+                    finished[index] = true;
+                }
+            }
+
+            // Take the first step for which all dependencies finished, as long
+            // as more steps are allowed to run concurrently, and no step is
+            // awaiting approval or input.
+            let ready = if running < MAX_CONCURRENT_STEPS && awaiting.is_empty() {
+                (0..steps.len())
+                    .find(|&i| {
+                        steps[i].is_some() && dependencies.of(i).iter().all(|&d| finished[d])
+                    })
+                    .and_then(|i| steps[i].take().map(|step| (i, step)))
+            } else {
+                None
+            };
 
-        let output: HashMap<String, String> = HashMap::default();
-        let context = Context::new()?;
-        let mut steps = self.steps(conn)?;
+            let (index, mut step) = match ready {
+                Some(ready) => ready,
+                None if running == 0 => break,
+                None => {
+                    let (index, mut step, ran) = receiver.recv()?;
+                    running -= 1;
 
-        let _ = steps
-            .iter_mut()
-            .try_fold(output, |output, step| step.run(conn, &context, output))?;
+                    if let JobStepStatus::AwaitingApproval | JobStepStatus::AwaitingInput =
+                        step.status
+                    {
+                        awaiting.push((index, step));
+                        continue;
+                    }
 
-        match steps.last() {
-            Some(step) => diesel::update(self)
-                .set(status.eq(Status::from(step.status)))
+                    finished[index] = true;
+
+                    match ran {
+                        Ok(Some(out)) => {
+                            let _ = output.insert(step.name.clone(), out);
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            // The step did not get to store its own result, for
+                            // example because no database connection was
+                            // available.
+                            if let JobStepStatus::Pending | JobStepStatus::Running = step.status {
+                                step.fail(conn, err)?;
+                            }
+
+                            if failure.is_none() {
+                                result = Status::from(step.status);
+                                failure = Some(Failure {
+                                    step: step.name.clone(),
+                                    error: step.output.clone().unwrap_or_default(),
+                                });
+                            }
+                        }
+                    }
+
+                    continue;
+                }
+            };
+
+            if context.is_cancelled() && failure.is_none() {
+                result = Status::Cancelled;
+            }
+
+            match policy_action(step.run_policy, failure.is_some(), context.is_cancelled()) {
+                PolicyAction::Cancel => {
+                    step.cancel(conn)?;
+                    finished[index] = true;
+                    continue;
+                }
+                PolicyAction::Skip => {
+                    step.skip(conn)?;
+                    finished[index] = true;
+                    continue;
+                }
+                PolicyAction::Run => {}
+            }
+
+            // Once the job failed, was cancelled, or timed out, the remaining
+            // steps clean up after it. These steps run in a fresh context
+            // sharing the job workspace, limited only by their own timeout.
+            let cleanup = failure.is_some()
+                || context.is_cancelled()
+                || timeout.as_ref().map_or(false, Timeout::has_passed);
+
+            let (context, timeout) = if cleanup {
+                (Context::in_workspace(context.workspace_path())?, None)
+            } else {
+                (context.fork(), timeout.clone())
+            };
+
+            running += 1;
+            let sender = sender.clone();
+            let pool = pool.clone();
+            let executor = executor.clone();
+            let failure = failure.clone();
+            let mut output = output.clone();
+
+            let _ = thread::spawn(move || {
+                let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+                    let conn = pool.get().map_err(|e| e.to_string())?;
+
+                    step.run(
+                        &conn,
+                        &pool,
+                        &context,
+                        &executor,
+                        timeout.as_ref(),
+                        failure.as_ref(),
+                        &mut output,
+                    )
+                    .map_err(|e| e.to_string())
+                }));
+
+                let ran = match ran {
+                    Ok(Ok(())) => Ok(output.remove(&step.name)),
+                    Ok(Err(err)) => Err(err),
+                    Err(_) => Err("job step panicked while running".to_owned()),
+                };
+
+                let _ = sender.send((index, step, ran));
+            });
+        }
+
+        if !awaiting.is_empty() {
+            return self.pause(conn).map_err(Into::into);
+        }
+
+        let claimed = jobs::table
+            .find(self.id)
+            .filter(jobs::worker_id.eq(self.worker_id));
+
+        let _ = diesel::update(claimed)
+            .set(jobs::status.eq(result))
+            .execute(conn)?;
+
+        Self::notify_blocked(conn).map_err(Into::into)
+    }
+
+    /// Pause the job, to wait for the approval of one or more of its steps, or
+    /// for their prompts to be answered, releasing the slot of the worker
+    /// running it.
+    ///
+    /// The job stays claimed by the worker, and the agent, if any. The worker
+    /// keeps the workspace of the job, and is the only worker to claim the job
+    /// once it continues, so that the remaining steps run in the same
+    /// workspace. If the worker stops before the job continues, the job is
+    /// recovered, see [`Job::recover`].
+    ///
+    /// If a step is awaiting input, the job is marked as awaiting input, even
+    /// if other steps are awaiting approval.
+    ///
+    /// If all awaiting steps were decided or answered while the job was still
+    /// running, the job is moved back to `Pending` right away.
+    fn pause(&self, conn: &PgConnection) -> QueryResult<()> {
+        conn.transaction(|| {
+            // The job is locked, so that no decision is made on any of its
+            // steps until the job is paused.
+            let job = jobs::table.find(self.id).for_update().first::<Self>(conn)?;
+            if job.worker_id != self.worker_id {
+                return Ok(());
+            }
+
+            let awaiting: Vec<JobStepStatus> = JobStep::belonging_to(self)
+                .select(job_steps::status)
+                .filter(job_steps::status.eq_any(vec![
+                    JobStepStatus::AwaitingApproval,
+                    JobStepStatus::AwaitingInput,
+                ]))
+                .load(conn)?;
+
+            let input = awaiting.iter().any(|status| match status {
+                JobStepStatus::AwaitingInput => true,
+                _ => false,
+            });
+
+            let status = match (input, awaiting.is_empty()) {
+                (true, _) => Status::AwaitingInput,
+                (false, false) => Status::AwaitingApproval,
+                (false, true) => {
+                    Self::notify_pending(conn)?;
+                    Status::Pending
+                }
+            };
+
+            diesel::update(self)
+                .set(jobs::status.eq(status))
                 .execute(conn)
                 .map(|_| ())
-                .map_err(Into::into),
-            None => Ok(()),
+        })
+    }
+}
+
+/// The statuses of the jobs that paused, and stay claimed by the worker that
+/// ran them, including the jobs that continue running once claimed again.
+///
+/// Only jobs that paused have the `Pending` status while claimed by a worker.
+fn paused_statuses() -> Vec<Status> {
+    vec![
+        Status::Pending,
+        Status::AwaitingApproval,
+        Status::AwaitingInput,
+    ]
+}
+
+/// The statuses of the jobs that are claimed by a worker, which are the
+/// running jobs, and the jobs that paused.
+fn claimed_statuses() -> Vec<Status> {
+    let mut statuses = paused_statuses();
+    statuses.push(Status::Running);
+    statuses
+}
+
+/// Resolve the dependencies of the provided steps of a job, ordered by their
+/// position.
+fn step_dependencies<'a, I>(steps: I) -> Result<StepDependencies, String>
+where
+    I: IntoIterator<Item = &'a JobStep>,
+{
+    StepDependencies::resolve(steps.into_iter().map(|step| {
+        let depends_on = step
+            .depends_on
+            .as_ref()
+            .map(|names| names.iter().map(String::as_str).collect());
+
+        (step.name.as_str(), depends_on)
+    }))
+}
+
+/// Stop the unfinished steps of a job of which the dependencies between the
+/// steps cannot be resolved, as none of the steps can run.
+///
+/// The first unfinished step fails with the provided error, so that the error
+/// is reported as the failure of the job. The other unfinished steps are
+/// cancelled.
+fn stop_unresolved<I>(conn: &PgConnection, steps: I, error: &str) -> QueryResult<()>
+where
+    I: IntoIterator<Item = JobStep>,
+{
+    let mut failed = false;
+    for mut step in steps {
+        match step.status {
+            JobStepStatus::Ok
+            | JobStepStatus::Skipped
+            | JobStepStatus::Failed
+            | JobStepStatus::Cancelled => {}
+            _ if failed => step.cancel(conn)?,
+            _ => {
+                let error = format!("job step dependencies cannot be resolved: {}", error);
+                step.fail(conn, error)?;
+                failed = true;
+            }
         }
     }
+
+    Ok(())
+}
+
+/// The way a step that is ready to run is handled, according to its run
+/// policy.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PolicyAction {
+    /// The step runs.
+    Run,
+
+    /// The step is skipped, as it only runs once another step failed.
+    Skip,
+
+    /// The step is cancelled, as it only runs as long as no step failed, and
+    /// the job is not cancelled.
+    Cancel,
+}
+
+/// Determine how a step with the provided run policy is handled, based on
+/// whether a step of the job failed, and whether the job is cancelled.
+///
+/// A step that runs on failure is skipped when the job is cancelled without
+/// any failed steps, while a step that always runs, still runs.
+fn policy_action(policy: StepRunPolicy, failed: bool, cancelled: bool) -> PolicyAction {
+    match policy {
+        StepRunPolicy::OnSuccess if failed || cancelled => PolicyAction::Cancel,
+        StepRunPolicy::OnFailure if !failed => PolicyAction::Skip,
+        _ => PolicyAction::Run,
+    }
 }
 
 /// Contains all the details needed to store a job in the database.
@@ -159,29 +1429,155 @@ pub(crate) struct NewJob<'a> {
     description: Option<&'a str>,
     status: Status,
     task_reference: Option<i32>,
+    scheduled_for: Option<NaiveDateTime>,
+    schedule_id: Option<i32>,
+    timeout_seconds: Option<i32>,
+    worker_id: Option<i32>,
+    parent_id: Option<i32>,
+    rerun_of_id: Option<i32>,
+    priority: i32,
+    queue: Option<&'a str>,
+    agent_id: Option<i32>,
+    session_id: Option<i32>,
     steps: Vec<NewJobStep<'a>>,
     variables: Vec<NewJobVariable<'a>>,
 }
 
-impl<'a> NewJob<'a> {
-    /// Initialize a `NewJob` struct, which can be inserted into the
-    /// database using the [`NewJob#create`] method.
-    pub(crate) fn new(name: &'a str, description: Option<&'a str>) -> Self {
-        Self {
-            name,
-            description,
-            status: Status::Pending,
-            task_reference: None,
-            steps: vec![],
-            variables: vec![],
-        }
+/// The reason pending jobs are not allowed to run yet, because of the
+/// concurrency limit or the lock group of their task.
+#[derive(Debug)]
+struct Conflict {
+    /// The reason stored on the blocked jobs.
+    reason: String,
+
+    /// The tasks of which none of the pending jobs are allowed to run.
+    task_ids: Vec<i32>,
+}
+
+/// An SQL expression returning the moment a job of the task of the job in the
+/// outer query was last claimed, or the earliest possible moment if no job of
+/// the task was claimed yet, or if the job has no task.
+///
+/// The task is looked up by its primary key, so the expression is cheap to
+/// evaluate for every pending job.
+fn task_last_claimed_at() -> SqlLiteral<Timestamp> {
+    sql("COALESCE((SELECT last_claimed_at FROM tasks \
+         WHERE tasks.id = jobs.task_reference), '-infinity')")
+}
+
+impl<'a> NewJob<'a> {
+    /// Initialize a `NewJob` struct, which can be inserted into the
+    /// database using the [`NewJob#create`] method.
+    pub(crate) fn new(name: &'a str, description: Option<&'a str>) -> Self {
+        Self {
+            name,
+            description,
+            status: Status::Pending,
+            task_reference: None,
+            scheduled_for: None,
+            schedule_id: None,
+            timeout_seconds: None,
+            worker_id: None,
+            parent_id: None,
+            rerun_of_id: None,
+            priority: 0,
+            queue: None,
+            agent_id: None,
+            session_id: None,
+            steps: vec![],
+            variables: vec![],
+        }
+    }
+
+    /// Create a job from a task, on behalf of the provided session, if any.
+    ///
+    /// The job uses the priority of the task, unless a priority is provided.
+    pub(crate) fn create_from_task(
+        conn: &PgConnection,
+        task: &'a Task,
+        variables: Vec<NewJobVariable<'a>>,
+        priority: Option<i32>,
+        session: Option<&Session>,
+    ) -> Result<Job, Box<dyn Error>> {
+        Self::create_from_task_with(conn, task, variables, |job| {
+            job.with_session(session);
+
+            if let Some(priority) = priority {
+                job.with_priority(priority)
+            }
+        })
+    }
+
+    /// Create a job from the task of a schedule.
+    ///
+    /// The job is created with the `Scheduled` status, and is moved to
+    /// `Pending` once the `scheduled_for` moment has passed.
+    pub(crate) fn create_from_schedule(
+        conn: &PgConnection,
+        task: &'a Task,
+        schedule: &Schedule,
+        scheduled_for: NaiveDateTime,
+        variables: Vec<NewJobVariable<'a>>,
+    ) -> Result<Job, Box<dyn Error>> {
+        Self::create_from_task_with(conn, task, variables, |job| {
+            job.with_schedule(schedule.id, scheduled_for)
+        })
+    }
+
+    /// Create a job from a task, run by a step of the provided parent job.
+    ///
+    /// The job is created with the `Running` status, claimed by the worker
+    /// running the parent job, as it is run by the step of the parent job,
+    /// instead of being picked up by a worker on its own.
+    ///
+    /// The concurrency limit and lock group of the task apply to child jobs
+    /// in the same way as to any other job, except that the lock group is not
+    /// held by the ancestors of the child job. An error is returned if the
+    /// child job is not allowed to run yet, instead of waiting for the jobs it
+    /// conflicts with, as its parent job would block its worker until then.
+    pub(crate) fn create_child(
+        conn: &PgConnection,
+        task: &'a Task,
+        parent: &Job,
+        variables: Vec<NewJobVariable<'a>>,
+    ) -> Result<Job, Box<dyn Error>> {
+        conn.transaction(|| {
+            if let Some(conflict) = Job::task_conflict(conn, task, &parent.lineage(conn)?)? {
+                return Err(format!(
+                    "child job of task `{}` is not allowed to run: {}",
+                    task.name, conflict.reason
+                )
+                .into());
+            }
+
+            Self::create_from_task_with(conn, task, variables, |job| job.with_parent(parent))
+        })
     }
 
-    pub(crate) fn create_from_task(
+    /// Create a job from a task, as a rerun of the provided job, on behalf of
+    /// the provided session, if any.
+    pub(crate) fn create_rerun(
         conn: &PgConnection,
         task: &'a Task,
+        original: &Job,
         variables: Vec<NewJobVariable<'a>>,
+        session: Option<&Session>,
     ) -> Result<Job, Box<dyn Error>> {
+        Self::create_from_task_with(conn, task, variables, |job| {
+            job.with_rerun_of(original.id);
+            job.with_session(session);
+        })
+    }
+
+    fn create_from_task_with<F>(
+        conn: &PgConnection,
+        task: &'a Task,
+        variables: Vec<NewJobVariable<'a>>,
+        configure: F,
+    ) -> Result<Job, Box<dyn Error>>
+    where
+        F: FnOnce(&mut Self),
+    {
         let steps = task.steps(conn)?;
         let steps = steps
             .iter()
@@ -190,8 +1586,12 @@ impl<'a> NewJob<'a> {
 
         let mut job = Self::new(&task.name, task.description.as_ref().map(String::as_ref));
         job.with_task_reference(task.id);
+        job.with_timeout_seconds(task.timeout_seconds);
+        job.with_priority(task.priority);
+        job.with_queue(task.queue.as_ref().map(String::as_ref));
         job.with_steps(steps);
         job.with_variables(variables);
+        configure(&mut job);
 
         job.create(conn).map_err(Into::into)
     }
@@ -200,6 +1600,50 @@ impl<'a> NewJob<'a> {
         self.task_reference = Some(task_id)
     }
 
+    /// Limit the number of seconds the job is allowed to run.
+    fn with_timeout_seconds(&mut self, seconds: Option<i32>) {
+        self.timeout_seconds = seconds
+    }
+
+    /// Set the priority of the job.
+    fn with_priority(&mut self, priority: i32) {
+        self.priority = priority
+    }
+
+    /// Require the job to be claimed by a worker serving the provided queue.
+    fn with_queue(&mut self, queue: Option<&'a str>) {
+        self.queue = queue
+    }
+
+    /// Mark the job as created by a schedule, to run at the provided moment.
+    fn with_schedule(&mut self, schedule_id: i32, scheduled_for: NaiveDateTime) {
+        self.status = Status::Scheduled;
+        self.schedule_id = Some(schedule_id);
+        self.scheduled_for = Some(scheduled_for);
+    }
+
+    /// Mark the job as the child of the provided job, running on the same
+    /// worker, and for the same agent, if any.
+    ///
+    /// The job is attributed to the session that created the parent job.
+    fn with_parent(&mut self, parent: &Job) {
+        self.status = Status::Running;
+        self.worker_id = parent.worker_id;
+        self.agent_id = parent.agent_id;
+        self.parent_id = Some(parent.id);
+        self.session_id = parent.session_id;
+    }
+
+    /// Mark the job as a rerun of the job with the provided ID.
+    fn with_rerun_of(&mut self, job_id: i32) {
+        self.rerun_of_id = Some(job_id)
+    }
+
+    /// Mark the job as created by the provided session, if any.
+    fn with_session(&mut self, session: Option<&Session>) {
+        self.session_id = session.map(|s| s.id)
+    }
+
     /// Attach zero or more steps to this job.
     ///
     /// `NewJob` takes ownership of the steps, but you are required to
@@ -259,6 +1703,16 @@ impl<'a> NewJob<'a> {
                 description.eq(&self.description),
                 status.eq(self.status),
                 task_reference.eq(self.task_reference),
+                scheduled_for.eq(self.scheduled_for),
+                schedule_id.eq(self.schedule_id),
+                timeout_seconds.eq(self.timeout_seconds),
+                worker_id.eq(self.worker_id),
+                parent_id.eq(self.parent_id),
+                rerun_of_id.eq(self.rerun_of_id),
+                priority.eq(self.priority),
+                queue.eq(self.queue),
+                agent_id.eq(self.agent_id),
+                session_id.eq(self.session_id),
             );
 
             let job = diesel::insert_into(jobs).values(&values).get_result(conn)?;
@@ -271,6 +1725,10 @@ impl<'a> NewJob<'a> {
                 .into_iter()
                 .try_for_each(|s| s.add_to_job(conn, &job))?;
 
+            if let Status::Pending = self.status {
+                Job::notify_pending(conn)?;
+            }
+
             Ok(job)
         })
     }
@@ -318,6 +1776,7 @@ pub(crate) mod graphql {
 
     use super::*;
     use crate::resources::JobVariableInput;
+    use chrono::{DateTime, Utc};
     use juniper::{object, FieldResult, GraphQLInputObject, ID};
 
     /// Contains all the data needed to create a new `Task`.
@@ -334,6 +1793,43 @@ pub(crate) mod graphql {
         /// variables in the task before creating the job. The final step
         /// configs are then stored alongside the job in the database.
         pub(crate) variables: Vec<JobVariableInput>,
+
+        /// An optional priority of the job, overriding the priority of the
+        /// task.
+        ///
+        /// Setting the priority requires the `mutation_set_job_priority`
+        /// privilege.
+        pub(crate) priority: Option<i32>,
+    }
+
+    /// The result of resuming a failed job.
+    #[derive(Clone, Debug)]
+    pub(crate) struct ResumeJobPayload {
+        pub(crate) job: Job,
+        pub(crate) workspace: ResumedWorkspace,
+    }
+
+    /// What happened to the workspace of the failed run of a resumed job.
+    #[derive(Clone, Copy, Debug, GraphQLEnum)]
+    pub(crate) enum ResumedWorkspace {
+        /// The workspace of the failed run is gone, and the resumed job runs in
+        /// a new, empty workspace. Files created by the steps that are kept,
+        /// such as a cloned repository, have to be recreated by the steps that
+        /// run again.
+        Recreated,
+    }
+
+    #[object(Context = RequestState)]
+    impl ResumeJobPayload {
+        /// The resumed job.
+        fn job() -> &Job {
+            &self.job
+        }
+
+        /// What happened to the workspace of the failed run.
+        fn workspace() -> ResumedWorkspace {
+            self.workspace
+        }
     }
 
     #[object(Context = RequestState)]
@@ -362,6 +1858,87 @@ pub(crate) mod graphql {
             self.status
         }
 
+        /// The reason the job is not allowed to run yet, if the job is
+        /// `PENDING`, and is waiting for other jobs to finish, because of the
+        /// `maxConcurrentJobs` or `lockGroup` of its task, or is waiting for a
+        /// worker serving its `queue`.
+        fn blocked_reason(context: &RequestState) -> FieldResult<Option<String>> {
+            let pending = match self.status {
+                Status::Pending => true,
+                _ => false,
+            };
+
+            if !pending {
+                return Ok(None);
+            }
+
+            if self.is_unserved(&context.live_queues()?) {
+                let queue = self.queue.as_ref().map_or("", String::as_ref);
+                return Ok(Some(format!(
+                    "Waiting for a worker serving the `{}` queue.",
+                    queue
+                )));
+            }
+
+            Ok(self.blocked_reason.clone())
+        }
+
+        /// The queue a worker has to serve to run the job, if any.
+        fn queue() -> Option<&str> {
+            self.queue.as_ref().map(String::as_ref)
+        }
+
+        /// The moment at which the cancellation of the job was requested, if
+        /// any.
+        ///
+        /// A job that is cancelled while running, keeps the `RUNNING` status
+        /// until the worker running the job stopped it, at which point the
+        /// status changes to `CANCELLED`.
+        fn cancellation_requested_at(context: &RequestState) -> FieldResult<Option<DateTime<Utc>>> {
+            Ok(self
+                .cancellation(&context.conn)?
+                .map(|c| DateTime::from_utc(c.requested_at, Utc)))
+        }
+
+        /// The number of seconds the job is allowed to run, if limited.
+        fn timeout_seconds() -> Option<i32> {
+            self.timeout_seconds
+        }
+
+        /// The priority of the job.
+        ///
+        /// Pending jobs with a higher priority run first.
+        fn priority() -> i32 {
+            self.priority
+        }
+
+        /// The moment at which a job created by a schedule is due to run.
+        ///
+        /// Returns `null` if the job was not created by a schedule.
+        fn scheduled_for() -> Option<DateTime<Utc>> {
+            self.scheduled_for.map(|t| DateTime::from_utc(t, Utc))
+        }
+
+        /// The moment at which the job was created.
+        fn created_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.created_at, Utc)
+        }
+
+        /// The moment at which the job failed, was cancelled or succeeded.
+        ///
+        /// Returns `null` if the job did not finish running.
+        fn finished_at() -> Option<DateTime<Utc>> {
+            self.finished_at.map(|t| DateTime::from_utc(t, Utc))
+        }
+
+        /// The schedule that created the job, if any.
+        ///
+        /// If the schedule has been removed since the job was created, this
+        /// will also return `null`.
+        fn schedule(context: &RequestState) -> FieldResult<Option<Schedule>> {
+            self.schedule(&context.conn).map_err(Into::into)
+        }
+
         /// The steps belonging to the job.
         ///
         /// This field can return `null`, but _only_ if a database error
@@ -382,6 +1959,41 @@ pub(crate) mod graphql {
             self.steps(&context.conn).map(Some).map_err(Into::into)
         }
 
+        /// The job that ran this job as part of one of its steps, using the
+        /// `RunTask` processor.
+        ///
+        /// Returns `null` if the job was not run by another job, or if the
+        /// parent job has been removed since.
+        fn parent(context: &RequestState) -> FieldResult<Option<Job>> {
+            self.parent(&context.conn).map_err(Into::into)
+        }
+
+        /// The jobs run by the steps of this job, using the `RunTask`
+        /// processor.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        fn children(context: &RequestState) -> FieldResult<Option<Vec<Job>>> {
+            self.children(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// The job of which this job is a rerun, created using the `rerunJob`
+        /// mutation.
+        ///
+        /// Returns `null` if the job is not a rerun, or if the original job
+        /// has been removed since.
+        fn rerun_of(context: &RequestState) -> FieldResult<Option<Job>> {
+            self.rerun_of(&context.conn).map_err(Into::into)
+        }
+
+        /// The jobs created as a rerun of this job.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        fn reruns(context: &RequestState) -> FieldResult<Option<Vec<Job>>> {
+            self.reruns(&context.conn).map(Some).map_err(Into::into)
+        }
+
         /// The task from which the job was created.
         ///
         /// A job _can_ but _does not have to_ be created from an existing
@@ -415,3 +2027,623 @@ pub(crate) mod graphql {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NewSession;
+    use crate::resources::NewTask;
+    use crate::Processor;
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel::result::Error as DieselError;
+    use processor_print_output_v1::PrintOutput;
+
+    fn database_url() -> String {
+        std::env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://postgres@localhost".to_owned())
+    }
+
+    fn connection() -> PgConnection {
+        PgConnection::establish(&database_url()).unwrap()
+    }
+
+    /// Create a failed job, with steps of the provided names, dependencies and
+    /// statuses.
+    fn failed_job(conn: &PgConnection, steps: &[(&str, Option<Vec<&str>>, JobStepStatus)]) -> Job {
+        job_with_steps(conn, Status::Failed, steps)
+    }
+
+    /// Create a job with the provided status, and steps of the provided names,
+    /// dependencies and statuses.
+    fn job_with_steps(
+        conn: &PgConnection,
+        status: Status,
+        steps: &[(&str, Option<Vec<&str>>, JobStepStatus)],
+    ) -> Job {
+        let processor = Processor::PrintOutput(PrintOutput {
+            output: "done".to_owned(),
+        });
+
+        let mut job = NewJob::new("Steps", None);
+        job.with_steps(
+            steps
+                .iter()
+                .zip(0..)
+                .map(|((name, depends_on, _), position)| {
+                    let mut step = NewJobStep::new(name, None, processor.clone(), position);
+                    step.with_depends_on(depends_on.clone());
+                    step
+                })
+                .collect(),
+        );
+
+        let job = job.create(conn).unwrap();
+        for (name, _, status) in steps {
+            let step = job_steps::table
+                .filter(job_steps::job_id.eq(job.id))
+                .filter(job_steps::name.eq(name));
+
+            let _ = diesel::update(step)
+                .set(job_steps::status.eq(*status))
+                .execute(conn)
+                .unwrap();
+        }
+
+        diesel::update(&job)
+            .set(jobs::status.eq(status))
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// Create a job without any steps, running on the provided worker.
+    fn running_job(conn: &PgConnection, worker: &WorkerRegistration) -> Job {
+        let mut job = NewJob::new("Running", None);
+        job.status = Status::Running;
+        job.worker_id = Some(worker.id);
+        job.create(conn).unwrap()
+    }
+
+    /// Create a job without any steps, with the provided status, claimed by
+    /// the provided agent hub for the provided agent.
+    fn agent_job(
+        conn: &PgConnection,
+        hub: &WorkerRegistration,
+        agent: &WorkerRegistration,
+        status: Status,
+    ) -> Job {
+        let mut job = NewJob::new("Agent", None);
+        job.status = status;
+        job.worker_id = Some(hub.id);
+        job.agent_id = Some(agent.id);
+        job.create(conn).unwrap()
+    }
+
+    /// Register a remote agent, using a new session.
+    fn register_agent(conn: &PgConnection) -> WorkerRegistration {
+        let session = NewSession::new(vec![]).create(conn).unwrap();
+        WorkerRegistration::create_agent(conn, &session, &[], 1).unwrap()
+    }
+
+    /// Create a job without any steps, run by a step of the provided job.
+    fn child_job(conn: &PgConnection, parent: &Job) -> Job {
+        let mut job = NewJob::new("Child", None);
+        job.with_parent(parent);
+        job.create(conn).unwrap()
+    }
+
+    fn reload(job: &Job, conn: &PgConnection) -> Job {
+        jobs::table.find(job.id).first(conn).unwrap()
+    }
+
+    fn step_statuses(job: &Job, conn: &PgConnection) -> Vec<JobStepStatus> {
+        job.steps(conn)
+            .unwrap()
+            .into_iter()
+            .map(|step| step.status)
+            .collect()
+    }
+
+    #[test]
+    fn test_resume_failed_steps_and_dependents() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let job = failed_job(
+                &conn,
+                &[
+                    ("a", None, JobStepStatus::Ok),
+                    ("b", Some(vec![]), JobStepStatus::Failed),
+                    ("c", Some(vec!["a"]), JobStepStatus::Ok),
+                    ("d", Some(vec!["b"]), JobStepStatus::Cancelled),
+                    ("e", Some(vec!["d"]), JobStepStatus::Ok),
+                    ("f", Some(vec!["c"]), JobStepStatus::Cancelled),
+                    ("g", Some(vec!["a"]), JobStepStatus::Skipped),
+                ],
+            );
+
+            let job = job.resume(&conn).unwrap();
+
+            assert_eq!(job.status, Status::Pending);
+            assert_eq!(
+                step_statuses(&job, &conn),
+                vec![
+                    JobStepStatus::Ok,
+                    JobStepStatus::Initialized,
+                    JobStepStatus::Ok,
+                    JobStepStatus::Initialized,
+                    JobStepStatus::Initialized,
+                    JobStepStatus::Initialized,
+                    JobStepStatus::Skipped,
+                ]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_resume_without_failed_step() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let job = failed_job(&conn, &[("a", None, JobStepStatus::Cancelled)]);
+
+            assert!(job.resume(&conn).is_err());
+            assert_eq!(step_statuses(&job, &conn), vec![JobStepStatus::Cancelled]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_resume_job_that_did_not_fail() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let job = failed_job(&conn, &[("a", None, JobStepStatus::Failed)]);
+            let job: Job = diesel::update(&job)
+                .set(jobs::status.eq(Status::Ok))
+                .get_result(&conn)
+                .unwrap();
+
+            assert!(job.resume(&conn).is_err());
+            assert_eq!(step_statuses(&job, &conn), vec![JobStepStatus::Failed]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_find_stopped_for_agent() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let hub = WorkerRegistration::create(&conn, &[], 1).unwrap();
+            let agent = register_agent(&conn);
+            let other = register_agent(&conn);
+
+            let running = agent_job(&conn, &hub, &agent, Status::Running);
+            let cancelled = agent_job(&conn, &hub, &agent, Status::Running);
+            let finished = agent_job(&conn, &hub, &agent, Status::Ok);
+            let elsewhere = agent_job(&conn, &hub, &other, Status::Running);
+            NewJobCancellation::new(cancelled.id).create(&conn).unwrap();
+
+            let unknown = elsewhere.id + 1;
+            let ids = vec![running.id, cancelled.id, finished.id, elsewhere.id, unknown];
+
+            assert_eq!(
+                Job::find_stopped_for_agent(&agent, &ids, &conn).unwrap(),
+                vec![cancelled.id, finished.id, elsewhere.id, unknown]
+            );
+            assert_eq!(
+                Job::find_stopped_for_agent(&other, &ids, &conn).unwrap(),
+                vec![running.id, cancelled.id, finished.id, unknown]
+            );
+            assert!(Job::find_stopped_for_agent(&agent, &[], &conn)
+                .unwrap()
+                .is_empty());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_policy_action() {
+        use PolicyAction::{Cancel, Run, Skip};
+        use StepRunPolicy::{Always, OnFailure, OnSuccess};
+
+        let cases = vec![
+            // (policy, failed, cancelled, action)
+            (OnSuccess, false, false, Run),
+            (OnSuccess, true, false, Cancel),
+            (OnSuccess, false, true, Cancel),
+            (OnSuccess, true, true, Cancel),
+            (OnFailure, false, false, Skip),
+            (OnFailure, true, false, Run),
+            (OnFailure, false, true, Skip),
+            (OnFailure, true, true, Run),
+            (Always, false, false, Run),
+            (Always, true, false, Run),
+            (Always, false, true, Run),
+            (Always, true, true, Run),
+        ];
+
+        for (policy, failed, cancelled, action) in cases {
+            assert_eq!(
+                policy_action(policy, failed, cancelled),
+                action,
+                "{:?} (failed: {}, cancelled: {})",
+                policy,
+                failed,
+                cancelled
+            );
+        }
+    }
+
+    #[test]
+    fn test_run_unresolved_dependencies() {
+        let conn = connection();
+        let pool = Pool::builder()
+            .max_size(1)
+            .build_unchecked(ConnectionManager::new(database_url()));
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let job = job_with_steps(
+                &conn,
+                Status::Running,
+                &[
+                    ("a", None, JobStepStatus::Ok),
+                    ("b", Some(vec!["c"]), JobStepStatus::Initialized),
+                    ("c", Some(vec!["b"]), JobStepStatus::Initialized),
+                    ("d", Some(vec!["a"]), JobStepStatus::Initialized),
+                ],
+            );
+
+            let context = Context::new().unwrap();
+            assert!(job.run(&conn, &pool, &context, &Executor::Inline).is_err());
+
+            assert_eq!(
+                step_statuses(&job, &conn),
+                vec![
+                    JobStepStatus::Ok,
+                    JobStepStatus::Failed,
+                    JobStepStatus::Cancelled,
+                    JobStepStatus::Cancelled,
+                ]
+            );
+
+            let failure = job.failure(&conn).unwrap().unwrap();
+            assert_eq!(failure.step, "b");
+            assert!(failure
+                .error
+                .starts_with("job step dependencies cannot be resolved"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_recover_jobs_of_lost_agent() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let hub = WorkerRegistration::create(&conn, &[], 1).unwrap();
+            let lost = register_agent(&conn);
+            let other = register_agent(&conn);
+
+            let running = agent_job(&conn, &hub, &lost, Status::Running);
+            let paused = agent_job(&conn, &hub, &lost, Status::AwaitingApproval);
+            let finished = agent_job(&conn, &hub, &lost, Status::Ok);
+            let elsewhere = agent_job(&conn, &hub, &other, Status::Running);
+
+            let orphaned = Job::find_orphaned(&lost, &conn).unwrap();
+            assert_eq!(
+                orphaned.iter().map(|job| job.id).collect::<Vec<_>>(),
+                vec![running.id, paused.id]
+            );
+
+            for job in orphaned {
+                let job = job.recover(&conn).unwrap();
+                assert_eq!(job.status, Status::Failed);
+                assert_eq!(job.worker_id, None);
+                assert_eq!(job.agent_id, None);
+            }
+
+            assert_eq!(reload(&finished, &conn).status, Status::Ok);
+            assert_eq!(reload(&elsewhere, &conn).agent_id, Some(other.id));
+
+            let orphaned = Job::find_orphaned(&hub, &conn).unwrap();
+            assert_eq!(
+                orphaned.iter().map(|job| job.id).collect::<Vec<_>>(),
+                vec![elsewhere.id]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_recover_child_jobs_with_parent() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let worker = WorkerRegistration::create(&conn, &[], 1).unwrap();
+            let parent = running_job(&conn, &worker);
+            let child = child_job(&conn, &parent);
+
+            let orphaned = Job::find_orphaned(&worker, &conn).unwrap();
+            assert_eq!(
+                orphaned.iter().map(|job| job.id).collect::<Vec<_>>(),
+                vec![parent.id]
+            );
+
+            let _ = parent.recover(&conn).unwrap();
+
+            let child = reload(&child, &conn);
+            assert_eq!(child.status, Status::Failed);
+            assert_eq!(child.worker_id, None);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_find_next_pending_skips_child_jobs() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let worker = WorkerRegistration::create(&conn, &[], 1).unwrap();
+            let parent = running_job(&conn, &worker);
+            let child = child_job(&conn, &parent);
+            let _ = diesel::update(&child)
+                .set(jobs::status.eq(Status::Pending))
+                .execute(&conn)
+                .unwrap();
+
+            let next = Job::find_next_unlocked_pending(&conn, &worker, None).unwrap();
+            assert_ne!(next.map(|job| job.id), Some(child.id));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_create_child_within_concurrency_limit() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let worker = WorkerRegistration::create(&conn, &[], 1).unwrap();
+            let parent = running_job(&conn, &worker);
+
+            let mut task = NewTask::new("Deploy API", None, vec![]);
+            task.with_max_concurrent_jobs(Some(1));
+            let task = task.create(&conn).unwrap();
+
+            let child = NewJob::create_child(&conn, &task, &parent, vec![]).unwrap();
+            assert!(NewJob::create_child(&conn, &task, &parent, vec![]).is_err());
+
+            let _ = diesel::update(&child)
+                .set(jobs::status.eq(Status::Ok))
+                .execute(&conn)
+                .unwrap();
+
+            let _ = NewJob::create_child(&conn, &task, &parent, vec![]).unwrap();
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_create_child_within_lock_group() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let worker = WorkerRegistration::create(&conn, &[], 1).unwrap();
+            let task = |name: &str| {
+                let mut task = NewTask::new(name, None, vec![]);
+                task.with_lock_group(Some("production"));
+                task.create(&conn).unwrap()
+            };
+
+            let release = task("Release");
+            let deploy = task("Deploy API");
+            let migrate = task("Migrate Database");
+
+            // The lock group held by the parent job is shared with its child.
+            let parent = NewJob::create_from_task(&conn, &release, vec![], None, None).unwrap();
+            let parent: Job = diesel::update(&parent)
+                .set((
+                    jobs::status.eq(Status::Running),
+                    jobs::worker_id.eq(worker.id),
+                ))
+                .get_result(&conn)
+                .unwrap();
+
+            let child = NewJob::create_child(&conn, &deploy, &parent, vec![]).unwrap();
+
+            // Any other job holding the lock group blocks the child.
+            let other = running_job(&conn, &worker);
+            assert!(NewJob::create_child(&conn, &migrate, &other, vec![]).is_err());
+
+            let _ = diesel::update(&child)
+                .set(jobs::status.eq(Status::Ok))
+                .execute(&conn)
+                .unwrap();
+
+            let _ = diesel::update(&parent)
+                .set(jobs::status.eq(Status::Ok))
+                .execute(&conn)
+                .unwrap();
+
+            let _ = NewJob::create_child(&conn, &migrate, &other, vec![]).unwrap();
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_resume_child_job() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let parent = failed_job(&conn, &[("a", None, JobStepStatus::Failed)]);
+            let child = failed_job(&conn, &[("b", None, JobStepStatus::Failed)]);
+            let child: Job = diesel::update(&child)
+                .set(jobs::parent_id.eq(parent.id))
+                .get_result(&conn)
+                .unwrap();
+
+            assert!(child.resume(&conn).is_err());
+            assert_eq!(reload(&child, &conn).status, Status::Failed);
+            assert_eq!(step_statuses(&child, &conn), vec![JobStepStatus::Failed]);
+
+            Ok(())
+        });
+    }
+
+    /// Pause the provided job, as if its first step is awaiting approval.
+    fn paused_job(conn: &PgConnection, job: &Job) -> Job {
+        let step = job_steps::table
+            .filter(job_steps::job_id.eq(job.id))
+            .order(job_steps::position)
+            .select(job_steps::id)
+            .first::<i32>(conn)
+            .unwrap();
+
+        let _ = diesel::update(job_steps::table.find(step))
+            .set(job_steps::status.eq(JobStepStatus::AwaitingApproval))
+            .execute(conn)
+            .unwrap();
+
+        job.pause(conn).unwrap();
+        reload(job, conn)
+    }
+
+    #[test]
+    fn test_pause_keeps_job_claimed() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let worker = WorkerRegistration::create(&conn, &[], 1).unwrap();
+            let other = WorkerRegistration::create(&conn, &[], 1).unwrap();
+
+            let job = job_with_steps(
+                &conn,
+                Status::Running,
+                &[("a", None, JobStepStatus::Initialized)],
+            );
+            let job: Job = diesel::update(&job)
+                .set((
+                    jobs::status.eq(Status::Running),
+                    jobs::worker_id.eq(worker.id),
+                ))
+                .get_result(&conn)
+                .unwrap();
+
+            let job = paused_job(&conn, &job);
+            assert_eq!(job.status, Status::AwaitingApproval);
+            assert_eq!(job.worker_id, Some(worker.id));
+            assert!(job.is_paused(&conn).unwrap());
+            assert_eq!(Job::find_paused(&worker, &conn).unwrap(), vec![job.id]);
+
+            // Once the job continues, only the worker keeping its workspace
+            // claims it.
+            let _ = diesel::update(&job)
+                .set(jobs::status.eq(Status::Pending))
+                .execute(&conn)
+                .unwrap();
+
+            let next = Job::find_next_unlocked_pending(&conn, &other, None).unwrap();
+            assert_ne!(next.map(|job| job.id), Some(job.id));
+
+            let next = Job::find_next_unlocked_pending(&conn, &worker, Some(&other)).unwrap();
+            assert_ne!(next.map(|job| job.id), Some(job.id));
+
+            let next = Job::find_next_unlocked_pending(&conn, &worker, None).unwrap();
+            assert_eq!(next.map(|job| job.id), Some(job.id));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_recover_paused_job() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let worker = WorkerRegistration::create(&conn, &[], 1).unwrap();
+
+            let job = job_with_steps(
+                &conn,
+                Status::Running,
+                &[
+                    ("a", None, JobStepStatus::Initialized),
+                    ("b", None, JobStepStatus::Initialized),
+                ],
+            );
+            let job: Job = diesel::update(&job)
+                .set((
+                    jobs::status.eq(Status::Running),
+                    jobs::worker_id.eq(worker.id),
+                ))
+                .get_result(&conn)
+                .unwrap();
+
+            let job = paused_job(&conn, &job);
+            let orphaned = Job::find_orphaned(&worker, &conn).unwrap();
+            assert_eq!(
+                orphaned.iter().map(|job| job.id).collect::<Vec<_>>(),
+                vec![job.id]
+            );
+
+            let job = job.recover(&conn).unwrap();
+            assert_eq!(job.status, Status::Failed);
+            assert_eq!(job.worker_id, None);
+            assert_eq!(
+                step_statuses(&job, &conn),
+                vec![JobStepStatus::Failed, JobStepStatus::Cancelled]
+            );
+
+            let failure = job.failure(&conn).unwrap().unwrap();
+            assert!(failure.error.starts_with("workspace lost"));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_answer_prompt_continues_on_worker() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let worker = WorkerRegistration::create(&conn, &[], 1).unwrap();
+            let job = job_with_steps(
+                &conn,
+                Status::Running,
+                &[("Pick branch", None, JobStepStatus::AwaitingInput)],
+            );
+            let job: Job = diesel::update(&job)
+                .set(jobs::worker_id.eq(worker.id))
+                .get_result(&conn)
+                .unwrap();
+
+            let mut step = job.steps(&conn).unwrap().remove(0);
+            step = diesel::update(&step)
+                .set(job_steps::prompt_variable.eq("Branch"))
+                .get_result(&conn)
+                .unwrap();
+
+            job.pause(&conn).unwrap();
+            assert_eq!(reload(&job, &conn).status, Status::AwaitingInput);
+
+            step.answer_prompt(&conn, "main", None).unwrap();
+
+            let job = reload(&job, &conn);
+            assert_eq!(job.status, Status::Pending);
+            assert_eq!(job.worker_id, Some(worker.id));
+            assert!(job.is_paused(&conn).unwrap());
+            assert_eq!(step_statuses(&job, &conn), vec![JobStepStatus::Ok]);
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,645 @@
+//! A [`JobConnection`] is a single page of jobs matching a [`Filter`], as
+//! returned by the paginated `jobs` fields of the GraphQL API.
+//!
+//! Pages follow the Relay cursor connection specification. Jobs are ordered
+//! from newest to oldest, and the cursor of a job is derived from its ID, so
+//! paging through the jobs is not affected by new jobs being created.
+
+use crate::resources::{Job, JobStatus};
+use crate::schema::jobs;
+use crate::server::RequestState;
+use chrono::NaiveDateTime;
+use diesel::pg::Pg;
+use diesel::prelude::*;
+
+/// The number of jobs on a page, if no page size is requested.
+const DEFAULT_PAGE_SIZE: i32 = 50;
+
+/// The maximum number of jobs on a single page.
+const MAX_PAGE_SIZE: i32 = 250;
+
+/// The prefix of the cursor of a job, followed by the ID of the job.
+const CURSOR_PREFIX: &str = "job:";
+
+/// The conditions a job has to match to be included in a page.
+///
+/// All time ranges include their start, but exclude their end.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Filter {
+    /// Only include jobs created from the task with this ID.
+    pub(crate) task_id: Option<i32>,
+
+    /// Only include jobs with one of these statuses.
+    pub(crate) statuses: Option<Vec<JobStatus>>,
+
+    /// Only include jobs created on or after this moment.
+    pub(crate) created_after: Option<NaiveDateTime>,
+
+    /// Only include jobs created before this moment.
+    pub(crate) created_before: Option<NaiveDateTime>,
+
+    /// Only include jobs that finished running on or after this moment.
+    ///
+    /// Jobs that did not finish running are excluded, if this value is set.
+    pub(crate) finished_after: Option<NaiveDateTime>,
+
+    /// Only include jobs that finished running before this moment.
+    ///
+    /// Jobs that did not finish running are excluded, if this value is set.
+    pub(crate) finished_before: Option<NaiveDateTime>,
+
+    /// Only include jobs created by the session with this ID.
+    pub(crate) session_id: Option<i32>,
+}
+
+impl Filter {
+    /// Only include jobs created from the provided task.
+    pub(crate) fn with_task_id(mut self, task_id: i32) -> Self {
+        self.task_id = Some(task_id);
+        self
+    }
+
+    /// Count the number of jobs matching the filter.
+    pub(crate) fn count(&self, conn: &PgConnection) -> QueryResult<i64> {
+        self.query().count().get_result(conn)
+    }
+
+    fn query<'a>(&self) -> jobs::BoxedQuery<'a, Pg> {
+        let mut query = jobs::table.into_boxed();
+
+        if let Some(task_id) = self.task_id {
+            query = query.filter(jobs::task_reference.eq(task_id));
+        }
+
+        if let Some(statuses) = &self.statuses {
+            query = query.filter(jobs::status.eq_any(statuses.clone()));
+        }
+
+        if let Some(created_after) = self.created_after {
+            query = query.filter(jobs::created_at.ge(created_after));
+        }
+
+        if let Some(created_before) = self.created_before {
+            query = query.filter(jobs::created_at.lt(created_before));
+        }
+
+        if let Some(finished_after) = self.finished_after {
+            query = query.filter(jobs::finished_at.ge(finished_after));
+        }
+
+        if let Some(finished_before) = self.finished_before {
+            query = query.filter(jobs::finished_at.lt(finished_before));
+        }
+
+        if let Some(session_id) = self.session_id {
+            query = query.filter(jobs::session_id.eq(session_id));
+        }
+
+        query
+    }
+}
+
+/// The slice of the jobs matching a filter to include in a page, using the
+/// arguments defined by the Relay cursor connection specification.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Range {
+    /// The number of jobs following the `after` cursor.
+    first: Option<i32>,
+
+    /// The ID of the job after which the page starts.
+    after: Option<i32>,
+
+    /// The number of jobs preceding the `before` cursor.
+    last: Option<i32>,
+
+    /// The ID of the job before which the page ends.
+    before: Option<i32>,
+}
+
+impl Range {
+    /// Create a new range from the provided pagination arguments.
+    ///
+    /// An error is returned if a cursor is invalid, if a page size is out of
+    /// bounds, or if both `first` and `last` are provided.
+    pub(crate) fn new(
+        first: Option<i32>,
+        after: Option<&str>,
+        last: Option<i32>,
+        before: Option<&str>,
+    ) -> Result<Self, String> {
+        if first.is_some() && last.is_some() {
+            return Err("`first` and `last` cannot be combined".to_owned());
+        }
+
+        for size in first.iter().chain(last.iter()) {
+            if *size < 0 || *size > MAX_PAGE_SIZE {
+                return Err(format!("page size must be between 0 and {}", MAX_PAGE_SIZE));
+            }
+        }
+
+        Ok(Self {
+            first,
+            after: after.map(decode_cursor).transpose()?,
+            last,
+            before: before.map(decode_cursor).transpose()?,
+        })
+    }
+}
+
+/// A page of jobs, and the details needed to request the surrounding pages.
+#[derive(Clone, Debug)]
+pub(crate) struct JobConnection {
+    jobs: Vec<Job>,
+    filter: Filter,
+    has_next_page: bool,
+    has_previous_page: bool,
+}
+
+impl JobConnection {
+    /// Find the jobs matching the provided filter, within the provided range.
+    ///
+    /// If `last` is provided, the page contains the jobs directly preceding
+    /// the `before` cursor (or the oldest jobs, if there is no cursor).
+    /// Otherwise, the page contains the jobs directly following the `after`
+    /// cursor (or the newest jobs, if there is no cursor).
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn find(filter: Filter, range: Range, conn: &PgConnection) -> QueryResult<Self> {
+        let mut query = filter.query();
+
+        if let Some(after) = range.after {
+            query = query.filter(jobs::id.lt(after));
+        }
+
+        if let Some(before) = range.before {
+            query = query.filter(jobs::id.gt(before));
+        }
+
+        // Paging backwards starts at the `before` cursor, so the jobs are
+        // loaded oldest first, and reversed afterwards.
+        let (size, backwards) = match (range.first, range.last) {
+            (None, Some(last)) => (last, true),
+            (first, _) => (first.unwrap_or(DEFAULT_PAGE_SIZE), false),
+        };
+
+        query = if backwards {
+            query.order(jobs::id.asc())
+        } else {
+            query.order(jobs::id.desc())
+        };
+
+        // One more job than requested is loaded, to know if there are more
+        // jobs beyond this page.
+        let mut nodes: Vec<Job> = query.limit(i64::from(size) + 1).load(conn)?;
+        let more = nodes.len() > size as usize;
+        nodes.truncate(size as usize);
+
+        let (has_next_page, has_previous_page) = if backwards {
+            nodes.reverse();
+
+            let next = match range.before {
+                None => false,
+                Some(before) => exists(filter.query().filter(jobs::id.le(before)), conn)?,
+            };
+
+            (next, more)
+        } else {
+            let previous = match range.after {
+                None => false,
+                Some(after) => exists(filter.query().filter(jobs::id.ge(after)), conn)?,
+            };
+
+            (more, previous)
+        };
+
+        Ok(Self {
+            jobs: nodes,
+            filter,
+            has_next_page,
+            has_previous_page,
+        })
+    }
+}
+
+/// Returns `true` if the query matches any job.
+fn exists(query: jobs::BoxedQuery<'_, Pg>, conn: &PgConnection) -> QueryResult<bool> {
+    query.first::<Job>(conn).optional().map(|job| job.is_some())
+}
+
+/// The opaque cursor pointing at the provided job.
+fn encode_cursor(job: &Job) -> String {
+    format!("{}{}", CURSOR_PREFIX, job.id)
+}
+
+/// The ID of the job the provided cursor points at.
+fn decode_cursor(cursor: &str) -> Result<i32, String> {
+    Some(cursor)
+        .filter(|cursor| cursor.starts_with(CURSOR_PREFIX))
+        .and_then(|cursor| cursor[CURSOR_PREFIX.len()..].parse().ok())
+        .ok_or_else(|| format!("invalid cursor: {}", cursor))
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use juniper::{object, FieldResult, GraphQLInputObject, GraphQLObject, ID};
+    use std::convert::TryFrom;
+
+    /// The conditions a job has to match to be returned.
+    ///
+    /// All conditions have to match. Time ranges include their start, but
+    /// exclude their end.
+    #[derive(Clone, Debug, Default, GraphQLInputObject)]
+    pub(crate) struct JobFilterInput {
+        /// Only return jobs created from the task with this `id`.
+        pub(crate) task_id: Option<ID>,
+
+        /// Only return jobs with one of these statuses.
+        pub(crate) statuses: Option<Vec<JobStatus>>,
+
+        /// Only return jobs created on or after this moment.
+        pub(crate) created_after: Option<DateTime<Utc>>,
+
+        /// Only return jobs created before this moment.
+        pub(crate) created_before: Option<DateTime<Utc>>,
+
+        /// Only return jobs that finished running on or after this moment.
+        pub(crate) finished_after: Option<DateTime<Utc>>,
+
+        /// Only return jobs that finished running before this moment.
+        pub(crate) finished_before: Option<DateTime<Utc>>,
+
+        /// Only return jobs created by the session with this `id`.
+        pub(crate) session_id: Option<ID>,
+    }
+
+    /// Information about the current page, used to request the next or
+    /// previous page.
+    #[derive(Clone, Debug, GraphQLObject)]
+    pub(crate) struct PageInfo {
+        /// Whether there are older jobs after this page.
+        has_next_page: bool,
+
+        /// Whether there are newer jobs before this page.
+        has_previous_page: bool,
+
+        /// The cursor of the first job on this page, if any.
+        start_cursor: Option<String>,
+
+        /// The cursor of the last job on this page, if any.
+        end_cursor: Option<String>,
+    }
+
+    /// A job on a page, and the cursor pointing at it.
+    #[derive(Clone, Debug)]
+    pub(crate) struct JobEdge(Job);
+
+    #[object(Context = RequestState)]
+    impl JobEdge {
+        /// The opaque cursor pointing at this job, to be used as the `after`
+        /// or `before` argument when requesting another page.
+        fn cursor() -> String {
+            encode_cursor(&self.0)
+        }
+
+        /// The job.
+        fn node() -> &Job {
+            &self.0
+        }
+    }
+
+    /// A page of jobs, ordered from newest to oldest.
+    #[object(Context = RequestState)]
+    impl JobConnection {
+        /// The jobs on this page, with their cursors.
+        fn edges() -> Vec<JobEdge> {
+            self.jobs.iter().cloned().map(JobEdge).collect()
+        }
+
+        /// The jobs on this page.
+        fn nodes() -> &[Job] {
+            &self.jobs
+        }
+
+        /// Information about this page.
+        fn page_info() -> PageInfo {
+            PageInfo {
+                has_next_page: self.has_next_page,
+                has_previous_page: self.has_previous_page,
+                start_cursor: self.jobs.first().map(encode_cursor),
+                end_cursor: self.jobs.last().map(encode_cursor),
+            }
+        }
+
+        /// The total number of jobs matching the filter, on all pages.
+        fn total_count(context: &RequestState) -> FieldResult<i32> {
+            let count = self.filter.count(&context.conn)?;
+
+            i32::try_from(count).map_err(Into::into)
+        }
+    }
+
+    impl TryFrom<&JobFilterInput> for Filter {
+        type Error = String;
+
+        fn try_from(input: &JobFilterInput) -> Result<Self, Self::Error> {
+            let id = |id: &Option<ID>| {
+                id.as_ref()
+                    .map(|id| id.parse::<i32>().map_err(|err| err.to_string()))
+                    .transpose()
+            };
+
+            let time = |time: Option<DateTime<Utc>>| time.map(|t| t.naive_utc());
+
+            Ok(Self {
+                task_id: id(&input.task_id)?,
+                statuses: input.statuses.clone(),
+                created_after: time(input.created_after),
+                created_before: time(input.created_before),
+                finished_after: time(input.finished_after),
+                finished_before: time(input.finished_before),
+                session_id: id(&input.session_id)?,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NewSession, Session};
+    use crate::resources::{NewJob, NewTask};
+    use chrono::NaiveDate;
+    use diesel::result::Error as DieselError;
+
+    fn connection() -> PgConnection {
+        let url = std::env::var("DATABASE_URL")
+            .unwrap_or_else(|_| "postgres://postgres@localhost".to_owned());
+
+        PgConnection::establish(&url).unwrap()
+    }
+
+    /// Create a job with the provided status, on behalf of the provided
+    /// session.
+    fn job(conn: &PgConnection, session: &Session, status: JobStatus) -> Job {
+        let mut job = NewJob::new("Paged", None);
+        job.status = status;
+        job.session_id = Some(session.id);
+        job.create(conn).unwrap()
+    }
+
+    /// Create a number of pending jobs on behalf of a new session, returning
+    /// the filter matching only those jobs, and the jobs ordered from oldest
+    /// to newest.
+    fn session_jobs(conn: &PgConnection, count: usize) -> (Filter, Vec<Job>) {
+        let session = NewSession::new(vec![]).create(conn).unwrap();
+        let filter = Filter {
+            session_id: Some(session.id),
+            ..Filter::default()
+        };
+
+        let jobs = (0..count)
+            .map(|_| job(conn, &session, JobStatus::Pending))
+            .collect();
+
+        (filter, jobs)
+    }
+
+    /// Find a page of jobs, returning the IDs of the jobs on the page, and
+    /// whether there is a next and a previous page.
+    fn page(
+        conn: &PgConnection,
+        filter: &Filter,
+        (first, after): (Option<i32>, Option<&Job>),
+        (last, before): (Option<i32>, Option<&Job>),
+    ) -> (Vec<i32>, bool, bool) {
+        let after = after.map(encode_cursor);
+        let before = before.map(encode_cursor);
+        let range = Range::new(
+            first,
+            after.as_ref().map(String::as_str),
+            last,
+            before.as_ref().map(String::as_str),
+        )
+        .unwrap();
+
+        let page = JobConnection::find(filter.clone(), range, conn).unwrap();
+        let ids = page.jobs.iter().map(|job| job.id).collect();
+
+        (ids, page.has_next_page, page.has_previous_page)
+    }
+
+    /// Find the IDs of all jobs matching the provided filter.
+    fn find(conn: &PgConnection, filter: Filter) -> Vec<i32> {
+        page(conn, &filter, (None, None), (None, None)).0
+    }
+
+    fn at(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd(2019, 10, day).and_hms(0, 0, 0)
+    }
+
+    #[test]
+    fn test_decode_cursor() {
+        assert_eq!(decode_cursor("job:12"), Ok(12));
+        assert!(decode_cursor("12").is_err());
+        assert!(decode_cursor("job:").is_err());
+        assert!(decode_cursor("task:12").is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(
+            Range::new(Some(10), Some("job:5"), None, None),
+            Ok(Range {
+                first: Some(10),
+                after: Some(5),
+                last: None,
+                before: None,
+            })
+        );
+
+        assert!(Range::new(Some(10), None, Some(10), None).is_err());
+        assert!(Range::new(Some(-1), None, None, None).is_err());
+        assert!(Range::new(None, None, Some(MAX_PAGE_SIZE + 1), None).is_err());
+        assert!(Range::new(None, None, None, Some("nope")).is_err());
+    }
+
+    #[test]
+    fn test_find_forwards() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let (filter, jobs) = session_jobs(&conn, 5);
+            let id = |index: usize| jobs[index].id;
+
+            assert_eq!(
+                page(&conn, &filter, (None, None), (None, None)),
+                (vec![id(4), id(3), id(2), id(1), id(0)], false, false)
+            );
+            assert_eq!(
+                page(&conn, &filter, (Some(2), None), (None, None)),
+                (vec![id(4), id(3)], true, false)
+            );
+            assert_eq!(
+                page(&conn, &filter, (Some(2), Some(&jobs[3])), (None, None)),
+                (vec![id(2), id(1)], true, true)
+            );
+            assert_eq!(
+                page(&conn, &filter, (Some(2), Some(&jobs[1])), (None, None)),
+                (vec![id(0)], false, true)
+            );
+            assert_eq!(
+                page(&conn, &filter, (Some(2), Some(&jobs[0])), (None, None)),
+                (vec![], false, true)
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_find_backwards() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let (filter, jobs) = session_jobs(&conn, 5);
+            let id = |index: usize| jobs[index].id;
+
+            assert_eq!(
+                page(&conn, &filter, (None, None), (Some(2), None)),
+                (vec![id(1), id(0)], false, true)
+            );
+            assert_eq!(
+                page(&conn, &filter, (None, None), (Some(2), Some(&jobs[1]))),
+                (vec![id(3), id(2)], true, true)
+            );
+            assert_eq!(
+                page(&conn, &filter, (None, None), (Some(2), Some(&jobs[3]))),
+                (vec![id(4)], true, false)
+            );
+            assert_eq!(
+                page(&conn, &filter, (None, None), (Some(2), Some(&jobs[4]))),
+                (vec![], true, false)
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_find_filters() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let session = NewSession::new(vec![]).create(&conn).unwrap();
+            let other = NewSession::new(vec![]).create(&conn).unwrap();
+            let task = NewTask::new("Paged", None, vec![]).create(&conn).unwrap();
+
+            let ok = job(&conn, &session, JobStatus::Ok);
+            let failed = job(&conn, &session, JobStatus::Failed);
+            let running = job(&conn, &session, JobStatus::Running);
+            let elsewhere = job(&conn, &other, JobStatus::Ok);
+
+            let running: Job = diesel::update(&running)
+                .set(jobs::task_reference.eq(task.id))
+                .get_result(&conn)?;
+
+            let session = Filter {
+                session_id: Some(session.id),
+                ..Filter::default()
+            };
+
+            assert_eq!(
+                find(&conn, session.clone()),
+                vec![running.id, failed.id, ok.id]
+            );
+            assert_eq!(
+                find(&conn, session.clone().with_task_id(task.id)),
+                vec![running.id]
+            );
+            assert_eq!(
+                find(
+                    &conn,
+                    Filter {
+                        statuses: Some(vec![JobStatus::Ok, JobStatus::Failed]),
+                        ..session.clone()
+                    }
+                ),
+                vec![failed.id, ok.id]
+            );
+            assert_eq!(
+                find(
+                    &conn,
+                    Filter {
+                        session_id: Some(other.id),
+                        ..Filter::default()
+                    }
+                ),
+                vec![elsewhere.id]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_find_time_filters() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let (filter, jobs) = session_jobs(&conn, 3);
+            for (job, day) in jobs.iter().zip(1..) {
+                let _ = diesel::update(job)
+                    .set(jobs::created_at.eq(at(day)))
+                    .execute(&conn)?;
+            }
+
+            let created = |after: Option<u32>, before: Option<u32>| {
+                let filter = Filter {
+                    created_after: after.map(at),
+                    created_before: before.map(at),
+                    ..filter.clone()
+                };
+
+                find(&conn, filter)
+            };
+
+            assert_eq!(created(Some(2), None), vec![jobs[2].id, jobs[1].id]);
+            assert_eq!(created(None, Some(2)), vec![jobs[0].id]);
+            assert_eq!(created(Some(1), Some(3)), vec![jobs[1].id, jobs[0].id]);
+
+            // Jobs are marked as finished when they get a final status, at the
+            // moment the transaction started.
+            let finished: Job = diesel::update(&jobs[1])
+                .set(jobs::status.eq(JobStatus::Ok))
+                .get_result(&conn)?;
+
+            let moment = finished.finished_at.unwrap();
+            let finished_within = |after: NaiveDateTime, before: NaiveDateTime| {
+                let filter = Filter {
+                    finished_after: Some(after),
+                    finished_before: Some(before),
+                    ..filter.clone()
+                };
+
+                find(&conn, filter)
+            };
+
+            let second = chrono::Duration::seconds(1);
+            assert_eq!(finished_within(moment, moment + second), vec![finished.id]);
+            assert!(finished_within(moment - second, moment).is_empty());
+            assert!(finished_within(moment + second, moment + second * 2).is_empty());
+
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,114 @@
+//! An [`Executor`] runs the processors of the steps of a job, either on the
+//! worker running the job, or on the remote agent the job was claimed for.
+//!
+//! On the worker, each processor runs in a child process of its own, so that a
+//! crashing processor does not take down the worker. See the [`isolation`]
+//! module for details.
+//!
+//! Processors provided by the server itself, such as the [`RunTask`]
+//! processor, always run on the worker, as they need access to the database.
+//!
+//! [`RunTask`]: crate::processor::run_task::RunTask
+//! [`isolation`]: crate::isolation
+
+use crate::isolation;
+use crate::models::{AgentDispatch, NewAgentDispatch};
+use crate::resources::JobStep;
+use crate::schema::worker_registrations;
+use crate::server::DatabasePool;
+use crate::Processor;
+use automaat_core::Context;
+use chrono::Utc;
+use diesel::dsl::exists;
+use diesel::prelude::*;
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+/// The interval at which a dispatched processor is checked for its result.
+const DISPATCH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Runs the processors of job steps.
+#[derive(Clone, Debug)]
+pub(crate) enum Executor {
+    /// Run the processors in child processes of the current worker.
+    Local,
+
+    /// Dispatch the processors to the remote agent with the provided
+    /// registration ID, and wait for the agent to report their results.
+    Agent(DatabasePool, i32),
+
+    /// Run the processors on the current thread, as a test binary cannot run
+    /// processors in child processes of its own.
+    #[cfg(test)]
+    Inline,
+}
+
+impl Executor {
+    /// Run the provided processor for a job step.
+    ///
+    /// A dispatched processor has to finish before the deadline of the
+    /// provided context. If the context is cancelled, or times out, the
+    /// dispatch is removed, and any result reported afterwards is ignored.
+    pub(crate) fn run(
+        &self,
+        step: &JobStep,
+        processor: &Processor,
+        context: &Context,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        match self {
+            Executor::Local => isolation::run(processor, context),
+            Executor::Agent(pool, agent_id) => dispatch(pool, *agent_id, step, processor, context),
+            #[cfg(test)]
+            Executor::Inline => processor.run(context),
+        }
+    }
+}
+
+fn dispatch(
+    pool: &DatabasePool,
+    agent_id: i32,
+    step: &JobStep,
+    processor: &Processor,
+    context: &Context,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let lost = "agent lost: the agent running this step stopped responding";
+
+    let dispatch = {
+        let conn = pool.get()?;
+        let agent = worker_registrations::table.find(agent_id);
+        if !diesel::select(exists(agent)).get_result(&conn)? {
+            return Err(lost.into());
+        }
+
+        let deadline_at = context
+            .remaining()
+            .and_then(|remaining| chrono::Duration::from_std(remaining).ok())
+            .map(|remaining| Utc::now().naive_utc() + remaining);
+
+        let processor = serde_json::to_string(processor)?;
+        NewAgentDispatch::new(agent_id, step, &processor, deadline_at).create(&conn)?
+    };
+
+    loop {
+        thread::sleep(DISPATCH_POLL_INTERVAL);
+
+        let conn = pool.get()?;
+        let dispatch = match AgentDispatch::find(dispatch.id, &conn)? {
+            Some(dispatch) => dispatch,
+            None => return Err(lost.into()),
+        };
+
+        if dispatch.finished_at.is_some() {
+            return match dispatch.error {
+                Some(error) => Err(error.into()),
+                None => Ok(dispatch.output),
+            };
+        }
+
+        if context.is_cancelled() || context.is_timed_out() {
+            dispatch.delete(&conn)?;
+            return Err("stopped waiting for the agent to finish running the step".into());
+        }
+    }
+}
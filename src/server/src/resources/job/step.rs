@@ -10,10 +10,16 @@
 //! [`Processor`]: crate::Processor
 //! [`Step`]: crate::resources::Step
 
-use crate::models::GlobalVariable;
-use crate::resources::{Job, Step};
-use crate::schema::job_steps;
-use crate::{server::RequestState, Processor};
+use crate::models::{GlobalVariable, Session};
+use crate::processor::{prompt::Prompt, run_task::RunTask, wait_for_approval::WaitForApproval};
+use crate::resources::{
+    Executor, ForEach, ForEachFailureMode, Job, JobStatus, JobStepAttempt, JobStepLog,
+    JobStepLogSink, NewJob, NewJobStepAttempt, NewJobVariable, RetryPolicy, Step, StepRunPolicy,
+    MAX_CHILD_JOB_DEPTH,
+};
+use crate::schema::{job_steps, jobs};
+use crate::server::{DatabasePool, RequestState};
+use crate::Processor;
 use automaat_core::Context;
 use chrono::prelude::*;
 use chrono::NaiveDateTime;
@@ -23,10 +29,28 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::{AsRef, TryFrom};
 use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
 use tera::{Context as TContext, Tera};
 
+pub(crate) mod attempt;
+pub(crate) mod log;
+
 const INVALID_SERIALIZED_DATA: &str = "unexpected serialized data stored in database";
 
+/// The interval at which a step waiting to be retried checks if its job got
+/// cancelled, or timed out.
+const RETRY_WAIT_INTERVAL: Duration = Duration::from_millis(500);
+
+const WORKER_LOST: &str = "worker lost: the worker running this step stopped responding";
+
+/// The output of a step that was awaiting approval or input, while the worker
+/// keeping the workspace of its job stopped.
+const WORKSPACE_LOST: &str =
+    "workspace lost: the worker keeping the workspace of this job stopped before the job continued";
+
 /// Contains all the data that can be used in processor templates.
 #[derive(Serialize)]
 struct TemplateData<'a> {
@@ -49,6 +73,17 @@ struct TemplateData<'a> {
     /// If two steps have the same name, the one that ran last will occupy that
     /// key with its output value.
     output: HashMap<&'a str, &'a str>,
+
+    /// The details of the step that failed, if any step of the job failed
+    /// before this step ran.
+    failure: Option<&'a Failure>,
+
+    /// The item for which the processor runs, if the step runs once for every
+    /// item in a JSON array.
+    item: Option<serde_json::Value>,
+
+    /// The index of the item for which the processor runs, if any.
+    index: Option<usize>,
 }
 
 /// Contains all exposed system variables.
@@ -59,8 +94,97 @@ struct ContextVariables<'a> {
     workspace_path: &'a str,
 }
 
+/// The work a job step has to do, once its templates are formalized.
+enum Plan {
+    /// The condition of the step is not met, so the step is skipped.
+    Skip,
+
+    /// Run the processor once.
+    Run(Processor),
+
+    /// Run the processor once for every item, each processor formalized using
+    /// its own item.
+    ForEach(ForEach, Vec<Processor>),
+}
+
+/// An event of a processor running for one of the items of a step, sent by the
+/// thread running the processor.
+enum ItemEvent {
+    /// An attempt to run the processor for the item at the index started.
+    Started(usize, i32),
+
+    /// An attempt to run the processor for the item at the index finished,
+    /// with the error of the attempt, if it failed.
+    Finished(usize, i32, Option<String>),
+
+    /// The processor finished running for the item at the index, returning
+    /// the result of its last attempt.
+    Done(usize, Result<Option<String>, String>),
+}
+
+/// The details of a failed job step, exposed to any steps that run after the
+/// failure.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Failure {
+    /// The name of the step that failed.
+    pub(crate) step: String,
+
+    /// The error with which the step failed.
+    pub(crate) error: String,
+}
+
+/// A moment by which a running job step has to finish, imposed by either the
+/// step itself, or the job to which the step belongs.
+#[derive(Clone, Debug)]
+pub(crate) struct Timeout {
+    deadline: Instant,
+    message: String,
+}
+
+impl Timeout {
+    /// The timeout of a job, starting now, if the job is limited in time.
+    ///
+    /// A child job is also limited by the deadline of the parent job step
+    /// running it, if any, whichever passes first.
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn for_job(seconds: Option<i32>, parent_deadline: Option<Instant>) -> Option<Self> {
+        let job = seconds.map(|seconds| Self {
+            deadline: Instant::now() + Duration::from_secs(seconds as u64),
+            message: format!("timeout: the job did not finish within {} seconds", seconds),
+        });
+
+        let parent = parent_deadline.map(|deadline| Self {
+            deadline,
+            message: "timeout: the parent job step running this job timed out".to_owned(),
+        });
+
+        match (job, parent) {
+            (Some(job), Some(parent)) if parent.deadline < job.deadline => Some(parent),
+            (None, parent) => parent,
+            (job, _) => job,
+        }
+    }
+
+    /// Returns `true` if the deadline of the timeout has passed.
+    pub(crate) fn has_passed(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// The timeout of a job step, starting now.
+    #[allow(clippy::cast_sign_loss)]
+    fn for_step(seconds: i32) -> Self {
+        Self {
+            deadline: Instant::now() + Duration::from_secs(seconds as u64),
+            message: format!(
+                "timeout: the step did not finish within {} seconds",
+                seconds
+            ),
+        }
+    }
+}
+
 /// The status of the job step.
-#[derive(Clone, Copy, Debug, DbEnum, GraphQLEnum, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, DbEnum, GraphQLEnum, Serialize, Deserialize)]
 #[PgType = "JobStepStatus"]
 #[graphql(name = "JobStepStatus")]
 pub enum Status {
@@ -73,16 +197,40 @@ pub enum Status {
     /// The job step is currently running and will either fail, or succeed.
     Running,
 
+    /// The job step is waiting for a session to approve or reject it, after
+    /// which the job continues.
+    AwaitingApproval,
+
+    /// The job step is waiting for a session to answer its prompt, after
+    /// which the job continues.
+    AwaitingInput,
+
     /// The job step failed to run due to an unforeseen error.
     Failed,
 
     /// The job step was cancelled, and will not run anymore.
     Cancelled,
 
+    /// The job step did not run, because its condition was not met.
+    Skipped,
+
     /// The job step ran and succeeded.
     Ok,
 }
 
+/// The decision made on a job step awaiting approval.
+#[derive(Clone, Copy, Debug, DbEnum, GraphQLEnum, Serialize, Deserialize)]
+#[PgType = "ApprovalDecision"]
+#[graphql(name = "ApprovalDecision")]
+pub(crate) enum ApprovalDecision {
+    /// The step was approved, and succeeded.
+    Approved,
+
+    /// The step was rejected, either by a session, or because the approval
+    /// request expired, and failed.
+    Rejected,
+}
+
 /// The model representing a job step stored in the database.
 #[derive(
     Clone, Debug, Deserialize, Serialize, AsChangeset, Associations, Identifiable, Queryable,
@@ -100,6 +248,46 @@ pub(crate) struct JobStep {
     pub(crate) status: Status,
     pub(crate) output: Option<String>,
     pub(crate) job_id: i32,
+
+    /// The number of seconds the step is allowed to run, if limited.
+    pub(crate) timeout_seconds: Option<i32>,
+    pub(crate) retry_policy: Option<serde_json::Value>,
+
+    /// The condition that has to be met for the step to run, if any.
+    pub(crate) when: Option<String>,
+    pub(crate) run_policy: StepRunPolicy,
+
+    /// The names of the steps this step depends on, or `None` if the step
+    /// depends on the step positioned before it.
+    pub(crate) depends_on: Option<Vec<String>>,
+
+    /// The serialized [`ForEach`] configuration, if the processor of the step
+    /// runs once for every item in a JSON array. Use [`JobStep::for_each`] to
+    /// deserialize it.
+    pub(crate) for_each: Option<serde_json::Value>,
+
+    /// The moment at which a step awaiting approval is rejected, if no
+    /// decision was made before then.
+    pub(crate) approval_expires_at: Option<NaiveDateTime>,
+    pub(crate) approval_decision: Option<ApprovalDecision>,
+
+    /// The ID of the session that approved or rejected the step, if any.
+    pub(crate) approval_decided_by: Option<i32>,
+    pub(crate) approval_decided_at: Option<NaiveDateTime>,
+    pub(crate) approval_comment: Option<String>,
+
+    /// The key of the job variable in which the answer to the prompt of the
+    /// step is stored, if the step prompts for input.
+    pub(crate) prompt_variable: Option<String>,
+    pub(crate) prompt_question: Option<String>,
+
+    /// The values one of which has to be picked as the answer to the prompt,
+    /// or `None` if any answer is accepted.
+    pub(crate) prompt_options: Option<Vec<String>>,
+
+    /// The ID of the session that answered the prompt, if any.
+    pub(crate) prompt_answered_by: Option<i32>,
+    pub(crate) prompt_answered_at: Option<NaiveDateTime>,
 }
 
 impl JobStep {
@@ -114,48 +302,672 @@ impl JobStep {
         serde_json::from_value(self.processor.clone()).ok()
     }
 
+    /// Returns the retry policy of this job step, if any.
+    ///
+    /// Similar to [`JobStep::processor`], `None` is also returned if the
+    /// policy could not be deserialized.
+    pub(crate) fn retry_policy(&self) -> Option<RetryPolicy> {
+        self.retry_policy
+            .clone()
+            .and_then(|policy| serde_json::from_value(policy).ok())
+    }
+
+    /// Returns the for each configuration of this job step, if any.
+    ///
+    /// Similar to [`JobStep::processor`], `None` is also returned if the
+    /// configuration could not be deserialized.
+    pub(crate) fn for_each(&self) -> Option<ForEach> {
+        self.for_each
+            .clone()
+            .and_then(|for_each| serde_json::from_value(for_each).ok())
+    }
+
+    pub(crate) fn attempts(&self, conn: &PgConnection) -> QueryResult<Vec<JobStepAttempt>> {
+        use crate::schema::job_step_attempts::dsl::*;
+
+        JobStepAttempt::belonging_to(self)
+            .order(id.asc())
+            .load(conn)
+    }
+
+    /// The output written by the processor of the step while it ran, skipping
+    /// the provided number of chunks.
+    pub(crate) fn logs(
+        &self,
+        offset: i64,
+        limit: Option<i64>,
+        conn: &PgConnection,
+    ) -> QueryResult<Vec<JobStepLog>> {
+        JobStepLog::find_for_step(self, offset, limit, conn)
+    }
+
     pub(crate) fn job(&self, conn: &PgConnection) -> QueryResult<Job> {
         use crate::schema::jobs::dsl::*;
 
         jobs.filter(id.eq(self.job_id)).first(conn)
     }
 
+    /// Run the step processor, and store the result.
+    ///
+    /// The output of the step is added to the provided output map, so that it
+    /// can be used by any steps that run after this one.
+    ///
+    /// If the context is cancelled while the step is running, the step is
+    /// marked as cancelled, instead of failed.
+    ///
+    /// If the step fails, it is retried according to its retry policy, if it
+    /// has one.
+    ///
+    /// If the step has a for each configuration, the processor runs once for
+    /// every item, and the output of the step is a JSON array containing the
+    /// output of each item.
+    ///
+    /// If the step has a condition that is not met, the processor does not
+    /// run, and the step is marked as skipped.
+    ///
+    /// The processor runs using the provided executor, which either runs it
+    /// on the current worker, or dispatches it to a remote agent.
+    ///
+    /// If the processor of the step is a [`RunTask`] processor, the referenced
+    /// task runs as a child job, using connections from the provided pool to
+    /// run its steps.
+    ///
+    /// If the processor of the step is a [`WaitForApproval`] processor, the
+    /// step is marked as awaiting approval, and the job is expected to pause
+    /// once its other running steps finished. The same applies to a [`Prompt`]
+    /// processor, for which the step is marked as awaiting input instead.
+    ///
+    /// If a step of the job failed before this step runs, the details of
+    /// that failure are provided, so that they can be used in the templates
+    /// of this step.
+    ///
+    /// The step has to finish before its own timeout, and before the provided
+    /// timeout of its job, whichever passes first. If it does not, the step is
+    /// marked as failed, with the timeout as its output. The timeout includes
+    /// the time spent retrying the step.
     pub(crate) fn run(
         &mut self,
         conn: &PgConnection,
+        pool: &DatabasePool,
         context: &Context,
-        mut output: HashMap<String, String>,
-    ) -> Result<HashMap<String, String>, Box<dyn Error>> {
+        executor: &Executor,
+        job_timeout: Option<&Timeout>,
+        failure: Option<&Failure>,
+        output: &mut HashMap<String, String>,
+    ) -> Result<(), Box<dyn Error>> {
         self.start(conn)?;
 
+        let timeout = match (self.timeout_seconds.map(Timeout::for_step), job_timeout) {
+            (Some(step), Some(job)) if job.deadline < step.deadline => Some(job.clone()),
+            (None, job) => job.cloned(),
+            (step, _) => step,
+        };
+
+        context.set_deadline(timeout.as_ref().map(|t| t.deadline));
+        context.set_log_sink(Some(Arc::new(JobStepLogSink::new(pool.clone(), self))));
+
         // TODO: this needs to go in a transaction, and the changes reverted if
         // they can't be saved... Also goes for many other places.
 
-        let result = match self.formalize_processor(&mut output, context, conn) {
-            Ok(p) => p.run(context),
-            Err(err) => Err(format!("job processor cannot be deserialized: {}", err).into()),
+        // If the timeout of the job already passed, the processor is not run
+        // at all.
+        let result = match self.prepare(output, failure, context, conn) {
+            _ if context.is_timed_out() => Ok(None),
+            Ok(Plan::Run(Processor::WaitForApproval(p))) => {
+                self.await_approval(conn, &p)?;
+                return Ok(());
+            }
+            Ok(Plan::Run(Processor::Prompt(p))) => match p.options() {
+                Ok(options) => {
+                    self.await_input(conn, p, options)?;
+                    return Ok(());
+                }
+                Err(err) => Err(err),
+            },
+            Ok(Plan::Run(p)) => self.run_attempts(conn, pool, context, executor, &p),
+            Ok(Plan::ForEach(for_each, p)) => {
+                self.run_for_each(conn, context, executor, &for_each, p)
+            }
+            Ok(Plan::Skip) => {
+                self.finished(conn, Status::Skipped, None)?;
+                return Ok(());
+            }
+            Err(err) => Err(err),
+        };
+
+        // Removing the log sink waits for the output of the processor to be
+        // stored, before the result of the step is.
+        context.set_log_sink(None);
+
+        let timed_out = match timeout {
+            Some(timeout) if context.is_timed_out() => Some(timeout.message),
+            _ => None,
         };
 
-        match result {
-            Ok(out) => {
+        match (result, timed_out) {
+            (Err(err), _) if context.is_cancelled() => {
+                let message = format!(
+                    "The job was cancelled while this step was running.\n\n{}",
+                    err
+                );
+                self.finished(conn, Status::Cancelled, Some(message))?;
+                Err(err)
+            }
+            (result, Some(message)) => {
+                let message = match result {
+                    Ok(_) => message,
+                    Err(err) => format!("{}\n\n{}", message, err),
+                };
+
+                self.finished(conn, Status::Failed, Some(message.clone()))?;
+                Err(message.into())
+            }
+            (Ok(out), None) => {
                 self.finished(conn, Status::Ok, out.clone())?;
 
                 let _ = output.insert(self.name.to_owned(), out.unwrap_or_default());
-                Ok(output)
+                Ok(())
             }
-            Err(err) => {
+            (Err(err), None) => {
                 self.finished(conn, Status::Failed, Some(err.to_string()))?;
                 Err(err)
             }
         }
     }
 
+    /// Run the processor until an attempt succeeds, or until the retry policy
+    /// of the step stops retrying. Every attempt is recorded.
+    ///
+    /// Attempts are not retried once the context is cancelled, or timed out.
+    fn run_attempts(
+        &self,
+        conn: &PgConnection,
+        pool: &DatabasePool,
+        context: &Context,
+        executor: &Executor,
+        processor: &Processor,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let policy = self.retry_policy();
+        let mut attempt = 1;
+
+        loop {
+            let mut record = NewJobStepAttempt::new(self, attempt).create(conn)?;
+            let result = match processor {
+                Processor::RunTask(run_task) => {
+                    self.run_task(conn, pool, context, executor, run_task)
+                }
+                processor => executor.run(self, processor, context),
+            };
+            record.finished(conn, result.as_ref().err().map(ToString::to_string))?;
+
+            let err = match result {
+                Ok(output) => return Ok(output),
+                Err(err) => err,
+            };
+
+            match retry_delay(policy.as_ref(), context, attempt, &err.to_string()) {
+                Some(delay) if wait(context, delay) => attempt += 1,
+                _ => return Err(err),
+            }
+        }
+    }
+
+    /// Run the task referenced by the provided processor as a child job of the
+    /// job of this step, and return the final output of the child job.
+    ///
+    /// The child job runs on the current thread, sharing the workspace and
+    /// cancellation of the provided context, and has to finish before the
+    /// deadline of the context passes. Its processors run using the same
+    /// executor as this step.
+    ///
+    /// A task cannot run itself, directly or through any of its child jobs,
+    /// and child jobs cannot be nested more than [`MAX_CHILD_JOB_DEPTH`]
+    /// levels deep.
+    /// A child job that pauses to wait for an approval, or for input, is
+    /// cancelled.
+    fn run_task(
+        &self,
+        conn: &PgConnection,
+        pool: &DatabasePool,
+        context: &Context,
+        executor: &Executor,
+        processor: &RunTask,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        // The task is resolved using the configuration of the step before its
+        // templates were rendered, which is the configuration authorized when
+        // creating the task running this job.
+        let task = match self.processor() {
+            Some(Processor::RunTask(configured)) => configured.task(conn)?,
+            _ => processor.task(conn)?,
+        };
+        let parent = self.job(conn)?;
+
+        if parent.depth(conn)? >= MAX_CHILD_JOB_DEPTH {
+            return Err(format!(
+                "task `{}` cannot run, as child jobs can only be nested {} level(s) deep",
+                task.name, MAX_CHILD_JOB_DEPTH
+            )
+            .into());
+        }
+
+        if parent.runs_task(conn, &task)? {
+            return Err(format!("task `{}` cannot run itself as a child job", task.name).into());
+        }
+
+        // The child job runs on the worker of this job, which therefore has to
+        // serve the queue required by the task.
+        if let Some(queue) = &task.queue {
+            if !parent.worker_serves(conn, queue)? {
+                return Err(format!(
+                    "task `{}` requires the `{}` queue, which the worker running this job does \
+                     not serve",
+                    task.name, queue
+                )
+                .into());
+            }
+        }
+
+        let mut child = NewJob::create_child(conn, &task, &parent, processor.job_variables())?;
+        if let Err(err) = child.run(conn, pool, &context.fork(), executor) {
+            child.as_failed(conn)?;
+            return Err(err);
+        }
+
+        let child: Job = jobs::table.find(child.id).first(conn)?;
+        match child.status {
+            JobStatus::Ok => child.output(conn).map_err(Into::into),
+            JobStatus::Cancelled => Err(format!("child job `{}` was cancelled", child.name).into()),
+            JobStatus::AwaitingApproval | JobStatus::AwaitingInput => {
+                // The child job runs as part of this step, so it cannot pause
+                // on its own.
+                let _ = child.cancel(conn)?;
+                Err(format!("child job `{}` cannot pause, as it runs inline", child.name).into())
+            }
+            _ => {
+                let details = child.failure(conn)?.map_or_else(String::new, |failure| {
+                    format!(" at step `{}`:\n\n{}", failure.step, failure.error)
+                });
+
+                Err(format!("child job `{}` failed{}", child.name, details).into())
+            }
+        }
+    }
+
+    /// Run the provided processors, one for every item of the step, and
+    /// combine their outputs into a JSON array. Outputs that are valid JSON
+    /// are added as JSON values, other outputs are added as strings.
+    ///
+    /// The processors run on separate threads, up to the configured number at
+    /// a time, each using a fork of the provided context. Each item is retried
+    /// according to the retry policy of the step, and every attempt is
+    /// recorded.
+    ///
+    /// Depending on the configured failure mode, no more items start running
+    /// once an item failed, or all items run before the step fails with the
+    /// errors of all failed items.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    fn run_for_each(
+        &self,
+        conn: &PgConnection,
+        context: &Context,
+        executor: &Executor,
+        for_each: &ForEach,
+        processors: Vec<Processor>,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let policy = self.retry_policy();
+        let mut outputs = vec![serde_json::Value::Null; processors.len()];
+        let mut processors = processors.into_iter().enumerate();
+        let mut records = HashMap::new();
+        let mut errors = vec![];
+        let mut running = 0;
+        let (sender, receiver) = mpsc::channel();
+
+        loop {
+            while running < for_each.concurrency()
+                && !context.is_cancelled()
+                && !context.is_timed_out()
+                && (errors.is_empty() || for_each.failure_mode == ForEachFailureMode::Collect)
+            {
+                let (index, processor) = match processors.next() {
+                    Some(next) => next,
+                    None => break,
+                };
+
+                running += 1;
+                let sender = sender.clone();
+                let context = context.fork();
+                let executor = executor.clone();
+                let policy = policy.clone();
+                let step = self.clone();
+
+                let _ = thread::spawn(move || {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        let policy = policy.as_ref();
+                        run_item(
+                            index, &step, &processor, &context, &executor, policy, &sender,
+                        )
+                    }))
+                    .unwrap_or_else(|_| Err("processor panicked while running".to_owned()));
+
+                    let _ = sender.send(ItemEvent::Done(index, result));
+                });
+            }
+
+            if running == 0 {
+                break;
+            }
+
+            match receiver.recv()? {
+                ItemEvent::Started(index, attempt) => {
+                    let mut record = NewJobStepAttempt::new(self, attempt);
+                    record.with_item(index as i32);
+
+                    let _ = records.insert((index, attempt), record.create(conn)?);
+                }
+                ItemEvent::Finished(index, attempt, error) => {
+                    if let Some(mut record) = records.remove(&(index, attempt)) {
+                        record.finished(conn, error)?;
+                    }
+                }
+                ItemEvent::Done(index, result) => {
+                    running -= 1;
+
+                    match result {
+                        Ok(Some(output)) => {
+                            outputs[index] = serde_json::from_str(&output)
+                                .unwrap_or_else(|_| serde_json::Value::String(output))
+                        }
+                        Ok(None) => {}
+                        Err(err) => errors.push(format!("item {}: {}", index, err)),
+                    }
+                }
+            }
+        }
+
+        // Items can remain unstarted if the step was cancelled, or timed out,
+        // in which case the step cannot succeed.
+        let remaining = processors.count();
+        if remaining > 0 && errors.is_empty() {
+            errors.push(format!("{} items did not run", remaining));
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join("\n").into());
+        }
+
+        serde_json::to_string(&outputs)
+            .map(Some)
+            .map_err(Into::into)
+    }
+
+    /// Mark the step as cancelled, meaning it will not run anymore.
+    pub(crate) fn cancel(&mut self, conn: &PgConnection) -> QueryResult<()> {
+        self.status = Status::Cancelled;
+
+        self.save_changes::<Self>(conn).map(|_| ())
+    }
+
+    /// Mark the step as awaiting approval by a session with the privilege
+    /// required by the provided processor.
+    fn await_approval(
+        &mut self,
+        conn: &PgConnection,
+        processor: &WaitForApproval,
+    ) -> QueryResult<()> {
+        let expires_at = processor
+            .expires_after_seconds
+            .map(|seconds| Utc::now().naive_utc() + chrono::Duration::seconds(seconds.into()));
+
+        let mut output = format!(
+            "Waiting for approval by a session with the `{}` privilege",
+            processor.privilege
+        );
+
+        if let Some(expires_at) = expires_at {
+            output.push_str(&format!(", until {} UTC", expires_at.format("%F %T")));
+        }
+
+        self.status = Status::AwaitingApproval;
+        self.approval_expires_at = expires_at;
+        self.output = Some(format!("{}.", output));
+
+        self.save_changes::<Self>(conn).map(|_| ())
+    }
+
+    /// Mark the step as awaiting input, asking the question of the provided
+    /// processor.
+    fn await_input(
+        &mut self,
+        conn: &PgConnection,
+        processor: Prompt,
+        options: Option<Vec<String>>,
+    ) -> QueryResult<()> {
+        self.status = Status::AwaitingInput;
+        self.output = Some(processor.question.clone());
+        self.prompt_variable = Some(processor.variable);
+        self.prompt_question = Some(processor.question);
+        self.prompt_options = options;
+
+        self.save_changes::<Self>(conn).map(|_| ())
+    }
+
+    /// Answer the prompt of the step, on behalf of the provided session, if
+    /// any.
+    ///
+    /// The answer is stored as an encrypted variable of the job, replacing
+    /// any existing variable with the same key, after which the step
+    /// succeeds. The answer itself is not shown in the output of the step.
+    ///
+    /// Once answered, the job of the step is moved back to `Pending`, in the
+    /// same way as when deciding on an approval, see
+    /// [`JobStep::decide_approval`].
+    ///
+    /// An error is returned if the step is not awaiting input, or if the
+    /// answer is not one of the options of the prompt.
+    pub(crate) fn answer_prompt(
+        &mut self,
+        conn: &PgConnection,
+        value: &str,
+        session: Option<&Session>,
+    ) -> Result<(), Box<dyn Error>> {
+        use crate::schema::job_variables;
+
+        conn.transaction(|| {
+            // The job is locked first, in the same order as a job that pauses,
+            // to prevent the answer from getting lost.
+            let job: Job = jobs::table.find(self.job_id).for_update().first(conn)?;
+            let mut step: Self = job_steps::table.find(self.id).for_update().first(conn)?;
+
+            let variable = match (step.status, &step.prompt_variable) {
+                (Status::AwaitingInput, Some(variable)) => variable.clone(),
+                _ => return Err("job step is not awaiting input".into()),
+            };
+
+            if let Some(options) = &step.prompt_options {
+                if !options.iter().any(|option| option == value) {
+                    return Err(format!("answer must be one of: {}", options.join(", ")).into());
+                }
+            }
+
+            let existing = job_variables::table
+                .filter(job_variables::job_id.eq(job.id))
+                .filter(job_variables::key.eq(&variable));
+
+            let _ = diesel::delete(existing).execute(conn)?;
+            NewJobVariable::new(&variable, value).add_to_job(conn, &job)?;
+
+            let now = Utc::now().naive_utc();
+            let answered_by = session.map_or_else(
+                || "anonymously".to_owned(),
+                |session| format!("by session {}", session.id),
+            );
+
+            let output = format!("Answered {} at {} UTC.", answered_by, now.format("%F %T"));
+
+            step.status = Status::Ok;
+            step.finished_at = Some(now);
+            step.output = Some(output);
+            step.prompt_answered_by = session.map(|session| session.id);
+            step.prompt_answered_at = Some(now);
+            *self = step.save_changes::<Self>(conn)?;
+
+            continue_job(conn, &job).map_err(Into::into)
+        })
+    }
+
+    /// Approve or reject the step, on behalf of the provided session, if any.
+    ///
+    /// An approved step succeeds, a rejected step fails. The decision is
+    /// stored on the step, and shown in its output, including the optional
+    /// comment.
+    ///
+    /// Once decided, the job of the step is moved back to `Pending`, to
+    /// continue running in its workspace, on the worker that ran it before
+    /// it paused. If the job is still running, because other steps of the job
+    /// did not yet finish, the job continues once it pauses instead.
+    ///
+    /// An error is returned if the step is not awaiting approval.
+    pub(crate) fn decide_approval(
+        &mut self,
+        conn: &PgConnection,
+        decision: ApprovalDecision,
+        session: Option<&Session>,
+        comment: Option<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        conn.transaction(|| {
+            // The job is locked first, in the same order as a job that pauses,
+            // to prevent the decision from getting lost.
+            let job: Job = jobs::table.find(self.job_id).for_update().first(conn)?;
+            let mut step: Self = job_steps::table.find(self.id).for_update().first(conn)?;
+
+            match step.status {
+                Status::AwaitingApproval => {}
+                _ => return Err("job step is not awaiting approval".into()),
+            }
+
+            let now = Utc::now().naive_utc();
+            let (status, verb) = match decision {
+                ApprovalDecision::Approved => (Status::Ok, "Approved"),
+                ApprovalDecision::Rejected => (Status::Failed, "Rejected"),
+            };
+
+            let decided_by = session.map_or_else(
+                || "automatically".to_owned(),
+                |session| format!("by session {}", session.id),
+            );
+
+            let mut output = format!("{} {} at {} UTC.", verb, decided_by, now.format("%F %T"));
+            if let Some(comment) = &comment {
+                output.push_str(&format!("\n\n{}", comment));
+            }
+
+            step.status = status;
+            step.finished_at = Some(now);
+            step.output = Some(output);
+            step.approval_decision = Some(decision);
+            step.approval_decided_by = session.map(|session| session.id);
+            step.approval_decided_at = Some(now);
+            step.approval_comment = comment;
+            *self = step.save_changes::<Self>(conn)?;
+
+            continue_job(conn, &job).map_err(Into::into)
+        })
+    }
+
+    /// Reject all steps of which the approval request expired.
+    ///
+    /// Returns the number of rejected steps.
+    pub(crate) fn reject_expired_approvals(conn: &PgConnection) -> QueryResult<usize> {
+        let expired: Vec<Self> = job_steps::table
+            .filter(job_steps::status.eq(Status::AwaitingApproval))
+            .filter(job_steps::approval_expires_at.le(Utc::now().naive_utc()))
+            .load(conn)?;
+
+        // A step can be decided by a session in the meantime, in which case it
+        // can no longer be rejected. This should not prevent the other steps
+        // from being rejected.
+        let rejected = expired
+            .into_iter()
+            .filter_map(|mut step| {
+                step.decide_approval(
+                    conn,
+                    ApprovalDecision::Rejected,
+                    None,
+                    Some("The approval request expired.".to_owned()),
+                )
+                .ok()
+            })
+            .count();
+
+        Ok(rejected)
+    }
+
+    /// Mark the step as skipped, meaning it will not run, because its run
+    /// policy does not apply to the outcome of the job.
+    pub(crate) fn skip(&mut self, conn: &PgConnection) -> QueryResult<()> {
+        self.status = Status::Skipped;
+
+        self.save_changes::<Self>(conn).map(|_| ())
+    }
+
+    /// Mark the step as failed with the provided error, for errors that
+    /// prevented the step from finishing its run.
+    pub(crate) fn fail(&mut self, conn: &PgConnection, error: String) -> QueryResult<()> {
+        self.finished(conn, Status::Failed, Some(error))
+    }
+
+    /// Mark the step as failed, because the worker running it was lost.
+    pub(crate) fn lost(&mut self, conn: &PgConnection) -> QueryResult<()> {
+        self.finished(conn, Status::Failed, Some(WORKER_LOST.to_owned()))
+    }
+
+    /// Mark the step as failed, because the worker keeping the workspace of
+    /// its paused job stopped, while the step was awaiting approval or input.
+    pub(crate) fn workspace_lost(&mut self, conn: &PgConnection) -> QueryResult<()> {
+        self.finished(conn, Status::Failed, Some(WORKSPACE_LOST.to_owned()))
+    }
+
+    /// Reset the step, so that it runs again once its job is requeued.
+    ///
+    /// Any output of the step is removed, except when the step was running
+    /// on a worker that was lost, in which case the output explains why the
+    /// step runs again.
+    pub(crate) fn requeue(&mut self, conn: &PgConnection) -> QueryResult<()> {
+        use crate::schema::job_steps::dsl::*;
+
+        self.output = match self.status {
+            Status::Running => Some(format!("{}, the job was requeued", WORKER_LOST)),
+            _ => None,
+        };
+        self.status = Status::Initialized;
+        self.started_at = None;
+        self.finished_at = None;
+
+        // Changes to `None` values are ignored by `save_changes`, so the
+        // columns are updated explicitly.
+        diesel::update(&*self)
+            .set((
+                status.eq(self.status),
+                started_at.eq(self.started_at),
+                finished_at.eq(self.finished_at),
+                output.eq(&self.output),
+            ))
+            .execute(conn)
+            .map(|_| ())
+    }
+
     fn start(&mut self, conn: &PgConnection) -> QueryResult<()> {
         self.status = Status::Running;
         self.started_at = Some(Utc::now().naive_utc());
 
+        // The stored step is kept, as the database stores the moment the step
+        // started with a lower precision, which is compared against when the
+        // step finishes.
         match self.save_changes::<Self>(conn) {
-            Ok(_) => Ok(()),
+            Ok(step) => {
+                *self = step;
+                Ok(())
+            }
             Err(err) => {
                 self.status = Status::Failed;
                 Err(err)
@@ -163,27 +975,46 @@ impl JobStep {
         }
     }
 
+    /// Store the result of the step.
+    ///
+    /// The result is only stored if the step is still in the state in which
+    /// it was loaded, or last stored. A step that was reset in the meantime,
+    /// because the worker running its job was considered lost and the job
+    /// was recovered, belongs to the next run of the job, so the result of
+    /// the lost run is dropped, and `NotFound` is returned instead.
     fn finished(
         &mut self,
         conn: &PgConnection,
         status: Status,
         output: Option<String>,
     ) -> QueryResult<()> {
-        self.finished_at = Some(Utc::now().naive_utc());
-        self.status = status;
-        self.output = output;
+        conn.transaction(|| {
+            let current: Self = job_steps::table.find(self.id).for_update().first(conn)?;
+            if current.status != self.status || current.started_at != self.started_at {
+                return Err(diesel::result::Error::NotFound);
+            }
 
-        self.save_changes::<Self>(conn).map(|_| ())
+            self.finished_at = Some(Utc::now().naive_utc());
+            self.status = status;
+            self.output = output;
+
+            self.save_changes::<Self>(conn).map(|_| ())
+        })
     }
 
     /// Takes the associated job step processor, and formalizes its definition
     /// by replacing any templated variables.
-    fn formalize_processor(
+    ///
+    /// If the step has a condition, and that condition is not met, the step is
+    /// skipped instead. If the step has a for each configuration, a processor
+    /// is formalized for every item.
+    fn prepare(
         &mut self,
-        output_values: &mut HashMap<String, String>,
+        output_values: &HashMap<String, String>,
+        failure: Option<&Failure>,
         context: &Context,
         conn: &PgConnection,
-    ) -> Result<Processor, Box<dyn Error>> {
+    ) -> Result<Plan, Box<dyn Error>> {
         let variables = self
             .job(conn)
             .and_then(|j| j.variables(conn))
@@ -214,13 +1045,65 @@ impl JobStep {
 
         // Build a dataset of key/value pairs that can be used in the template
         // as variables and their substituted values.
-        let data = TemplateData {
+        let mut data = TemplateData {
             var,
             global,
             context: context_variables,
             output,
+            failure,
+            item: None,
+            index: None,
+        };
+
+        if let Some(when) = &self.when {
+            match condition_met(when, &data) {
+                Ok(true) => {}
+                Ok(false) => return Ok(Plan::Skip),
+                Err(err) => {
+                    return Err(format!("job step condition cannot be evaluated: {}", err).into())
+                }
+            }
+        }
+
+        let for_each = match self.for_each() {
+            Some(for_each) => for_each,
+            None => {
+                return match self.formalize_config(&data) {
+                    Ok(processor) => Ok(Plan::Run(processor)),
+                    Err(err) => {
+                        Err(format!("job processor cannot be deserialized: {}", err).into())
+                    }
+                };
+            }
         };
 
+        let items: Vec<serde_json::Value> = render(&for_each.items, &data)
+            .and_then(|items| serde_json::from_str(&items).map_err(|e| e.to_string()))
+            .map_err(|e| format!("job step items cannot be evaluated: {}", e))?;
+
+        let mut processors = Vec::with_capacity(items.len());
+        for (index, item) in items.into_iter().enumerate() {
+            data.item = Some(item);
+            data.index = Some(index);
+
+            match self.formalize_config(&data) {
+                Ok(processor) => processors.push(processor),
+                Err(err) => {
+                    return Err(format!(
+                        "job processor cannot be deserialized for item {}: {}",
+                        index, err
+                    )
+                    .into())
+                }
+            }
+        }
+
+        Ok(Plan::ForEach(for_each, processors))
+    }
+
+    /// Replaces any templated variables in the processor configuration, using
+    /// the provided dataset.
+    fn formalize_config(&self, data: &TemplateData<'_>) -> Result<Processor, Box<dyn Error>> {
         // The processor is serialized as `{ "ProcessorType": { ... } }` in the
         // database in order for Serde to know to which processor to deserialize
         // the JSON to.
@@ -241,7 +1124,7 @@ impl JobStep {
         // templates.
         config
             .values_mut()
-            .try_for_each(|v| self.formalize_value(v, &data))?;
+            .try_for_each(|v| self.formalize_value(v, data))?;
 
         serde_json::from_value(processor).map_err(Into::into)
     }
@@ -276,33 +1159,156 @@ impl JobStep {
             return Ok(());
         };
 
-        let context = TContext::from_serialize(data).map_err(|e| e.to_string())?;
+        *value = render(value.as_str().unwrap(), data)?.into();
+
+        Ok(())
+    }
+}
 
-        let mut tera = Tera::default();
-        tera.add_raw_template("processor configuration", value.as_str().unwrap())
-            .map_err(|e| e.to_string())?;
+/// Move the provided job back to `Pending` if it paused, so that it continues
+/// running on the worker that keeps its workspace.
+fn continue_job(conn: &PgConnection, job: &Job) -> QueryResult<()> {
+    match job.status {
+        JobStatus::AwaitingApproval | JobStatus::AwaitingInput => {
+            let _ = diesel::update(job)
+                .set(jobs::status.eq(JobStatus::Pending))
+                .execute(conn)?;
 
-        match tera.render("processor configuration", context) {
-            Ok(string) => *value = string.into(),
-            Err(err) => {
-                use tera::ErrorKind::*;
-
-                let string = match err.kind {
-                    FilterNotFound(string) => format!("missing template filter: {}", string),
-                    TestNotFound(string) => format!("missing template test: {}", string),
-                    FunctionNotFound(string) => format!("missing template function: {}", string),
-                    Json(string) => format!("template json error: {}", string),
-                    _ => match err.source() {
-                        Some(source) => format!("template error: {}", source.to_string()),
-                        None => format!("unknown template error: {}", err.to_string()),
-                    },
-                };
+            Job::notify_pending(conn)
+        }
+        _ => Ok(()),
+    }
+}
 
-                return Err(string);
+/// Render the provided template, using a dataset of key/value pairs, using a
+/// Jinja-like templating language (using the Tera crate).
+fn render(template: &str, data: &TemplateData<'_>) -> Result<String, String> {
+    let context = TContext::from_serialize(data).map_err(|e| e.to_string())?;
+
+    let mut tera = Tera::default();
+    tera.add_raw_template("processor configuration", template)
+        .map_err(|e| e.to_string())?;
+
+    tera.render("processor configuration", context)
+        .map_err(|err| {
+            use tera::ErrorKind::*;
+
+            match err.kind {
+                FilterNotFound(string) => format!("missing template filter: {}", string),
+                TestNotFound(string) => format!("missing template test: {}", string),
+                FunctionNotFound(string) => format!("missing template function: {}", string),
+                Json(string) => format!("template json error: {}", string),
+                _ => match err.source() {
+                    Some(source) => format!("template error: {}", source.to_string()),
+                    None => format!("unknown template error: {}", err.to_string()),
+                },
             }
+        })
+}
+
+/// Evaluate the provided condition as a template expression, using the same
+/// dataset used to formalize the processor configuration.
+///
+/// The condition is met if the expression is truthy, as defined by the `if`
+/// statement of the templating language.
+fn condition_met(condition: &str, data: &TemplateData<'_>) -> Result<bool, String> {
+    render(&condition_template(condition), data).map(|result| result == "true")
+}
+
+/// Validate that the provided condition is a valid template expression, see
+/// [`condition_met`].
+///
+/// Whether the condition is met is only known once the step runs, as the
+/// expression can use the output of the steps that ran before it.
+pub(crate) fn validate_condition(condition: &str) -> Result<(), String> {
+    let mut tera = Tera::default();
+    tera.add_raw_template("condition", &condition_template(condition))
+        .map_err(|err| match err.source() {
+            Some(source) => source.to_string(),
+            None => err.to_string(),
+        })
+}
+
+/// The template rendering `true` if the provided condition is met.
+fn condition_template(condition: &str) -> String {
+    format!("{{% if {} %}}true{{% endif %}}", condition)
+}
+
+/// Run the processor for the item at the provided index, until an attempt
+/// succeeds, or until the provided retry policy stops retrying.
+///
+/// The start and end of every attempt are sent as events, so that they can be
+/// recorded by the thread owning the database connection.
+fn run_item(
+    index: usize,
+    step: &JobStep,
+    processor: &Processor,
+    context: &Context,
+    executor: &Executor,
+    policy: Option<&RetryPolicy>,
+    events: &mpsc::Sender<ItemEvent>,
+) -> Result<Option<String>, String> {
+    let mut attempt = 1;
+
+    loop {
+        let _ = events.send(ItemEvent::Started(index, attempt));
+        let result = executor
+            .run(step, processor, context)
+            .map_err(|e| e.to_string());
+        let _ = events.send(ItemEvent::Finished(
+            index,
+            attempt,
+            result.as_ref().err().cloned(),
+        ));
+
+        let err = match result {
+            Ok(output) => return Ok(output),
+            Err(err) => err,
         };
 
-        Ok(())
+        match retry_delay(policy, context, attempt, &err) {
+            Some(delay) if wait(context, delay) => attempt += 1,
+            _ => return Err(err),
+        }
+    }
+}
+
+/// Returns the delay before the next attempt, if the failed attempt should be
+/// retried according to the provided retry policy.
+///
+/// Attempts are not retried once the context is cancelled, or timed out.
+fn retry_delay(
+    policy: Option<&RetryPolicy>,
+    context: &Context,
+    attempt: i32,
+    error: &str,
+) -> Option<Duration> {
+    match policy {
+        Some(policy) if !context.is_cancelled() && !context.is_timed_out() => {
+            policy.retry_delay(attempt, error)
+        }
+        _ => None,
+    }
+}
+
+/// Wait for the provided delay to pass.
+///
+/// Returns `false` if the context is cancelled, or times out, before the delay
+/// passed.
+fn wait(context: &Context, delay: Duration) -> bool {
+    let until = Instant::now() + delay;
+
+    loop {
+        if context.is_cancelled() || context.is_timed_out() {
+            return false;
+        }
+
+        let now = Instant::now();
+        if now >= until {
+            return true;
+        }
+
+        thread::sleep(RETRY_WAIT_INTERVAL.min(until - now));
     }
 }
 
@@ -319,6 +1325,12 @@ pub(crate) struct NewJobStep<'a> {
     finished_at: Option<NaiveDateTime>,
     output: Option<&'a str>,
     status: Status,
+    timeout_seconds: Option<i32>,
+    retry_policy: Option<RetryPolicy>,
+    when: Option<&'a str>,
+    run_policy: StepRunPolicy,
+    depends_on: Option<Vec<&'a str>>,
+    for_each: Option<ForEach>,
 }
 
 impl<'a> NewJobStep<'a> {
@@ -339,9 +1351,48 @@ impl<'a> NewJobStep<'a> {
             finished_at: None,
             output: None,
             status: Status::Initialized,
+            timeout_seconds: None,
+            retry_policy: None,
+            when: None,
+            run_policy: StepRunPolicy::OnSuccess,
+            depends_on: None,
+            for_each: None,
         }
     }
 
+    /// Limit the number of seconds the step is allowed to run.
+    pub(crate) fn with_timeout_seconds(&mut self, seconds: Option<i32>) {
+        self.timeout_seconds = seconds
+    }
+
+    /// Retry the step according to the provided policy, if it fails to run.
+    pub(crate) fn with_retry_policy(&mut self, policy: Option<RetryPolicy>) {
+        self.retry_policy = policy
+    }
+
+    /// Only run the step if the provided template expression evaluates to a
+    /// truthy value.
+    pub(crate) fn with_when(&mut self, when: Option<&'a str>) {
+        self.when = when
+    }
+
+    /// Define when the step runs, depending on the success of the steps that
+    /// ran before it.
+    pub(crate) fn with_run_policy(&mut self, policy: StepRunPolicy) {
+        self.run_policy = policy
+    }
+
+    /// Run the step once all of the steps with the provided names finished,
+    /// instead of once the step positioned before it finished.
+    pub(crate) fn with_depends_on(&mut self, names: Option<Vec<&'a str>>) {
+        self.depends_on = names
+    }
+
+    /// Run the processor of the step once for every item in a JSON array.
+    pub(crate) fn with_for_each(&mut self, for_each: Option<ForEach>) {
+        self.for_each = for_each
+    }
+
     /// Add a step to a [`Job`], by storing it in the database as an
     /// association.
     ///
@@ -362,6 +1413,12 @@ impl<'a> NewJobStep<'a> {
             status.eq(Status::Pending),
             output.eq(&self.output),
             job_id.eq(job.id),
+            timeout_seconds.eq(self.timeout_seconds),
+            retry_policy.eq(self.retry_policy.map(serde_json::to_value).transpose()?),
+            when.eq(self.when),
+            run_policy.eq(self.run_policy),
+            depends_on.eq(&self.depends_on),
+            for_each.eq(self.for_each.map(serde_json::to_value).transpose()?),
         );
 
         diesel::insert_into(job_steps)
@@ -418,6 +1475,78 @@ pub(crate) mod graphql {
             self.position
         }
 
+        /// The number of seconds the step is allowed to run, if limited.
+        fn timeout_seconds() -> Option<i32> {
+            self.timeout_seconds
+        }
+
+        /// The policy used to retry the step if it fails to run, if any.
+        fn retry_policy() -> Option<RetryPolicy> {
+            self.retry_policy()
+        }
+
+        /// The condition that has to be met for the step to run, if any.
+        ///
+        /// If the condition was not met, the step has the `SKIPPED` status.
+        fn when() -> Option<&str> {
+            self.when.as_ref().map(String::as_ref)
+        }
+
+        /// Defines when the step runs, depending on the success of the steps
+        /// that ran before it.
+        fn run_policy() -> StepRunPolicy {
+            self.run_policy
+        }
+
+        /// The names of the steps this step depends on, or `null` if the step
+        /// depends on the step positioned before it.
+        fn depends_on() -> Option<Vec<&str>> {
+            self.depends_on
+                .as_ref()
+                .map(|names| names.iter().map(String::as_str).collect())
+        }
+
+        /// The configuration used to run the processor of the step once for
+        /// every item in a JSON array, if any.
+        fn for_each() -> Option<ForEach> {
+            self.for_each()
+        }
+
+        /// All attempts to run the step, in order.
+        ///
+        /// A step without a retry policy has at most one attempt. A step that
+        /// did not run yet has no attempts.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        fn attempts(context: &RequestState) -> FieldResult<Option<Vec<JobStepAttempt>>> {
+            self.attempts(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// The output written by the processor of the step while it runs, in
+        /// chunks, in the order it was written.
+        ///
+        /// The first `offset` chunks are skipped. To follow the output of a
+        /// running step, add the number of returned chunks to the offset, and
+        /// repeat the query with the new offset, until the step finished.
+        ///
+        /// At most `limit` chunks are returned, if provided.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        fn logs(
+            context: &RequestState,
+            offset: Option<i32>,
+            limit: Option<i32>,
+        ) -> FieldResult<Option<Vec<JobStepLog>>> {
+            let offset = i64::from(offset.unwrap_or(0).max(0));
+            let limit = limit.map(|limit| i64::from(limit.max(0)));
+
+            self.logs(offset, limit, &context.conn)
+                .map(Some)
+                .map_err(Into::into)
+        }
+
         fn started_at() -> Option<DateTime<Utc>> {
             self.started_at.map(|t| DateTime::from_utc(t, Utc))
         }
@@ -435,6 +1564,22 @@ pub(crate) mod graphql {
             StepOutput(self.output.as_ref().map(String::as_ref))
         }
 
+        /// The approval details of the step, if the step uses the
+        /// `WaitForApproval` processor, and is awaiting approval, or was
+        /// approved or rejected.
+        fn approval() -> Option<StepApproval<'_>> {
+            match (self.status, self.approval_decision) {
+                (Status::AwaitingApproval, _) | (_, Some(_)) => Some(StepApproval(self)),
+                _ => None,
+            }
+        }
+
+        /// The prompt details of the step, if the step uses the `Prompt`
+        /// processor, and is awaiting input, or was answered.
+        fn prompt() -> Option<StepPrompt<'_>> {
+            self.prompt_question.as_ref().map(|_| StepPrompt(self))
+        }
+
         /// The job to which the step belongs.
         ///
         /// This field can return `null`, but _only_ if a database error
@@ -457,6 +1602,91 @@ pub(crate) mod graphql {
         }
     }
 
+    /// The approval details of a step using the `WaitForApproval` processor.
+    #[derive(Clone, Debug)]
+    pub(crate) struct StepApproval<'a>(&'a JobStep);
+
+    #[object]
+    impl<'a> StepApproval<'a> {
+        /// The moment at which the step is rejected, if no decision is made
+        /// before then.
+        ///
+        /// Returns `null` if the approval request does not expire.
+        fn expires_at() -> Option<DateTime<Utc>> {
+            self.0
+                .approval_expires_at
+                .map(|t| DateTime::from_utc(t, Utc))
+        }
+
+        /// The decision made on the step, or `null` if the step is still
+        /// awaiting approval.
+        fn decision() -> Option<ApprovalDecision> {
+            self.0.approval_decision
+        }
+
+        /// The ID of the session that approved or rejected the step.
+        ///
+        /// Returns `null` if no decision was made yet, or if the step was
+        /// rejected because the approval request expired.
+        fn decided_by() -> Option<ID> {
+            self.0.approval_decided_by.map(|id| ID::new(id.to_string()))
+        }
+
+        /// The moment at which the decision was made, if any.
+        fn decided_at() -> Option<DateTime<Utc>> {
+            self.0
+                .approval_decided_at
+                .map(|t| DateTime::from_utc(t, Utc))
+        }
+
+        /// The comment provided with the decision, if any.
+        fn comment() -> Option<&str> {
+            self.0.approval_comment.as_ref().map(String::as_ref)
+        }
+    }
+
+    /// The prompt details of a step using the `Prompt` processor.
+    #[derive(Clone, Debug)]
+    pub(crate) struct StepPrompt<'a>(&'a JobStep);
+
+    #[object]
+    impl<'a> StepPrompt<'a> {
+        /// The key of the job variable in which the answer is stored.
+        fn variable() -> &str {
+            self.0.prompt_variable.as_ref().map_or("", String::as_ref)
+        }
+
+        /// The question asked by the step.
+        fn question() -> &str {
+            self.0.prompt_question.as_ref().map_or("", String::as_ref)
+        }
+
+        /// The values one of which has to be picked as the answer.
+        ///
+        /// Returns `null` if any answer is accepted.
+        fn options() -> Option<Vec<&str>> {
+            self.0
+                .prompt_options
+                .as_ref()
+                .map(|options| options.iter().map(String::as_str).collect())
+        }
+
+        /// The ID of the session that answered the prompt.
+        ///
+        /// Returns `null` if the prompt is not yet answered, or was answered
+        /// without an authenticated session.
+        fn answered_by() -> Option<ID> {
+            self.0.prompt_answered_by.map(|id| ID::new(id.to_string()))
+        }
+
+        /// The moment at which the prompt was answered, if any.
+        fn answered_at() -> Option<DateTime<Utc>> {
+            self.0
+                .prompt_answered_at
+                .map(|t| DateTime::from_utc(t, Utc))
+        }
+    }
+
     /// The output of the step, presented in different formats.
     #[derive(Clone, Debug, Deserialize, Serialize)]
     pub(crate) struct StepOutput<'a>(Option<&'a str>);
@@ -494,11 +1724,223 @@ impl<'a> TryFrom<&'a Step> for NewJobStep<'a> {
     type Error = serde_json::Error;
 
     fn try_from(step: &'a Step) -> Result<Self, Self::Error> {
-        Ok(Self::new(
+        let mut job_step = Self::new(
             &step.name,
             step.description.as_ref().map(String::as_ref),
             serde_json::from_value(step.processor.clone())?,
             step.position,
-        ))
+        );
+
+        job_step.with_timeout_seconds(step.timeout_seconds);
+        job_step.with_retry_policy(step.retry_policy()?);
+        job_step.with_for_each(step.for_each()?);
+        job_step.with_when(step.when.as_ref().map(String::as_str));
+        job_step.with_run_policy(step.run_policy);
+        job_step.with_depends_on(
+            step.depends_on
+                .as_ref()
+                .map(|names| names.iter().map(String::as_str).collect()),
+        );
+        Ok(job_step)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::NewJobStep;
+    use diesel::result::Error as DieselError;
+    use processor_print_output_v1::PrintOutput;
+    use processor_string_regex_v1::StringRegex;
+
+    fn connection() -> PgConnection {
+        let url = std::env::var("DATABASE_URL")
+            .unwrap_or_else(|_| "postgres://postgres@localhost".to_owned());
+
+        PgConnection::establish(&url).unwrap()
+    }
+
+    /// Create a job with a single step, running the provided processor for
+    /// every item the provided template renders to, one item at a time.
+    fn for_each_step(
+        conn: &PgConnection,
+        processor: Processor,
+        items: &str,
+        failure_mode: ForEachFailureMode,
+    ) -> JobStep {
+        let mut step = NewJobStep::new("Each", None, processor, 0);
+        step.with_for_each(Some(ForEach {
+            items: items.to_owned(),
+            concurrency: 1,
+            failure_mode,
+        }));
+
+        let mut job = NewJob::new("For Each", None);
+        job.with_steps(vec![step]);
+        job.create(conn).unwrap().steps(conn).unwrap().remove(0)
+    }
+
+    /// A processor failing for every item that does not start with `ok`.
+    fn ok_items() -> Processor {
+        Processor::StringRegex(StringRegex {
+            input: "{{ item }}".to_owned(),
+            regex: "^ok".to_owned(),
+            mismatch_error: Some("{{ item }} is not ok".to_owned()),
+            replace: None,
+        })
+    }
+
+    /// Run the processor of the step for all its items.
+    fn run_items(step: &mut JobStep, conn: &PgConnection) -> Result<Option<String>, String> {
+        let context = Context::new().unwrap();
+
+        match step.prepare(&HashMap::new(), None, &context, conn) {
+            Ok(Plan::ForEach(for_each, processors)) => step
+                .run_for_each(conn, &context, &Executor::Inline, &for_each, processors)
+                .map_err(|err| err.to_string()),
+            Ok(_) => panic!("step does not run for each item"),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    /// The items for which the processor of the step ran.
+    fn attempted_items(step: &JobStep, conn: &PgConnection) -> Vec<Option<i32>> {
+        let mut items: Vec<_> = step
+            .attempts(conn)
+            .unwrap()
+            .into_iter()
+            .map(|attempt| attempt.item)
+            .collect();
+
+        items.sort();
+        items
+    }
+
+    #[test]
+    fn test_run_for_each_renders_items() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let processor = Processor::PrintOutput(PrintOutput {
+                output: "{{ index }}: {{ item.name }}".to_owned(),
+            });
+
+            let items = r#"[{"name": "a"}, {"name": "b"}]"#;
+            let mut step = for_each_step(&conn, processor, items, ForEachFailureMode::FailFast);
+
+            assert_eq!(
+                run_items(&mut step, &conn),
+                Ok(Some(r#"["0: a","1: b"]"#.to_owned()))
+            );
+            assert_eq!(attempted_items(&step, &conn), vec![Some(0), Some(1)]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_run_for_each_aggregates_outputs() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let processor = Processor::PrintOutput(PrintOutput {
+                output: "{{ item }}".to_owned(),
+            });
+
+            let items = r#"[1, true, "text"]"#;
+            let mut step = for_each_step(&conn, processor, items, ForEachFailureMode::FailFast);
+
+            // Outputs that are valid JSON are included as is, all other
+            // outputs are included as strings.
+            assert_eq!(
+                run_items(&mut step, &conn),
+                Ok(Some(r#"[1,true,"text"]"#.to_owned()))
+            );
+
+            let mut step = for_each_step(&conn, ok_items(), "[]", ForEachFailureMode::FailFast);
+            assert_eq!(run_items(&mut step, &conn), Ok(Some("[]".to_owned())));
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_run_for_each_fail_fast() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let items = r#"["ok", "bad", "ok", "worse"]"#;
+            let mut step = for_each_step(&conn, ok_items(), items, ForEachFailureMode::FailFast);
+
+            assert_eq!(
+                run_items(&mut step, &conn),
+                Err("item 1: bad is not ok".to_owned())
+            );
+            assert_eq!(attempted_items(&step, &conn), vec![Some(0), Some(1)]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_run_for_each_collect() {
+        let conn = connection();
+
+        conn.test_transaction::<_, DieselError, _>(|| {
+            let items = r#"["ok", "bad", "ok", "worse"]"#;
+            let mut step = for_each_step(&conn, ok_items(), items, ForEachFailureMode::Collect);
+
+            assert_eq!(
+                run_items(&mut step, &conn),
+                Err("item 1: bad is not ok\nitem 3: worse is not ok".to_owned())
+            );
+            assert_eq!(
+                attempted_items(&step, &conn),
+                vec![Some(0), Some(1), Some(2), Some(3)]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn test_condition_met() {
+        let var = vec![("Environment", "production")].into_iter().collect();
+        let output = vec![("Fetch", "found")].into_iter().collect();
+        let failure = Failure {
+            step: "Deploy".to_owned(),
+            error: "boom".to_owned(),
+        };
+
+        let data = TemplateData {
+            var,
+            global: HashMap::new(),
+            context: ContextVariables {
+                workspace_path: "/tmp",
+            },
+            output,
+            failure: Some(&failure),
+            item: None,
+            index: None,
+        };
+
+        let met = |condition: &str| condition_met(condition, &data);
+
+        assert_eq!(met(r#"var["Environment"] == "production""#), Ok(true));
+        assert_eq!(met(r#"var["Environment"] == "staging""#), Ok(false));
+        assert_eq!(met(r#"output["Fetch"] == "found""#), Ok(true));
+        assert_eq!(met(r#"output["Fetch"] != "found""#), Ok(false));
+        assert_eq!(met(r#"failure.step == "Deploy""#), Ok(true));
+        assert_eq!(met(r#"failure and failure.error == "other""#), Ok(false));
+        assert!(met("var[").is_err());
+    }
+
+    #[test]
+    fn test_validate_condition() {
+        assert!(validate_condition(r#"var["Environment"] == "production""#).is_ok());
+        assert!(validate_condition("failure").is_ok());
+        assert!(validate_condition("var[").is_err());
+        assert!(validate_condition("true %}{% endif").is_err());
+        assert!(validate_condition("").is_err());
     }
 }
@@ -0,0 +1,134 @@
+//! A [`JobStepAttempt`] records a single attempt to run a [`JobStep`].
+//!
+//! A step that has no retry policy is attempted once. A step that has a retry
+//! policy can be attempted multiple times, until one of the attempts succeeds,
+//! or the policy decides to stop retrying.
+//!
+//! A step that runs once for every item in a JSON array records the attempts
+//! of each item separately.
+
+use crate::resources::JobStep;
+use crate::schema::job_step_attempts;
+use crate::server::RequestState;
+use chrono::prelude::*;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+/// The model representing a job step attempt stored in the database.
+#[derive(Clone, Debug, AsChangeset, Associations, Identifiable, Queryable)]
+#[belongs_to(JobStep)]
+#[table_name = "job_step_attempts"]
+pub(crate) struct JobStepAttempt {
+    pub(crate) id: i32,
+    pub(crate) attempt: i32,
+    pub(crate) started_at: NaiveDateTime,
+    pub(crate) finished_at: Option<NaiveDateTime>,
+    pub(crate) error: Option<String>,
+    pub(crate) job_step_id: i32,
+
+    /// The index of the item the attempt ran for, if the step runs once for
+    /// every item in a JSON array.
+    pub(crate) item: Option<i32>,
+}
+
+impl JobStepAttempt {
+    /// Mark the attempt as finished, with the error of the attempt, if it
+    /// failed.
+    pub(crate) fn finished(
+        &mut self,
+        conn: &PgConnection,
+        error: Option<String>,
+    ) -> QueryResult<()> {
+        self.finished_at = Some(Utc::now().naive_utc());
+        self.error = error;
+
+        self.save_changes::<Self>(conn).map(|_| ())
+    }
+}
+
+/// Use this struct to start a new job step attempt.
+#[derive(Debug, Insertable)]
+#[table_name = "job_step_attempts"]
+pub(crate) struct NewJobStepAttempt {
+    attempt: i32,
+    started_at: NaiveDateTime,
+    job_step_id: i32,
+    item: Option<i32>,
+}
+
+impl NewJobStepAttempt {
+    /// Initialize a new attempt of the provided job step, starting now.
+    ///
+    /// Attempts are numbered starting at `1`.
+    pub(crate) fn new(step: &JobStep, attempt: i32) -> Self {
+        Self {
+            attempt,
+            started_at: Utc::now().naive_utc(),
+            job_step_id: step.id,
+            item: None,
+        }
+    }
+
+    /// Record the attempt as running for the item at the provided index.
+    pub(crate) fn with_item(&mut self, index: i32) {
+        self.item = Some(index)
+    }
+
+    /// Save the attempt in the database.
+    pub(crate) fn create(self, conn: &PgConnection) -> QueryResult<JobStepAttempt> {
+        diesel::insert_into(job_step_attempts::table)
+            .values(&self)
+            .get_result(conn)
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use juniper::{object, ID};
+
+    #[object(Context = RequestState)]
+    impl JobStepAttempt {
+        /// The unique identifier for a specific attempt.
+        fn id() -> ID {
+            ID::new(self.id.to_string())
+        }
+
+        /// The number of the attempt, starting at `1` for the first attempt.
+        fn attempt() -> i32 {
+            self.attempt
+        }
+
+        /// The index of the item the attempt ran for, if the step runs once
+        /// for every item in a JSON array.
+        fn item() -> Option<i32> {
+            self.item
+        }
+
+        /// The moment at which the attempt started.
+        fn started_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.started_at, Utc)
+        }
+
+        /// The moment at which the attempt finished, or `null` if it is still
+        /// running.
+        fn finished_at() -> Option<DateTime<Utc>> {
+            self.finished_at.map(|t| DateTime::from_utc(t, Utc))
+        }
+
+        /// The error that made the attempt fail, or `null` if the attempt
+        /// succeeded, or is still running.
+        fn error() -> Option<&str> {
+            self.error.as_ref().map(String::as_ref)
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! A [`JobStepLog`] records a chunk of output written by the processor of a
+//! [`JobStep`], while the processor is running.
+//!
+//! Chunks are stored shortly after they are written, so that clients can
+//! follow the output of a long-running step, by fetching the chunks written
+//! after the ones they already fetched.
+
+use crate::resources::JobStep;
+use crate::schema::job_step_logs;
+use crate::server::{DatabasePool, RequestState};
+use automaat_core::{LogSink, LogStream};
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use juniper::GraphQLEnum;
+use serde::{Deserialize, Serialize};
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The maximum amount of time a chunk of output is buffered by a [`Sink`],
+/// before it is stored along with the other chunks written in that time.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The maximum number of chunks a [`Sink`] stores at once.
+const MAX_BATCH_SIZE: usize = 100;
+
+/// The maximum amount of time a [`Sink`] waits for a database connection to
+/// store a batch of chunks, before dropping the batch.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The stream to which a chunk of output was written.
+#[derive(Clone, Copy, Debug, DbEnum, GraphQLEnum, Serialize, Deserialize)]
+#[PgType = "JobStepLogStream"]
+#[graphql(name = "JobStepLogStream")]
+pub(crate) enum Stream {
+    /// The standard output stream.
+    Stdout,
+
+    /// The standard error stream.
+    Stderr,
+}
+
+impl From<LogStream> for Stream {
+    fn from(stream: LogStream) -> Self {
+        match stream {
+            LogStream::Stdout => Stream::Stdout,
+            LogStream::Stderr => Stream::Stderr,
+        }
+    }
+}
+
+/// The model representing a chunk of job step output stored in the database.
+#[derive(Clone, Debug, Associations, Identifiable, Queryable)]
+#[belongs_to(JobStep)]
+#[table_name = "job_step_logs"]
+pub(crate) struct JobStepLog {
+    pub(crate) id: i32,
+    pub(crate) stream: Stream,
+    pub(crate) chunk: String,
+    pub(crate) created_at: NaiveDateTime,
+    pub(crate) job_step_id: i32,
+}
+
+impl JobStepLog {
+    /// The Postgres channel on which a notification is sent whenever a chunk
+    /// of output is stored. The payload of the notification is the ID of the
+    /// job step.
+    ///
+    /// These notifications are sent by a database trigger.
+    pub(crate) const CHANNEL: &'static str = "automaat_job_step_logs";
+
+    /// Find the chunks of output of the provided step, in the order they were
+    /// written, skipping the provided number of chunks.
+    pub(crate) fn find_for_step(
+        step: &JobStep,
+        offset: i64,
+        limit: Option<i64>,
+        conn: &PgConnection,
+    ) -> QueryResult<Vec<Self>> {
+        let query = Self::belonging_to(step)
+            .order(job_step_logs::id)
+            .offset(offset);
+
+        match limit {
+            Some(limit) => query.limit(limit).load(conn),
+            None => query.load(conn),
+        }
+    }
+}
+
+/// Use this struct to store a new chunk of job step output.
+#[derive(Debug, Insertable)]
+#[table_name = "job_step_logs"]
+pub(crate) struct NewJobStepLog<'a> {
+    stream: Stream,
+    chunk: &'a str,
+    job_step_id: i32,
+}
+
+impl<'a> NewJobStepLog<'a> {
+    /// Initialize a new chunk of output, written to the provided stream by
+    /// the processor of the job step with the provided ID.
+    pub(crate) fn new(job_step_id: i32, stream: Stream, chunk: &'a str) -> Self {
+        Self {
+            stream,
+            chunk,
+            job_step_id,
+        }
+    }
+
+    /// Save the chunk in the database.
+    pub(crate) fn create(self, conn: &PgConnection) -> QueryResult<()> {
+        Self::create_all(&[self], conn)
+    }
+
+    /// Save the provided chunks in the database, using a single query.
+    ///
+    /// Postgres does not allow NUL characters in text values, so these are
+    /// removed from the chunks.
+    pub(crate) fn create_all(logs: &[Self], conn: &PgConnection) -> QueryResult<()> {
+        let chunks: Vec<_> = logs.iter().map(|log| log.chunk.replace('\0', "")).collect();
+        let logs: Vec<_> = logs
+            .iter()
+            .zip(&chunks)
+            .map(|(log, chunk)| NewJobStepLog::new(log.job_step_id, log.stream, chunk))
+            .collect();
+
+        diesel::insert_into(job_step_logs::table)
+            .values(&logs)
+            .execute(conn)
+            .map(|_| ())
+    }
+}
+
+/// A [`LogSink`] storing the output of the processor of a job step in the
+/// database, while it runs.
+///
+/// Appending a chunk never blocks the processor. The chunks are buffered, and
+/// stored in batches on a separate thread, using a connection of its own, so
+/// that a processor writing many small chunks does not run a query for each
+/// of them. A batch that can't be stored is dropped, without failing the step.
+///
+/// Dropping the sink waits for the buffered chunks to be stored.
+#[derive(Debug)]
+pub(crate) struct Sink {
+    sender: Option<Mutex<mpsc::Sender<(Stream, String)>>>,
+    flusher: Option<thread::JoinHandle<()>>,
+}
+
+impl Sink {
+    /// Create a new sink for the provided job step.
+    pub(crate) fn new(pool: DatabasePool, step: &JobStep) -> Self {
+        let job_step_id = step.id;
+        let (sender, receiver) = mpsc::channel();
+        let flusher = thread::spawn(move || flush(&pool, job_step_id, &receiver));
+
+        Self {
+            sender: Some(Mutex::new(sender)),
+            flusher: Some(flusher),
+        }
+    }
+}
+
+impl LogSink for Sink {
+    fn append(&self, stream: LogStream, chunk: &str) {
+        if let Some(sender) = &self.sender {
+            let _ = sender
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .send((stream.into(), chunk.to_owned()));
+        }
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        drop(self.sender.take());
+
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+    }
+}
+
+/// Store the chunks received from a [`Sink`] in batches, until the sink is
+/// dropped, and all its chunks are stored.
+///
+/// A batch is stored once it is full, or once the first chunk of the batch is
+/// buffered for the flush interval.
+fn flush(pool: &DatabasePool, job_step_id: i32, receiver: &mpsc::Receiver<(Stream, String)>) {
+    while let Ok(first) = receiver.recv() {
+        let deadline = Instant::now() + FLUSH_INTERVAL;
+        let mut batch = vec![first];
+
+        while batch.len() < MAX_BATCH_SIZE {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            match receiver.recv_timeout(deadline - now) {
+                Ok(chunk) => batch.push(chunk),
+                Err(_) => break,
+            }
+        }
+
+        if let Ok(conn) = pool.get_timeout(CONNECTION_TIMEOUT) {
+            let logs: Vec<_> = batch
+                .iter()
+                .map(|(stream, chunk)| NewJobStepLog::new(job_step_id, *stream, chunk))
+                .collect();
+
+            let _ = NewJobStepLog::create_all(&logs, &conn);
+        }
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use juniper::{object, ID};
+
+    #[object(Context = RequestState)]
+    impl JobStepLog {
+        /// The unique identifier for a specific chunk of output.
+        fn id() -> ID {
+            ID::new(self.id.to_string())
+        }
+
+        /// The stream to which the processor wrote the output.
+        fn stream() -> Stream {
+            self.stream
+        }
+
+        /// The output written by the processor.
+        fn chunk() -> &str {
+            &self.chunk
+        }
+
+        /// The moment at which the output was stored.
+        fn created_at() -> DateTime<Utc> {
+            DateTime::from_utc(self.created_at, Utc)
+        }
+    }
+}
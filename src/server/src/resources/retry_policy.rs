@@ -0,0 +1,226 @@
+//! A [`RetryPolicy`] describes if, and how often, a failed step is retried.
+//!
+//! The policy is stored alongside a [`Step`], and copied to every [`JobStep`]
+//! created from that step. Each attempt to run a job step is recorded, so that
+//! the errors of failed attempts remain visible, even if a later attempt
+//! succeeds.
+//!
+//! [`Step`]: crate::resources::Step
+//! [`JobStep`]: crate::resources::JobStep
+
+use juniper::{GraphQLEnum, GraphQLObject};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// The strategy used to calculate the delay between two attempts.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, GraphQLEnum)]
+pub(crate) enum Backoff {
+    /// Wait the same delay before every attempt.
+    Fixed,
+
+    /// Increase the delay by the initial delay after every attempt.
+    Linear,
+
+    /// Double the delay after every attempt.
+    Exponential,
+}
+
+/// The policy used to retry a step that failed to run.
+#[derive(Clone, Debug, Deserialize, Serialize, GraphQLObject)]
+pub(crate) struct RetryPolicy {
+    /// The maximum number of times the step runs, including the first
+    /// attempt.
+    pub(crate) max_attempts: i32,
+
+    /// The strategy used to calculate the delay between two attempts.
+    pub(crate) backoff: Backoff,
+
+    /// The number of seconds to wait before the first retry.
+    pub(crate) delay_seconds: i32,
+
+    /// The maximum number of seconds to wait between two attempts, if any.
+    pub(crate) max_delay_seconds: Option<i32>,
+
+    /// An optional list of error fragments. If provided, a failed attempt is
+    /// only retried if its error contains one of these fragments.
+    pub(crate) retryable_errors: Option<Vec<String>>,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the next attempt, after the provided attempt
+    /// failed with the provided error.
+    ///
+    /// Returns `None` if the step should not be retried, either because it ran
+    /// out of attempts, or because the error is not retryable.
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn retry_delay(&self, attempt: i32, error: &str) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.is_retryable(error) {
+            return None;
+        }
+
+        let delay = i64::from(self.delay_seconds);
+        let delay = match self.backoff {
+            Backoff::Fixed => delay,
+            Backoff::Linear => delay.saturating_mul(i64::from(attempt)),
+            Backoff::Exponential => delay.saturating_mul(1_i64 << (attempt - 1).min(32)),
+        };
+
+        let delay = match self.max_delay_seconds {
+            Some(max) => delay.min(i64::from(max)),
+            None => delay,
+        };
+
+        Some(Duration::from_secs(delay as u64))
+    }
+
+    fn is_retryable(&self, error: &str) -> bool {
+        match &self.retryable_errors {
+            None => true,
+            Some(fragments) => fragments.iter().any(|f| error.contains(f.as_str())),
+        }
+    }
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use juniper::GraphQLInputObject;
+
+    /// Contains all the data needed to retry a step that failed to run.
+    #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
+    pub(crate) struct RetryPolicyInput {
+        /// The maximum number of times the step runs, including the first
+        /// attempt.
+        ///
+        /// This value has to be at least `1`.
+        pub(crate) max_attempts: i32,
+
+        /// The strategy used to calculate the delay between two attempts.
+        ///
+        /// Defaults to `FIXED`.
+        pub(crate) backoff: Option<Backoff>,
+
+        /// The number of seconds to wait before the first retry.
+        ///
+        /// Defaults to `1`.
+        pub(crate) delay_seconds: Option<i32>,
+
+        /// The maximum number of seconds to wait between two attempts.
+        ///
+        /// Use this to limit the delay of the `LINEAR` and `EXPONENTIAL`
+        /// backoff strategies.
+        pub(crate) max_delay_seconds: Option<i32>,
+
+        /// An optional list of error fragments.
+        ///
+        /// If provided, a failed attempt is only retried if its error contains
+        /// one of these fragments, for example `timed out`. Without this list,
+        /// all errors are retried.
+        pub(crate) retryable_errors: Option<Vec<String>>,
+    }
+}
+
+impl TryFrom<&graphql::RetryPolicyInput> for RetryPolicy {
+    type Error = String;
+
+    fn try_from(input: &graphql::RetryPolicyInput) -> Result<Self, Self::Error> {
+        let delay_seconds = input.delay_seconds.unwrap_or(1);
+
+        if input.max_attempts < 1 {
+            return Err("Retry policy needs at least one attempt.".to_owned());
+        }
+
+        if delay_seconds < 0 || input.max_delay_seconds.map_or(false, |max| max < 0) {
+            return Err("Retry policy delays cannot be negative.".to_owned());
+        }
+
+        Ok(Self {
+            max_attempts: input.max_attempts,
+            backoff: input.backoff.unwrap_or(Backoff::Fixed),
+            delay_seconds,
+            max_delay_seconds: input.max_delay_seconds,
+            retryable_errors: input.retryable_errors.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(backoff: Backoff) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            backoff,
+            delay_seconds: 2,
+            max_delay_seconds: None,
+            retryable_errors: None,
+        }
+    }
+
+    fn delays(policy: &RetryPolicy) -> Vec<Option<u64>> {
+        (1..=5)
+            .map(|attempt| policy.retry_delay(attempt, "error").map(|d| d.as_secs()))
+            .collect()
+    }
+
+    #[test]
+    fn test_fixed_backoff() {
+        let policy = policy(Backoff::Fixed);
+
+        assert_eq!(
+            delays(&policy),
+            vec![Some(2), Some(2), Some(2), Some(2), None]
+        );
+    }
+
+    #[test]
+    fn test_linear_backoff() {
+        let policy = policy(Backoff::Linear);
+
+        assert_eq!(
+            delays(&policy),
+            vec![Some(2), Some(4), Some(6), Some(8), None]
+        );
+    }
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = policy(Backoff::Exponential);
+
+        assert_eq!(
+            delays(&policy),
+            vec![Some(2), Some(4), Some(8), Some(16), None]
+        );
+    }
+
+    #[test]
+    fn test_max_delay() {
+        let mut policy = policy(Backoff::Exponential);
+        policy.max_delay_seconds = Some(5);
+
+        assert_eq!(
+            delays(&policy),
+            vec![Some(2), Some(4), Some(5), Some(5), None]
+        );
+    }
+
+    #[test]
+    fn test_retryable_errors() {
+        let mut policy = policy(Backoff::Fixed);
+        policy.retryable_errors = Some(vec!["timed out".to_owned()]);
+
+        assert!(policy.retry_delay(1, "operation timed out").is_some());
+        assert!(policy.retry_delay(1, "404 not found").is_none());
+    }
+}
@@ -0,0 +1,407 @@
+//! A [`Schedule`] triggers a [`Task`] at fixed moments in time, described by a
+//! cron expression.
+//!
+//! A schedule holds a fixed set of variable values, which are used to create a
+//! new [`Job`] each time the schedule fires.
+//!
+//! The next job of an active schedule is always created ahead of time, with
+//! the `Scheduled` status. Once that job is due, the [`Scheduler`] moves it to
+//! the `Pending` state, after which a worker picks it up, and the job after it
+//! is created.
+//!
+//! [`Scheduler`]: crate::scheduler::Scheduler
+
+use crate::models::{NewScheduleVariable, ScheduleVariable};
+use crate::resources::{Job, JobStatus, NewJob, NewJobVariable, Task};
+use crate::schema::{jobs, schedules};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::error::Error;
+use std::str::FromStr;
+
+/// The model representing a schedule stored in the database.
+#[derive(
+    Clone, Debug, Deserialize, Serialize, AsChangeset, Associations, Identifiable, Queryable,
+)]
+#[belongs_to(Task)]
+#[table_name = "schedules"]
+pub(crate) struct Schedule {
+    pub(crate) id: i32,
+    pub(crate) expression: String,
+    pub(crate) timezone: String,
+    pub(crate) paused: bool,
+    pub(crate) task_id: i32,
+}
+
+impl Schedule {
+    /// Find all active schedules that have no upcoming job.
+    pub(crate) fn find_unscheduled(conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        let scheduled_job = jobs::schedule_id
+            .eq(schedules::id.nullable())
+            .and(jobs::status.eq(JobStatus::Scheduled));
+
+        schedules::table
+            .left_join(jobs::table.on(scheduled_job))
+            .filter(schedules::paused.eq(false))
+            .filter(jobs::id.nullable().is_null())
+            .select(schedules::all_columns)
+            .order(schedules::id)
+            .load(conn)
+    }
+
+    pub(crate) fn task(&self, conn: &PgConnection) -> QueryResult<Task> {
+        use crate::schema::tasks::dsl::*;
+
+        tasks.filter(id.eq(self.task_id)).first(conn)
+    }
+
+    pub(crate) fn variables(&self, conn: &PgConnection) -> QueryResult<Vec<ScheduleVariable>> {
+        ScheduleVariable::find_by_schedule(self, conn)
+    }
+
+    /// The upcoming job of the schedule, if any.
+    pub(crate) fn next_job(&self, conn: &PgConnection) -> QueryResult<Option<Job>> {
+        Job::belonging_to(self)
+            .filter(jobs::status.eq(JobStatus::Scheduled))
+            .first(conn)
+            .optional()
+    }
+
+    /// Create the upcoming job of the schedule.
+    ///
+    /// The job is scheduled for the first moment the schedule fires, after
+    /// both the current time, and the moment of any job created by the
+    /// schedule before.
+    ///
+    /// Returns `None` if the schedule is paused, or if it never fires again.
+    pub(crate) fn schedule_next_job(
+        &self,
+        conn: &PgConnection,
+    ) -> Result<Option<Job>, Box<dyn Error>> {
+        use diesel::dsl::max;
+
+        if self.paused {
+            return Ok(None);
+        }
+
+        let previous = Job::belonging_to(self)
+            .select(max(jobs::scheduled_for))
+            .first::<Option<NaiveDateTime>>(conn)?;
+
+        let now = Utc::now().naive_utc();
+        let after = previous.map_or(now, |previous| previous.max(now));
+        let scheduled_for = match next_occurrence(&self.expression, &self.timezone, after)? {
+            None => return Ok(None),
+            Some(time) => time,
+        };
+
+        let task = self.task(conn)?;
+        let variables = self.variables(conn)?;
+        let variables = variables
+            .iter()
+            .map(|v| NewJobVariable::new(&v.key, &v.value))
+            .collect();
+
+        NewJob::create_from_schedule(conn, &task, self, scheduled_for, variables).map(Some)
+    }
+
+    /// Pause the schedule.
+    ///
+    /// The upcoming job of the schedule is removed, and no new jobs are
+    /// created until the schedule is resumed.
+    pub(crate) fn pause(&mut self, conn: &PgConnection) -> QueryResult<Self> {
+        conn.transaction(|| {
+            self.delete_scheduled_jobs(conn)?;
+
+            self.paused = true;
+            self.save_changes(conn)
+        })
+    }
+
+    /// Resume a paused schedule, and create its upcoming job.
+    pub(crate) fn resume(&mut self, conn: &PgConnection) -> Result<Self, Box<dyn Error>> {
+        conn.transaction(|| {
+            self.paused = false;
+            let schedule: Self = self.save_changes(conn)?;
+
+            if schedule.next_job(conn)?.is_none() {
+                let _ = schedule.schedule_next_job(conn)?;
+            }
+
+            Ok(schedule)
+        })
+    }
+
+    /// Delete the schedule, and its upcoming job.
+    ///
+    /// Jobs created by the schedule that already ran are kept.
+    pub(crate) fn delete(self, conn: &PgConnection) -> QueryResult<()> {
+        conn.transaction(|| {
+            self.delete_scheduled_jobs(conn)?;
+
+            diesel::delete(&self).execute(conn).map(|_| ())
+        })
+    }
+
+    fn delete_scheduled_jobs(&self, conn: &PgConnection) -> QueryResult<()> {
+        let scheduled = Job::belonging_to(self).filter(jobs::status.eq(JobStatus::Scheduled));
+
+        diesel::delete(scheduled).execute(conn).map(|_| ())
+    }
+}
+
+/// Contains all the details needed to store a schedule in the database.
+///
+/// Use [`NewSchedule::new`] to initialize this struct.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct NewSchedule<'a> {
+    expression: &'a str,
+    timezone: &'a str,
+    task_id: i32,
+    variables: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> NewSchedule<'a> {
+    /// Initialize a `NewSchedule` struct, which can be inserted into the
+    /// database using the [`NewSchedule#create`] method.
+    pub(crate) fn new(expression: &'a str, timezone: &'a str, task_id: i32) -> Self {
+        Self {
+            expression,
+            timezone,
+            task_id,
+            variables: vec![],
+        }
+    }
+
+    /// Attach zero or more variable values to this schedule.
+    ///
+    /// Can be called multiple times to append more variables.
+    pub(crate) fn with_variables(&mut self, mut variables: Vec<(&'a str, &'a str)>) {
+        self.variables.append(&mut variables)
+    }
+
+    /// Persist the schedule into the database, and create its first upcoming
+    /// job.
+    ///
+    /// An error is returned if the cron expression or the timezone are
+    /// invalid, or if the task requires variables the schedule has no value
+    /// for.
+    pub(crate) fn create(self, conn: &PgConnection) -> Result<Schedule, Box<dyn Error>> {
+        use crate::schema::schedules::dsl::*;
+
+        let _ = parse_expression(self.expression)?;
+        let _ = parse_timezone(self.timezone)?;
+
+        conn.transaction(|| {
+            let values = (
+                expression.eq(&self.expression),
+                timezone.eq(&self.timezone),
+                task_id.eq(self.task_id),
+            );
+
+            let schedule: Schedule = diesel::insert_into(schedules)
+                .values(&values)
+                .get_result(conn)?;
+
+            self.variables.iter().try_for_each(|(key, value)| {
+                NewScheduleVariable::new(key, value, schedule.id).create(conn)
+            })?;
+
+            // Creating the first job also validates the variable values against
+            // the task variables.
+            let _ = schedule.schedule_next_job(conn)?;
+
+            Ok(schedule)
+        })
+    }
+}
+
+/// Parse a cron expression.
+///
+/// Both the regular five-field syntax (starting with minutes) and the extended
+/// syntax with a leading seconds field are supported.
+fn parse_expression(expression: &str) -> Result<cron::Schedule, Box<dyn Error>> {
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_owned(),
+    };
+
+    cron::Schedule::from_str(&expression)
+        .map_err(|err| format!("invalid cron expression: {}", err).into())
+}
+
+fn parse_timezone(timezone: &str) -> Result<Tz, Box<dyn Error>> {
+    timezone
+        .parse()
+        .map_err(|_| format!("unknown timezone: {}", timezone).into())
+}
+
+/// Return the first moment (in UTC) after `after` at which the cron expression
+/// fires, interpreting the expression in the provided timezone.
+fn next_occurrence(
+    expression: &str,
+    timezone: &str,
+    after: NaiveDateTime,
+) -> Result<Option<NaiveDateTime>, Box<dyn Error>> {
+    let schedule = parse_expression(expression)?;
+    let timezone = parse_timezone(timezone)?;
+    let after = DateTime::<Utc>::from_utc(after, Utc).with_timezone(&timezone);
+
+    Ok(schedule.after(&after).next().map(|time| time.naive_utc()))
+}
+
+pub(crate) mod graphql {
+    //! All GraphQL related functionality is encapsulated in this module. The
+    //! relevant functions and structs are re-exported through
+    //! [`crate::graphql`].
+    //!
+    //! API documentation in this module is also used in the GraphQL API itself
+    //! as documentation for the clients.
+    //!
+    //! You can browse to `/graphql/playground` to see all relevant query,
+    //! mutation, and type documentation.
+
+    use super::*;
+    use crate::resources::JobVariableInput;
+    use crate::server::RequestState;
+    use juniper::{object, FieldResult, GraphQLInputObject, ID};
+
+    /// Contains all the data needed to create a new `Schedule`.
+    #[derive(Clone, Debug, Deserialize, Serialize, GraphQLInputObject)]
+    pub(crate) struct CreateScheduleInput {
+        /// The `id` of the task to run on a schedule.
+        pub(crate) task_id: ID,
+
+        /// The cron expression defining when the task runs.
+        ///
+        /// Both the regular five-field syntax (e.g. `30 9 * * Mon-Fri`) and
+        /// the extended syntax with a leading seconds field (e.g.
+        /// `0 30 9 * * Mon-Fri`) are supported.
+        pub(crate) expression: String,
+
+        /// The timezone in which the cron expression is interpreted, such as
+        /// `Europe/Amsterdam`.
+        ///
+        /// Defaults to `UTC`.
+        pub(crate) timezone: Option<String>,
+
+        /// The variable values used for each job created by the schedule.
+        ///
+        /// A value must be provided for every variable of the task.
+        pub(crate) variables: Vec<JobVariableInput>,
+    }
+
+    #[object(Context = RequestState)]
+    impl Schedule {
+        /// The unique identifier for a specific schedule.
+        fn id() -> ID {
+            ID::new(self.id.to_string())
+        }
+
+        /// The cron expression defining when the task runs.
+        fn expression() -> &str {
+            self.expression.as_ref()
+        }
+
+        /// The timezone in which the cron expression is interpreted.
+        fn timezone() -> &str {
+            self.timezone.as_ref()
+        }
+
+        /// Whether the schedule is paused.
+        ///
+        /// A paused schedule does not create any jobs until it is resumed.
+        fn paused() -> bool {
+            self.paused
+        }
+
+        /// The task run by the schedule.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        fn task(context: &RequestState) -> FieldResult<Option<Task>> {
+            self.task(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// The upcoming job of the schedule.
+        ///
+        /// This job has the `SCHEDULED` status, and its `scheduledFor` field
+        /// defines when it runs.
+        ///
+        /// Returns `null` if the schedule is paused, or never fires again.
+        fn next_job(context: &RequestState) -> FieldResult<Option<Job>> {
+            self.next_job(&context.conn).map_err(Into::into)
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a graphql::CreateScheduleInput> for NewSchedule<'a> {
+    type Error = String;
+
+    fn try_from(input: &'a graphql::CreateScheduleInput) -> Result<Self, Self::Error> {
+        let task_id = input
+            .task_id
+            .parse::<i32>()
+            .map_err(|_| format!("invalid task ID: {}", input.task_id.to_string()))?;
+
+        let timezone = input.timezone.as_ref().map_or("UTC", String::as_str);
+        let variables = input
+            .variables
+            .iter()
+            .map(|v| (v.key.as_str(), v.value.as_str()))
+            .collect();
+
+        let mut schedule = Self::new(&input.expression, timezone, task_id);
+        schedule.with_variables(variables);
+
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naive(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_next_occurrence_five_fields() {
+        let next = next_occurrence("30 9 * * *", "UTC", naive("2019-09-17 10:00:00")).unwrap();
+
+        assert_eq!(next, Some(naive("2019-09-18 09:30:00")))
+    }
+
+    #[test]
+    fn test_next_occurrence_six_fields() {
+        let next = next_occurrence("15 * * * * *", "UTC", naive("2019-09-17 10:00:00")).unwrap();
+
+        assert_eq!(next, Some(naive("2019-09-17 10:00:15")))
+    }
+
+    #[test]
+    fn test_next_occurrence_timezone() {
+        let next = next_occurrence(
+            "0 9 * * *",
+            "Europe/Amsterdam",
+            naive("2019-09-17 00:00:00"),
+        )
+        .unwrap();
+
+        assert_eq!(next, Some(naive("2019-09-17 07:00:00")))
+    }
+
+    #[test]
+    fn test_next_occurrence_invalid_expression() {
+        assert!(next_occurrence("invalid", "UTC", naive("2019-09-17 00:00:00")).is_err())
+    }
+
+    #[test]
+    fn test_next_occurrence_invalid_timezone() {
+        assert!(next_occurrence("0 9 * * *", "Mars/Olympus", naive("2019-09-17 00:00:00")).is_err())
+    }
+}
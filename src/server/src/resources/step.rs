@@ -5,14 +5,40 @@
 //!
 //! [`Processor`]: crate::Processor
 
-use crate::resources::Task;
+use crate::resources::{validate_step_condition, ForEach, RetryPolicy, Task};
 use crate::schema::{steps, variable_advertisements};
 use crate::{server::RequestState, Processor};
 use diesel::prelude::*;
+use juniper::GraphQLEnum;
 use serde::{Deserialize, Serialize};
 use std::convert::{AsRef, TryFrom, TryInto};
 use std::error::Error;
 
+pub(crate) mod dependencies;
+
+/// Defines when a step runs, depending on the success of the steps that ran
+/// before it.
+#[derive(Clone, Copy, Debug, PartialEq, DbEnum, GraphQLEnum, Serialize, Deserialize)]
+#[PgType = "StepRunPolicy"]
+#[graphql(name = "StepRunPolicy")]
+pub(crate) enum RunPolicy {
+    /// The step only runs if none of the steps before it failed.
+    OnSuccess,
+
+    /// The step only runs if one of the steps before it failed.
+    OnFailure,
+
+    /// The step always runs, whether any of the steps before it failed or
+    /// not.
+    Always,
+}
+
+impl Default for RunPolicy {
+    fn default() -> Self {
+        Self::OnSuccess
+    }
+}
+
 /// The model representing a step stored in the database.
 #[derive(Clone, Debug, Deserialize, Serialize, Associations, Identifiable, Queryable)]
 #[belongs_to(Task)]
@@ -24,6 +50,19 @@ pub(crate) struct Step {
     pub(crate) processor: serde_json::Value,
     pub(crate) position: i32,
     pub(crate) task_id: i32,
+
+    /// The number of seconds the step is allowed to run, if limited.
+    pub(crate) timeout_seconds: Option<i32>,
+    pub(crate) retry_policy: Option<serde_json::Value>,
+
+    /// The condition that has to be met for the step to run, if any.
+    pub(crate) when: Option<String>,
+    pub(crate) run_policy: RunPolicy,
+
+    /// The names of the steps this step depends on, or `None` if the step
+    /// depends on the step positioned before it.
+    pub(crate) depends_on: Option<Vec<String>>,
+    pub(crate) for_each: Option<serde_json::Value>,
 }
 
 impl Step {
@@ -31,6 +70,22 @@ impl Step {
         serde_json::from_value(self.processor.clone())
     }
 
+    /// Returns the retry policy of the step, if any.
+    pub(crate) fn retry_policy(&self) -> Result<Option<RetryPolicy>, serde_json::Error> {
+        self.retry_policy
+            .clone()
+            .map(serde_json::from_value)
+            .transpose()
+    }
+
+    /// Returns the for each configuration of the step, if any.
+    pub(crate) fn for_each(&self) -> Result<Option<ForEach>, serde_json::Error> {
+        self.for_each
+            .clone()
+            .map(serde_json::from_value)
+            .transpose()
+    }
+
     pub(crate) fn task(&self, conn: &PgConnection) -> QueryResult<Task> {
         use crate::schema::tasks::dsl::*;
 
@@ -49,6 +104,12 @@ pub(crate) struct NewStep<'a> {
     position: i32,
     advertised_variable_key: Option<&'a str>,
     task_id: Option<i32>,
+    timeout_seconds: Option<i32>,
+    retry_policy: Option<RetryPolicy>,
+    when: Option<&'a str>,
+    run_policy: RunPolicy,
+    depends_on: Option<Vec<&'a str>>,
+    for_each: Option<ForEach>,
 }
 
 impl<'a> NewStep<'a> {
@@ -68,9 +129,54 @@ impl<'a> NewStep<'a> {
             position,
             advertised_variable_key,
             task_id: None,
+            timeout_seconds: None,
+            retry_policy: None,
+            when: None,
+            run_policy: RunPolicy::OnSuccess,
+            depends_on: None,
+            for_each: None,
         }
     }
 
+    /// Limit the number of seconds the step is allowed to run.
+    pub(crate) fn with_timeout_seconds(&mut self, seconds: Option<i32>) {
+        self.timeout_seconds = seconds
+    }
+
+    /// Retry the step according to the provided policy, if it fails to run.
+    pub(crate) fn with_retry_policy(&mut self, policy: Option<RetryPolicy>) {
+        self.retry_policy = policy
+    }
+
+    /// Only run the step if the provided template expression evaluates to a
+    /// truthy value.
+    pub(crate) fn with_when(&mut self, when: Option<&'a str>) {
+        self.when = when
+    }
+
+    /// Define when the step runs, depending on the success of the steps that
+    /// ran before it.
+    pub(crate) fn with_run_policy(&mut self, policy: RunPolicy) {
+        self.run_policy = policy
+    }
+
+    /// Run the step once all of the steps with the provided names finished,
+    /// instead of once the step positioned before it finished.
+    pub(crate) fn with_depends_on(&mut self, names: Option<Vec<&'a str>>) {
+        self.depends_on = names
+    }
+
+    /// The names of the steps this step depends on, or `None` if the step
+    /// depends on the step positioned before it.
+    pub(crate) fn depends_on(&self) -> Option<&[&'a str]> {
+        self.depends_on.as_ref().map(Vec::as_slice)
+    }
+
+    /// Run the processor of the step once for every item in a JSON array.
+    pub(crate) fn with_for_each(&mut self, for_each: Option<ForEach>) {
+        self.for_each = for_each
+    }
+
     /// Add a step to a [`Task`], by storing it in the database as an
     /// association.
     ///
@@ -99,6 +205,12 @@ impl<'a> NewStep<'a> {
             steps::processor.eq(serde_json::to_value(self.processor)?),
             steps::position.eq(&self.position),
             steps::task_id.eq(self.task_id.unwrap_or(task.id)),
+            steps::timeout_seconds.eq(self.timeout_seconds),
+            steps::retry_policy.eq(self.retry_policy.map(serde_json::to_value).transpose()?),
+            steps::when.eq(self.when),
+            steps::run_policy.eq(self.run_policy),
+            steps::depends_on.eq(&self.depends_on),
+            steps::for_each.eq(self.for_each.map(serde_json::to_value).transpose()?),
         );
 
         let advertised_key = &self.advertised_variable_key;
@@ -139,6 +251,7 @@ pub(crate) mod graphql {
     //! mutation, and type documentation.
 
     use super::*;
+    use crate::resources::{ForEachInput, RetryPolicyInput};
     use crate::ProcessorInput;
     use juniper::{object, FieldResult, GraphQLInputObject, ID};
 
@@ -185,6 +298,58 @@ pub(crate) mod graphql {
         /// its input, can use the task this step belongs to to fetch that
         /// value.
         pub(crate) advertised_variable_key: Option<String>,
+
+        /// An optional number of seconds the step is allowed to run.
+        ///
+        /// If the step runs longer, it fails with a timeout error, and the job
+        /// running the step fails as well.
+        pub(crate) timeout_seconds: Option<i32>,
+
+        /// An optional policy to retry the step if it fails to run.
+        ///
+        /// Without a policy, a failing step fails the job right away.
+        pub(crate) retry_policy: Option<RetryPolicyInput>,
+
+        /// An optional condition that has to be met for the step to run.
+        ///
+        /// The condition is a template expression, using the same variables
+        /// that are available in the processor configuration, for example
+        /// `var["Environment"] == "production"`.
+        ///
+        /// If the condition is not met, the step is skipped, and the job
+        /// continues with the next step. A condition that is not a valid
+        /// expression is rejected when the task is created.
+        pub(crate) when: Option<String>,
+
+        /// Define when the step runs, depending on the success of the steps
+        /// that ran before it.
+        ///
+        /// Use `ON_FAILURE` for steps that handle a failed job, for example to
+        /// send a notification, and `ALWAYS` for steps that clean up after the
+        /// job, whether it failed or not. These steps have access to the
+        /// `failure.step` and `failure.error` template variables, describing
+        /// the step that failed.
+        ///
+        /// Defaults to `ON_SUCCESS`.
+        pub(crate) run_policy: Option<RunPolicy>,
+
+        /// An optional list of names of the steps this step depends on.
+        ///
+        /// The step runs once all of these steps finished. Steps that do not
+        /// depend on each other run concurrently, sharing the same workspace.
+        ///
+        /// If no list is provided, the step depends on the step positioned
+        /// before it. Provide an empty list to run the step as soon as the job
+        /// starts.
+        pub(crate) depends_on: Option<Vec<String>>,
+
+        /// An optional configuration to run the processor of the step once
+        /// for every item in a JSON array.
+        ///
+        /// The output of the step is a JSON array, containing the output of
+        /// the processor for each item. Outputs that are valid JSON are
+        /// included as JSON values, other outputs are included as strings.
+        pub(crate) for_each: Option<ForEachInput>,
     }
 
     #[object(Context = RequestState)]
@@ -222,6 +387,46 @@ pub(crate) mod graphql {
             self.position
         }
 
+        /// The number of seconds the step is allowed to run, if limited.
+        fn timeout_seconds() -> Option<i32> {
+            self.timeout_seconds
+        }
+
+        /// The policy used to retry the step if it fails to run, if any.
+        ///
+        /// This query can fail, if the policy failed to be deserialized.
+        fn retry_policy() -> FieldResult<Option<RetryPolicy>> {
+            self.retry_policy().map_err(Into::into)
+        }
+
+        /// The condition that has to be met for the step to run, if any.
+        fn when() -> Option<&str> {
+            self.when.as_ref().map(String::as_ref)
+        }
+
+        /// Defines when the step runs, depending on the success of the steps
+        /// that ran before it.
+        fn run_policy() -> RunPolicy {
+            self.run_policy
+        }
+
+        /// The names of the steps this step depends on, or `null` if the step
+        /// depends on the step positioned before it.
+        fn depends_on() -> Option<Vec<&str>> {
+            self.depends_on
+                .as_ref()
+                .map(|names| names.iter().map(String::as_str).collect())
+        }
+
+        /// The configuration used to run the processor of the step once for
+        /// every item in a JSON array, if any.
+        ///
+        /// This query can fail, if the configuration failed to be
+        /// deserialized.
+        fn for_each() -> FieldResult<Option<ForEach>> {
+            self.for_each().map_err(Into::into)
+        }
+
         /// The task to which the step belongs.
         ///
         /// This field can return `null`, but _only_ if a database error
@@ -250,12 +455,44 @@ impl<'a> TryFrom<(usize, &'a graphql::CreateStepInput)> for NewStep<'a> {
     type Error = String;
 
     fn try_from((index, input): (usize, &'a graphql::CreateStepInput)) -> Result<Self, String> {
-        Ok(Self::new(
+        if input.timeout_seconds.map_or(false, |t| t <= 0) {
+            return Err("Step timeout must be at least one second.".to_owned());
+        }
+
+        let retry_policy = match &input.retry_policy {
+            None => None,
+            Some(policy) => Some(policy.try_into()?),
+        };
+
+        let for_each = match &input.for_each {
+            None => None,
+            Some(for_each) => Some(for_each.try_into()?),
+        };
+
+        if let Some(when) = &input.when {
+            validate_step_condition(when)
+                .map_err(|err| format!("Step condition is invalid: {}", err))?;
+        }
+
+        let mut step = Self::new(
             &input.name,
             input.description.as_ref().map(String::as_str),
             input.processor.clone().try_into()?,
             index as i32,
             input.advertised_variable_key.as_ref().map(String::as_str),
-        ))
+        );
+
+        step.with_timeout_seconds(input.timeout_seconds);
+        step.with_retry_policy(retry_policy);
+        step.with_for_each(for_each);
+        step.with_when(input.when.as_ref().map(String::as_str));
+        step.with_run_policy(input.run_policy.unwrap_or_default());
+        step.with_depends_on(
+            input
+                .depends_on
+                .as_ref()
+                .map(|names| names.iter().map(String::as_str).collect()),
+        );
+        Ok(step)
     }
 }
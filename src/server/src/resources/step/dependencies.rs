@@ -0,0 +1,218 @@
+//! The [`Dependencies`] of a series of steps define the order in which the
+//! steps run.
+//!
+//! A step either explicitly lists the names of the steps it depends on, or it
+//! implicitly depends on the step positioned before it. Steps that do not
+//! depend on each other, directly or indirectly, can run concurrently.
+
+/// The resolved dependencies of a series of steps, referencing each step by
+/// its index in the series.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Dependencies(Vec<Vec<usize>>);
+
+impl Dependencies {
+    /// Resolve the dependencies of the provided steps, given as pairs of the
+    /// step name, and the names of the steps it depends on, if explicitly
+    /// defined.
+    ///
+    /// The steps have to be ordered by their position.
+    ///
+    /// Returns an error if a step depends on an unknown step, or if the
+    /// dependencies form a cycle.
+    pub(crate) fn resolve<'a, I>(steps: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = (&'a str, Option<Vec<&'a str>>)>,
+    {
+        let steps: Vec<_> = steps.into_iter().collect();
+        let mut dependencies = Vec::with_capacity(steps.len());
+
+        for (index, (name, depends_on)) in steps.iter().enumerate() {
+            let indices = match depends_on {
+                None if index == 0 => vec![],
+                None => vec![index - 1],
+                Some(names) => names
+                    .iter()
+                    .map(|dependency| {
+                        steps
+                            .iter()
+                            .position(|(name, _)| name == dependency)
+                            .ok_or_else(|| {
+                                format!("Step `{}` depends on unknown step `{}`.", name, dependency)
+                            })
+                    })
+                    .collect::<Result<_, _>>()?,
+            };
+
+            dependencies.push(indices);
+        }
+
+        let dependencies = Self(dependencies);
+        if let Some(cycle) = dependencies.find_cycle() {
+            let names: Vec<_> = cycle.iter().map(|&i| format!("`{}`", steps[i].0)).collect();
+
+            return Err(format!(
+                "Step dependencies cannot form a cycle: {}.",
+                names.join(" -> ")
+            ));
+        }
+
+        Ok(dependencies)
+    }
+
+    /// Returns the indices of the steps the step at the provided index depends
+    /// on.
+    pub(crate) fn of(&self, index: usize) -> &[usize] {
+        self.0.get(index).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns, for each step, whether it is one of the provided steps, or
+    /// depends on one of them, directly or indirectly.
+    ///
+    /// The provided steps are marked by their index in the series.
+    pub(crate) fn with_dependents(&self, steps: &[bool]) -> Vec<bool> {
+        let mut selected = steps.to_vec();
+        selected.resize(self.0.len(), false);
+
+        // Steps can depend on steps positioned after them, so the series is
+        // walked until no more dependents are found.
+        loop {
+            let mut found = false;
+            for (index, dependencies) in self.0.iter().enumerate() {
+                if !selected[index] && dependencies.iter().any(|&d| selected[d]) {
+                    selected[index] = true;
+                    found = true;
+                }
+            }
+
+            if !found {
+                return selected;
+            }
+        }
+    }
+
+    /// Returns the indices of the steps forming a cycle, starting and ending
+    /// with the same step, if any.
+    fn find_cycle(&self) -> Option<Vec<usize>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Visit {
+            New,
+            Active,
+            Done,
+        }
+
+        fn visit(
+            dependencies: &[Vec<usize>],
+            index: usize,
+            visits: &mut [Visit],
+            path: &mut Vec<usize>,
+        ) -> Option<Vec<usize>> {
+            match visits[index] {
+                Visit::Done => return None,
+                Visit::Active => {
+                    let start = path.iter().position(|&i| i == index).unwrap_or(0);
+                    let mut cycle = path[start..].to_vec();
+                    cycle.push(index);
+
+                    return Some(cycle);
+                }
+                Visit::New => {}
+            }
+
+            visits[index] = Visit::Active;
+            path.push(index);
+
+            for &dependency in &dependencies[index] {
+                if let Some(cycle) = visit(dependencies, dependency, visits, path) {
+                    return Some(cycle);
+                }
+            }
+
+            let _ = path.pop();
+            visits[index] = Visit::Done;
+            None
+        }
+
+        let mut visits = vec![Visit::New; self.0.len()];
+        let mut path = vec![];
+
+        (0..self.0.len()).find_map(|index| visit(&self.0, index, &mut visits, &mut path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_implicit_dependencies() {
+        let steps = vec![("a", None), ("b", None), ("c", None)];
+
+        assert_eq!(
+            Dependencies::resolve(steps),
+            Ok(Dependencies(vec![vec![], vec![0], vec![1]]))
+        );
+    }
+
+    #[test]
+    fn test_explicit_dependencies() {
+        let steps = vec![
+            ("a", Some(vec![])),
+            ("b", Some(vec![])),
+            ("c", Some(vec!["a", "b"])),
+            ("d", None),
+        ];
+
+        assert_eq!(
+            Dependencies::resolve(steps),
+            Ok(Dependencies(vec![vec![], vec![], vec![0, 1], vec![2]]))
+        );
+    }
+
+    #[test]
+    fn test_with_dependents() {
+        let steps = vec![
+            ("a", Some(vec!["c"])),
+            ("b", Some(vec![])),
+            ("c", Some(vec![])),
+            ("d", Some(vec!["a"])),
+            ("e", Some(vec!["b"])),
+        ];
+
+        let dependencies = Dependencies::resolve(steps).unwrap();
+
+        assert_eq!(
+            dependencies.with_dependents(&[false, false, true, false, false]),
+            vec![true, false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn test_unknown_dependency() {
+        let steps = vec![("a", None), ("b", Some(vec!["c"]))];
+
+        assert_eq!(
+            Dependencies::resolve(steps),
+            Err("Step `b` depends on unknown step `c`.".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_cycle() {
+        let steps = vec![("a", Some(vec!["c"])), ("b", None), ("c", None)];
+
+        assert_eq!(
+            Dependencies::resolve(steps),
+            Err("Step dependencies cannot form a cycle: `a` -> `c` -> `b` -> `a`.".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_self_dependency() {
+        let steps = vec![("a", Some(vec!["a"]))];
+
+        assert_eq!(
+            Dependencies::resolve(steps),
+            Err("Step dependencies cannot form a cycle: `a` -> `a`.".to_owned())
+        );
+    }
+}
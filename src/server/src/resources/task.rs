@@ -16,9 +16,11 @@
 //! [`variable`]: crate::resources::variable
 
 use super::OnConflict;
-use crate::resources::{NewStep, NewVariable, Step, Variable};
+use crate::resources::{NewStep, NewVariable, Schedule, Step, StepDependencies, Variable};
 use crate::schema::{jobs, steps, tasks, variables};
 use crate::server::RequestState;
+use crate::Processor;
+use chrono::NaiveDateTime;
 use diesel::dsl::sql;
 use diesel::prelude::*;
 use diesel::sql_types::{BigInt, Integer, NotNull, Nullable, Text};
@@ -52,6 +54,33 @@ pub(crate) struct Task {
     pub(crate) name: String,
     pub(crate) description: Option<String>,
     pub(crate) labels: Vec<String>,
+
+    /// Whether jobs of this task are requeued when the worker running them is
+    /// lost, instead of being marked as failed.
+    pub(crate) requeue_on_worker_loss: bool,
+
+    /// The number of seconds jobs of this task are allowed to run, if
+    /// limited.
+    pub(crate) timeout_seconds: Option<i32>,
+
+    /// The number of jobs of this task that are allowed to run at the same
+    /// time, if limited.
+    pub(crate) max_concurrent_jobs: Option<i32>,
+
+    /// The name of the lock group of this task, if any. Only one job of all
+    /// the tasks in the same lock group is allowed to run at the same time.
+    pub(crate) lock_group: Option<String>,
+
+    /// The priority of the jobs of this task. Pending jobs with a higher
+    /// priority run first.
+    pub(crate) priority: i32,
+
+    /// The queue a worker has to serve to run the jobs of this task, if any.
+    pub(crate) queue: Option<String>,
+
+    /// The moment a worker last claimed a job of this task, if ever. Pending
+    /// jobs of tasks that were claimed least recently run first.
+    pub(crate) last_claimed_at: Option<NaiveDateTime>,
 }
 
 impl Task {
@@ -63,6 +92,30 @@ impl Task {
             .load(conn)
     }
 
+    pub(crate) fn schedules(&self, conn: &PgConnection) -> QueryResult<Vec<Schedule>> {
+        use crate::schema::schedules::dsl::*;
+
+        Schedule::belonging_to(self).order(id.asc()).load(conn)
+    }
+
+    /// Returns the tasks run by the `RunTask` steps of this task.
+    ///
+    /// Returns an error if any of those steps references a task that does not
+    /// exist.
+    pub(crate) fn sub_tasks(
+        &self,
+        conn: &PgConnection,
+    ) -> Result<Vec<Self>, Box<dyn error::Error>> {
+        self.steps(conn)?
+            .iter()
+            .filter_map(|step| match step.processor() {
+                Ok(Processor::RunTask(processor)) => Some(processor),
+                _ => None,
+            })
+            .map(|processor| processor.task(conn))
+            .collect()
+    }
+
     pub(crate) fn variables(&self, conn: &PgConnection) -> QueryResult<Vec<Variable>> {
         use crate::schema::variables::dsl::*;
 
@@ -145,6 +198,12 @@ pub(crate) struct NewTask<'a> {
     name: &'a str,
     description: Option<&'a str>,
     labels: Vec<&'a str>,
+    requeue_on_worker_loss: bool,
+    timeout_seconds: Option<i32>,
+    max_concurrent_jobs: Option<i32>,
+    lock_group: Option<&'a str>,
+    priority: i32,
+    queue: Option<&'a str>,
     variables: Vec<NewVariable<'a>>,
     steps: Vec<NewStep<'a>>,
 }
@@ -157,11 +216,53 @@ impl<'a> NewTask<'a> {
             name,
             description,
             labels,
+            requeue_on_worker_loss: false,
+            timeout_seconds: None,
+            max_concurrent_jobs: None,
+            lock_group: None,
+            priority: 0,
+            queue: None,
             variables: vec![],
             steps: vec![],
         }
     }
 
+    /// Requeue the jobs of this task when the worker running them is lost,
+    /// instead of marking them as failed.
+    ///
+    /// Only enable this for tasks that can safely run more than once.
+    pub(crate) fn with_requeue_on_worker_loss(&mut self, requeue: bool) {
+        self.requeue_on_worker_loss = requeue
+    }
+
+    /// Limit the number of seconds jobs of this task are allowed to run.
+    pub(crate) fn with_timeout_seconds(&mut self, seconds: Option<i32>) {
+        self.timeout_seconds = seconds
+    }
+
+    /// Limit the number of jobs of this task that are allowed to run at the
+    /// same time.
+    pub(crate) fn with_max_concurrent_jobs(&mut self, max: Option<i32>) {
+        self.max_concurrent_jobs = max
+    }
+
+    /// Add this task to a lock group, to prevent its jobs from running at the
+    /// same time as any job of the other tasks in that group.
+    pub(crate) fn with_lock_group(&mut self, group: Option<&'a str>) {
+        self.lock_group = group
+    }
+
+    /// Set the priority of the jobs of this task.
+    pub(crate) fn with_priority(&mut self, priority: i32) {
+        self.priority = priority
+    }
+
+    /// Require the jobs of this task to run on a worker serving the provided
+    /// queue.
+    pub(crate) fn with_queue(&mut self, queue: Option<&'a str>) {
+        self.queue = queue
+    }
+
     /// Attach variables to this task.
     ///
     /// `NewTask` takes ownership of the variables, but you are required to
@@ -187,7 +288,12 @@ impl<'a> NewTask<'a> {
     ///
     /// Persisting the data happens within a transaction that is rolled back if
     /// any data fails to persist.
+    ///
+    /// Returns an error if any step depends on an unknown step, or if the
+    /// dependencies between the steps form a cycle.
     pub(crate) fn create(self, conn: &PgConnection) -> Result<Task, Box<dyn error::Error>> {
+        self.validate_dependencies()?;
+
         conn.transaction(|| {
             use crate::schema::tasks::dsl::*;
 
@@ -196,6 +302,12 @@ impl<'a> NewTask<'a> {
                 name.eq(&self.name),
                 description.eq(&self.description),
                 labels.eq(&self.labels),
+                requeue_on_worker_loss.eq(self.requeue_on_worker_loss),
+                timeout_seconds.eq(self.timeout_seconds),
+                max_concurrent_jobs.eq(self.max_concurrent_jobs),
+                lock_group.eq(self.lock_group),
+                priority.eq(self.priority),
+                queue.eq(self.queue),
             );
 
             let task = diesel::insert_into(tasks).values(values).get_result(conn)?;
@@ -217,11 +329,19 @@ impl<'a> NewTask<'a> {
         use diesel::dsl::{any, not};
         use diesel::insert_into;
 
+        self.validate_dependencies()?;
+
         conn.build_transaction().deferrable().run(|| {
             let values = (
                 tasks::name.eq(&self.name),
                 tasks::description.eq(&self.description),
                 tasks::labels.eq(&self.labels),
+                tasks::requeue_on_worker_loss.eq(self.requeue_on_worker_loss),
+                tasks::timeout_seconds.eq(self.timeout_seconds),
+                tasks::max_concurrent_jobs.eq(self.max_concurrent_jobs),
+                tasks::lock_group.eq(self.lock_group),
+                tasks::priority.eq(self.priority),
+                tasks::queue.eq(self.queue),
             );
 
             let task: Task = insert_into(tasks::table)
@@ -265,6 +385,17 @@ impl<'a> NewTask<'a> {
         })
     }
 
+    /// Validate that all steps depend on existing steps of the task, and that
+    /// the dependencies between the steps do not form a cycle.
+    fn validate_dependencies(&self) -> Result<(), String> {
+        StepDependencies::resolve(
+            self.steps
+                .iter()
+                .map(|step| (step.name, step.depends_on().map(<[_]>::to_vec))),
+        )
+        .map(|_| ())
+    }
+
     fn create_or_update_associations(
         self,
         task: &Task,
@@ -294,7 +425,10 @@ pub(crate) mod graphql {
     //! mutation, and type documentation.
 
     use super::*;
-    use crate::resources::{CreateStepInput, CreateVariableInput, Step, Variable};
+    use crate::resources::{
+        CreateStepInput, CreateVariableInput, JobConnection, JobFilter, JobFilterInput, JobRange,
+        Step, Variable,
+    };
     use juniper::{object, FieldResult, GraphQLInputObject, ID};
 
     /// Contains all the data needed to create a new `Task`.
@@ -321,6 +455,51 @@ pub(crate) mod graphql {
         /// Labels can be used to restrict who can run what task.
         pub(crate) labels: Option<Vec<String>>,
 
+        /// Whether jobs of the task are requeued when the worker running them
+        /// is lost, for example because it crashed.
+        ///
+        /// By default, such jobs are marked as failed. Only enable this for
+        /// tasks that can safely run more than once, as a requeued job runs
+        /// again from its first step.
+        pub(crate) requeue_on_worker_loss: Option<bool>,
+
+        /// An optional number of seconds jobs of the task are allowed to run.
+        ///
+        /// If a job runs longer, the running step fails with a timeout error,
+        /// and the job fails as well.
+        pub(crate) timeout_seconds: Option<i32>,
+
+        /// An optional number of jobs of the task that are allowed to run at
+        /// the same time.
+        ///
+        /// Jobs that would exceed the limit stay `PENDING`, with the reason
+        /// they cannot run yet available as their `blockedReason`, until
+        /// enough running jobs of the task finished. Jobs that are paused, for
+        /// example while awaiting approval, count as running.
+        pub(crate) max_concurrent_jobs: Option<i32>,
+
+        /// An optional name of a lock group shared with other tasks.
+        ///
+        /// Only one job of all tasks in the same lock group is allowed to run
+        /// at the same time. Other jobs stay `PENDING` until the running job
+        /// finished, in the same way as for `maxConcurrentJobs`.
+        pub(crate) lock_group: Option<String>,
+
+        /// An optional priority of the jobs of the task.
+        ///
+        /// Pending jobs with a higher priority run before jobs with a lower
+        /// priority. Defaults to `0`.
+        pub(crate) priority: Option<i32>,
+
+        /// An optional queue a worker has to serve to run the jobs of the
+        /// task, for example because the jobs need access to a particular
+        /// network.
+        ///
+        /// Workers serve the queues they are started with, using
+        /// `automaat worker --queues <queue>,...`. Without a queue, jobs of
+        /// the task run on any worker.
+        pub(crate) queue: Option<String>,
+
         /// An optional list of variables attached to the task.
         ///
         /// Without variables, a task can only be used for one single
@@ -398,6 +577,45 @@ pub(crate) mod graphql {
             self.labels.iter().map(String::as_str).collect()
         }
 
+        /// Whether jobs of the task are requeued when the worker running them
+        /// is lost, instead of being marked as failed.
+        fn requeue_on_worker_loss() -> bool {
+            self.requeue_on_worker_loss
+        }
+
+        /// The number of seconds jobs of the task are allowed to run, if
+        /// limited.
+        fn timeout_seconds() -> Option<i32> {
+            self.timeout_seconds
+        }
+
+        /// The number of jobs of the task that are allowed to run at the same
+        /// time, if limited.
+        fn max_concurrent_jobs() -> Option<i32> {
+            self.max_concurrent_jobs
+        }
+
+        /// The name of the lock group of the task, if any.
+        ///
+        /// Only one job of all tasks in the same lock group is allowed to run
+        /// at the same time.
+        fn lock_group() -> Option<&str> {
+            self.lock_group.as_ref().map(String::as_ref)
+        }
+
+        /// The priority of the jobs of the task.
+        ///
+        /// Pending jobs with a higher priority run first.
+        fn priority() -> i32 {
+            self.priority
+        }
+
+        /// The queue a worker has to serve to run the jobs of the task, if
+        /// any.
+        fn queue() -> Option<&str> {
+            self.queue.as_ref().map(String::as_ref)
+        }
+
         /// The variables belonging to the task.
         ///
         /// This field can return `null`, but _only_ if a database error
@@ -437,6 +655,38 @@ pub(crate) mod graphql {
         fn steps(context: &RequestState) -> FieldResult<Option<Vec<Step>>> {
             self.steps(&context.conn).map(Some).map_err(Into::into)
         }
+
+        /// The schedules running the task at fixed moments in time.
+        ///
+        /// This field can return `null`, but _only_ if a database error
+        /// prevents the data from being retrieved.
+        ///
+        /// If the task has no schedules, an empty array is returned instead.
+        fn schedules(context: &RequestState) -> FieldResult<Option<Vec<Schedule>>> {
+            self.schedules(&context.conn).map(Some).map_err(Into::into)
+        }
+
+        /// A page of the jobs created from the task, ordered from newest to
+        /// oldest.
+        ///
+        /// See `QueryRoot.jobs` for details on pagination and filtering. The
+        /// `taskId` of the provided filter is ignored.
+        fn jobs(
+            context: &RequestState,
+            first: Option<i32>,
+            after: Option<String>,
+            last: Option<i32>,
+            before: Option<String>,
+            filter: Option<JobFilterInput>,
+        ) -> FieldResult<JobConnection> {
+            let after = after.as_ref().map(String::as_str);
+            let before = before.as_ref().map(String::as_str);
+            let range = JobRange::new(first, after, last, before)?;
+            let filter = filter.as_ref().map(JobFilter::try_from).transpose()?;
+            let filter = filter.unwrap_or_default().with_task_id(self.id);
+
+            JobConnection::find(filter, range, &context.conn).map_err(Into::into)
+        }
     }
 }
 
@@ -456,6 +706,22 @@ impl<'a> TryFrom<&'a graphql::CreateTaskInput> for NewTask<'a> {
             return Err("Task labels cannot start with `mutation_` or `query_`.".to_owned());
         }
 
+        if input.timeout_seconds.map_or(false, |t| t <= 0) {
+            return Err("Task timeout must be at least one second.".to_owned());
+        }
+
+        if input.max_concurrent_jobs.map_or(false, |max| max <= 0) {
+            return Err("Task concurrency limit must be at least one job.".to_owned());
+        }
+
+        if input.lock_group.as_ref().map_or(false, String::is_empty) {
+            return Err("Task lock group cannot be empty.".to_owned());
+        }
+
+        if input.queue.as_ref().map_or(false, String::is_empty) {
+            return Err("Task queue cannot be empty.".to_owned());
+        }
+
         let mut task = Self::new(
             &input.name,
             input.description.as_ref().map(String::as_ref),
@@ -477,6 +743,12 @@ impl<'a> TryFrom<&'a graphql::CreateTaskInput> for NewTask<'a> {
             .map(TryInto::try_into)
             .collect::<Result<Vec<_>, Self::Error>>()?;
 
+        task.with_requeue_on_worker_loss(input.requeue_on_worker_loss.unwrap_or(false));
+        task.with_timeout_seconds(input.timeout_seconds);
+        task.with_max_concurrent_jobs(input.max_concurrent_jobs);
+        task.with_lock_group(input.lock_group.as_ref().map(String::as_ref));
+        task.with_priority(input.priority.unwrap_or(0));
+        task.with_queue(input.queue.as_ref().map(String::as_ref));
         task.with_variables(variables);
         task.with_steps(steps);
         Ok(task)
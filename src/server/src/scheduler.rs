@@ -0,0 +1,130 @@
+use crate::resources::{Job, JobStep, Schedule};
+use crate::server::DatabasePool;
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Bool};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{error::Error, thread, time};
+
+sql_function!(fn pg_try_advisory_xact_lock(key: BigInt) -> Bool);
+
+/// The key of the advisory lock taken by the scheduler.
+///
+/// Every worker runs a scheduler, but the lock guarantees only one of them
+/// schedules jobs at any moment in time.
+const SCHEDULER_LOCK: i64 = 0x6175_746f_6d61_6174;
+
+/// The interval at which the scheduler checks for due jobs.
+const SCHEDULER_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// The maximum interval between two attempts to schedule jobs, while these
+/// attempts keep failing.
+const MAX_BACKOFF: time::Duration = time::Duration::from_secs(60);
+
+/// The number of consecutive failed attempts to schedule jobs, after which the
+/// scheduler stops.
+const MAX_CONSECUTIVE_ERRORS: u32 = 10;
+
+/// The scheduler creates the jobs of all active schedules, and moves these
+/// jobs to the `Pending` state once they are due to run.
+///
+/// It also rejects any job steps of which the approval request expired.
+pub(crate) struct Scheduler {
+    pool: DatabasePool,
+}
+
+impl Scheduler {
+    /// Create a new scheduler.
+    pub(crate) const fn new(pool: DatabasePool) -> Self {
+        Self { pool }
+    }
+
+    /// Schedule jobs until the `running` flag is unset.
+    ///
+    /// An error while scheduling jobs, such as a lost database connection,
+    /// does not stop the scheduler right away. Scheduling is retried, waiting
+    /// twice as long after every consecutive error, up to [`MAX_BACKOFF`].
+    ///
+    /// # Errors
+    ///
+    /// Once scheduling failed [`MAX_CONSECUTIVE_ERRORS`] times in a row, the
+    /// scheduler stops, returning the last error.
+    pub(crate) fn run_while(&self, running: &AtomicBool) -> Result<(), String> {
+        let mut errors = 0;
+
+        while running.load(Ordering::SeqCst) {
+            match self.schedule_jobs() {
+                Ok(()) => errors = 0,
+                Err(err) => {
+                    errors += 1;
+                    if errors >= MAX_CONSECUTIVE_ERRORS {
+                        return Err(format!("scheduler error: {}", err));
+                    }
+                }
+            }
+
+            // The interval is slept in ticks, so that a long backoff does not
+            // delay stopping the scheduler.
+            let mut remaining = backoff(errors);
+            while remaining > time::Duration::default() && running.load(Ordering::SeqCst) {
+                let tick = remaining.min(SCHEDULER_INTERVAL);
+                thread::sleep(tick);
+                remaining -= tick;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Enqueue all scheduled jobs that are due, reject all job steps of which
+    /// the approval request expired, and create the upcoming job of any
+    /// schedule that has none.
+    ///
+    /// If another scheduler is already doing the same, this method returns
+    /// without doing anything.
+    pub(crate) fn schedule_jobs(&self) -> Result<(), Box<dyn Error>> {
+        let conn = self.pool.get()?;
+
+        conn.transaction(|| {
+            let lock = diesel::select(pg_try_advisory_xact_lock(SCHEDULER_LOCK));
+            if !lock.get_result::<bool>(&conn)? {
+                return Ok(());
+            }
+
+            let _ = Job::enqueue_due(&conn)?;
+            let _ = JobStep::reject_expired_approvals(&conn)?;
+
+            for schedule in Schedule::find_unscheduled(&conn)? {
+                // A schedule can fail to create its next job, for example when
+                // a variable was added to its task, for which the schedule has
+                // no value. This should not prevent other schedules from
+                // running, so the failed schedule is retried on the next run.
+                let _ = conn.transaction(|| schedule.schedule_next_job(&conn));
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// The interval to wait before scheduling jobs again, after the provided
+/// number of consecutive errors.
+fn backoff(errors: u32) -> time::Duration {
+    SCHEDULER_INTERVAL
+        .checked_mul(1 << errors.min(16))
+        .map_or(MAX_BACKOFF, |interval| interval.min(MAX_BACKOFF))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(0), SCHEDULER_INTERVAL);
+        assert_eq!(backoff(1), SCHEDULER_INTERVAL * 2);
+        assert_eq!(backoff(3), SCHEDULER_INTERVAL * 8);
+        assert_eq!(backoff(6), MAX_BACKOFF);
+        assert_eq!(backoff(MAX_CONSECUTIVE_ERRORS), MAX_BACKOFF);
+        assert_eq!(backoff(u32::max_value()), MAX_BACKOFF);
+    }
+}
@@ -6,6 +6,13 @@ table! {
         name -> Text,
         description -> Nullable<Text>,
         labels -> Array<Text>,
+        requeue_on_worker_loss -> Bool,
+        timeout_seconds -> Nullable<Integer>,
+        max_concurrent_jobs -> Nullable<Integer>,
+        lock_group -> Nullable<Text>,
+        priority -> Integer,
+        queue -> Nullable<Text>,
+        last_claimed_at -> Nullable<Timestamp>,
     }
 }
 
@@ -17,6 +24,12 @@ table! {
         processor -> Jsonb,
         position -> Integer,
         task_id -> Integer,
+        timeout_seconds -> Nullable<Integer>,
+        retry_policy -> Nullable<Jsonb>,
+        when -> Nullable<Text>,
+        run_policy -> crate::resources::StepRunPolicyMapping,
+        depends_on -> Nullable<Array<Text>>,
+        for_each -> Nullable<Jsonb>,
     }
 }
 
@@ -32,6 +45,52 @@ table! {
         status -> crate::resources::JobStepStatusMapping,
         output -> Nullable<Text>,
         job_id -> Integer,
+        timeout_seconds -> Nullable<Integer>,
+        retry_policy -> Nullable<Jsonb>,
+        when -> Nullable<Text>,
+        run_policy -> crate::resources::StepRunPolicyMapping,
+        depends_on -> Nullable<Array<Text>>,
+        for_each -> Nullable<Jsonb>,
+        approval_expires_at -> Nullable<Timestamp>,
+        approval_decision -> Nullable<crate::resources::ApprovalDecisionMapping>,
+        approval_decided_by -> Nullable<Integer>,
+        approval_decided_at -> Nullable<Timestamp>,
+        approval_comment -> Nullable<Text>,
+        prompt_variable -> Nullable<Text>,
+        prompt_question -> Nullable<Text>,
+        prompt_options -> Nullable<Array<Text>>,
+        prompt_answered_by -> Nullable<Integer>,
+        prompt_answered_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    job_step_attempts (id) {
+        id -> Integer,
+        attempt -> Integer,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        error -> Nullable<Text>,
+        job_step_id -> Integer,
+        item -> Nullable<Integer>,
+    }
+}
+
+table! {
+    job_step_logs (id) {
+        id -> Integer,
+        stream -> crate::resources::JobStepLogStreamMapping,
+        chunk -> Text,
+        created_at -> Timestamp,
+        job_step_id -> Integer,
+    }
+}
+
+table! {
+    job_cancellations (id) {
+        id -> Integer,
+        requested_at -> Timestamp,
+        job_id -> Integer,
     }
 }
 
@@ -51,6 +110,38 @@ table! {
         description -> Nullable<Text>,
         status -> crate::resources::JobStatusMapping,
         task_reference -> Nullable<Integer>,
+        scheduled_for -> Nullable<Timestamp>,
+        schedule_id -> Nullable<Integer>,
+        worker_id -> Nullable<Integer>,
+        timeout_seconds -> Nullable<Integer>,
+        parent_id -> Nullable<Integer>,
+        rerun_of_id -> Nullable<Integer>,
+        blocked_reason -> Nullable<Text>,
+        priority -> Integer,
+        queue -> Nullable<Text>,
+        agent_id -> Nullable<Integer>,
+        created_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        session_id -> Nullable<Integer>,
+    }
+}
+
+table! {
+    schedules (id) {
+        id -> Integer,
+        expression -> Text,
+        timezone -> Text,
+        paused -> Bool,
+        task_id -> Integer,
+    }
+}
+
+table! {
+    schedule_variables (id) {
+        id -> Integer,
+        key -> Text,
+        value -> Bytea,
+        schedule_id -> Integer,
     }
 }
 
@@ -82,6 +173,32 @@ table! {
     }
 }
 
+table! {
+    worker_registrations (id) {
+        id -> Integer,
+        started_at -> Timestamp,
+        heartbeat_at -> Timestamp,
+        queues -> Array<Text>,
+        concurrency -> Integer,
+        session_id -> Nullable<Integer>,
+    }
+}
+
+table! {
+    agent_dispatches (id) {
+        id -> Integer,
+        processor -> Bytea,
+        deadline_at -> Nullable<Timestamp>,
+        claimed_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
+        output -> Nullable<Text>,
+        error -> Nullable<Text>,
+        agent_id -> Integer,
+        job_id -> Integer,
+        job_step_id -> Integer,
+    }
+}
+
 table! {
     sessions (id) {
         id -> Integer,
@@ -92,19 +209,38 @@ table! {
 
 joinable!(steps -> tasks (task_id));
 joinable!(job_steps -> jobs (job_id));
+joinable!(job_step_attempts -> job_steps (job_step_id));
+joinable!(job_step_logs -> job_steps (job_step_id));
+joinable!(job_cancellations -> jobs (job_id));
 joinable!(job_variables -> jobs (job_id));
 joinable!(jobs -> tasks (task_reference));
+joinable!(jobs -> schedules (schedule_id));
+joinable!(jobs -> worker_registrations (worker_id));
+joinable!(jobs -> sessions (session_id));
+joinable!(schedules -> tasks (task_id));
+joinable!(schedule_variables -> schedules (schedule_id));
 joinable!(variables -> tasks (task_id));
 joinable!(variable_advertisements -> steps (step_id));
+joinable!(worker_registrations -> sessions (session_id));
+joinable!(agent_dispatches -> worker_registrations (agent_id));
+joinable!(agent_dispatches -> jobs (job_id));
+joinable!(agent_dispatches -> job_steps (job_step_id));
 
 allow_tables_to_appear_in_same_query!(
     tasks,
     steps,
     job_steps,
+    job_step_attempts,
+    job_step_logs,
+    job_cancellations,
     job_variables,
     jobs,
+    schedules,
+    schedule_variables,
     variables,
     variable_advertisements,
     global_variables,
+    worker_registrations,
+    agent_dispatches,
     sessions,
 );
@@ -1,7 +1,9 @@
-use crate::graphql::{MutationRoot, QueryRoot, Schema};
+use crate::agent::protocol;
+use crate::graphql::{MutationRoot, QueryRoot, Schema, SubscriptionRoot, SubscriptionSchema};
 use crate::handlers;
 use crate::middleware::RemoveContentLengthHeader;
-use crate::models::Session;
+use crate::models::{Session, WorkerRegistration};
+use crate::worker::{listener_url, LOST_AFTER_SECONDS};
 use actix_files::Files;
 use actix_web::error::BlockingError;
 use actix_web::{
@@ -11,10 +13,22 @@ use actix_web::{
 };
 use diesel::pg::PgConnection;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::result::QueryResult;
+use juniper::EmptyMutation;
 use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use std::cell::RefCell;
 use std::sync::Arc;
 use std::{env, error::Error, fmt};
 
+mod agents;
+mod subscriptions;
+
+pub(crate) use agents::AgentHub;
+pub(crate) use subscriptions::{
+    Connection as SubscriptionConnection, Subscription, SubscriptionHub, Topic,
+    PROTOCOL as SUBSCRIPTION_PROTOCOL,
+};
+
 pub(crate) struct RequestState {
     pub(crate) conn: PooledConnection<ConnectionManager<PgConnection>>,
 
@@ -26,6 +40,14 @@ pub(crate) struct RequestState {
     /// details were provided. If details _are_ provided, but they do not match
     /// any known session data, an authorization error is returned instead.
     pub(crate) session: Option<Session>,
+
+    /// The state of the subscription being executed, if the request is
+    /// executing a subscription.
+    pub(crate) subscription: Option<RefCell<Subscription>>,
+
+    /// The queues served by live workers, loaded once per request, when first
+    /// needed.
+    live_queues: RefCell<Option<Vec<String>>>,
 }
 
 impl RequestState {
@@ -33,13 +55,66 @@ impl RequestState {
         conn: PooledConnection<ConnectionManager<PgConnection>>,
         session: Option<Session>,
     ) -> Self {
-        Self { conn, session }
+        Self {
+            conn,
+            session,
+            subscription: None,
+            live_queues: RefCell::new(None),
+        }
+    }
+
+    /// Create the state used to execute a subscription, which can be updated
+    /// by the fields of the subscription.
+    pub(crate) fn for_subscription(
+        conn: PooledConnection<ConnectionManager<PgConnection>>,
+        session: Option<Session>,
+        subscription: Subscription,
+    ) -> Self {
+        Self {
+            conn,
+            session,
+            subscription: Some(RefCell::new(subscription)),
+            live_queues: RefCell::new(None),
+        }
+    }
+
+    /// Returns the state of the subscription executed using this request
+    /// state, once it finished.
+    pub(crate) fn into_subscription(self) -> Subscription {
+        self.subscription
+            .map(RefCell::into_inner)
+            .unwrap_or_default()
+    }
+
+    /// Returns the queues served by live workers.
+    ///
+    /// The queues are loaded once per request, so that listing many jobs does
+    /// not query the workers for every job.
+    pub(crate) fn live_queues(&self) -> QueryResult<Vec<String>> {
+        if let Some(queues) = self.live_queues.borrow().as_ref() {
+            return Ok(queues.clone());
+        }
+
+        let queues = WorkerRegistration::live_queues(LOST_AFTER_SECONDS, &self.conn)?;
+        *self.live_queues.borrow_mut() = Some(queues.clone());
+
+        Ok(queues)
+    }
+
+    /// Returns the state of the subscription being executed, or an error if
+    /// the request is not executing a subscription.
+    pub(crate) fn subscription(&self) -> Result<&RefCell<Subscription>, &'static str> {
+        self.subscription
+            .as_ref()
+            .ok_or("subscriptions are only available over a WebSocket")
     }
 }
 
 #[derive(Debug)]
 pub(crate) enum ServerError {
     Authentication,
+    NotFound,
+    BadRequest(String),
     Json(serde_json::Error),
     Internal(String),
 }
@@ -48,6 +123,8 @@ impl fmt::Display for ServerError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let message = match self {
             ServerError::Authentication => "Unauthorized".to_owned(),
+            ServerError::NotFound => "Not Found".to_owned(),
+            ServerError::BadRequest(string) => string.to_owned(),
             ServerError::Json(err) => err.to_string(),
             ServerError::Internal(string) => string.to_owned(),
         };
@@ -60,6 +137,8 @@ impl ResponseError for ServerError {
     fn error_response(&self) -> HttpResponse {
         let code = match self {
             ServerError::Authentication => StatusCode::UNAUTHORIZED,
+            ServerError::NotFound => StatusCode::NOT_FOUND,
+            ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ServerError::Json(_) => StatusCode::BAD_REQUEST,
             ServerError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
@@ -88,6 +167,7 @@ where
 
 pub(crate) trait InternalServerError: fmt::Display {}
 impl InternalServerError for r2d2::Error {}
+impl InternalServerError for diesel::result::Error {}
 
 impl<T> From<T> for ServerError
 where
@@ -102,6 +182,12 @@ pub(crate) type DatabasePool = Pool<ConnectionManager<PgConnection>>;
 
 pub(crate) struct State {
     pub(crate) pool: DatabasePool,
+
+    /// Runs jobs on behalf of remote agents.
+    pub(crate) agents: AgentHub,
+
+    /// Notifies GraphQL subscriptions of changes.
+    pub(crate) subscriptions: SubscriptionHub,
 }
 
 pub(crate) struct Server {
@@ -111,19 +197,34 @@ pub(crate) struct Server {
 impl Server {
     pub(crate) fn from_environment() -> Result<Self, Box<dyn Error>> {
         let database_url = env::var("DATABASE_URL")?;
-        let pool = Pool::new(ConnectionManager::new(database_url))?;
+        let pool = Pool::new(ConnectionManager::new(database_url.as_str()))?;
 
         crate::embedded_migrations::run(&pool.get()?)?;
+        let agents = AgentHub::from_environment(&database_url)?;
+        let subscriptions = SubscriptionHub::new(&listener_url(&database_url)?);
 
         Ok(Self {
-            state: State { pool },
+            state: State {
+                pool,
+                agents,
+                subscriptions,
+            },
         })
     }
 
     pub(crate) fn run_to_completion(self) -> Result<(), Box<dyn Error>> {
         let bind = env::var("SERVER_BIND").unwrap_or_else(|_| "0.0.0.0:8000".to_owned());
         let schema = Arc::new(Schema::new(QueryRoot, MutationRoot));
+        let subscription_schema = Arc::new(SubscriptionSchema::new(
+            SubscriptionRoot,
+            EmptyMutation::new(),
+        ));
+
         let state = Arc::new(self.state);
+        let agents = state.agents.clone();
+        let services = agents.start();
+        let subscriptions = state.subscriptions.clone();
+        let listener = subscriptions.start();
 
         let server = HttpServer::new(move || {
             let root = env::var("SERVER_ROOT").unwrap_or_else(|_| "/public".to_owned());
@@ -139,11 +240,37 @@ impl Server {
                 .wrap(RemoveContentLengthHeader)
                 .data(state.clone())
                 .data(schema.clone())
+                .data(subscription_schema.clone())
                 .route("/graphql/playground", web::get().to(handlers::playground))
                 .route("/graphql/graphiql", web::get().to(handlers::graphiql))
+                .route(
+                    "/graphql/subscriptions",
+                    web::get().to(handlers::subscriptions),
+                )
                 .route("/graphql", web::get().to_async(handlers::graphql))
                 .route("/graphql", web::post().to_async(handlers::graphql))
                 .route("/health", web::get().to(handlers::health))
+                .service(
+                    web::scope(protocol::PATH)
+                        .route("", web::post().to_async(handlers::register_agent))
+                        .route("/{id}", web::delete().to_async(handlers::deregister_agent))
+                        .route(
+                            "/{id}/heartbeat",
+                            web::post().to_async(handlers::agent_heartbeat),
+                        )
+                        .route(
+                            "/{id}/dispatches",
+                            web::post().to_async(handlers::claim_dispatch),
+                        )
+                        .route(
+                            "/{id}/dispatches/{dispatch}",
+                            web::post().to_async(handlers::finish_dispatch),
+                        )
+                        .route(
+                            "/{id}/dispatches/{dispatch}/logs",
+                            web::post().to_async(handlers::append_dispatch_log),
+                        ),
+                )
                 .service(Files::new("/", root).index_file("index.html"))
         });
 
@@ -160,6 +287,11 @@ impl Server {
             server.bind(bind)
         }?;
 
-        server.run().map_err(Into::into)
+        // Once the server stops accepting requests, agents can no longer
+        // report back, so the jobs running for them are recovered.
+        let result = server.run().map_err(Into::into);
+        subscriptions.stop(listener);
+
+        result.and(agents.stop(services))
     }
 }
@@ -0,0 +1,301 @@
+//! The [`AgentHub`] runs jobs on behalf of remote agents, which have no access
+//! to the database.
+//!
+//! The hub claims pending jobs for the agents, and runs them on the server, in
+//! the same way a worker does. Instead of running the processors of the job
+//! steps itself, the hub dispatches the rendered processor configurations to
+//! the agent the job was claimed for, and waits for the agent to report back
+//! the results.
+//!
+//! See the [`protocol`] module for the requests used by the agents.
+//!
+//! [`protocol`]: crate::agent::protocol
+
+use crate::agent::protocol::Dispatch;
+use crate::models::{AgentDispatch, WorkerRegistration};
+use crate::resources::{Executor, Job};
+use crate::server::DatabasePool;
+use crate::worker::{listener_url, run_claimed_job, Heartbeat, Listener, Wakeup};
+use chrono::Utc;
+use diesel::pg::PgConnection;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use futures::future::Future;
+use futures::sync::oneshot;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::{env, error::Error, thread, time};
+
+/// The maximum time a request of an agent waits for a dispatch, before
+/// returning without one.
+const CLAIM_TIMEOUT: time::Duration = time::Duration::from_secs(25);
+
+/// Claims and runs jobs for remote agents.
+///
+/// The hub has a worker registration of its own, which is used to claim jobs
+/// for the agents, and to recover those jobs if the server is lost.
+#[derive(Clone)]
+pub(crate) struct AgentHub {
+    pool: DatabasePool,
+    database_url: String,
+
+    /// The database URL used by the listener, see [`listener_url`].
+    listener_url: String,
+    registration: WorkerRegistration,
+
+    /// Wakes up agents waiting for a dispatch, once a job is ready to run, or
+    /// a processor is dispatched.
+    wakeup: Arc<Wakeup>,
+    running: Arc<AtomicBool>,
+
+    /// The threads on which agents wait for a dispatch.
+    claims: Arc<ClaimThreads>,
+}
+
+/// A fixed number of threads on which agents wait for their next dispatch.
+///
+/// Waiting for a dispatch can take up to the claim timeout, so agents wait on
+/// threads of their own, instead of on the threads used by the server for
+/// other blocking operations, such as GraphQL requests. Once all threads are
+/// in use, agents wait for a thread to become available.
+struct ClaimThreads {
+    sender: Mutex<mpsc::Sender<Box<dyn FnOnce() + Send>>>,
+}
+
+/// The threads keeping the hub alive, while the server is running.
+pub(crate) struct Services {
+    heartbeat: Heartbeat,
+    listener: thread::JoinHandle<()>,
+}
+
+impl AgentHub {
+    /// Create a new hub, registering it as a worker.
+    ///
+    /// Each job running for an agent uses one connection to run the job,
+    /// another one to watch for cancellation requests, and one for each step
+    /// of the job running concurrently, including the steps of its child jobs,
+    /// see [`connections_per_job`]. The size of the connection pool used by
+    /// the hub is configured using `SERVER_AGENT_POOL_SIZE`.
+    ///
+    /// [`connections_per_job`]: crate::resources::connections_per_job
+    pub(crate) fn from_environment(database_url: &str) -> Result<Self, Box<dyn Error>> {
+        let listener_url = listener_url(database_url)?;
+        let pool_size = match env::var("SERVER_AGENT_POOL_SIZE") {
+            Err(_) => 32,
+            Ok(size) => size.parse()?,
+        };
+
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .build(ConnectionManager::new(database_url))?;
+
+        let claim_threads = match env::var("SERVER_AGENT_CLAIM_THREADS") {
+            Err(_) => 32,
+            Ok(size) => size.parse()?,
+        };
+
+        if claim_threads == 0 {
+            return Err("SERVER_AGENT_CLAIM_THREADS must be at least 1".into());
+        }
+
+        let registration = WorkerRegistration::create(&pool.get()?, &[], 0)?;
+
+        Ok(Self {
+            pool,
+            database_url: database_url.to_owned(),
+            listener_url,
+            registration,
+            wakeup: Arc::default(),
+            running: Arc::new(AtomicBool::new(true)),
+            claims: Arc::new(ClaimThreads::start(claim_threads)),
+        })
+    }
+
+    /// Start recording heartbeats for the hub, and listening for pending jobs
+    /// and dispatched processors.
+    ///
+    /// If the hub is considered lost, it stops claiming jobs for agents.
+    pub(crate) fn start(&self) -> Services {
+        let running = self.running.clone();
+        let wakeup = self.wakeup.clone();
+        let heartbeat =
+            Heartbeat::start(&self.database_url, self.registration.clone(), move || {
+                running.store(false, Ordering::SeqCst);
+                wakeup.notify();
+            });
+
+        let listener = Listener::new(
+            self.listener_url.clone(),
+            vec![Job::PENDING_CHANNEL, AgentDispatch::CHANNEL],
+            self.wakeup.clone(),
+        );
+
+        let running = self.running.clone();
+        let listener = thread::spawn(move || listener.run_while(&running));
+
+        Services {
+            heartbeat,
+            listener,
+        }
+    }
+
+    /// Stop the hub, once the server no longer accepts requests.
+    ///
+    /// Agents can no longer report the results of their processors, so the
+    /// jobs still running for them are recovered, in the same way as the jobs
+    /// of a lost worker, before the registration of the hub is removed.
+    pub(crate) fn stop(&self, services: Services) -> Result<(), Box<dyn Error>> {
+        self.running.store(false, Ordering::SeqCst);
+        self.wakeup.notify();
+
+        let _ = services.listener.join();
+        let result = services.heartbeat.stop();
+
+        let conn = self.pool.get()?;
+        for job in Job::find_orphaned(&self.registration, &conn)? {
+            let _ = job.recover(&conn)?;
+        }
+
+        self.registration.delete(&conn)?;
+        result.map_err(Into::into)
+    }
+
+    /// Wait for the next processor to run on the provided agent, on one of the
+    /// claim threads of the hub, see [`AgentHub::next_dispatch`].
+    ///
+    /// The size of the claim thread pool is configured using
+    /// `SERVER_AGENT_CLAIM_THREADS`.
+    pub(crate) fn claim(
+        &self,
+        agent: WorkerRegistration,
+        accept_jobs: bool,
+    ) -> impl Future<Item = Option<Dispatch>, Error = String> {
+        let (sender, receiver) = oneshot::channel();
+        let hub = self.clone();
+
+        self.claims.spawn(move || {
+            let dispatch = hub.next_dispatch(&agent, accept_jobs);
+            let _ = sender.send(dispatch.map_err(|err| err.to_string()));
+        });
+
+        receiver.then(|result| match result {
+            Ok(dispatch) => dispatch,
+            Err(_) => Err("the claim thread stopped".to_owned()),
+        })
+    }
+
+    /// Wait for the next processor to run on the provided agent.
+    ///
+    /// If no processor is dispatched to the agent, the agent accepts new jobs,
+    /// and it runs less jobs than its configured concurrency, the next pending
+    /// job from the queues of the agent is claimed, and starts running on a
+    /// separate thread.
+    ///
+    /// Returns `None` if no processor was dispatched before the claim timeout.
+    fn next_dispatch(
+        &self,
+        agent: &WorkerRegistration,
+        accept_jobs: bool,
+    ) -> Result<Option<Dispatch>, Box<dyn Error>> {
+        let deadline = time::Instant::now() + CLAIM_TIMEOUT;
+
+        while self.running.load(Ordering::SeqCst) {
+            let generation = self.wakeup.generation();
+            let conn = self.pool.get()?;
+
+            if let Some(dispatch) = AgentDispatch::claim_next(agent, &conn)? {
+                let processor = serde_json::from_str(&dispatch.processor(&conn)?)?;
+                let timeout = dispatch.deadline_at.map(|deadline| {
+                    (deadline - Utc::now().naive_utc())
+                        .to_std()
+                        .unwrap_or_default()
+                });
+
+                return Ok(Some(Dispatch {
+                    id: dispatch.id,
+                    job_id: dispatch.job_id,
+                    processor,
+                    timeout,
+                }));
+            }
+
+            let capacity = i64::from(agent.concurrency);
+            if accept_jobs && Job::count_running_for_agent(agent, &conn)? < capacity {
+                let registration = &self.registration;
+                if let Some(job) = Job::claim_next_pending(&conn, registration, Some(agent))? {
+                    self.start_job(conn, job, agent.id);
+                    continue;
+                }
+            }
+
+            let now = time::Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+
+            drop(conn);
+            self.wakeup.wait(generation, deadline - now);
+        }
+
+        Err("the server is shutting down".into())
+    }
+
+    /// Run a job claimed for the provided agent on a separate thread, using
+    /// the provided connection.
+    ///
+    /// If the job can't be stored, it remains running until the hub stops, at
+    /// which point it is recovered.
+    fn start_job(
+        &self,
+        conn: PooledConnection<ConnectionManager<PgConnection>>,
+        job: Job,
+        agent_id: i32,
+    ) {
+        let pool = self.pool.clone();
+
+        let _ = thread::spawn(move || {
+            let executor = Executor::Agent(pool.clone(), agent_id);
+            let _ = run_claimed_job(&pool, &conn, job, &executor, None);
+        });
+    }
+}
+
+impl ClaimThreads {
+    /// Start the provided number of claim threads.
+    ///
+    /// The threads stop once the hub, and all its clones, are dropped.
+    fn start(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..size {
+            let receiver = receiver.clone();
+            let _ = thread::spawn(move || loop {
+                let claim = receiver
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .recv();
+
+                match claim {
+                    Ok(claim) => claim(),
+                    Err(_) => return,
+                }
+            });
+        }
+
+        Self {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    /// Run the provided claim on the first available claim thread.
+    fn spawn<F>(&self, claim: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let _ = self
+            .sender
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .send(Box::new(claim));
+    }
+}
@@ -0,0 +1,213 @@
+//! GraphQL subscriptions, served over a WebSocket.
+//!
+//! Juniper does not support subscriptions, so instead, a subscription is a
+//! document executed against the [`SubscriptionRoot`] as if it were a query.
+//! While executing, the fields of the subscription record the jobs and job
+//! steps they depend on, as [`Topic`]s.
+//!
+//! The [`SubscriptionHub`] listens for Postgres notifications about changes to
+//! jobs, job steps and their logs, which are sent by database triggers. Each
+//! notification is passed on to all open connections, which execute their
+//! subscriptions depending on the changed topic again, and send the result to
+//! the client, if it changed.
+//!
+//! See [`Connection`] for the protocol used by clients.
+//!
+//! [`SubscriptionRoot`]: crate::graphql::SubscriptionRoot
+
+use crate::resources::{Job, JobStepLog};
+use actix::{Message, Recipient};
+use fallible_iterator::FallibleIterator;
+use postgres::{Connection as PgConnection, TlsMode};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::{thread, time};
+
+mod connection;
+
+pub(crate) use connection::{Connection, PROTOCOL};
+
+/// The maximum time the listener blocks waiting for a notification, before
+/// checking if it should keep running.
+const LISTEN_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
+/// The time to wait before reconnecting, after the listener connection failed.
+const RECONNECT_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+/// Something a subscription depends on, and for which the server receives
+/// notifications when it changes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Topic {
+    /// The job with the provided ID, or any of its steps.
+    Job(i32),
+
+    /// The logs of the job step with the provided ID.
+    JobStepLogs(i32),
+}
+
+impl Topic {
+    fn from_notification(channel: &str, payload: &str) -> Option<Self> {
+        let id = payload.parse().ok()?;
+
+        match channel {
+            Job::UPDATED_CHANNEL => Some(Topic::Job(id)),
+            JobStepLog::CHANNEL => Some(Topic::JobStepLogs(id)),
+            _ => None,
+        }
+    }
+}
+
+/// The state of a single subscription, kept between its executions.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Subscription {
+    /// The topics the subscription depended on, when it was last executed.
+    pub(crate) topics: HashSet<Topic>,
+
+    /// The number of log chunks already sent to the client, by job step ID.
+    pub(crate) log_offsets: HashMap<i32, i64>,
+}
+
+/// Signals an open connection that a topic changed.
+///
+/// If the topic is `None`, any topic might have changed, for example because
+/// notifications were missed while the listener reconnected.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Changed(pub(crate) Option<Topic>);
+
+impl Message for Changed {
+    type Result = ();
+}
+
+/// Passes on notifications about changed topics to all open connections.
+#[derive(Clone)]
+pub(crate) struct SubscriptionHub {
+    database_url: String,
+    connections: Arc<Mutex<HashMap<usize, Recipient<Changed>>>>,
+    next_id: Arc<AtomicUsize>,
+    running: Arc<AtomicBool>,
+}
+
+impl SubscriptionHub {
+    /// Create a new hub, listening for notifications on the provided
+    /// database, once started.
+    ///
+    /// The provided URL is expected to be prepared by [`listener_url`].
+    ///
+    /// [`listener_url`]: crate::worker::listener_url
+    pub(crate) fn new(database_url: &str) -> Self {
+        Self {
+            database_url: database_url.to_owned(),
+            connections: Arc::default(),
+            next_id: Arc::default(),
+            running: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    /// Start listening for notifications on a separate thread.
+    pub(crate) fn start(&self) -> thread::JoinHandle<()> {
+        let hub = self.clone();
+
+        thread::spawn(move || hub.run())
+    }
+
+    /// Stop listening for notifications, once the server no longer accepts
+    /// requests.
+    pub(crate) fn stop(&self, listener: thread::JoinHandle<()>) {
+        self.running.store(false, Ordering::SeqCst);
+
+        let _ = listener.join();
+    }
+
+    /// Pass on all future notifications to the provided connection, until it
+    /// is disconnected using the returned ID.
+    pub(crate) fn connect(&self, connection: Recipient<Changed>) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let _ = self
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id, connection);
+
+        id
+    }
+
+    /// Stop passing on notifications to the connection with the provided ID.
+    pub(crate) fn disconnect(&self, id: usize) {
+        let _ = self
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&id);
+    }
+
+    fn broadcast(&self, topic: Option<Topic>) {
+        let connections = self
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        for connection in connections.values() {
+            let _ = connection.do_send(Changed(topic));
+        }
+    }
+
+    /// Listen for notifications until stopped.
+    ///
+    /// Diesel does not support receiving notifications, so the hub uses a
+    /// separate connection, using the `postgres` crate. If the connection
+    /// fails, the hub reconnects after a short interval.
+    fn run(&self) {
+        while self.running.load(Ordering::SeqCst) {
+            if self.listen().is_err() {
+                thread::sleep(RECONNECT_INTERVAL);
+            }
+        }
+    }
+
+    fn listen(&self) -> postgres::Result<()> {
+        let conn = PgConnection::connect(self.database_url.as_str(), TlsMode::None)?;
+        for channel in &[Job::UPDATED_CHANNEL, JobStepLog::CHANNEL] {
+            let _ = conn.execute(&format!("LISTEN {}", channel), &[])?;
+        }
+
+        // Any change that happened while the hub was not connected would
+        // otherwise go unnoticed.
+        self.broadcast(None);
+
+        let notifications = conn.notifications();
+        let mut notifications = notifications.timeout_iter(LISTEN_TIMEOUT);
+
+        while self.running.load(Ordering::SeqCst) {
+            if let Some(notification) = notifications.next()? {
+                let topic = Topic::from_notification(&notification.channel, &notification.payload);
+                if topic.is_some() {
+                    self.broadcast(topic);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_from_notification() {
+        assert_eq!(
+            Topic::from_notification(Job::UPDATED_CHANNEL, "12"),
+            Some(Topic::Job(12))
+        );
+
+        assert_eq!(
+            Topic::from_notification(JobStepLog::CHANNEL, "3"),
+            Some(Topic::JobStepLogs(3))
+        );
+
+        assert_eq!(Topic::from_notification(Job::UPDATED_CHANNEL, "x"), None);
+        assert_eq!(Topic::from_notification("unknown", "1"), None);
+    }
+}
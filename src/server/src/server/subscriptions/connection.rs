@@ -0,0 +1,584 @@
+//! A [`Connection`] serves the subscriptions of a single client, over a
+//! WebSocket.
+//!
+//! Clients connect to `/graphql/subscriptions`, and communicate using the
+//! `graphql-ws` protocol, also used by Apollo clients:
+//!
+//! | Message                | Sent by | Payload                                     |
+//! |------------------------|---------|---------------------------------------------|
+//! | `connection_init`      | client  | `{ "Authorization": "<token>" }`, or `{}`   |
+//! | `connection_ack`       | server  |                                             |
+//! | `connection_error`     | server  | `{ "message": "..." }`                      |
+//! | `start`                | client  | `{ "query", "variables", "operationName" }` |
+//! | `data`                 | server  | The result of the subscription              |
+//! | `error`                | server  | `{ "message": "..." }`                      |
+//! | `stop`                 | client  |                                             |
+//! | `complete`             | server  |                                             |
+//! | `ka`                   | server  |                                             |
+//! | `connection_terminate` | client  |                                             |
+//!
+//! The session token can also be provided in the `Authorization` header of the
+//! request opening the WebSocket. Similar to other GraphQL requests, a
+//! connection without a token is unauthenticated, and a connection with an
+//! unknown token is closed.
+//!
+//! The result of a subscription is sent once it starts, and again every time
+//! it changes.
+
+use super::{Changed, Subscription, SubscriptionHub, Topic};
+use crate::graphql::SubscriptionSchema;
+use crate::handlers::authenticate;
+use crate::models::Session;
+use crate::server::{DatabasePool, RequestState, ServerError};
+use actix::{
+    fut, Actor, ActorContext, ActorFuture, AsyncContext, Handler, StreamHandler, WrapFuture,
+};
+use actix_web::web;
+use actix_web_actors::ws;
+use juniper::{http::GraphQLRequest, InputValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The WebSocket sub-protocol spoken by the connection.
+pub(crate) const PROTOCOL: &str = "graphql-ws";
+
+/// The interval at which a keep-alive message is sent to the client.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A message sent by the client.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    ConnectionInit {
+        #[serde(default)]
+        payload: Option<InitPayload>,
+    },
+    Start {
+        id: String,
+        payload: Request,
+    },
+    Stop {
+        id: String,
+    },
+    ConnectionTerminate,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct InitPayload {
+    #[serde(rename = "Authorization", alias = "authorization")]
+    authorization: Option<String>,
+}
+
+/// The subscription requested by the client.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Request {
+    query: String,
+    operation_name: Option<String>,
+    variables: Option<InputValue>,
+}
+
+/// A message sent to the client.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    ConnectionAck,
+    ConnectionError {
+        payload: ErrorPayload,
+    },
+    Data {
+        id: &'a str,
+        payload: &'a Value,
+    },
+    Error {
+        id: &'a str,
+        payload: ErrorPayload,
+    },
+    Complete {
+        id: &'a str,
+    },
+    #[serde(rename = "ka")]
+    KeepAlive,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorPayload {
+    message: String,
+}
+
+impl ErrorPayload {
+    fn new<T: Into<String>>(message: T) -> Self {
+        Self {
+            message: message.into(),
+        }
+    }
+}
+
+/// A subscription started by the client.
+#[derive(Debug)]
+struct Operation {
+    /// The subscription, with its operations turned into queries.
+    request: Arc<Request>,
+
+    /// The state of the subscription, or `None` while it is being executed.
+    subscription: Option<Subscription>,
+
+    /// The topics the subscription depended on, when it last executed
+    /// successfully, or `None` if it did not yet.
+    topics: Option<HashSet<Topic>>,
+
+    /// Set if a topic of the subscription changed while it was being executed.
+    stale: bool,
+
+    /// The last result sent to the client.
+    last_result: Option<Value>,
+}
+
+impl Operation {
+    fn depends_on(&self, topic: Option<Topic>) -> bool {
+        match (topic, &self.topics) {
+            (Some(topic), Some(topics)) => topics.contains(&topic),
+            _ => true,
+        }
+    }
+}
+
+/// The WebSocket connection of a single client.
+pub(crate) struct Connection {
+    pool: DatabasePool,
+    schema: Arc<SubscriptionSchema>,
+    hub: SubscriptionHub,
+
+    /// The ID of the connection within the hub, once started.
+    hub_id: Option<usize>,
+
+    /// The session token provided when opening the WebSocket, if any.
+    token: Option<String>,
+
+    /// Set once the client initialized the connection, and its session is
+    /// authenticated.
+    initialized: bool,
+    session: Option<Session>,
+
+    /// The subscriptions of the client, by the ID provided by the client.
+    operations: HashMap<String, Operation>,
+}
+
+impl Connection {
+    /// Create a new connection, authenticating the client using the provided
+    /// session token, unless another token is provided once the client
+    /// initializes the connection.
+    pub(crate) fn new(
+        pool: DatabasePool,
+        schema: Arc<SubscriptionSchema>,
+        hub: SubscriptionHub,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            pool,
+            schema,
+            hub,
+            hub_id: None,
+            token,
+            initialized: false,
+            session: None,
+            operations: HashMap::new(),
+        }
+    }
+
+    fn receive(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let message = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(err) => {
+                let payload = ErrorPayload::new(format!("invalid message: {}", err));
+                return send(&ServerMessage::ConnectionError { payload }, ctx);
+            }
+        };
+
+        match message {
+            ClientMessage::ConnectionInit { payload } => {
+                let token = payload.and_then(|p| p.authorization);
+                self.initialize(token.or_else(|| self.token.clone()), ctx);
+            }
+            ClientMessage::Start { id, payload } => self.start(id, payload, ctx),
+            ClientMessage::Stop { id } => {
+                let _ = self.operations.remove(&id);
+                send(&ServerMessage::Complete { id: &id }, ctx);
+            }
+            ClientMessage::ConnectionTerminate => ctx.stop(),
+        }
+    }
+
+    /// Authenticate the session of the client, and acknowledge the connection
+    /// if it is valid. Otherwise, the connection is closed.
+    fn initialize(&mut self, token: Option<String>, ctx: &mut ws::WebsocketContext<Self>) {
+        let pool = self.pool.clone();
+        let future = web::block(move || match token {
+            None => Ok(None),
+            Some(token) => authenticate(&token, &pool.get()?).map(Some),
+        })
+        .into_actor(self)
+        .then(|result, connection, ctx| {
+            match result {
+                Ok(session) => {
+                    connection.initialized = true;
+                    connection.session = session;
+                    send(&ServerMessage::ConnectionAck, ctx);
+                }
+                Err(err) => {
+                    let payload = ErrorPayload::new(ServerError::from(err).to_string());
+                    send(&ServerMessage::ConnectionError { payload }, ctx);
+                    ctx.close(None);
+                    ctx.stop();
+                }
+            };
+
+            fut::ok(())
+        });
+
+        let _ = ctx.spawn(future);
+    }
+
+    fn start(&mut self, id: String, mut request: Request, ctx: &mut ws::WebsocketContext<Self>) {
+        if !self.initialized {
+            let payload = ErrorPayload::new("the connection is not initialized");
+            return send(&ServerMessage::Error { id: &id, payload }, ctx);
+        }
+
+        request.query = match as_query(&request.query) {
+            Ok(query) => query,
+            Err(message) => {
+                let payload = ErrorPayload::new(message);
+                return send(&ServerMessage::Error { id: &id, payload }, ctx);
+            }
+        };
+
+        let operation = Operation {
+            request: Arc::new(request),
+            subscription: Some(Subscription::default()),
+            topics: None,
+            stale: false,
+            last_result: None,
+        };
+
+        let _ = self.operations.insert(id.clone(), operation);
+        self.execute(id, ctx);
+    }
+
+    /// Execute the subscription with the provided ID on a separate thread, or
+    /// execute it again once it finishes, if it is already being executed.
+    fn execute(&mut self, id: String, ctx: &mut ws::WebsocketContext<Self>) {
+        let (request, subscription) = match self.operations.get_mut(&id) {
+            None => return,
+            Some(operation) => match operation.subscription.take() {
+                Some(subscription) => (operation.request.clone(), subscription),
+                None => {
+                    operation.stale = true;
+                    return;
+                }
+            },
+        };
+
+        let pool = self.pool.clone();
+        let schema = self.schema.clone();
+        let session = self.session.clone();
+
+        let future = web::block(move || {
+            Ok::<_, ()>(run_subscription(
+                &pool,
+                &schema,
+                session,
+                &request,
+                subscription,
+            ))
+        })
+        .into_actor(self)
+        .then(move |result, connection, ctx| {
+            if let Ok((result, subscription)) = result {
+                connection.finish(id, result, subscription, ctx);
+            }
+
+            fut::ok(())
+        });
+
+        let _ = ctx.spawn(future);
+    }
+
+    /// Send the result of a subscription to the client, if it changed since
+    /// the last result that was sent.
+    fn finish(
+        &mut self,
+        id: String,
+        result: Result<Value, String>,
+        subscription: Subscription,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        let operation = match self.operations.get_mut(&id) {
+            Some(operation) => operation,
+            None => return,
+        };
+
+        match result {
+            Err(message) => {
+                let payload = ErrorPayload::new(message);
+                send(&ServerMessage::Error { id: &id, payload }, ctx);
+            }
+            Ok(result) => {
+                operation.topics = Some(subscription.topics.clone());
+
+                if operation.last_result.as_ref() != Some(&result) {
+                    let payload = &result;
+                    send(&ServerMessage::Data { id: &id, payload }, ctx);
+                    operation.last_result = Some(result);
+                }
+            }
+        }
+
+        operation.subscription = Some(subscription);
+        if operation.stale {
+            operation.stale = false;
+            self.execute(id, ctx);
+        }
+    }
+}
+
+impl Actor for Connection {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.hub_id = Some(self.hub.connect(ctx.address().recipient()));
+
+        let _ = ctx.run_interval(KEEP_ALIVE_INTERVAL, |connection, ctx| {
+            if connection.initialized {
+                send(&ServerMessage::KeepAlive, ctx);
+            }
+        });
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        if let Some(id) = self.hub_id.take() {
+            self.hub.disconnect(id);
+        }
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for Connection {
+    fn handle(&mut self, message: ws::Message, ctx: &mut Self::Context) {
+        match message {
+            ws::Message::Text(text) => self.receive(&text, ctx),
+            ws::Message::Ping(message) => ctx.pong(&message),
+            ws::Message::Close(_) => ctx.stop(),
+            ws::Message::Binary(_) | ws::Message::Pong(_) | ws::Message::Nop => {}
+        }
+    }
+}
+
+impl Handler<Changed> for Connection {
+    type Result = ();
+
+    fn handle(&mut self, Changed(topic): Changed, ctx: &mut Self::Context) {
+        let ids: Vec<_> = self
+            .operations
+            .iter()
+            .filter(|(_, operation)| operation.depends_on(topic))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in ids {
+            self.execute(id, ctx);
+        }
+    }
+}
+
+fn send(message: &ServerMessage<'_>, ctx: &mut ws::WebsocketContext<Connection>) {
+    if let Ok(text) = serde_json::to_string(message) {
+        ctx.text(text);
+    }
+}
+
+/// Execute a subscription as a query, and return its result, together with
+/// the updated state of the subscription.
+fn run_subscription(
+    pool: &DatabasePool,
+    schema: &SubscriptionSchema,
+    session: Option<Session>,
+    request: &Request,
+    mut subscription: Subscription,
+) -> (Result<Value, String>, Subscription) {
+    let conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(err) => return (Err(err.to_string()), subscription),
+    };
+
+    subscription.topics.clear();
+    let state = RequestState::for_subscription(conn, session, subscription);
+    let request = GraphQLRequest::new(
+        request.query.clone(),
+        request.operation_name.clone(),
+        request.variables.clone(),
+    );
+
+    let result = serde_json::to_value(request.execute(schema, &state)).map_err(|e| e.to_string());
+    (result, state.into_subscription())
+}
+
+/// Turn the subscription operations of a GraphQL document into queries, so
+/// that Juniper can execute them.
+///
+/// Returns an error if the document contains any other operations.
+fn as_query(document: &str) -> Result<String, &'static str> {
+    const OTHER_OPERATION: &str = "only subscription operations are supported";
+
+    let bytes = document.as_bytes();
+    let mut query = String::with_capacity(document.len());
+    let mut copied = 0;
+    let mut found = false;
+
+    // The nesting level of braces, parentheses and brackets, and whether the
+    // next token starts a new definition.
+    let mut depth = 0_usize;
+    let mut definition = true;
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'"' => i = skip_string(bytes, i),
+            b'{' if depth == 0 && definition => return Err(OTHER_OPERATION),
+            b'{' | b'(' | b'[' => {
+                depth += 1;
+                i += 1;
+            }
+            b'}' | b')' | b']' => {
+                depth = depth.saturating_sub(1);
+                definition = depth == 0 && bytes[i] == b'}';
+                i += 1;
+            }
+            byte if byte == b'_' || byte == b'$' || byte.is_ascii_alphabetic() => {
+                let start = i;
+                i += 1;
+                while i < bytes.len() && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric()) {
+                    i += 1;
+                }
+
+                if depth > 0 || !definition {
+                    continue;
+                }
+
+                definition = false;
+                match &document[start..i] {
+                    "fragment" => {}
+                    "subscription" => {
+                        query.push_str(&document[copied..start]);
+                        query.push_str("query");
+                        copied = i;
+                        found = true;
+                    }
+                    _ => return Err(OTHER_OPERATION),
+                }
+            }
+            _ => i += 1,
+        }
+    }
+
+    if !found {
+        return Err("the document contains no subscription operation");
+    }
+
+    query.push_str(&document[copied..]);
+    Ok(query)
+}
+
+/// Returns the position right after the string starting at the provided
+/// position.
+///
+/// Block strings only have a single escape sequence, `\"""`, any other
+/// backslash within a block string is part of its value.
+fn skip_string(bytes: &[u8], start: usize) -> usize {
+    if bytes[start..].starts_with(b"\"\"\"") {
+        return skip_block_string(bytes, start);
+    }
+
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+
+    i
+}
+
+/// Returns the position right after the block string starting at the
+/// provided position.
+fn skip_block_string(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 3;
+    while i < bytes.len() {
+        if bytes[i..].starts_with(b"\\\"\"\"") {
+            i += 4;
+        } else if bytes[i..].starts_with(b"\"\"\"") {
+            return i + 3;
+        } else {
+            i += 1;
+        }
+    }
+
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_as_query() {
+        let document = r#"
+            # Follow a job.
+            subscription Job($id: ID!) {
+              jobUpdated(id: $id) { ...job }
+            }
+
+            fragment job on Job { id name }
+        "#;
+
+        let query = as_query(document).unwrap();
+        assert_eq!(query, document.replace("subscription Job", "query Job"));
+    }
+
+    #[test]
+    fn test_as_query_strings() {
+        let document = r#"subscription { jobUpdated(id: "{ query }") { id } }"#;
+
+        assert_eq!(
+            as_query(document).unwrap(),
+            r#"query { jobUpdated(id: "{ query }") { id } }"#
+        );
+    }
+
+    #[test]
+    fn test_as_query_block_strings() {
+        let document = r#"subscription { a(b: """C:\\""") } subscription { """) { id } }"#;
+
+        assert_eq!(
+            as_query(document).unwrap(),
+            document.replacen("subscription", "query", 1)
+        );
+    }
+
+    #[test]
+    fn test_as_query_other_operations() {
+        assert!(as_query("{ job(id: 1) { id } }").is_err());
+        assert!(as_query("query { job(id: 1) { id } }").is_err());
+        assert!(as_query("subscription { a } mutation { b }").is_err());
+        assert!(as_query("fragment job on Job { id }").is_err());
+    }
+}
@@ -1,11 +1,50 @@
-use crate::resources::Job;
+use crate::models::{JobCancellation, WorkerRegistration};
+use crate::resources::{connections_per_job, Executor, Job};
+use crate::scheduler::Scheduler;
+use crate::server::DatabasePool;
+use automaat_core::{Cancellation, Context, ContextError};
 use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
 use std::{env, error::Error, thread, time};
 
+mod heartbeat;
+mod listener;
+
+pub(crate) use heartbeat::{Heartbeat, LOST_AFTER_SECONDS};
+pub(crate) use listener::{listener_url, Listener, Wakeup};
+
+/// The interval at which a running job is checked for cancellation requests.
+const CANCELLATION_INTERVAL: time::Duration = time::Duration::from_millis(500);
+
+/// The interval at which idle slots poll for pending jobs.
+///
+/// Slots are woken up as soon as a new job is pending, so this interval only
+/// serves as a fallback, in case a notification is missed.
+const POLL_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+#[derive(Clone)]
 pub(crate) struct Worker {
-    conn: PgConnection,
+    pool: DatabasePool,
+    database_url: String,
+
+    /// The database URL used by the listener, see [`listener_url`].
+    listener_url: String,
+
+    /// The registration of the worker, used to detect lost workers.
+    registration: WorkerRegistration,
+
+    /// Wakes up idle slots once a job is ready to run.
+    wakeup: Arc<Wakeup>,
+
+    /// The workspaces of the jobs that paused while running on the worker.
+    workspaces: Arc<Workspaces>,
+
+    /// The number of jobs the worker runs concurrently, each job running in
+    /// its own "slot" thread.
+    concurrency: usize,
 }
 
 pub(crate) enum Event {
@@ -16,57 +55,425 @@ pub(crate) enum Event {
 
 impl Worker {
     /// Create a new worker instance.
-    pub(crate) fn from_environment() -> Result<Self, Box<dyn Error>> {
+    ///
+    /// The provided arguments can contain a `--queues` option, with a comma
+    /// separated list of queues from which the worker claims jobs. Without
+    /// that option, the queues are read from `WORKER_QUEUES`. Jobs that do not
+    /// require a queue are claimed by any worker.
+    pub(crate) fn from_environment(args: &[String]) -> Result<Self, Box<dyn Error>> {
         let database_url = env::var("DATABASE_URL")?;
-        let conn = PgConnection::establish(&database_url)?;
+        let listener_url = listener_url(&database_url)?;
+        let queues = match parse_queues_option(args)? {
+            Some(queues) => queues,
+            None => match env::var("WORKER_QUEUES") {
+                Err(_) => vec![],
+                Ok(queues) => parse_queues(&queues)?,
+            },
+        };
+
+        let concurrency = match env::var("WORKER_CONCURRENCY") {
+            Err(_) => 1,
+            Ok(concurrency) => concurrency.parse()?,
+        };
+
+        if concurrency == 0 {
+            return Err("WORKER_CONCURRENCY must be at least 1".into());
+        }
 
+        // Each slot runs one job at a time, using up to `connections_per_job`
+        // connections, which is 22 with the current limits. The scheduler
+        // uses one additional connection, so a worker running two jobs
+        // concurrently uses up to `2 * 22 + 1 = 45` connections.
+        //
+        // The heartbeat and the listener use connections of their own, so that
+        // they keep running, even if all connections of the pool are in use.
+        #[allow(clippy::cast_possible_truncation)]
+        let pool = Pool::builder()
+            .max_size((concurrency * connections_per_job()) as u32 + 1)
+            .build(ConnectionManager::new(database_url.as_str()))?;
+
+        let conn = pool.get()?;
         crate::embedded_migrations::run(&conn)?;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+        let registration = WorkerRegistration::create(&conn, &queues, concurrency as i32)?;
 
-        Ok(Self { conn })
+        Ok(Self {
+            pool,
+            database_url,
+            listener_url,
+            registration,
+            wakeup: Arc::default(),
+            workspaces: Arc::default(),
+            concurrency,
+        })
     }
 
     /// Start polling for pending jobs and run them to completion.
     ///
+    /// The worker runs up to the configured number of jobs concurrently. Idle
+    /// slots are woken up by a [`Listener`] as soon as a job is ready to run.
+    ///
+    /// The worker also runs a [`Scheduler`] on a separate thread, to create
+    /// the jobs of any active schedules, and a [`Heartbeat`], to signal the
+    /// worker is still alive, and to recover the jobs of any lost workers.
+    ///
     /// This method blocks until a Unix `SIGINT` or `SIGTERM` signal is
-    /// received. When any of these signals are received, any running job runs
-    /// to completion, before the method returns.
+    /// received. When any of these signals are received, all running jobs run
+    /// to completion, before the worker removes its registration and the
+    /// method returns. The jobs that paused on the worker lose their
+    /// workspace, and are recovered in the same way as the jobs of a lost
+    /// worker.
     pub(crate) fn run_to_completion(self) -> Result<(), Box<dyn Error>> {
         let running = Arc::new(AtomicBool::new(true));
         let closer = running.clone();
-        ctrlc::set_handler(move || closer.store(false, Ordering::SeqCst))?;
+        let wakeup = self.wakeup.clone();
+        ctrlc::set_handler(move || {
+            closer.store(false, Ordering::SeqCst);
+            wakeup.notify();
+        })?;
+
+        let heartbeat = self.start_heartbeat(running.clone());
+        let listener = self.start_listener(running.clone());
+        let mut threads = vec![self.start_scheduler(running.clone())];
+        for _ in 0..self.concurrency {
+            threads.push(self.start_slot(running.clone()));
+        }
+
+        // Wait for all threads to finish, before returning the first error
+        // encountered (if any).
+        let mut result = Ok(());
+        for thread in threads {
+            let thread_result = thread
+                .join()
+                .unwrap_or_else(|_| Err("worker thread panicked".to_owned()));
 
+            result = result.and(thread_result);
+        }
+
+        let _ = listener.join();
+        result = result.and(heartbeat.stop());
+
+        let conn = self.pool.get()?;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            for job in Job::find_orphaned(&self.registration, &conn)? {
+                let _ = job.recover(&conn)?;
+            }
+
+            self.registration.delete(&conn)
+        })?;
+
+        result.map_err(Into::into)
+    }
+
+    /// Run jobs on a separate thread, one job at a time, until the `running`
+    /// flag is unset.
+    ///
+    /// If the slot stops because of an error, the flag is unset, to stop the
+    /// other slots as well.
+    fn start_slot(&self, running: Arc<AtomicBool>) -> thread::JoinHandle<Result<(), String>> {
+        let worker = self.clone();
+
+        thread::spawn(move || {
+            let result = worker.run_slot(&running);
+
+            running.store(false, Ordering::SeqCst);
+            worker.wakeup.notify();
+            result
+        })
+    }
+
+    fn run_slot(&self, running: &AtomicBool) -> Result<(), String> {
         while running.load(Ordering::SeqCst) {
             use Event::*;
-            match self.run_single_job() {
-                NoPendingJob => thread::sleep(time::Duration::from_millis(100)),
+
+            let generation = self.wakeup.generation();
+            let conn = self.pool.get().map_err(|e| e.to_string())?;
+            match self.run_single_job(&conn) {
+                NoPendingJob => {
+                    let paused =
+                        Job::find_paused(&self.registration, &conn).map_err(|e| e.to_string())?;
+
+                    self.workspaces.retain(&paused);
+                    self.wakeup.wait(generation, POLL_INTERVAL)
+                }
                 Done => {}
-                DatabaseError(err) => return Err(err.into()),
+                DatabaseError(err) => return Err(err.to_string()),
             };
         }
 
         Ok(())
     }
 
-    /// Find a pending job in the database, and run it to completion.
-    pub(crate) fn run_single_job(&self) -> Event {
-        use Event::*;
+    /// Record heartbeats on a separate thread, until the returned heartbeat is
+    /// stopped.
+    ///
+    /// If the heartbeat stops because of an error, the `running` flag is
+    /// unset, to stop the worker as well.
+    fn start_heartbeat(&self, running: Arc<AtomicBool>) -> Heartbeat {
+        let wakeup = self.wakeup.clone();
 
-        let result = self.conn.transaction(|| {
-            let mut job = match Job::find_next_unlocked_pending(&self.conn) {
-                Ok(Some(job)) => job,
-                Ok(None) => return Ok(NoPendingJob),
-                Err(err) => return Err(err),
-            };
+        Heartbeat::start(&self.database_url, self.registration.clone(), move || {
+            running.store(false, Ordering::SeqCst);
+            wakeup.notify();
+        })
+    }
 
-            job.as_running(&self.conn)?
-                .run(&self.conn)
-                .or_else(|_| job.as_failed(&self.conn).map(|_| ()))
-                .map(|_| Done)
-        });
+    /// Listen for pending jobs on a separate thread, until the `running` flag
+    /// is unset.
+    fn start_listener(&self, running: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+        let listener = Listener::new(
+            self.listener_url.clone(),
+            vec![Job::PENDING_CHANNEL],
+            self.wakeup.clone(),
+        );
 
-        match result {
-            Ok(event) => event,
+        thread::spawn(move || listener.run_while(&running))
+    }
+
+    /// Run the scheduler on a separate thread, until the `running` flag is
+    /// unset.
+    ///
+    /// If the scheduler stops because of an error, the flag is unset, to stop
+    /// the worker as well.
+    fn start_scheduler(&self, running: Arc<AtomicBool>) -> thread::JoinHandle<Result<(), String>> {
+        let scheduler = Scheduler::new(self.pool.clone());
+        let wakeup = self.wakeup.clone();
+
+        thread::spawn(move || {
+            let result = scheduler.run_while(&running);
+
+            running.store(false, Ordering::SeqCst);
+            wakeup.notify();
+            result
+        })
+    }
+
+    /// Claim a pending job in the database, and run it to completion.
+    pub(crate) fn run_single_job(&self, conn: &PgConnection) -> Event {
+        use Event::*;
+
+        let job = match Job::claim_next_pending(conn, &self.registration, None) {
+            Ok(Some(job)) => job,
+            Ok(None) => return NoPendingJob,
+            Err(err) => return DatabaseError(err),
+        };
+
+        match run_claimed_job(
+            &self.pool,
+            conn,
+            job,
+            &Executor::Local,
+            Some(&self.workspaces),
+        ) {
+            Ok(_) => Done,
             Err(err) => DatabaseError(err),
         }
     }
 }
+
+/// Run a claimed job to completion, using the provided executor to run the
+/// processors of its steps.
+///
+/// While the job is running, a separate thread watches for any requests to
+/// cancel the job, and cancels the job context if one is found.
+///
+/// If workspaces are provided, a job that pauses keeps its workspace, which is
+/// used again once the job continues. Without them, the job continues in a new
+/// workspace, which is the case for jobs running for an agent, as the agent
+/// keeps the workspace instead.
+///
+/// The job is not run inside a transaction, so that every change to the state
+/// of the job and its steps is visible as soon as it happens.
+pub(crate) fn run_claimed_job(
+    pool: &DatabasePool,
+    conn: &PgConnection,
+    mut job: Job,
+    executor: &Executor,
+    workspaces: Option<&Workspaces>,
+) -> QueryResult<()> {
+    let context = match workspaces {
+        Some(workspaces) => workspaces.take(job.id),
+        None => Context::new(),
+    };
+
+    match context {
+        Err(_) => job.as_failed(conn),
+        Ok(context) => {
+            let watcher = CancellationWatcher::start(pool.clone(), &job, &context);
+            let result = job
+                .run(conn, pool, &context, executor)
+                .or_else(|_| job.as_failed(conn));
+
+            watcher.stop();
+
+            if let Some(workspaces) = workspaces {
+                if result.is_ok() && job.is_paused(conn)? {
+                    workspaces.keep(job.id, context);
+                }
+            }
+
+            result
+        }
+    }
+}
+
+/// The workspaces of the jobs that paused while running on a worker, to wait
+/// for an approval, or for input.
+///
+/// Once such a job continues, it is claimed by the same worker, and runs its
+/// remaining steps in the workspace it paused with.
+#[derive(Debug, Default)]
+pub(crate) struct Workspaces(Mutex<HashMap<i32, Context>>);
+
+impl Workspaces {
+    /// Take the context of the job with the provided ID, if the job paused,
+    /// or create a new context otherwise.
+    fn take(&self, job_id: i32) -> Result<Context, ContextError> {
+        let context = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&job_id);
+
+        context.map_or_else(Context::new, Ok)
+    }
+
+    /// Keep the context of the job with the provided ID, until the job
+    /// continues.
+    fn keep(&self, job_id: i32, context: Context) {
+        let _ = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(job_id, context);
+    }
+
+    /// Remove the workspaces of all jobs, except for the provided jobs that
+    /// are still paused, for example because the other jobs were cancelled.
+    fn retain(&self, paused: &[i32]) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|job_id, _| paused.contains(job_id))
+    }
+}
+
+/// Returns the queues provided with the `--queues` option, if any.
+///
+/// Both `--queues deploy,db` and `--queues=deploy,db` are accepted.
+pub(crate) fn parse_queues_option(args: &[String]) -> Result<Option<Vec<String>>, Box<dyn Error>> {
+    match args {
+        [] => Ok(None),
+        [option, queues] if option == "--queues" => parse_queues(queues).map(Some),
+        [option] if option.starts_with("--queues=") => {
+            parse_queues(&option["--queues=".len()..]).map(Some)
+        }
+        _ => Err("usage: automaat [worker|agent] [--queues <queue>,...]".into()),
+    }
+}
+
+/// Parse a comma separated list of queues.
+pub(crate) fn parse_queues(queues: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let queues: Vec<String> = queues.split(',').map(|q| q.trim().to_owned()).collect();
+
+    if queues.iter().any(String::is_empty) {
+        return Err("queue names cannot be empty".into());
+    }
+
+    Ok(queues)
+}
+
+/// Watches the database for a request to cancel a running job, and cancels
+/// the job context once such a request is found.
+///
+/// The job context is also cancelled once the job is no longer claimed by the
+/// worker running it, because the worker was considered lost, and the job was
+/// recovered by another worker. The running job then stops, without storing
+/// any of its results.
+///
+/// The watcher uses its own database connection, as the connection of the
+/// worker slot is blocked while the job is running.
+struct CancellationWatcher {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl CancellationWatcher {
+    /// Start watching for cancellation requests of the provided job.
+    fn start(pool: DatabasePool, job: &Job, context: &Context) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let job = job.clone();
+        let cancellation = context.cancellation();
+
+        let handle = thread::spawn(move || Self::watch(&pool, &job, &cancellation, &stopped));
+
+        Self { stop, handle }
+    }
+
+    /// Stop watching for cancellation requests.
+    fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+
+    fn watch(
+        pool: &DatabasePool,
+        job: &Job,
+        cancellation: &Cancellation,
+        stopped: &mpsc::Receiver<()>,
+    ) {
+        // If no connection can be acquired, the job can't be cancelled, but it
+        // will still run to completion.
+        let conn = match pool.get() {
+            Ok(conn) => conn,
+            Err(_) => return,
+        };
+
+        loop {
+            if let Ok(Some(_)) = JobCancellation::find_by_job(job, &conn) {
+                return cancellation.cancel();
+            }
+
+            if let Ok(false) = job.is_claimed(&conn) {
+                return cancellation.cancel();
+            }
+
+            match stopped.recv_timeout(CANCELLATION_INTERVAL) {
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                _ => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn test_queues_option() {
+        let expected = Some(vec!["deploy".to_owned(), "db".to_owned()]);
+
+        assert_eq!(parse_queues_option(&args(&[])).unwrap(), None);
+        assert_eq!(
+            parse_queues_option(&args(&["--queues", "deploy, db"])).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_queues_option(&args(&["--queues=deploy,db"])).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_invalid_queues_option() {
+        assert!(parse_queues_option(&args(&["--queues"])).is_err());
+        assert!(parse_queues_option(&args(&["--queues", "deploy,,db"])).is_err());
+        assert!(parse_queues_option(&args(&["--concurrency", "2"])).is_err());
+        assert!(parse_queues_option(&args(&["--queues=db", "--queues=deploy"])).is_err());
+    }
+}
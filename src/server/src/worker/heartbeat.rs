@@ -0,0 +1,118 @@
+use crate::models::WorkerRegistration;
+use crate::resources::Job;
+use crate::server::DatabasePool;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use std::sync::mpsc;
+use std::{thread, time};
+
+/// The interval at which a worker records a heartbeat.
+const HEARTBEAT_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+/// The number of seconds after which a worker that did not record a heartbeat
+/// is considered lost.
+///
+/// This is a multiple of the heartbeat interval, to prevent a worker from
+/// being considered lost because of a single slow or failed heartbeat.
+pub(crate) const LOST_AFTER_SECONDS: i32 = 30;
+
+/// Periodically records a heartbeat of the worker, and recovers the jobs of any
+/// workers that stopped recording heartbeats.
+///
+/// The heartbeat keeps running until it is explicitly stopped, so that the
+/// worker is not considered lost while it finishes its running jobs during
+/// shutdown.
+pub(crate) struct Heartbeat {
+    stop: mpsc::Sender<()>,
+    handle: thread::JoinHandle<Result<(), String>>,
+}
+
+impl Heartbeat {
+    /// Start recording heartbeats for the provided worker registration.
+    ///
+    /// The heartbeat uses a database connection of its own, which is
+    /// re-established if it breaks, so that it is never held up by the jobs
+    /// of the worker using all connections of their pool.
+    ///
+    /// The `on_error` callback is called if the heartbeat stops because of an
+    /// error.
+    pub(crate) fn start<F>(
+        database_url: &str,
+        registration: WorkerRegistration,
+        on_error: F,
+    ) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let pool = Pool::builder()
+            .max_size(1)
+            .build_unchecked(ConnectionManager::new(database_url));
+
+        let (stop, stopped) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let result = Self::run(&pool, &registration, &stopped);
+            if result.is_err() {
+                on_error();
+            }
+
+            result
+        });
+
+        Self { stop, handle }
+    }
+
+    /// Stop recording heartbeats, returning the error that stopped the
+    /// heartbeat earlier, if any.
+    pub(crate) fn stop(self) -> Result<(), String> {
+        let _ = self.stop.send(());
+        self.handle
+            .join()
+            .unwrap_or_else(|_| Err("heartbeat thread panicked".to_owned()))
+    }
+
+    fn run(
+        pool: &DatabasePool,
+        registration: &WorkerRegistration,
+        stopped: &mpsc::Receiver<()>,
+    ) -> Result<(), String> {
+        loop {
+            let conn = pool.get().map_err(|e| e.to_string())?;
+
+            // If the registration is gone, another worker considered this
+            // worker lost, and already recovered its jobs.
+            if !registration.heartbeat(&conn).map_err(|e| e.to_string())? {
+                return Err("worker was considered lost after missing heartbeats".to_owned());
+            }
+
+            reap_lost_workers(&conn).map_err(|e| e.to_string())?;
+
+            match stopped.recv_timeout(HEARTBEAT_INTERVAL) {
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+/// Recover the running jobs of all lost workers, and remove the registrations
+/// of those workers.
+///
+/// Remote agents are reaped in the same way, recovering the jobs claimed for
+/// them, even though those jobs are run by the agent hub of a server that is
+/// still alive, see [`Job::find_orphaned`].
+///
+/// Every worker reaps lost workers, but the registrations and jobs are locked
+/// while being recovered, so each job is only recovered once.
+fn reap_lost_workers(conn: &PgConnection) -> QueryResult<()> {
+    conn.transaction(|| {
+        for worker in WorkerRegistration::find_lost(LOST_AFTER_SECONDS, conn)? {
+            for job in Job::find_orphaned(&worker, conn)? {
+                let _ = job.recover(conn)?;
+            }
+
+            worker.delete(conn)?;
+        }
+
+        Ok(())
+    })
+}
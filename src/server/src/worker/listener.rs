@@ -0,0 +1,265 @@
+use fallible_iterator::FallibleIterator;
+use postgres::{Connection, TlsMode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::{env, thread, time};
+
+/// The maximum time the listener blocks waiting for a notification, before
+/// checking if it should keep running.
+const LISTEN_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
+/// The time to wait before reconnecting, after the listener connection failed.
+const RECONNECT_INTERVAL: time::Duration = time::Duration::from_secs(5);
+
+/// A signal used to wake up idle worker slots, once new jobs are ready to run.
+///
+/// Each signal increases the "generation" of the wakeup. A slot records the
+/// generation before looking for a pending job, and if it finds none, waits
+/// for the generation to change. This guarantees no signal is missed between
+/// looking for a job, and starting to wait.
+#[derive(Debug, Default)]
+pub(crate) struct Wakeup {
+    generation: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl Wakeup {
+    /// The current generation of the wakeup signal.
+    pub(crate) fn generation(&self) -> u64 {
+        *self
+            .generation
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wake up all waiting slots.
+    pub(crate) fn notify(&self) {
+        let mut generation = self
+            .generation
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *generation = generation.wrapping_add(1);
+
+        self.condvar.notify_all();
+    }
+
+    /// Block until the generation differs from the provided one, or until the
+    /// timeout expires.
+    pub(crate) fn wait(&self, since: u64, timeout: time::Duration) {
+        let deadline = time::Instant::now() + timeout;
+        let mut generation = self
+            .generation
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        while *generation == since {
+            let now = time::Instant::now();
+            if now >= deadline {
+                return;
+            }
+
+            generation = self
+                .condvar
+                .wait_timeout(generation, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+/// Prepare the provided database URL for the connections listening for
+/// notifications, see [`Listener`].
+///
+/// These connections are made using the `postgres` crate, without TLS
+/// support. Instead of failing to connect over and over again once running,
+/// a URL that requires TLS, using `sslmode=require`, `verify-ca` or
+/// `verify-full`, is rejected on startup. The `PGSSLMODE` environment variable
+/// is used if the URL does not configure `sslmode`, as is done by the other
+/// database connections.
+///
+/// The `sslmode` parameter is removed from the returned URL, as the
+/// `postgres` crate would otherwise pass it on to the server as a run-time
+/// parameter.
+pub(crate) fn listener_url(database_url: &str) -> Result<String, String> {
+    without_sslmode(
+        database_url,
+        env::var("PGSSLMODE").ok().as_ref().map(String::as_str),
+    )
+}
+
+/// See [`listener_url`], using the provided SSL mode if the URL does not
+/// configure any.
+fn without_sslmode(database_url: &str, default_mode: Option<&str>) -> Result<String, String> {
+    let mut parts = database_url.splitn(2, '?');
+    let base = parts.next().unwrap_or_default();
+    let query = parts.next().unwrap_or_default();
+
+    let mut mode = default_mode;
+    let mut params = vec![];
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let mut pair = param.splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some("sslmode"), value) => mode = value,
+            _ => params.push(param),
+        }
+    }
+
+    match mode {
+        None | Some("disable") | Some("allow") | Some("prefer") => {}
+        Some(mode) => {
+            return Err(format!(
+                "sslmode={} is not supported: notifications are received over \
+                 connections without TLS",
+                mode
+            ))
+        }
+    }
+
+    if params.is_empty() {
+        Ok(base.to_owned())
+    } else {
+        Ok(format!("{}?{}", base, params.join("&")))
+    }
+}
+
+/// Listens for Postgres notifications about pending jobs, and wakes up the
+/// idle worker slots when one is received.
+///
+/// The listener can listen on multiple channels, for example to also wake up
+/// agents waiting for a dispatch.
+///
+/// Diesel does not support receiving notifications, so the listener uses a
+/// separate connection, using the `postgres` crate. The listener connects to
+/// the URL prepared by [`listener_url`].
+pub(crate) struct Listener {
+    database_url: String,
+    channels: Vec<&'static str>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl Listener {
+    /// Create a new listener, waking up slots through the provided signal
+    /// when a notification is received on any of the provided channels.
+    pub(crate) const fn new(
+        database_url: String,
+        channels: Vec<&'static str>,
+        wakeup: Arc<Wakeup>,
+    ) -> Self {
+        Self {
+            database_url,
+            channels,
+            wakeup,
+        }
+    }
+
+    /// Listen for notifications until the `running` flag is unset.
+    ///
+    /// If the connection to the database fails, the listener reconnects after
+    /// a short interval. In the meantime, the worker slots fall back to
+    /// polling for pending jobs.
+    pub(crate) fn run_while(&self, running: &AtomicBool) {
+        while running.load(Ordering::SeqCst) {
+            if self.listen(running).is_err() {
+                thread::sleep(RECONNECT_INTERVAL);
+            }
+        }
+    }
+
+    fn listen(&self, running: &AtomicBool) -> postgres::Result<()> {
+        let conn = Connection::connect(self.database_url.as_str(), TlsMode::None)?;
+        for channel in &self.channels {
+            let _ = conn.execute(&format!("LISTEN {}", channel), &[])?;
+        }
+
+        // Any job that became pending while the listener was not connected
+        // would otherwise have to wait for the fallback poll.
+        self.wakeup.notify();
+
+        let notifications = conn.notifications();
+        let mut notifications = notifications.timeout_iter(LISTEN_TIMEOUT);
+
+        while running.load(Ordering::SeqCst) {
+            if notifications.next()?.is_some() {
+                self.wakeup.notify();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_without_sslmode() {
+        let url = "postgres://postgres@localhost/automaat";
+
+        assert_eq!(without_sslmode(url, None).unwrap(), url);
+        assert_eq!(
+            without_sslmode(&format!("{}?sslmode=disable", url), None).unwrap(),
+            url
+        );
+        assert_eq!(
+            without_sslmode(
+                &format!("{}?application_name=automaat&sslmode=prefer", url),
+                Some("require")
+            )
+            .unwrap(),
+            format!("{}?application_name=automaat", url)
+        );
+        assert_eq!(without_sslmode(url, Some("allow")).unwrap(), url);
+    }
+
+    #[test]
+    fn test_without_sslmode_requiring_tls() {
+        let url = "postgres://postgres@localhost/automaat";
+
+        for mode in &["require", "verify-ca", "verify-full"] {
+            assert!(without_sslmode(&format!("{}?sslmode={}", url, mode), None).is_err());
+            assert!(without_sslmode(url, Some(mode)).is_err());
+        }
+    }
+
+    #[test]
+    fn test_wakeup_timeout() {
+        let wakeup = Wakeup::default();
+        let start = time::Instant::now();
+
+        wakeup.wait(wakeup.generation(), time::Duration::from_millis(20));
+
+        assert!(start.elapsed() >= time::Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_wakeup_missed_notification() {
+        let wakeup = Wakeup::default();
+        let generation = wakeup.generation();
+        wakeup.notify();
+
+        let start = time::Instant::now();
+        wakeup.wait(generation, time::Duration::from_secs(10));
+
+        assert!(start.elapsed() < time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_wakeup_notify() {
+        let wakeup = Arc::new(Wakeup::default());
+        let generation = wakeup.generation();
+
+        let notifier = wakeup.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(time::Duration::from_millis(20));
+            notifier.notify();
+        });
+
+        let start = time::Instant::now();
+        wakeup.wait(generation, time::Duration::from_secs(10));
+        handle.join().unwrap();
+
+        assert!(start.elapsed() < time::Duration::from_secs(10));
+        assert_ne!(wakeup.generation(), generation);
+    }
+}
@@ -1,14 +1,15 @@
 //! A visual representation of the result of a job.
 
 use crate::model::job::{
-    Job,
-    Status::{Failed, Succeeded},
+    self, Job, Prompt,
+    Status::{AwaitingInput, Failed, Succeeded},
 };
 use crate::utils;
 use dodrio::bumpalo::collections::string::String as BString;
 use dodrio::{Node, Render, RenderContext};
 use std::marker::PhantomData;
 use wasm_bindgen::UnwrapThrowExt;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 
 /// The `JobResult` component.
 pub(crate) struct JobResult<'a, C> {
@@ -40,6 +41,9 @@ trait Views<'b> {
     /// The job result output content.
     fn body(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
 
+    /// The form to answer the prompt of a job that is awaiting input.
+    fn prompt(&self, cx: &mut RenderContext<'b>, prompt: &Prompt) -> Node<'b>;
+
     /// The staging area for the job result.
     ///
     /// This is a hidden container that contains the raw escaped HTML output.
@@ -48,13 +52,17 @@ trait Views<'b> {
     fn staging(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
 }
 
-impl<'a, 'b, C> Views<'b> for JobResult<'a, C> {
+impl<'a, 'b, C> Views<'b> for JobResult<'a, C>
+where
+    C: job::Actions,
+{
     fn header(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
         use dodrio::builder::*;
 
         let title = match &self.job.status {
             Succeeded(_) => "Success!",
             Failed(_) => "Failed!",
+            AwaitingInput(_) => "Input Required",
             _ => unreachable!(),
         };
 
@@ -98,7 +106,70 @@ impl<'a, 'b, C> Views<'b> for JobResult<'a, C> {
     fn body(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
         use dodrio::builder::*;
 
-        section(&cx).attr("class", "body").finish()
+        let mut body = section(&cx).attr("class", "body");
+        if let AwaitingInput(prompt) = &self.job.status {
+            body = body.child(self.prompt(cx, prompt));
+        }
+
+        body.finish()
+    }
+
+    fn prompt(&self, cx: &mut RenderContext<'b>, prompt: &Prompt) -> Node<'b> {
+        use dodrio::builder::*;
+
+        let question = BString::from_str_in(&prompt.question, cx.bump).into_bump_str();
+
+        let field = match &prompt.options {
+            Some(options) => {
+                let options: Vec<_> = options
+                    .iter()
+                    .map(|v| BString::from_str_in(v, cx.bump).into_bump_str())
+                    .map(|v| option(&cx).attr("value", v).child(text(v)).finish())
+                    .collect();
+
+                div(&cx)
+                    .attr("class", "select")
+                    .child(
+                        select(&cx)
+                            .attr("class", "answer")
+                            .attr("aria-label", question)
+                            .children(options)
+                            .finish(),
+                    )
+                    .finish()
+            }
+            None => input(&cx)
+                .attr("class", "answer")
+                .attr("type", "text")
+                .attr("aria-label", question)
+                .finish(),
+        };
+
+        let step_id = prompt.step_id.clone();
+        let submit = button(&cx)
+            .attr("type", "button")
+            .attr("class", "continue")
+            .child(span(&cx).child(text("Continue ")).finish())
+            .child(span(&cx).child(i(&cx).finish()).finish())
+            .on("click", move |root, vdom, _event| {
+                // The answer field has no name, so that it is not submitted as
+                // a variable of the task when running the task again.
+                let value = utils::element::<HtmlSelectElement>(".job-prompt .answer")
+                    .map(|el| el.value())
+                    .or_else(|| {
+                        utils::element::<HtmlInputElement>(".job-prompt .answer")
+                            .map(|el| el.value())
+                    })
+                    .unwrap_or_default();
+
+                C::answer_prompt(root, vdom, step_id.clone(), value)
+            })
+            .finish();
+
+        div(&cx)
+            .attr("class", "job-prompt")
+            .children([p(&cx).child(text(question)).finish(), field, submit])
+            .finish()
     }
 
     fn staging(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
@@ -119,21 +190,28 @@ impl<'a, 'b, C> Views<'b> for JobResult<'a, C> {
     }
 }
 
-impl<'a, C> Render for JobResult<'a, C> {
+impl<'a, C> Render for JobResult<'a, C>
+where
+    C: job::Actions,
+{
     fn render<'b>(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
         use dodrio::builder::*;
 
         let class = match &self.job.status {
             Succeeded(_) => "job-result success",
             Failed(_) => "job-result failed",
+            AwaitingInput(_) => "job-result prompt",
             _ => unreachable!(),
         };
 
         let class = BString::from_str_in(class, cx.bump).into_bump_str();
 
-        div(&cx)
-            .attr("class", class)
-            .children([self.header(cx), self.body(cx), self.staging(cx)])
-            .finish()
+        // A job awaiting input has no output to stage yet.
+        let mut children = vec![self.header(cx), self.body(cx)];
+        if !self.job.is_awaiting_input() {
+            children.push(self.staging(cx));
+        }
+
+        div(&cx).attr("class", class).children(children).finish()
     }
 }
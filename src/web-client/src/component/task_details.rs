@@ -4,7 +4,7 @@
 
 use crate::app::App;
 use crate::component;
-use crate::model::job::{self, Job};
+use crate::model::job::{self, Job, Progress, Status};
 use crate::model::session::{self, AccessMode};
 use crate::model::task::{self, Task};
 use crate::utils;
@@ -60,6 +60,9 @@ trait Views<'b> {
     /// The list of variables belonging to the task.
     fn variables(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
 
+    /// The progress of the active job, while it is running.
+    fn progress(&self, cx: &mut RenderContext<'b>, progress: &Progress) -> Node<'b>;
+
     /// The footer section of the task details. This contains the navigation
     /// buttons for exiting the details view, or running the task.
     fn footer(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
@@ -76,6 +79,9 @@ trait Views<'b> {
     /// The run button to start running a task.
     fn btn_run(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
 
+    /// The cancel button to stop the active job, if it is still running.
+    fn btn_cancel(&self, cx: &mut RenderContext<'b>) -> Option<Node<'b>>;
+
     /// The (disabled) "missing authorization" button.
     fn btn_unauthorized(&self, cx: &mut RenderContext<'b>) -> Node<'b>;
 
@@ -113,9 +119,11 @@ where
         // etc)... I need to figure out why this active job thing was added in
         // the first place, but I suspect we don't need it anymore.
         if let Some(job) = self.task.active_job() {
-            if job.is_completed() {
+            if job.is_completed() || job.is_awaiting_input() {
                 let result = component::JobResult::<C>::new(job);
                 body = body.child(result.render(cx));
+            } else if let Status::Running(progress) = &job.status {
+                body = body.child(self.progress(cx, progress));
             }
         } else if !self.task.finished_jobs().is_empty() {
             let id = self.task.id();
@@ -164,6 +172,27 @@ where
             .finish()
     }
 
+    fn progress(&self, cx: &mut RenderContext<'b>, progress: &Progress) -> Node<'b> {
+        use dodrio::builder::*;
+
+        let label = match &progress.step {
+            Some(step) => format!(
+                "Running step {} of {}: {}",
+                progress.finished + 1,
+                progress.total,
+                step
+            ),
+            None => format!("Finished {} of {} steps", progress.finished, progress.total),
+        };
+
+        let label = BString::from_str_in(&label, cx.bump).into_bump_str();
+
+        div(&cx)
+            .attr("class", "job-progress")
+            .child(text(label))
+            .finish()
+    }
+
     fn footer(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
         use dodrio::builder::*;
 
@@ -180,7 +209,10 @@ where
             }
         };
 
-        footer(&cx).children([self.btn_back(cx), action]).finish()
+        let mut buttons = vec![self.btn_back(cx), action];
+        buttons.extend(self.btn_cancel(cx));
+
+        footer(&cx).children(buttons).finish()
     }
 
     fn btn_back(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
@@ -257,6 +289,25 @@ where
             .finish()
     }
 
+    fn btn_cancel(&self, cx: &mut RenderContext<'b>) -> Option<Node<'b>> {
+        use dodrio::builder::*;
+
+        let job = self.task.active_job().filter(|job| job.is_running())?;
+        let id = job.remote_id.clone()?;
+
+        let node = button(&cx)
+            .attr("type", "button")
+            .attr("class", "cancel")
+            .child(span(&cx).child(text("Cancel ")).finish())
+            .child(span(&cx).child(i(&cx).finish()).finish())
+            .on("click", move |root, vdom, _event| {
+                C::abort(root, vdom, id.clone())
+            })
+            .finish();
+
+        Some(node)
+    }
+
     fn btn_unauthorized(&self, cx: &mut RenderContext<'b>) -> Node<'b> {
         use dodrio::builder::*;
 
@@ -301,7 +352,7 @@ where
                 let vdom2 = vdom.clone();
                 spawn_local({
                     C::run(root, vdom.clone(), id.clone(), map)
-                        .and_then(move |job_id| C::poll_result(tasks, vdom, job_id, id, client))
+                        .and_then(move |job_id| C::watch_result(tasks, vdom, job_id, id, client))
                         .and_then(move |_| C::render_task_details(vdom2))
                 });
 
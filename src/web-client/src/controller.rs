@@ -3,20 +3,26 @@
 
 use crate::model::{job, session, statistics, task, tasks};
 use crate::router::Route;
-use crate::service::GraphqlService;
+use crate::service::{GraphqlError, GraphqlService};
 use crate::utils;
 use crate::App;
 use dodrio::{RootRender, VdomWeak};
 use futures::{future, prelude::*};
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::rc::Rc;
-use std::time::Duration;
 use wasm_bindgen::UnwrapThrowExt;
 use wasm_bindgen_futures::spawn_local;
-use wasm_timer::{Delay, Instant};
 use web_sys::HtmlElement;
 
+/// The delay before subscribing to the updates of a job again, after the
+/// connection to the server closed.
+const RESUBSCRIBE_DELAY_MS: i32 = 1_000;
+
+/// The maximum delay before subscribing to the updates of a job again, when
+/// the connection to the server keeps closing.
+const MAX_RESUBSCRIBE_DELAY_MS: i32 = 30_000;
+
 /// The main application controller.
 #[derive(Clone, Debug, Default)]
 pub(crate) struct Controller;
@@ -187,6 +193,7 @@ impl task::Actions for Controller {
                     Some(JobVariableInput { key, value })
                 })
                 .collect(),
+            priority: None,
         };
 
         let lock = app.cloned_tasks();
@@ -294,135 +301,209 @@ impl task::Actions for Controller {
 
 impl job::Actions for Controller {
     #[allow(clippy::wildcard_enum_match_arm)]
-    fn poll_result(
+    fn watch_result(
         lock: Rc<RefCell<tasks::Tasks>>,
         vdom: VdomWeak,
         id: job::RemoteId,
         task_id: task::Id,
         client: GraphqlService,
     ) -> Box<dyn Future<Item = (), Error = ()> + 'static> {
-        use crate::graphql::{fetch_job_result::*, FetchJobResult};
-        use futures::future::{loop_fn, Loop};
+        use crate::graphql::{job_updated::*, JobUpdated};
         use graphql_client::Response;
 
-        let tries = 0;
-        let future = loop_fn(
-            (tries, client, lock, id, task_id, vdom),
-            |(tries, client, lock, id, task_id, vdom)| {
-                let variables = Variables { id: id.to_string() };
-
-                // After the first request to check if the job finished, each
-                // subsequent request will be done after a small delay, to
-                // prevent flooding the server with requests.
-                let delay = move |response| {
-                    let timeout = if tries == 0 { 0 } else { 500 };
-
-                    Delay::new(Instant::now() + Duration::from_millis(timeout))
-                        .map(|_| response)
-                        .map_err(|_| vec![])
-                };
+        let remote_id = id.to_string();
+
+        // Check the response of the server and either return any errors
+        // returned by the server, or pass along the job details.
+        let handle_response = |response: Response<ResponseData>| {
+            if let Some(err) = response.errors {
+                Err(err.iter().map(|e| e.message.to_owned()).collect())
+            } else if let Some(data) = response.data {
+                match data.job {
+                    None => Err(vec!["no job data returned".to_owned()]),
+                    Some(job) => Ok(job),
+                }
+            } else {
+                Err(vec!["unknown server error".to_owned()])
+            }
+        };
 
-                // Check the response of the server and either return any
-                // errors returned by the server, or pass along the request
-                // body.
-                let handle_response = |response: Response<ResponseData>| {
-                    if let Some(err) = response.errors {
-                        Err(err.iter().map(|e| e.message.to_owned()).collect())
-                    } else if let Some(data) = response.data {
-                        match data.job {
-                            None => Err(vec!["no job data returned".to_owned()]),
-                            Some(job) => Ok(job),
-                        }
-                    } else {
-                        Err(vec!["unknown server error".to_owned()])
+        // Update the job status, including the possible error or success
+        // message, based on the server response.
+        let update_state = move |result: Result<JobUpdatedJob, Vec<String>>| {
+            use job::Status;
+            use JobStatus::*;
+            use JobStepStatus as S;
+
+            let mut tasks = lock.try_borrow_mut().unwrap_throw();
+            let task = tasks.get_mut(&task_id).unwrap_throw();
+            let job = task
+                .jobs
+                .iter_mut()
+                .find(|j| j.remote_id.as_ref() == Some(&id))
+                .unwrap_throw();
+
+            job.status = match result {
+                Err(err) => Status::Failed(Some(err.join("\n")).into()),
+                Ok(result) => match result.status {
+                    SCHEDULED | PENDING => Status::Delivered,
+                    RUNNING | AWAITING_APPROVAL => {
+                        let steps = result.steps.as_ref().map_or(&[][..], Vec::as_slice);
+
+                        Status::Running(job::Progress {
+                            step: steps
+                                .iter()
+                                .find(|step| {
+                                    step.status == S::RUNNING || step.status == S::AWAITING_APPROVAL
+                                })
+                                .map(|step| step.name.clone()),
+                            finished: steps
+                                .iter()
+                                .filter(|s| s.status == S::OK || s.status == S::SKIPPED)
+                                .count(),
+                            total: steps.len(),
+                        })
                     }
-                };
-
-                // Update the job status, including the possible error or
-                // success message, based on the server response.
-                let update_state = move |result: Result<FetchJobResultJob, Vec<String>>| {
-                    use job::Status;
-                    use JobStatus::*;
-                    use JobStepStatus as S;
-
-                    let mut tasks = lock.try_borrow_mut().unwrap_throw();
-                    let task = tasks.get_mut(&task_id).unwrap_throw();
-                    let job = task
-                        .jobs
-                        .iter_mut()
-                        .find(|j| j.remote_id.as_ref() == Some(&id))
-                        .unwrap_throw();
-
-                    job.status = match result {
-                        Err(err) => Status::Failed(Some(err.join("\n")).into()),
-                        Ok(result) => match result.status {
-                            SCHEDULED | PENDING | RUNNING => Status::Delivered,
-                            FAILED | CANCELLED | OK => match result.steps.as_ref() {
-                                None => Status::Succeeded(Some("task has no steps").into()),
-                                Some(steps) => {
-                                    let step = match steps
-                                        .iter()
-                                        .find(|step| step.status == JobStepStatus::FAILED)
-                                    {
-                                        Some(s) => s,
-                                        None => steps.last().unwrap_throw(),
-                                    };
-
-                                    match &step.status {
-                                        S::OK => Status::Succeeded((&step.output).into()),
-                                        _ => Status::Failed((&step.output).into()),
-                                    }
+                    AWAITING_INPUT => {
+                        let steps = result.steps.as_ref().map_or(&[][..], Vec::as_slice);
+
+                        // The prompt is not yet known if the job paused
+                        // before the step stored it.
+                        steps
+                            .iter()
+                            .filter(|step| step.status == S::AWAITING_INPUT)
+                            .find_map(|step| step.prompt.as_ref().map(|p| (step, p)))
+                            .map_or(Status::Delivered, |(step, prompt)| {
+                                Status::AwaitingInput(job::Prompt {
+                                    step_id: step.id.clone(),
+                                    question: prompt.question.clone(),
+                                    options: prompt.options.clone(),
+                                })
+                            })
+                    }
+                    FAILED | CANCELLED | OK => match result.steps.as_ref() {
+                        None => Status::Succeeded(Some("task has no steps").into()),
+                        Some(steps) => {
+                            let step = match steps.iter().find(|step| {
+                                step.status == S::FAILED
+                                    || (step.status == S::CANCELLED && step.output.is_some())
+                            }) {
+                                Some(s) => s,
+                                None => steps
+                                    .iter()
+                                    .rev()
+                                    .find(|step| step.status != S::SKIPPED)
+                                    .unwrap_or_else(|| steps.last().unwrap_throw()),
+                            };
+
+                            match &step.status {
+                                S::OK | S::SKIPPED => Status::Succeeded((&step.output).into()),
+                                S::CANCELLED if step.output.is_none() => {
+                                    Status::Failed(Some("job cancelled").into())
                                 }
-                            },
-                            _unknown => unreachable!(),
-                        },
-                    };
+                                _ => Status::Failed((&step.output).into()),
+                            }
+                        }
+                    },
+                    _unknown => unreachable!(),
+                },
+            };
 
-                    if tries > 120 && job.is_running() {
-                        job.status =
-                            Status::Failed(Some("timeout waiting for job to complete").into());
-                    }
+            let status = job.status.clone();
+            drop(tasks);
 
-                    let status = job.status.clone();
-                    drop(tasks);
+            vdom.schedule_render();
+            Ok(status)
+        };
 
-                    Ok((lock, id, task_id, status))
-                };
+        // The server sends the job details when subscribing, and every time
+        // they change, until the job either failed or succeeded, at which
+        // point the subscription is dropped.
+        //
+        // If the connection to the server closes before then, the job is
+        // subscribed to again. The delay before subscribing again doubles with
+        // every attempt that did not receive any job details.
+        let future = future::loop_fn(0, move |attempt: u32| {
+            let variables = Variables {
+                id: remote_id.clone(),
+            };
 
-                // Depending on the new job status, either keep polling the
-                // server for the final status, or break out of the loop.
-                let new_client = client.clone();
-                let retry_or_break = move |(lock, id, task_id, status)| {
-                    vdom.schedule_render();
-
-                    match status {
-                        job::Status::Delivered => Ok(Loop::Continue((
-                            tries + 1,
-                            new_client,
-                            lock,
-                            id,
-                            task_id,
-                            vdom,
-                        ))),
-                        job::Status::Created => unreachable!(),
-                        _ => Ok(Loop::Break(())),
-                    }
-                };
+            let received = Rc::new(Cell::new(false));
+            let update_state = update_state.clone();
 
-                client
-                    .request(FetchJobResult, variables)
-                    .map_err(|err| vec![err.to_string()])
-                    .and_then(delay)
-                    .and_then(handle_response)
-                    .then(update_state)
-                    .and_then(retry_or_break)
-            },
-        );
+            client
+                .subscribe(JobUpdated, variables)
+                .then(|result| match result {
+                    Err(GraphqlError::Disconnected) => Err(()),
+                    result => Ok(result
+                        .map_err(|err| vec![err.to_string()])
+                        .and_then(handle_response)),
+                })
+                .and_then(update_state)
+                .take_while(|status| {
+                    Ok(match status {
+                        job::Status::Delivered
+                        | job::Status::Running(_)
+                        | job::Status::AwaitingInput(_) => true,
+                        _ => false,
+                    })
+                })
+                .for_each({
+                    let received = received.clone();
+                    move |_| {
+                        received.set(true);
+                        Ok(())
+                    }
+                })
+                .then(move |result| match result {
+                    Ok(()) => future::Either::A(future::ok(future::Loop::Break(()))),
+                    Err(()) => {
+                        let attempt = if received.get() { 0 } else { attempt };
+                        let delay = RESUBSCRIBE_DELAY_MS
+                            .saturating_mul(1 << attempt.min(5))
+                            .min(MAX_RESUBSCRIBE_DELAY_MS);
+
+                        future::Either::B(
+                            utils::delay(delay).map(move |_| future::Loop::Continue(attempt + 1)),
+                        )
+                    }
+                })
+        });
 
         Box::new(future)
     }
 
-    fn abort(_root: &mut dyn RootRender, _vdom: VdomWeak, _id: job::RemoteId) {}
+    fn abort(root: &mut dyn RootRender, _vdom: VdomWeak, id: job::RemoteId) {
+        use crate::graphql::{cancel_job::Variables, CancelJob};
+
+        let app = root.unwrap_mut::<App>();
+        let variables = Variables { id: id.to_string() };
+
+        // The new job status is picked up by the `watch_result` future that is
+        // still running for this job, so the response can be ignored.
+        spawn_local(
+            app.client
+                .request(CancelJob, variables)
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+    }
+
+    fn answer_prompt(root: &mut dyn RootRender, _vdom: VdomWeak, step_id: String, value: String) {
+        use crate::graphql::{answer_job_step::Variables, AnswerJobStep};
+
+        let app = root.unwrap_mut::<App>();
+        let variables = Variables { id: step_id, value };
+
+        // Similar to aborting a job, the job continuing is picked up by the
+        // `watch_result` future that is still running for this job.
+        spawn_local(
+            app.client
+                .request(AnswerJobStep, variables)
+                .map(|_| ())
+                .map_err(|_| ()),
+        );
+    }
 }
 
 impl statistics::Actions for Controller {
@@ -432,6 +513,7 @@ impl statistics::Actions for Controller {
     ) -> Box<dyn Future<Item = (), Error = ()> + 'static> {
         use crate::graphql::fetch_statistics::*;
         use crate::graphql::FetchStatistics;
+        use std::convert::TryFrom;
 
         let app = root.unwrap_mut::<App>();
         let stats = app.cloned_statistics();
@@ -442,23 +524,19 @@ impl statistics::Actions for Controller {
                 response
                     .ok()
                     .and_then(|r| r.data)
-                    .map(|d| (d.tasks, d.jobs))
+                    .map(|d| (d.tasks, d.running, d.failed))
                     .ok_or(())
             })
-            .and_then(move |(tasks, jobs)| {
+            .and_then(move |(tasks, running, failed)| {
                 let mut stats = stats.try_borrow_mut().unwrap_throw();
 
-                let running = jobs
-                    .iter()
-                    .filter(|j| j.status == JobStatus::RUNNING)
-                    .count();
+                let count = |total: i64| usize::try_from(total).unwrap_or_default();
 
-                let failed = jobs
-                    .iter()
-                    .filter(|j| j.status == JobStatus::FAILED)
-                    .count();
-
-                stats.update(tasks.len(), running, failed);
+                stats.update(
+                    tasks.len(),
+                    count(running.total_count),
+                    count(failed.total_count),
+                );
                 vdom.render().map_err(|_| ())
             });
 
@@ -1,4 +1,4 @@
-//! The derived GraphQL query and mutation structures.
+//! The derived GraphQL query, mutation and subscription structures.
 
 use graphql_client::GraphQLQuery;
 
@@ -38,14 +38,32 @@ pub(crate) struct FetchTaskDetails;
 )]
 pub(crate) struct CreateJob;
 
-/// Fetch the details of a job.
+/// Receive the details of a job, every time they change.
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "schema.graphql",
-    query_path = "queries/fetch_job_result.graphql",
+    query_path = "queries/job_updated.graphql",
     response_derives = "Debug, Clone"
 )]
-pub(crate) struct FetchJobResult;
+pub(crate) struct JobUpdated;
+
+/// Cancel a scheduled, pending or running job.
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "queries/cancel_job.graphql",
+    response_derives = "Debug, Clone"
+)]
+pub(crate) struct CancelJob;
+
+/// Answer the prompt of a job step that is awaiting input.
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "schema.graphql",
+    query_path = "queries/answer_job_step.graphql",
+    response_derives = "Debug, Clone"
+)]
+pub(crate) struct AnswerJobStep;
 
 /// Fetch the details of the active session (if any).
 #[derive(GraphQLQuery)]
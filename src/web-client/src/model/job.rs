@@ -1,7 +1,7 @@
 //! A `Job` is an instance of a `Task` that is either scheduled to run, is
 //! actively running on the server, or ran in the past.
 
-use crate::graphql::fetch_job_result::FetchJobResultJobStepsOutput;
+use crate::graphql::job_updated::JobUpdatedJobStepsOutput;
 use crate::model::{task, tasks};
 use crate::service::GraphqlService;
 use dodrio::{RootRender, VdomWeak};
@@ -39,7 +39,7 @@ impl Job {
         use Status::*;
 
         match self.status {
-            Created | Delivered => false,
+            Created | Delivered | Running(_) | AwaitingInput(_) => false,
             Succeeded(_) | Failed(_) => true,
         }
     }
@@ -49,6 +49,15 @@ impl Job {
     pub(crate) fn is_running(&self) -> bool {
         !self.is_completed()
     }
+
+    /// Returns `true` if the job paused on the server, until the prompt of one
+    /// of its steps is answered.
+    pub(crate) fn is_awaiting_input(&self) -> bool {
+        match self.status {
+            Status::AwaitingInput(_) => true,
+            _ => false,
+        }
+    }
 }
 
 /// The job output, containing both the html and text (markdown) output.
@@ -99,8 +108,8 @@ where
     }
 }
 
-impl From<&FetchJobResultJobStepsOutput> for Output {
-    fn from(input: &FetchJobResultJobStepsOutput) -> Self {
+impl From<&JobUpdatedJobStepsOutput> for Output {
+    fn from(input: &JobUpdatedJobStepsOutput) -> Self {
         Self {
             html: input.html.clone(),
             text: input.text.clone(),
@@ -108,6 +117,33 @@ impl From<&FetchJobResultJobStepsOutput> for Output {
     }
 }
 
+/// The progress of a job that is running on the server.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub(crate) struct Progress {
+    /// The name of the step that is currently running, if any.
+    pub(crate) step: Option<String>,
+
+    /// The number of steps that finished running.
+    pub(crate) finished: usize,
+
+    /// The total number of steps of the job.
+    pub(crate) total: usize,
+}
+
+/// The prompt of a job step that is waiting for an answer.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub(crate) struct Prompt {
+    /// The ID of the job step that asks the question.
+    pub(crate) step_id: String,
+
+    /// The question to answer.
+    pub(crate) question: String,
+
+    /// The values one of which has to be picked as the answer, or `None` if
+    /// any answer is accepted.
+    pub(crate) options: Option<Vec<String>>,
+}
+
 /// The status of the job.
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub(crate) enum Status {
@@ -117,6 +153,13 @@ pub(crate) enum Status {
     /// The job was successfully delivered to the server.
     Delivered,
 
+    /// The server reported the job to be running its steps.
+    Running(Progress),
+
+    /// The server reported the job to be waiting for an answer to the prompt
+    /// of one of its steps.
+    AwaitingInput(Prompt),
+
     /// The server reported a successful run of the job.
     Succeeded(Output),
 
@@ -137,6 +180,8 @@ impl fmt::Display for Status {
         match self {
             Created => f.write_str("status-created"),
             Delivered => f.write_str("status-delivered"),
+            Running(_) => f.write_str("status-running"),
+            AwaitingInput(_) => f.write_str("status-awaiting-input"),
             Succeeded(_) => f.write_str("status-succeeded"),
             Failed(_) => f.write_str("status-failed"),
         }
@@ -163,14 +208,11 @@ impl fmt::Display for RemoteId {
 /// The actions a controller has to implement to bridge between the UI and the
 /// model.
 pub(crate) trait Actions {
-    /// Asks the server for the result of a job.
-    ///
-    /// If the job is still pending or running, this function should block until
-    /// the job either failed, or succeeded.
+    /// Subscribes to the result of a job on the server.
     ///
-    /// Once a final state is reached, the job must be updated with the new
-    /// details.
-    fn poll_result(
+    /// The job is updated every time its details change on the server. The
+    /// returned future resolves once the job either failed, or succeeded.
+    fn watch_result(
         tasks: Rc<RefCell<tasks::Tasks>>,
         vdom: VdomWeak,
         id: RemoteId,
@@ -183,4 +225,9 @@ pub(crate) trait Actions {
     /// This function can be used to stop a running job if the results of the
     /// job are no longer relevant.
     fn abort(root: &mut dyn RootRender, vdom: VdomWeak, id: RemoteId);
+
+    /// Answer the prompt of a job step that is awaiting input.
+    ///
+    /// The job continues running on the server once the prompt is answered.
+    fn answer_prompt(root: &mut dyn RootRender, vdom: VdomWeak, step_id: String, value: String);
 }
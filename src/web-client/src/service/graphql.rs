@@ -1,10 +1,23 @@
-//! The GraphQL service is a thin wrapper around a GraphQL-capable HTTP client.
+//! The GraphQL service is a thin wrapper around a GraphQL-capable HTTP client,
+//! and a WebSocket connection for subscriptions.
 
-use crate::CookieService;
+use crate::{utils, CookieService};
 use failure::{Compat, Fail};
-use futures::future::Future;
+use futures::prelude::*;
+use futures::unsync::mpsc;
+use gloo_events::EventListener;
 use graphql_client::{web, GraphQLQuery, Response};
+use js_sys::{Object, Reflect, JSON};
 use std::{error, fmt};
+use wasm_bindgen::{JsCast, JsValue, UnwrapThrowExt};
+use web_sys::{MessageEvent, WebSocket};
+
+/// The WebSocket sub-protocol used for subscriptions.
+const SUBSCRIPTION_PROTOCOL: &str = "graphql-ws";
+
+/// The ID of the operation started on a subscription connection. Each
+/// subscription uses its own connection, so the ID is always the same.
+const SUBSCRIPTION_ID: &str = "1";
 
 /// The GraphQL service.
 #[derive(Clone)]
@@ -23,6 +36,13 @@ pub(crate) enum Error {
     /// GraphQL client error.
     Client(Compat<web::ClientError>),
 
+    /// Subscription error.
+    Subscription(String),
+
+    /// The subscription connection to the server could not be opened, or it
+    /// closed.
+    Disconnected,
+
     /// Authentication error.
     Authentication,
 }
@@ -31,6 +51,8 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Client(err) => write!(f, "{}", err),
+            Error::Subscription(err) => f.write_str(err),
+            Error::Disconnected => f.write_str("connection to server closed"),
             Error::Authentication => f.write_str("authentication"),
         }
     }
@@ -40,7 +62,7 @@ impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Error::Client(err) => Some(err),
-            Error::Authentication => None,
+            Error::Subscription(_) | Error::Disconnected | Error::Authentication => None,
         }
     }
 }
@@ -71,15 +93,172 @@ impl Service {
         client
             .call(query, variables)
             .map_err(|err| Error::Client(err.compat()))
-            .and_then(move |response| {
-                if let Some(errors) = &response.errors {
-                    if errors.iter().any(|e| e.message == "Unauthorized") {
-                        cookie.remove("session");
-                        return futures::future::err(Error::Authentication);
-                    }
+            .and_then(move |response| authenticated(response, &cookie))
+    }
+
+    /// Subscribe to a GraphQL subscription on the server.
+    ///
+    /// The returned stream yields a response every time the result of the
+    /// subscription changes. The subscription is stopped once the stream is
+    /// dropped. If the connection to the server can't be opened, or closes,
+    /// the stream returns an [`Error::Disconnected`] error, after which the
+    /// subscription can be started again.
+    pub(crate) fn subscribe<Q: GraphQLQuery + 'static>(
+        &self,
+        _query: Q,
+        variables: Q::Variables,
+    ) -> Subscription<Response<Q::ResponseData>> {
+        let (sender, receiver) = mpsc::unbounded();
+        let socket = match WebSocket::new_with_str(&self.subscription_url(), SUBSCRIPTION_PROTOCOL)
+        {
+            Ok(socket) => socket,
+            Err(_) => {
+                let _ = sender.unbounded_send(Err(Error::Disconnected));
+
+                return Subscription {
+                    socket: None,
+                    receiver,
+                    _listeners: vec![],
+                };
+            }
+        };
+
+        // The connection is authenticated using the session token, if any,
+        // before the subscription is started.
+        let init = Object::new();
+        if let Some(ref auth) = self.cookie.get("session") {
+            Reflect::set(&init, &"Authorization".into(), &auth.into()).unwrap_throw();
+        }
+
+        let query = JsValue::from_serde(&Q::build_query(variables)).unwrap_throw();
+        let messages = [message("connection_init", &init), message("start", &query)];
+
+        let open = EventListener::new(&socket, "open", {
+            let socket = socket.clone();
+            move |_| {
+                for message in &messages {
+                    let _ = socket.send_with_str(message);
                 }
+            }
+        });
+
+        let receive = EventListener::new(&socket, "message", {
+            let sender = sender.clone();
+            let cookie = self.cookie.clone();
+            move |event| {
+                let data = event.unchecked_ref::<MessageEvent>().data();
+                let message = match data.as_string().and_then(|d| JSON::parse(&d).ok()) {
+                    None => return,
+                    Some(message) => message,
+                };
+
+                let kind = field(&message, "type").as_string().unwrap_or_default();
+                let payload = field(&message, "payload");
+                let result = match kind.as_str() {
+                    "data" => match payload.into_serde() {
+                        Ok(response) => authenticated(response, &cookie),
+                        Err(err) => Err(Error::Subscription(err.to_string())),
+                    },
+                    "error" | "connection_error" => {
+                        let message = field(&payload, "message").as_string();
+                        match message.as_ref().map(String::as_str) {
+                            Some("Unauthorized") => {
+                                cookie.remove("session");
+                                Err(Error::Authentication)
+                            }
+                            _ => Err(Error::Subscription(
+                                message.unwrap_or_else(|| "unknown server error".to_owned()),
+                            )),
+                        }
+                    }
+                    _ => return,
+                };
 
-                futures::future::ok(response)
-            })
+                let _ = sender.unbounded_send(result);
+            }
+        });
+
+        let close = EventListener::new(&socket, "close", move |_| {
+            let _ = sender.unbounded_send(Err(Error::Disconnected));
+        });
+
+        Subscription {
+            socket: Some(socket),
+            receiver,
+            _listeners: vec![open, receive, close],
+        }
     }
+
+    /// The WebSocket URL of the subscriptions endpoint, on the same host as
+    /// the application.
+    fn subscription_url(&self) -> String {
+        let location = utils::window().location();
+        let scheme = match location.protocol() {
+            Ok(ref protocol) if protocol == "https:" => "wss:",
+            _ => "ws:",
+        };
+
+        format!(
+            "{}//{}{}/subscriptions",
+            scheme,
+            location.host().unwrap_throw(),
+            self.endpoint
+        )
+    }
+}
+
+/// A GraphQL subscription, receiving results from the server until dropped.
+pub(crate) struct Subscription<T> {
+    socket: Option<WebSocket>,
+    receiver: mpsc::UnboundedReceiver<Result<T, Error>>,
+    _listeners: Vec<EventListener>,
+}
+
+impl<T> Stream for Subscription<T> {
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(Some(result))) => result.map(|item| Async::Ready(Some(item))),
+            Ok(Async::Ready(None)) | Err(_) => Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        if let Some(socket) = &self.socket {
+            let _ = socket.close();
+        }
+    }
+}
+
+/// Check if the server rejected the session, in which case the session is
+/// removed, and an authentication error is returned.
+fn authenticated<T>(response: Response<T>, cookie: &CookieService) -> Result<Response<T>, Error> {
+    if let Some(errors) = &response.errors {
+        if errors.iter().any(|e| e.message == "Unauthorized") {
+            cookie.remove("session");
+            return Err(Error::Authentication);
+        }
+    }
+
+    Ok(response)
+}
+
+/// Serialize a subscription protocol message of the provided type.
+fn message(kind: &str, payload: &JsValue) -> String {
+    let message = Object::new();
+    Reflect::set(&message, &"type".into(), &kind.into()).unwrap_throw();
+    Reflect::set(&message, &"id".into(), &SUBSCRIPTION_ID.into()).unwrap_throw();
+    Reflect::set(&message, &"payload".into(), payload).unwrap_throw();
+
+    JSON::stringify(&message).unwrap_throw().into()
+}
+
+/// Get a field of a JavaScript object, or `undefined` if it does not exist.
+fn field(object: &JsValue, name: &str) -> JsValue {
+    Reflect::get(object, &name.into()).unwrap_or(JsValue::UNDEFINED)
 }
@@ -5,5 +5,5 @@ mod graphql;
 mod shortcut;
 
 pub(crate) use cookie::Service as CookieService;
-pub(crate) use graphql::Service as GraphqlService;
+pub(crate) use graphql::{Error as GraphqlError, Service as GraphqlService};
 pub(crate) use shortcut::Service as ShortcutService;
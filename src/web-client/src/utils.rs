@@ -1,8 +1,10 @@
 //! Small utility functions.
 
-use js_sys::Array;
+use futures::Future;
+use js_sys::{Array, Promise};
 use std::collections::HashMap;
 use wasm_bindgen::{JsCast, UnwrapThrowExt};
+use wasm_bindgen_futures::JsFuture;
 use web_sys::{HtmlInputElement, HtmlSelectElement, Url};
 
 /// Get the current location hash, if any.
@@ -118,6 +120,17 @@ pub(crate) fn window() -> web_sys::Window {
     web_sys::window().unwrap_throw()
 }
 
+/// Returns a future that resolves once the provided number of milliseconds
+/// passed.
+pub(crate) fn delay(milliseconds: i32) -> impl Future<Item = (), Error = ()> {
+    let promise = Promise::new(&mut |resolve, _| {
+        let _ =
+            window().set_timeout_with_callback_and_timeout_and_arguments_0(&resolve, milliseconds);
+    });
+
+    JsFuture::from(promise).map(|_| ()).map_err(|_| ())
+}
+
 /// Get the top-level document.
 pub(crate) fn document() -> web_sys::Document {
     window().document().unwrap_throw()